@@ -0,0 +1,176 @@
+use std::collections::VecDeque;
+use std::hint::black_box;
+
+use cpu_tset::{Allocator, BlockHandle, ProcBuilder, Process, Strategy};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+
+// how many processes and how many blocks each the workloads start out with
+const PROCESSES: usize = 64;
+const BLOCKS: usize = 64;
+
+// the same sizes every run, so two runs are comparable, a mix of lots of small blocks and a
+// few big ones like the VM ends up with
+struct Sizes(u32);
+
+impl Sizes {
+    fn next(&mut self) -> u32 {
+        // xorshift32
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+
+        match self.0 % 16 {
+            0 => 1024 + self.0 % 3072,
+            1..=4 => 128 + self.0 % 896,
+            _ => 8 + self.0 % 120,
+        }
+    }
+}
+
+// an allocator with `PROCESSES` processes holding `BLOCKS` blocks each, every other one freed
+// again so the free list is full of holes, along with the live blocks oldest first
+fn fragmented(allocator: Allocator) -> (Allocator, Vec<Process>, VecDeque<BlockHandle>, Sizes) {
+    let mut allocator = allocator;
+    let mut ids = ProcBuilder::new();
+    let processes: Vec<Process> = (0..PROCESSES)
+        .map(|_| allocator.register_process(ids.count()).unwrap())
+        .collect();
+
+    let mut sizes = Sizes(0x9e37_79b9);
+    let mut live = VecDeque::new();
+    for i in 0..PROCESSES * BLOCKS {
+        let handle = allocator
+            .alloc(processes[i % PROCESSES], sizes.next())
+            .unwrap();
+        if i % 2 == 0 {
+            allocator.free(handle).unwrap();
+        } else {
+            live.push_back(handle);
+        }
+    }
+
+    (allocator, processes, live, sizes)
+}
+
+// every way of picking free blocks there is
+fn allocators() -> [(&'static str, Allocator); 4] {
+    [
+        ("first_fit", Allocator::with_strategy(Strategy::FirstFit)),
+        ("best_fit", Allocator::with_strategy(Strategy::BestFit)),
+        ("worst_fit", Allocator::with_strategy(Strategy::WorstFit)),
+        ("buddy", Allocator::buddy()),
+    ]
+}
+
+// the steady state of a busy VM: the oldest block goes and a new one of some other size comes
+fn churn(c: &mut Criterion) {
+    let mut group = c.benchmark_group("churn");
+
+    for (name, allocator) in allocators() {
+        let (mut allocator, processes, mut live, mut sizes) = fragmented(allocator);
+        let mut i = 0;
+
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                // safe to unwrap because there's always a block for every one freed
+                allocator.free(live.pop_front().unwrap()).unwrap();
+
+                i = (i + 1) % PROCESSES;
+                let handle = allocator.alloc(processes[i], sizes.next()).unwrap();
+                live.push_back(black_box(handle));
+            })
+        });
+    }
+
+    group.finish();
+}
+
+// allocating and freeing right away, which is mostly finding a free block that fits
+fn alloc_free(c: &mut Criterion) {
+    let mut group = c.benchmark_group("alloc_free");
+
+    for (name, allocator) in allocators() {
+        let (mut allocator, processes, _, _) = fragmented(allocator);
+
+        for size in [16, 256, 4096] {
+            group.bench_with_input(BenchmarkId::new(name, size), &size, |b, size| {
+                b.iter(|| {
+                    let handle = allocator.alloc(processes[0], *size).unwrap();
+                    allocator.free(black_box(handle)).unwrap()
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+// sharing a block with another process and that process letting go of it again
+fn share(c: &mut Criterion) {
+    let (mut allocator, processes, live, _) = fragmented(Allocator::new());
+    let handle = live[live.len() / 2];
+    let target = processes
+        .iter()
+        .copied()
+        .find(|x| *x != handle.process_id())
+        .unwrap();
+
+    c.bench_function("share", |b| {
+        b.iter(|| {
+            let shared = allocator.share(handle, target).unwrap();
+            allocator.free(black_box(shared)).unwrap()
+        })
+    });
+}
+
+// looking blocks up, which every access of a guest goes through
+fn borrow(c: &mut Criterion) {
+    let mut group = c.benchmark_group("borrow");
+    let (mut allocator, _, live, _) = fragmented(Allocator::new());
+    let handle = live[live.len() / 2];
+    let range = allocator.range(handle).unwrap();
+
+    group.bench_function("borrow", |b| {
+        b.iter(|| allocator.borrow(black_box(handle)).unwrap().len())
+    });
+    group.bench_function("range_borrow", |b| {
+        b.iter(|| {
+            allocator
+                .range_borrow(handle.process_id(), black_box(range.clone()))
+                .unwrap()
+                .len()
+        })
+    });
+    group.bench_function("read_u32", |b| {
+        b.iter(|| {
+            allocator
+                .read_u32(handle.process_id(), black_box(range.start))
+                .unwrap()
+        })
+    });
+    group.bench_function("borrow_mut", |b| {
+        b.iter(|| allocator.borrow_mut(black_box(handle)).unwrap()[0] ^= 1)
+    });
+
+    group.finish();
+}
+
+// a process exiting with all of its blocks still allocated
+fn clean_process(c: &mut Criterion) {
+    c.bench_function("clean_process", |b| {
+        b.iter_batched(
+            || {
+                let (allocator, processes, _, _) = fragmented(Allocator::new());
+                (allocator, processes[PROCESSES / 2])
+            },
+            |(mut allocator, process)| {
+                allocator.clean_process(process).unwrap();
+                allocator
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(benches, churn, alloc_free, share, borrow, clean_process);
+criterion_main!(benches);
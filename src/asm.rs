@@ -0,0 +1,1162 @@
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+use crate::isa::{self, Instr, Operand};
+use crate::link::{self, LinkError};
+use crate::object::{self, Object, RelocKind, Relocation, Section, Symbol};
+
+use expr::{Expr, Scope, Value};
+
+mod expr;
+mod include;
+mod macros;
+
+pub use include::preprocess;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum AsmError {
+    UnknownMnemonic(usize, String),
+    OperandCount(usize, String, usize),
+    BadRegister(usize, String),
+    BadImmediate(usize, String),
+    ImmediateTooLarge(usize, String),
+    BadLabel(usize, String),
+    DuplicateLabel(usize, String),
+    UndefinedLabel(usize, String),
+    BadMacro(usize, String),
+    UnterminatedMacro(usize, String),
+    DuplicateMacro(usize, String),
+    MacroRecursion(usize, String),
+    UnknownDirective(usize, String),
+    BadString(usize, String),
+    BadAlign(usize, u32),
+    OrgBackwards(usize, u32),
+    IncludeNotFound(usize, String),
+    IncludeCycle(usize, String),
+    BadExpression(usize, String),
+    NotRelocatable(usize, String),
+    DivisionByZero(usize, String),
+    ConstantBeforeEqu(usize, String),
+    Link(LinkError),
+}
+
+impl std::error::Error for AsmError {}
+
+pub type Result<T> = std::result::Result<T, AsmError>;
+
+impl AsmError {
+    /// The line of the source the error is on, link errors aren't on any.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            AsmError::UnknownMnemonic(line, _)
+            | AsmError::OperandCount(line, _, _)
+            | AsmError::BadRegister(line, _)
+            | AsmError::BadImmediate(line, _)
+            | AsmError::ImmediateTooLarge(line, _)
+            | AsmError::BadLabel(line, _)
+            | AsmError::DuplicateLabel(line, _)
+            | AsmError::UndefinedLabel(line, _)
+            | AsmError::BadMacro(line, _)
+            | AsmError::UnterminatedMacro(line, _)
+            | AsmError::DuplicateMacro(line, _)
+            | AsmError::MacroRecursion(line, _)
+            | AsmError::UnknownDirective(line, _)
+            | AsmError::BadString(line, _)
+            | AsmError::BadAlign(line, _)
+            | AsmError::OrgBackwards(line, _)
+            | AsmError::IncludeNotFound(line, _)
+            | AsmError::IncludeCycle(line, _)
+            | AsmError::BadExpression(line, _)
+            | AsmError::NotRelocatable(line, _)
+            | AsmError::DivisionByZero(line, _)
+            | AsmError::ConstantBeforeEqu(line, _) => Some(*line),
+            AsmError::Link(_) => None,
+        }
+    }
+
+    // the text the error is about, which is what a span points at
+    fn subject(&self) -> Option<String> {
+        match self {
+            AsmError::UnknownMnemonic(_, text)
+            | AsmError::OperandCount(_, text, _)
+            | AsmError::BadRegister(_, text)
+            | AsmError::BadImmediate(_, text)
+            | AsmError::ImmediateTooLarge(_, text)
+            | AsmError::BadLabel(_, text)
+            | AsmError::DuplicateLabel(_, text)
+            | AsmError::UndefinedLabel(_, text)
+            | AsmError::BadMacro(_, text)
+            | AsmError::UnterminatedMacro(_, text)
+            | AsmError::DuplicateMacro(_, text)
+            | AsmError::MacroRecursion(_, text)
+            | AsmError::UnknownDirective(_, text)
+            | AsmError::BadString(_, text)
+            | AsmError::IncludeNotFound(_, text)
+            | AsmError::IncludeCycle(_, text)
+            | AsmError::BadExpression(_, text)
+            | AsmError::NotRelocatable(_, text)
+            | AsmError::DivisionByZero(_, text)
+            | AsmError::ConstantBeforeEqu(_, text) => Some(text.clone()),
+            AsmError::BadAlign(_, _) => Some(".align".to_string()),
+            AsmError::OrgBackwards(_, _) => Some(".org".to_string()),
+            AsmError::Link(_) => None,
+        }
+    }
+
+    /// What went wrong, without the line in front of it.
+    pub fn message(&self) -> String {
+        match self {
+            AsmError::UnknownMnemonic(_, name) => format!("unknown mnemonic `{}`", name),
+            AsmError::OperandCount(_, name, expected) => {
+                format!("`{}` takes exactly {} operand(s)", name, expected)
+            }
+            AsmError::BadRegister(_, reg) => format!("`{}` is not a valid register", reg),
+            AsmError::BadImmediate(_, imm) => format!("`{}` is not a valid number", imm),
+            AsmError::ImmediateTooLarge(_, imm) => {
+                format!("`{}` does not fit in the requested operand size", imm)
+            }
+            AsmError::BadLabel(_, label) => format!("`{}` is not a valid label name", label),
+            AsmError::DuplicateLabel(_, label) => format!("label `{}` is already defined", label),
+            AsmError::UndefinedLabel(_, label) => format!("label `{}` is never defined", label),
+            AsmError::BadMacro(_, text) => format!("malformed macro directive `{}`", text),
+            AsmError::UnterminatedMacro(_, name) => {
+                format!("macro `{}` is missing its `%endmacro`", name)
+            }
+            AsmError::DuplicateMacro(_, name) => format!("macro `{}` is already defined", name),
+            AsmError::MacroRecursion(_, name) => format!(
+                "macro `{}` expands too deeply, it probably invokes itself",
+                name
+            ),
+            AsmError::UnknownDirective(_, name) => format!("unknown directive `{}`", name),
+            AsmError::BadString(_, text) => format!("`{}` is not a valid string literal", text),
+            AsmError::BadAlign(_, align) => format!("alignment {} is not a power of two", align),
+            AsmError::OrgBackwards(_, address) => format!(
+                "`.org {:#x}` would move backwards over already emitted bytes",
+                address
+            ),
+            AsmError::IncludeNotFound(_, file) => format!("couldn't find include `{}`", file),
+            AsmError::IncludeCycle(_, file) => {
+                format!("including `{}` would include it inside of itself", file)
+            }
+            AsmError::BadExpression(_, text) => format!("`{}` is not a valid expression", text),
+            AsmError::NotRelocatable(_, text) => {
+                format!(
+                    "`{}` can't be worked out before the labels are placed",
+                    text
+                )
+            }
+            AsmError::DivisionByZero(_, text) => format!("`{}` divides by zero", text),
+            AsmError::ConstantBeforeEqu(_, name) => {
+                format!("constant `{}` is used before its `.equ`", name)
+            }
+            AsmError::Link(err) => err.to_string(),
+        }
+    }
+
+    /// Where in `src` (the text that was assembled) the error is.
+    ///
+    /// The span covers the text the error is about if it can be found on the line, and the
+    /// whole line otherwise (like for lines coming out of a macro expansion).
+    pub fn span(&self, src: &str) -> Option<Span> {
+        let line = self.line()?;
+        let text = src.lines().nth(line.checked_sub(1)?).unwrap_or("");
+        let code = match find_unquoted(text, ';') {
+            Some(at) => &text[..at],
+            None => text,
+        };
+
+        let (at, len) = match self.subject().and_then(|x| code.find(&x).map(|at| (at, x))) {
+            Some((at, subject)) => (at, subject.chars().count()),
+            None => {
+                let at = code.len() - code.trim_start().len();
+                (at, code.trim().chars().count())
+            }
+        };
+
+        Some(Span {
+            line,
+            column: code[..at].chars().count() + 1,
+            len: len.max(1),
+        })
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line() {
+            Some(line) => write!(f, "line {}: {}", line, self.message()),
+            None => write!(f, "{}", self.message()),
+        }
+    }
+}
+
+/// A place in a source text, lines and columns (counted in characters) start at 1.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub len: usize,
+}
+
+/// An error along with the file it's in and where, formatted like compilers do:
+///
+/// ```text
+/// main.s:3:5: error: `r9` is not a valid register
+///     3 | MOV r9, 1
+///       |     ^^
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub span: Option<Span>,
+    /// The source line the span is on, empty without a span.
+    pub text: String,
+    pub error: AsmError,
+}
+
+impl Diagnostic {
+    /// Locate `error` in `src`, which came from `file`.
+    pub fn new(file: &str, src: &str, error: AsmError) -> Self {
+        let span = error.span(src);
+        let text = span
+            .and_then(|x| src.lines().nth(x.line - 1))
+            .unwrap_or("")
+            .to_string();
+
+        Self {
+            file: file.to_string(),
+            span,
+            text,
+            error,
+        }
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let span = match self.span {
+            Some(span) => span,
+            None => return write!(f, "{}: error: {}", self.file, self.error.message()),
+        };
+
+        let gutter = span.line.to_string().len();
+        writeln!(
+            f,
+            "{}:{}:{}: error: {}",
+            self.file,
+            span.line,
+            span.column,
+            self.error.message()
+        )?;
+        writeln!(
+            f,
+            "{:>width$} | {}",
+            span.line,
+            self.text,
+            width = gutter + 4
+        )?;
+
+        // tabs stay tabs so the carets line up with the text above them
+        let lead: String = self
+            .text
+            .chars()
+            .take(span.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        write!(
+            f,
+            "{:>width$} | {}{}",
+            "",
+            lead,
+            "^".repeat(span.len),
+            width = gutter + 4
+        )
+    }
+}
+
+/// A source text ready to be assembled, with `%include`s already resolved by `preprocess()`
+/// along with which file and line every line of it came from.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Preprocessed {
+    pub text: String,
+    pub files: Vec<String>,
+    // (index into files, line in that file) for every line of the text
+    origins: Vec<(usize, usize)>,
+}
+
+impl Preprocessed {
+    /// Wrap a source text which doesn't include anything, `file` is the name it's shown as.
+    pub fn new(text: &str, file: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            files: vec![file.to_string()],
+            origins: (1..=text.lines().count()).map(|x| (0, x)).collect(),
+        }
+    }
+
+    // which file and line a line of the text came from
+    fn origin(&self, line: usize) -> (usize, usize) {
+        line.checked_sub(1)
+            .and_then(|x| self.origins.get(x))
+            .copied()
+            .unwrap_or((0, line))
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Size {
+    Byte,
+    Word,
+    Dword,
+}
+
+impl Size {
+    fn bytes(self) -> u32 {
+        match self {
+            Size::Byte => 1,
+            Size::Word => 2,
+            Size::Dword => 4,
+        }
+    }
+
+    fn reloc(self) -> RelocKind {
+        match self {
+            Size::Byte => RelocKind::Abs8,
+            Size::Word => RelocKind::Abs16,
+            Size::Dword => RelocKind::Abs32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Source {
+    Reg(u8),
+    Imm(Option<Size>, Expr),
+}
+
+/// An instruction whose operands may still refer to labels.
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Stmt {
+    Jump(u8, Expr),
+    Modded(u8, u8, Source),
+    Not(u8),
+    Bare(u8),
+    Section(String),
+    Global(String),
+    Extern(String),
+    Equ(String, Expr),
+    Data(Size, Vec<Expr>),
+    Ascii(Vec<u8>),
+    Org(u32),
+    Align(u32),
+}
+
+impl Stmt {
+    // the size has to be known before the labels are, so immediates referring to labels without a
+    // size keyword are always encoded as dwords, which fit any address, `offset` is where the
+    // statement starts in its section which only matters for `.org` and `.align` and `constants`
+    // are the ones defined before the statement
+    fn size(&self, offset: u32, constants: &HashMap<String, u32>) -> u32 {
+        match self {
+            Stmt::Jump(_, _) => 5,
+            Stmt::Modded(_, _, Source::Reg(_)) => 4,
+            Stmt::Modded(_, _, Source::Imm(size, expr)) => {
+                let operand = match (size, expr) {
+                    (Some(Size::Byte), _) => 1,
+                    (Some(Size::Word), _) => 2,
+                    (Some(Size::Dword), _) => 4,
+                    (None, expr) => match expr.constant(constants) {
+                        Some(value) => Operand::imm(value).size(),
+                        None => 4,
+                    },
+                };
+
+                3 + operand
+            }
+            Stmt::Not(_) => 2,
+            Stmt::Bare(_) => 1,
+            Stmt::Section(_) | Stmt::Global(_) | Stmt::Extern(_) | Stmt::Equ(_, _) => 0,
+            Stmt::Data(size, values) => size.bytes() * values.len() as u32,
+            Stmt::Ascii(bytes) => bytes.len() as u32,
+            Stmt::Org(address) => address.saturating_sub(offset),
+            Stmt::Align(align) => (align - offset % align) % align,
+        }
+    }
+}
+
+/// A parsed source line, both the label and the statement are optional (`loop: ADD r0, 1`).
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Line {
+    label: Option<String>,
+    stmt: Option<Stmt>,
+}
+
+/// Assemble a whole source text into bytecode, which is the only object linked on its own (see
+/// `assemble_object` for the syntax).
+///
+/// It errors on the first line that couldn't be assembled, or if the program couldn't be linked.
+pub fn assemble(src: &str) -> Result<Vec<u8>> {
+    let object = assemble_object(src)?;
+    link::link(&[object]).map_err(AsmError::Link)
+}
+
+/// Assemble a whole source text like `assemble()`, but also produce a listing which shows the
+/// address and encoded bytes of every source line next to it.
+///
+/// Lines which expand into more than one statement (macro invocations) get one row per
+/// statement, the rows after the first one leave the source column empty.
+pub fn assemble_listing(src: &str) -> Result<(Vec<u8>, String)> {
+    let (object, placed) = assemble_inner(src).map_err(first)?;
+    let bases = link::layout(std::slice::from_ref(&object));
+    let image = link::link(std::slice::from_ref(&object)).map_err(AsmError::Link)?;
+
+    let mut listing = String::new();
+    for (idx, line) in src.lines().enumerate() {
+        let line_no = idx + 1;
+        let mut rows = placed.iter().filter(|x| x.line_no == line_no).peekable();
+
+        if rows.peek().is_none() {
+            // safe to unwrap because writing into a string can't fail
+            let row = format!("{:>5} {:8} {:20} {}", line_no, "", "", line);
+            writeln!(listing, "{}", row.trim_end()).unwrap();
+            continue;
+        }
+
+        for (row, stmt) in rows.enumerate() {
+            let address = bases[0][stmt.section] + stmt.offset;
+            let bytes: Vec<String> = image[address as usize..(address + stmt.size) as usize]
+                .iter()
+                .map(|x| format!("{:02x}", x))
+                .collect();
+
+            let source = if row == 0 { line } else { "" };
+            let row = format!(
+                "{:>5} {:08x} {:20} {}",
+                line_no,
+                address,
+                bytes.join(" "),
+                source
+            );
+            writeln!(listing, "{}", row.trim_end()).unwrap();
+        }
+    }
+
+    Ok((image, listing))
+}
+
+/// Assemble a whole source text into a relocatable object, which still has to go through
+/// `link::link` before it can run.
+///
+/// Every line holds at most one instruction, written as the mnemonic followed by comma separated
+/// operands (`MOV r0, 12`, `CMP r1, r0`, `JLZ 0`), anything after a `;` is a comment. Immediates
+/// can be decimal, hex (`0x`) or binary (`0b`) and are encoded in the smallest mode they fit in,
+/// unless they're prefixed by a size keyword (`byte`, `word` or `dword`).
+///
+/// A line can start with a label (`loop:`), which can then be used instead of a number in any
+/// operand, before or after its definition. Immediates referring to labels are encoded as dwords
+/// unless given a size keyword.
+///
+/// Operands can also be expressions (`MOV r0, (TABLE + 4*3)`) using `+ - * / % & | ^ << >>`,
+/// the unary `-` and `~` and parentheses, with the precedence they have in C. Arithmetic wraps
+/// around like it does on the machine. Anything involving a label has to be a label plus or
+/// minus a constant, except for the difference of two labels in the same section. Names for
+/// constants are given with `.equ NAME, expression` and, unlike labels, have to be defined
+/// before they're used, immediates which only use numbers and constants get the smallest mode
+/// they fit in.
+///
+/// Code goes into the `.text` section unless a `.section name` directive (or the `.text` and
+/// `.data` shorthands) switches to another one. Labels are local to the object unless exported
+/// with `.global name`, and labels from other objects have to be declared with `.extern name`
+/// before they can be used.
+///
+/// Constants are emitted with `.byte`, `.word` and `.dword` followed by comma separated values
+/// (numbers or labels), and strings with `.ascii "text"` which understands the `\n`, `\t`, `\0`,
+/// `\\` and `\"` escapes. `.org address` pads the current section up to the given offset and
+/// `.align n` pads it up to a multiple of `n`, which also makes the linker start the whole
+/// section at a multiple of `n`. Padding in `.text` is done with `NOP`s, everywhere else with
+/// zeros.
+///
+/// Macros are expanded before anything else, they're defined NASM style between a
+/// `%macro name param_count` and a `%endmacro` line, take their arguments as `%1`, `%2`, ... and
+/// can have labels local to each expansion by prefixing them with `%%`.
+///
+/// `%include "file"` lines only work on sources which went through `preprocess()`, since a
+/// source text on its own has nowhere to look the files up.
+///
+/// It errors on the first line that couldn't be assembled.
+pub fn assemble_object(src: &str) -> Result<Object> {
+    Ok(assemble_inner(src).map_err(first)?.0)
+}
+
+/// Assemble a whole source text into a relocatable object like `assemble_object()`, but also
+/// fill in its line table so the addresses can be mapped back to lines of `source`, which is
+/// the name the source file should be shown as.
+///
+/// It errors on the first line that couldn't be assembled.
+pub fn assemble_object_with_lines(src: &str, source: &str) -> Result<Object> {
+    let (object, placed) = assemble_inner(src).map_err(first)?;
+    Ok(with_lines(object, &placed, &Preprocessed::new(src, source)))
+}
+
+/// Assemble a whole source text into a relocatable object with a line table like
+/// `assemble_object_with_lines()`, but keep going after errors so all of them can be reported
+/// at once, each with the place in `source` (the name of the file) it's at.
+///
+/// Lines with errors are left out and assembly goes on with the rest, the only exception being
+/// errors in macro definitions which stop everything right away.
+///
+/// It errors with every problem it found, sorted by line.
+pub fn assemble_diagnostics(
+    src: &str,
+    source: &str,
+) -> std::result::Result<Object, Vec<Diagnostic>> {
+    assemble_source(&Preprocessed::new(src, source))
+}
+
+/// Assemble a source which went through `preprocess()` like `assemble_diagnostics()`, the
+/// diagnostics and the line table point into the files the lines came from.
+///
+/// It errors with every problem it found, sorted by the order they appear in the text.
+pub fn assemble_source(source: &Preprocessed) -> std::result::Result<Object, Vec<Diagnostic>> {
+    match assemble_inner(&source.text) {
+        Ok((object, placed)) => Ok(with_lines(object, &placed, source)),
+        Err(errors) => Err(errors
+            .into_iter()
+            .map(|err| {
+                let mut diagnostic = Diagnostic::new(&source.files[0], &source.text, err);
+                if let Some(span) = diagnostic.span.as_mut() {
+                    let (file, line) = source.origin(span.line);
+                    diagnostic.file = source.files[file].clone();
+                    span.line = line;
+                }
+
+                diagnostic
+            })
+            .collect()),
+    }
+}
+
+fn with_lines(mut object: Object, placed: &[Placed], source: &Preprocessed) -> Object {
+    // statements which don't take any space (like `.align` on an aligned offset) would point at
+    // the next statement as well
+    object.lines = placed
+        .iter()
+        .filter(|x| x.size > 0)
+        .map(|x| {
+            let (file, line) = source.origin(x.line_no);
+
+            object::Line {
+                section: x.section,
+                offset: x.offset,
+                file,
+                line: line as u32,
+            }
+        })
+        .collect();
+    object.files = source.files.clone();
+
+    object
+}
+
+// where the bytes of a statement ended up in the object
+#[derive(Debug, Copy, Clone)]
+struct Placed {
+    line_no: usize,
+    section: usize,
+    offset: u32,
+    size: u32,
+}
+
+// the first of the errors `assemble_inner()` found, for the functions which only report one
+fn first(errors: Vec<AsmError>) -> AsmError {
+    // safe to unwrap because errors are only returned when there's at least one
+    errors.into_iter().next().unwrap()
+}
+
+// keeps going after errors where it can, returning all of them sorted by line
+fn assemble_inner(src: &str) -> std::result::Result<(Object, Vec<Placed>), Vec<AsmError>> {
+    let mut errors = vec![];
+
+    let mut lines = vec![];
+    for (line_no, line) in macros::expand(src).map_err(|err| vec![err])? {
+        match parse_line(line_no, &line) {
+            Ok(parsed) => lines.push((line_no, parsed)),
+            Err(err) => {
+                errors.push(err);
+
+                // keep the label of a broken line, so its uses don't turn into more errors
+                if let (Some(name), _) = split_label(strip_comment(&line)) {
+                    if is_label(name) {
+                        let label = Some(name.to_string());
+                        lines.push((line_no, Line { label, stmt: None }));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut object = Object::new();
+    object.sections.push(Section {
+        name: ".text".to_string(),
+        align: 1,
+        data: vec![],
+    });
+
+    // first pass, lay out the sections and figure out where every label and constant points to
+    let mut symbols: HashMap<String, usize> = HashMap::new();
+    let mut constants: HashMap<String, u32> = HashMap::new();
+    // `.equ`s using names nobody defined yet, which might be labels further down
+    let mut undefined = vec![];
+    let mut offsets = vec![0u32];
+    let mut globals = vec![];
+    let mut current = 0;
+    for (line_no, line) in lines.iter() {
+        if let Some(label) = &line.label {
+            if constants.contains_key(label) {
+                errors.push(AsmError::DuplicateLabel(*line_no, label.clone()));
+            }
+
+            let defined = define_symbol(
+                &mut object,
+                &mut symbols,
+                *line_no,
+                label,
+                Some(current),
+                offsets[current],
+            );
+            errors.extend(defined.err());
+        }
+
+        match &line.stmt {
+            Some(Stmt::Section(name)) => {
+                current = section_index(&mut object, name);
+                offsets.resize(object.sections.len(), 0);
+            }
+            Some(Stmt::Global(name)) => globals.push((*line_no, name.clone())),
+            Some(Stmt::Extern(name)) => {
+                let defined = define_symbol(&mut object, &mut symbols, *line_no, name, None, 0);
+                errors.extend(defined.err());
+            }
+            Some(Stmt::Equ(name, expr)) => {
+                if symbols.contains_key(name) || constants.contains_key(name) {
+                    errors.push(AsmError::DuplicateLabel(*line_no, name.clone()));
+                    continue;
+                }
+
+                let scope = Scope {
+                    symbols: &symbols,
+                    defined: &object.symbols,
+                    constants: &constants,
+                    later: &HashMap::new(),
+                };
+                match expr.eval(*line_no, &scope) {
+                    Ok(Value::Abs(value)) => {
+                        constants.insert(name.clone(), value);
+                    }
+                    Ok(Value::Rel(_, _)) => {
+                        errors.push(AsmError::NotRelocatable(*line_no, expr.to_string()));
+                    }
+                    Err(AsmError::UndefinedLabel(_, label)) => {
+                        undefined.push((*line_no, label, expr));
+                    }
+                    Err(err) => errors.push(err),
+                }
+            }
+            Some(Stmt::Org(address)) if *address < offsets[current] => {
+                errors.push(AsmError::OrgBackwards(*line_no, *address));
+            }
+            Some(stmt) => {
+                if let Stmt::Align(align) = stmt {
+                    let section = &mut object.sections[current];
+                    section.align = section.align.max(*align);
+                }
+
+                offsets[current] += stmt.size(offsets[current], &constants);
+            }
+            None => {}
+        }
+    }
+
+    for (line_no, label, expr) in undefined {
+        if symbols.contains_key(&label) {
+            errors.push(AsmError::NotRelocatable(line_no, expr.to_string()));
+        } else {
+            errors.push(AsmError::UndefinedLabel(line_no, label));
+        }
+    }
+
+    for (line_no, name) in globals {
+        match symbols.get(&name) {
+            Some(&idx) if object.symbols[idx].section.is_some() => {
+                object.symbols[idx].global = true;
+            }
+            _ => errors.push(AsmError::UndefinedLabel(line_no, name)),
+        }
+    }
+
+    // second pass, now that all the labels are known every statement can be encoded
+    let mut placed = vec![];
+    let mut current = 0;
+    let mut defined = HashMap::new();
+    for (line_no, line) in lines.iter() {
+        match &line.stmt {
+            Some(Stmt::Section(name)) => current = section_index(&mut object, name),
+            Some(Stmt::Equ(name, _)) => {
+                if let Some(&value) = constants.get(name) {
+                    defined.insert(name.clone(), value);
+                }
+            }
+            Some(Stmt::Global(_)) | Some(Stmt::Extern(_)) | None => {}
+            Some(stmt) => {
+                let pad = if object.sections[current].name == ".text" {
+                    isa::NOP
+                } else {
+                    0
+                };
+
+                let scope = Scope {
+                    symbols: &symbols,
+                    defined: &object.symbols,
+                    constants: &defined,
+                    later: &constants,
+                };
+                let data = &mut object.sections[current].data;
+                let start = data.len() as u32;
+                let fixups = match emit(*line_no, stmt, start, pad, &scope, data) {
+                    Ok(fixups) => fixups,
+                    Err(err) => {
+                        errors.push(err);
+                        continue;
+                    }
+                };
+
+                placed.push(Placed {
+                    line_no: *line_no,
+                    section: current,
+                    offset: start,
+                    size: data.len() as u32 - start,
+                });
+
+                for (field, symbol, addend, kind) in fixups {
+                    object.relocations.push(Relocation {
+                        section: current,
+                        offset: start + field,
+                        symbol,
+                        addend,
+                        kind,
+                    });
+                }
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        errors.sort_by_key(|x| x.line());
+        return Err(errors);
+    }
+
+    Ok((object, placed))
+}
+
+fn section_index(object: &mut Object, name: &str) -> usize {
+    if let Some(idx) = object.sections.iter().position(|x| x.name == name) {
+        idx
+    } else {
+        object.sections.push(Section {
+            name: name.to_string(),
+            align: 1,
+            data: vec![],
+        });
+
+        object.sections.len() - 1
+    }
+}
+
+fn define_symbol(
+    object: &mut Object,
+    symbols: &mut HashMap<String, usize>,
+    line_no: usize,
+    name: &str,
+    section: Option<usize>,
+    offset: u32,
+) -> Result<()> {
+    if symbols.contains_key(name) {
+        return Err(AsmError::DuplicateLabel(line_no, name.to_string()));
+    }
+
+    symbols.insert(name.to_string(), object.symbols.len());
+    object.symbols.push(Symbol {
+        name: name.to_string(),
+        section,
+        offset,
+        global: false,
+    });
+
+    Ok(())
+}
+
+// a value is either known right away or will be filled in by the linker, in which case the
+// returned value is a placeholder and the symbol index and addend are given
+fn eval(line_no: usize, expr: &Expr, scope: &Scope) -> Result<(u32, Option<(usize, u32)>)> {
+    match expr.eval(line_no, scope)? {
+        Value::Abs(value) => Ok((value, None)),
+        Value::Rel(symbol, addend) => Ok((addend, Some((symbol, addend)))),
+    }
+}
+
+// (offset of the field inside the statement, symbol, addend, relocation kind)
+type Fixup = (u32, usize, u32, RelocKind);
+
+// append the bytes of a statement to `data`, `offset` being where it starts in the section and
+// `pad` the byte used by `.org` and `.align`
+fn emit(
+    line_no: usize,
+    stmt: &Stmt,
+    offset: u32,
+    pad: u8,
+    scope: &Scope,
+    data: &mut Vec<u8>,
+) -> Result<Vec<Fixup>> {
+    let mut fixups = vec![];
+
+    match stmt {
+        Stmt::Data(size, values) => {
+            for (idx, expr) in values.iter().enumerate() {
+                let (value, symbol) = eval(line_no, expr, scope)?;
+                if let Some((symbol, addend)) = symbol {
+                    fixups.push((idx as u32 * size.bytes(), symbol, addend, size.reloc()));
+                }
+
+                let too_large = || AsmError::ImmediateTooLarge(line_no, value.to_string());
+                match size {
+                    Size::Byte => data.push(u8::try_from(value).map_err(|_| too_large())?),
+                    Size::Word => data.extend_from_slice(
+                        &u16::try_from(value).map_err(|_| too_large())?.to_le_bytes(),
+                    ),
+                    Size::Dword => data.extend_from_slice(&value.to_le_bytes()),
+                }
+            }
+        }
+        Stmt::Ascii(bytes) => data.extend_from_slice(bytes),
+        Stmt::Org(_) | Stmt::Align(_) => {
+            let len = stmt.size(offset, scope.constants) as usize;
+            data.resize(data.len() + len, pad);
+        }
+        _ => {
+            let (instr, fixup) = resolve(line_no, stmt, scope)?;
+            instr.encode(data);
+            fixups.extend(fixup);
+        }
+    }
+
+    Ok(fixups)
+}
+
+fn resolve(line_no: usize, stmt: &Stmt, scope: &Scope) -> Result<(Instr, Option<Fixup>)> {
+    let resolved = match stmt {
+        Stmt::Jump(opcode, expr) => {
+            let (value, symbol) = eval(line_no, expr, scope)?;
+
+            (
+                Instr::Jump(*opcode, value),
+                symbol.map(|(x, addend)| (1, x, addend, RelocKind::Abs32)),
+            )
+        }
+        Stmt::Modded(opcode, target, Source::Reg(reg)) => {
+            (Instr::Modded(*opcode, *target, Operand::Reg(*reg)), None)
+        }
+        Stmt::Modded(opcode, target, Source::Imm(size, expr)) => {
+            let (value, symbol) = eval(line_no, expr, scope)?;
+            let too_large = || AsmError::ImmediateTooLarge(line_no, value.to_string());
+
+            // this has to pick the same size as `Stmt::size()` did in the first pass
+            let (source, kind) = match size {
+                Some(Size::Byte) => (
+                    Operand::Byte(u8::try_from(value).map_err(|_| too_large())?),
+                    RelocKind::Abs8,
+                ),
+                Some(Size::Word) => (
+                    Operand::Word(u16::try_from(value).map_err(|_| too_large())?),
+                    RelocKind::Abs16,
+                ),
+                Some(Size::Dword) => (Operand::Dword(value), RelocKind::Abs32),
+                None => match expr.constant(scope.constants) {
+                    Some(_) => (Operand::imm(value), RelocKind::Abs32),
+                    None => (Operand::Dword(value), RelocKind::Abs32),
+                },
+            };
+
+            (
+                Instr::Modded(*opcode, *target, source),
+                symbol.map(|(x, addend)| (3, x, addend, kind)),
+            )
+        }
+        Stmt::Not(reg) => (Instr::Not(*reg), None),
+        Stmt::Bare(opcode) => (Instr::Bare(*opcode), None),
+        Stmt::Section(_)
+        | Stmt::Global(_)
+        | Stmt::Extern(_)
+        | Stmt::Equ(_, _)
+        | Stmt::Data(_, _)
+        | Stmt::Ascii(_)
+        | Stmt::Org(_)
+        | Stmt::Align(_) => unreachable!("directives never get resolved into instructions"),
+    };
+
+    Ok(resolved)
+}
+
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        && !is_register(name)
+}
+
+// finds the first `needle` in `line` which isn't inside of a string literal
+pub(crate) fn find_unquoted(line: &str, needle: char) -> Option<usize> {
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (idx, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            _ if c == needle && !quoted => return Some(idx),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn strip_comment(line: &str) -> &str {
+    match find_unquoted(line, ';') {
+        Some(idx) => line[..idx].trim(),
+        None => line.trim(),
+    }
+}
+
+// splits `label: rest` into its parts, the label being `None` if the line doesn't have one
+fn split_label(line: &str) -> (Option<&str>, &str) {
+    match find_unquoted(line, ':') {
+        Some(idx) => (Some(line[..idx].trim()), line[idx + 1..].trim()),
+        None => (None, line),
+    }
+}
+
+fn split_operands(rest: &str) -> Vec<&str> {
+    let mut operands = vec![];
+    let mut rest = rest.trim();
+
+    if rest.is_empty() {
+        return operands;
+    }
+
+    while let Some(idx) = find_unquoted(rest, ',') {
+        operands.push(rest[..idx].trim());
+        rest = &rest[idx + 1..];
+    }
+    operands.push(rest.trim());
+
+    operands
+}
+
+fn parse_line(line_no: usize, line: &str) -> Result<Line> {
+    let (label, line) = split_label(strip_comment(line));
+
+    if let Some(name) = label {
+        if !is_label(name) {
+            return Err(AsmError::BadLabel(line_no, name.to_string()));
+        }
+    }
+    let label = label.map(str::to_string);
+
+    if line.is_empty() {
+        return Ok(Line { label, stmt: None });
+    }
+
+    let (name, rest) = match line.split_once(char::is_whitespace) {
+        Some((name, rest)) => (name, rest.trim()),
+        None => (line, ""),
+    };
+
+    if name.starts_with('.') {
+        return Ok(Line {
+            label,
+            stmt: Some(parse_directive(line_no, name, rest)?),
+        });
+    }
+
+    let operands = split_operands(rest);
+
+    let opcode = match isa::opcode(name) {
+        Some(opcode) => opcode,
+        None => return Err(AsmError::UnknownMnemonic(line_no, name.to_string())),
+    };
+
+    let expected = if isa::is_modded(opcode) {
+        2
+    } else if isa::is_jump(opcode) || opcode == isa::NOT {
+        1
+    } else {
+        0
+    };
+
+    if operands.len() != expected {
+        return Err(AsmError::OperandCount(line_no, name.to_string(), expected));
+    }
+
+    let stmt = if isa::is_jump(opcode) {
+        Stmt::Jump(opcode, parse_expr(line_no, operands[0])?)
+    } else if isa::is_modded(opcode) {
+        let target = parse_register(line_no, operands[0])?;
+        let source = parse_source(line_no, operands[1])?;
+
+        Stmt::Modded(opcode, target, source)
+    } else if opcode == isa::NOT {
+        Stmt::Not(parse_register(line_no, operands[0])?)
+    } else {
+        Stmt::Bare(opcode)
+    };
+
+    Ok(Line {
+        label,
+        stmt: Some(stmt),
+    })
+}
+
+fn parse_directive(line_no: usize, name: &str, rest: &str) -> Result<Stmt> {
+    let named = |rest: &str| -> Result<String> {
+        if is_label(rest) {
+            Ok(rest.to_string())
+        } else {
+            Err(AsmError::BadLabel(line_no, rest.to_string()))
+        }
+    };
+
+    let values = |size: Size| -> Result<Stmt> {
+        let mut exprs = vec![];
+        for operand in split_operands(rest) {
+            exprs.push(parse_expr(line_no, operand)?);
+        }
+
+        Ok(Stmt::Data(size, exprs))
+    };
+
+    match name.to_ascii_lowercase().as_str() {
+        ".text" | ".data" if rest.is_empty() => Ok(Stmt::Section(name.to_ascii_lowercase())),
+        ".section" => Ok(Stmt::Section(named(rest)?)),
+        ".global" => Ok(Stmt::Global(named(rest)?)),
+        ".extern" => Ok(Stmt::Extern(named(rest)?)),
+        ".equ" => match split_operands(rest)[..] {
+            [name, value] => Ok(Stmt::Equ(named(name)?, parse_expr(line_no, value)?)),
+            _ => Err(AsmError::OperandCount(line_no, name.to_string(), 2)),
+        },
+        ".byte" => values(Size::Byte),
+        ".word" => values(Size::Word),
+        ".dword" => values(Size::Dword),
+        ".ascii" => Ok(Stmt::Ascii(parse_string(line_no, rest)?)),
+        ".org" => Ok(Stmt::Org(parse_number(line_no, rest)?)),
+        ".align" => {
+            let align = parse_number(line_no, rest)?;
+            if align.is_power_of_two() {
+                Ok(Stmt::Align(align))
+            } else {
+                Err(AsmError::BadAlign(line_no, align))
+            }
+        }
+        _ => Err(AsmError::UnknownDirective(line_no, name.to_string())),
+    }
+}
+
+fn parse_string(line_no: usize, operand: &str) -> Result<Vec<u8>> {
+    let bad = || AsmError::BadString(line_no, operand.to_string());
+
+    let inner = operand
+        .strip_prefix('"')
+        .and_then(|x| x.strip_suffix('"'))
+        .ok_or_else(bad)?;
+
+    let mut bytes = vec![];
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some('\\') => '\\',
+                Some('"') => '"',
+                _ => return Err(bad()),
+            },
+            // an unescaped quote means the literal ended before the last character
+            '"' => return Err(bad()),
+            c => c,
+        };
+
+        let mut buf = [0u8; 4];
+        bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+
+    Ok(bytes)
+}
+
+fn is_register(operand: &str) -> bool {
+    let mut chars = operand.chars();
+    matches!(chars.next(), Some('r' | 'R'))
+        && !chars.as_str().is_empty()
+        && chars.all(|c| c.is_ascii_digit())
+}
+
+pub(crate) fn parse_register(line_no: usize, operand: &str) -> Result<u8> {
+    if is_register(operand) {
+        if let Ok(reg) = operand[1..].parse::<u8>() {
+            if reg < isa::REGISTERS {
+                return Ok(reg);
+            }
+        }
+    }
+
+    Err(AsmError::BadRegister(line_no, operand.to_string()))
+}
+
+pub(crate) fn parse_number(line_no: usize, operand: &str) -> Result<u32> {
+    let lower = operand.to_ascii_lowercase();
+
+    let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        u32::from_str_radix(bin, 2)
+    } else {
+        lower.parse::<u32>()
+    };
+
+    parsed.map_err(|_| AsmError::BadImmediate(line_no, operand.to_string()))
+}
+
+fn parse_expr(line_no: usize, operand: &str) -> Result<Expr> {
+    expr::parse(line_no, operand)
+}
+
+fn parse_source(line_no: usize, operand: &str) -> Result<Source> {
+    if is_register(operand) {
+        return Ok(Source::Reg(parse_register(line_no, operand)?));
+    }
+
+    // anything that isn't a size keyword is part of the expression (`TABLE + 4`)
+    let (size, imm) = match operand.split_once(char::is_whitespace) {
+        Some((size, imm)) => match size.to_ascii_lowercase().as_str() {
+            "byte" => (Some(Size::Byte), imm.trim()),
+            "word" => (Some(Size::Word), imm.trim()),
+            "dword" => (Some(Size::Dword), imm.trim()),
+            _ => (None, operand),
+        },
+        None => (None, operand),
+    };
+
+    Ok(Source::Imm(size, parse_expr(line_no, imm)?))
+}
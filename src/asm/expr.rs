@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use super::{is_label, is_register, parse_number, AsmError, Result};
+use crate::object::Symbol;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(super) enum UnOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(super) enum BinOp {
+    Or,
+    Xor,
+    And,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl BinOp {
+    // higher binds tighter, the order is the one of C
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Or => 1,
+            BinOp::Xor => 2,
+            BinOp::And => 3,
+            BinOp::Shl | BinOp::Shr => 4,
+            BinOp::Add | BinOp::Sub => 5,
+            BinOp::Mul | BinOp::Div | BinOp::Rem => 6,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Or => "|",
+            BinOp::Xor => "^",
+            BinOp::And => "&",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Rem => "%",
+        }
+    }
+}
+
+/// A value written in an operand, which is either known right away or refers to labels and
+/// constants, possibly doing arithmetic on them (`TABLE + 4*3`).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(super) enum Expr {
+    Num(u32),
+    Label(String),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Num(value) => write!(f, "{}", value),
+            Expr::Label(name) => write!(f, "{}", name),
+            Expr::Unary(UnOp::Neg, expr) => write!(f, "-{}", expr),
+            Expr::Unary(UnOp::Not, expr) => write!(f, "~{}", expr),
+            Expr::Binary(op, lhs, rhs) => write!(f, "({} {} {})", lhs, op.symbol(), rhs),
+        }
+    }
+}
+
+/// What an expression evaluates to, relocatable values are only known once the linker places
+/// the symbol they're relative to.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(super) enum Value {
+    Abs(u32),
+    // (symbol index, addend)
+    Rel(usize, u32),
+}
+
+/// Everything a name in an expression can refer to at some point of the source.
+pub(super) struct Scope<'a> {
+    pub symbols: &'a HashMap<String, usize>,
+    pub defined: &'a [Symbol],
+    /// The `.equ` constants defined so far.
+    pub constants: &'a HashMap<String, u32>,
+    /// All the `.equ` constants of the source, to tell apart the ones used before they're
+    /// defined from names which don't exist at all.
+    pub later: &'a HashMap<String, u32>,
+}
+
+impl Scope<'_> {
+    fn lookup(&self, line_no: usize, name: &str) -> Result<Value> {
+        if let Some(&value) = self.constants.get(name) {
+            Ok(Value::Abs(value))
+        } else if let Some(&idx) = self.symbols.get(name) {
+            Ok(Value::Rel(idx, 0))
+        } else if self.later.contains_key(name) {
+            Err(AsmError::ConstantBeforeEqu(line_no, name.to_string()))
+        } else {
+            Err(AsmError::UndefinedLabel(line_no, name.to_string()))
+        }
+    }
+}
+
+impl Expr {
+    /// Evaluate the expression, arithmetic wraps around like it does on the machine.
+    ///
+    /// Relocatable values can only be offset by constants, except that subtracting two labels
+    /// of the same section gives the distance between them.
+    ///
+    /// It errors on undefined names, division by zero or anything else the linker couldn't
+    /// work out.
+    pub(super) fn eval(&self, line_no: usize, scope: &Scope) -> Result<Value> {
+        let value = match self {
+            Expr::Num(value) => Value::Abs(*value),
+            Expr::Label(name) => scope.lookup(line_no, name)?,
+            Expr::Unary(op, expr) => match (op, expr.eval(line_no, scope)?) {
+                (UnOp::Neg, Value::Abs(value)) => Value::Abs(value.wrapping_neg()),
+                (UnOp::Not, Value::Abs(value)) => Value::Abs(!value),
+                _ => return Err(AsmError::NotRelocatable(line_no, self.to_string())),
+            },
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = lhs.eval(line_no, scope)?;
+                let rhs = rhs.eval(line_no, scope)?;
+
+                match (op, lhs, rhs) {
+                    (_, Value::Abs(lhs), Value::Abs(rhs)) => {
+                        Value::Abs(self.apply(line_no, *op, lhs, rhs)?)
+                    }
+                    (BinOp::Add, Value::Rel(symbol, addend), Value::Abs(value))
+                    | (BinOp::Add, Value::Abs(value), Value::Rel(symbol, addend)) => {
+                        Value::Rel(symbol, addend.wrapping_add(value))
+                    }
+                    (BinOp::Sub, Value::Rel(symbol, addend), Value::Abs(value)) => {
+                        Value::Rel(symbol, addend.wrapping_sub(value))
+                    }
+                    (BinOp::Sub, Value::Rel(a, a_addend), Value::Rel(b, b_addend)) => {
+                        let (a, b) = (&scope.defined[a], &scope.defined[b]);
+                        if a.section.is_none() || a.section != b.section {
+                            return Err(AsmError::NotRelocatable(line_no, self.to_string()));
+                        }
+
+                        Value::Abs(
+                            a.offset
+                                .wrapping_add(a_addend)
+                                .wrapping_sub(b.offset.wrapping_add(b_addend)),
+                        )
+                    }
+                    _ => return Err(AsmError::NotRelocatable(line_no, self.to_string())),
+                }
+            }
+        };
+
+        Ok(value)
+    }
+
+    fn apply(&self, line_no: usize, op: BinOp, lhs: u32, rhs: u32) -> Result<u32> {
+        let by_zero = || AsmError::DivisionByZero(line_no, self.to_string());
+
+        let value = match op {
+            BinOp::Or => lhs | rhs,
+            BinOp::Xor => lhs ^ rhs,
+            BinOp::And => lhs & rhs,
+            BinOp::Shl => lhs.checked_shl(rhs).unwrap_or(0),
+            BinOp::Shr => lhs.checked_shr(rhs).unwrap_or(0),
+            BinOp::Add => lhs.wrapping_add(rhs),
+            BinOp::Sub => lhs.wrapping_sub(rhs),
+            BinOp::Mul => lhs.wrapping_mul(rhs),
+            BinOp::Div => lhs.checked_div(rhs).ok_or_else(by_zero)?,
+            BinOp::Rem => lhs.checked_rem(rhs).ok_or_else(by_zero)?,
+        };
+
+        Ok(value)
+    }
+
+    /// The value of the expression if it only uses numbers and the constants defined so far,
+    /// which is what decides whether an immediate can be encoded in less than a dword.
+    pub(super) fn constant(&self, constants: &HashMap<String, u32>) -> Option<u32> {
+        let scope = Scope {
+            symbols: &HashMap::new(),
+            defined: &[],
+            constants,
+            later: &HashMap::new(),
+        };
+
+        match self.eval(0, &scope) {
+            Ok(Value::Abs(value)) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Token {
+    Num(u32),
+    Name(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+const OPS: [&str; 12] = ["<<", ">>", "|", "^", "&", "+", "-", "*", "/", "%", "~", "("];
+
+fn tokenize(line_no: usize, text: &str) -> Result<Vec<Token>> {
+    let bad = || AsmError::BadExpression(line_no, text.to_string());
+
+    let mut tokens = vec![];
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let word_len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+            .unwrap_or(rest.len());
+
+        if c.is_ascii_digit() {
+            tokens.push(Token::Num(parse_number(line_no, &rest[..word_len])?));
+            rest = &rest[word_len..];
+        } else if word_len > 0 {
+            let name = &rest[..word_len];
+            if !is_label(name) || is_register(name) {
+                return Err(bad());
+            }
+
+            tokens.push(Token::Name(name.to_string()));
+            rest = &rest[word_len..];
+        } else if c == ')' {
+            tokens.push(Token::Close);
+            rest = &rest[1..];
+        } else {
+            let op = OPS.iter().find(|x| rest.starts_with(**x)).ok_or_else(bad)?;
+            tokens.push(if *op == "(" {
+                Token::Open
+            } else {
+                Token::Op(op)
+            });
+            rest = &rest[op.len()..];
+        }
+
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    line_no: usize,
+    text: &'a str,
+    tokens: Vec<Token>,
+    at: usize,
+}
+
+impl Parser<'_> {
+    fn bad(&self) -> AsmError {
+        AsmError::BadExpression(self.line_no, self.text.to_string())
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.at).cloned();
+        self.at += 1;
+        token
+    }
+
+    fn peek_op(&self) -> Option<BinOp> {
+        let op = match self.tokens.get(self.at)? {
+            Token::Op("|") => BinOp::Or,
+            Token::Op("^") => BinOp::Xor,
+            Token::Op("&") => BinOp::And,
+            Token::Op("<<") => BinOp::Shl,
+            Token::Op(">>") => BinOp::Shr,
+            Token::Op("+") => BinOp::Add,
+            Token::Op("-") => BinOp::Sub,
+            Token::Op("*") => BinOp::Mul,
+            Token::Op("/") => BinOp::Div,
+            Token::Op("%") => BinOp::Rem,
+            _ => return None,
+        };
+
+        Some(op)
+    }
+
+    // precedence climbing, only operators binding at least as tight as `min` are taken
+    fn binary(&mut self, min: u8) -> Result<Expr> {
+        let mut lhs = self.unary()?;
+
+        while let Some(op) = self.peek_op().filter(|x| x.precedence() >= min) {
+            self.at += 1;
+            let rhs = self.binary(op.precedence() + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        match self.next().ok_or_else(|| self.bad())? {
+            Token::Num(value) => Ok(Expr::Num(value)),
+            Token::Name(name) => Ok(Expr::Label(name)),
+            Token::Op("-") => Ok(Expr::Unary(UnOp::Neg, Box::new(self.unary()?))),
+            Token::Op("~") => Ok(Expr::Unary(UnOp::Not, Box::new(self.unary()?))),
+            Token::Open => {
+                let expr = self.binary(0)?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err(self.bad()),
+                }
+            }
+            _ => Err(self.bad()),
+        }
+    }
+}
+
+/// Parse an operand into an expression, a plain number or label is the simplest one there is.
+///
+/// It errors if the operand isn't a valid expression.
+pub(super) fn parse(line_no: usize, text: &str) -> Result<Expr> {
+    let mut parser = Parser {
+        line_no,
+        text,
+        tokens: tokenize(line_no, text)?,
+        at: 0,
+    };
+
+    let expr = parser.binary(0)?;
+    if parser.at != parser.tokens.len() {
+        return Err(parser.bad());
+    }
+
+    Ok(expr)
+}
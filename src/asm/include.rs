@@ -0,0 +1,89 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::macros::directive;
+use super::{parse_string, strip_comment, AsmError, Diagnostic, Preprocessed};
+
+// how deep includes can nest, which only matters for files including themselves through
+// different paths that canonicalize differently
+const MAX_DEPTH: usize = 64;
+
+struct Resolver<'a> {
+    include_paths: &'a [PathBuf],
+    source: Preprocessed,
+    // canonical paths of the files currently being included, to catch cycles
+    stack: Vec<PathBuf>,
+}
+
+/// Resolve all the `%include "file"` lines of `src`, which is the text of the file at `path`,
+/// by replacing them with the text of the files they name, recursively.
+///
+/// Included files are looked up next to the file including them first and then in every one of
+/// `include_paths` in order.
+///
+/// It errors if an included file couldn't be found or read, or if a file ends up including
+/// itself.
+pub fn preprocess(
+    src: &str,
+    path: &Path,
+    include_paths: &[PathBuf],
+) -> std::result::Result<Preprocessed, Vec<Diagnostic>> {
+    let mut resolver = Resolver {
+        include_paths,
+        source: Preprocessed {
+            text: String::new(),
+            files: vec![],
+            origins: vec![],
+        },
+        stack: vec![],
+    };
+
+    resolver.file(src, path)?;
+    Ok(resolver.source)
+}
+
+impl Resolver<'_> {
+    fn file(&mut self, src: &str, path: &Path) -> std::result::Result<(), Vec<Diagnostic>> {
+        let name = path.display().to_string();
+        let file = self.source.files.len();
+        self.source.files.push(name.clone());
+        self.stack
+            .push(fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+
+        for (idx, line) in src.lines().enumerate() {
+            let line_no = idx + 1;
+            let error = |err: AsmError| vec![Diagnostic::new(&name, src, err)];
+
+            let target = match directive(strip_comment(line), "%include") {
+                Some(target) => parse_string(line_no, target).map_err(error)?,
+                None => {
+                    self.source.text.push_str(line);
+                    self.source.text.push('\n');
+                    self.source.origins.push((file, line_no));
+                    continue;
+                }
+            };
+
+            let target = String::from_utf8_lossy(&target).into_owned();
+            let not_found = || error(AsmError::IncludeNotFound(line_no, target.clone()));
+
+            let base = path.parent().unwrap_or(Path::new(""));
+            let found = std::iter::once(base)
+                .chain(self.include_paths.iter().map(PathBuf::as_path))
+                .map(|dir| dir.join(&target))
+                .find(|x| x.is_file())
+                .ok_or_else(not_found)?;
+
+            let canonical = fs::canonicalize(&found).unwrap_or_else(|_| found.clone());
+            if self.stack.contains(&canonical) || self.stack.len() >= MAX_DEPTH {
+                return Err(error(AsmError::IncludeCycle(line_no, target.clone())));
+            }
+
+            let text = fs::read_to_string(&found).map_err(|_| not_found())?;
+            self.file(&text, &found)?;
+        }
+
+        self.stack.pop();
+        Ok(())
+    }
+}
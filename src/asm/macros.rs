@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+use super::{split_label, split_operands, strip_comment, AsmError, Result};
+
+// how deep macros can invoke other macros before we give up, anything deeper is almost certainly
+// a macro invoking itself
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone)]
+struct Macro {
+    params: usize,
+    body: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct Expander {
+    macros: HashMap<String, Macro>,
+    // bumped on every expansion so `%%` labels are unique to it
+    expansions: usize,
+    out: Vec<(usize, String)>,
+}
+
+/// Expand all the macros in a source text, returning the resulting lines along with the line
+/// number they came from (for expanded lines, the line of the invocation).
+///
+/// Macros are defined NASM style, a `%macro name count` line followed by the body and ended by a
+/// `%endmacro` line. Inside the body `%1`, `%2`, ... are replaced by the arguments and labels
+/// prefixed by `%%` are made unique to every expansion, so macros can hold their own loops. A
+/// macro is invoked like an instruction (`push2 r0, r1`) and has to be defined before that.
+pub(super) fn expand(src: &str) -> Result<Vec<(usize, String)>> {
+    let mut expander = Expander::default();
+    let mut lines = src.lines().enumerate().map(|(idx, line)| (idx + 1, line));
+
+    while let Some((line_no, line)) = lines.next() {
+        let code = strip_comment(line);
+
+        if let Some(header) = directive(code, "%macro") {
+            let (name, params) = match header.split_once(char::is_whitespace) {
+                Some((name, params)) => (name, params.trim()),
+                None => (header, "0"),
+            };
+
+            let params = match params.parse::<usize>() {
+                Ok(params) if !name.is_empty() => params,
+                _ => return Err(AsmError::BadMacro(line_no, code.to_string())),
+            };
+
+            let mut body = vec![];
+            loop {
+                match lines.next() {
+                    Some((_, line)) if directive(strip_comment(line), "%endmacro").is_some() => {
+                        break
+                    }
+                    Some((_, line)) => body.push(line.to_string()),
+                    None => return Err(AsmError::UnterminatedMacro(line_no, name.to_string())),
+                }
+            }
+
+            let key = name.to_ascii_lowercase();
+            if expander.macros.contains_key(&key) {
+                return Err(AsmError::DuplicateMacro(line_no, name.to_string()));
+            }
+
+            expander.macros.insert(key, Macro { params, body });
+        } else if directive(code, "%endmacro").is_some() {
+            return Err(AsmError::BadMacro(line_no, code.to_string()));
+        } else if let Some(target) = directive(code, "%include") {
+            // includes are resolved when assembling files, a source text on its own has nowhere
+            // to look them up
+            return Err(AsmError::IncludeNotFound(line_no, target.to_string()));
+        } else {
+            expander.expand_line(line_no, line, 0)?;
+        }
+    }
+
+    Ok(expander.out)
+}
+
+impl Expander {
+    fn expand_line(&mut self, line_no: usize, line: &str, depth: usize) -> Result<()> {
+        // a label in front of an invocation stays on its own line, pointing at the expansion
+        let (label, code) = split_label(strip_comment(line));
+
+        let (name, rest) = match code.split_once(char::is_whitespace) {
+            Some((name, rest)) => (name, rest.trim()),
+            None => (code, ""),
+        };
+
+        let mac = match self.macros.get(&name.to_ascii_lowercase()) {
+            Some(mac) => mac.clone(),
+            None => {
+                self.out.push((line_no, line.to_string()));
+                return Ok(());
+            }
+        };
+
+        if depth >= MAX_DEPTH {
+            return Err(AsmError::MacroRecursion(line_no, name.to_string()));
+        }
+
+        let args = split_operands(rest);
+
+        if args.len() != mac.params {
+            return Err(AsmError::OperandCount(
+                line_no,
+                name.to_string(),
+                mac.params,
+            ));
+        }
+
+        if let Some(label) = label {
+            self.out.push((line_no, format!("{}:", label)));
+        }
+
+        self.expansions += 1;
+        let local = format!("__m{}_", self.expansions);
+
+        for body_line in mac.body.iter() {
+            let mut body_line = body_line.replace("%%", &local);
+
+            // go from the highest parameter down so `%1` doesn't eat the start of `%10`
+            for (idx, arg) in args.iter().enumerate().rev() {
+                body_line = body_line.replace(&format!("%{}", idx + 1), arg);
+            }
+
+            self.expand_line(line_no, &body_line, depth + 1)?;
+        }
+
+        Ok(())
+    }
+}
+
+// if the line is the given directive, returns whatever comes after it
+pub(super) fn directive<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let (word, rest) = match line.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (line, ""),
+    };
+
+    if word.eq_ignore_ascii_case(name) {
+        Some(rest)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,203 @@
+use std::fmt;
+
+use crate::asm::{self, AsmError};
+use crate::vm::{Program, VmError};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum TestError {
+    Asm(AsmError),
+    BadAssertion(usize, String),
+    Vm(VmError),
+    /// The program was still running after the given amount of instructions.
+    Timeout(u64),
+}
+
+impl std::error::Error for TestError {}
+
+pub type Result<T> = std::result::Result<T, TestError>;
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TestError::Asm(err) => write!(f, "{}", err),
+            TestError::BadAssertion(line, text) => {
+                write!(f, "line {}: `{}` is not a valid assertion", line, text)
+            }
+            TestError::Vm(err) => write!(f, "{}", err),
+            TestError::Timeout(budget) => {
+                write!(f, "the program didn't halt within {} instructions", budget)
+            }
+        }
+    }
+}
+
+/// What an assertion looks at once the program halted.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Place {
+    Reg(u8),
+    /// A single byte of memory.
+    Mem(u32),
+    Counter,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    // longer operators first so `<=` isn't taken for `<`
+    const ALL: [(&'static str, Comparison); 6] = [
+        ("==", Comparison::Eq),
+        ("!=", Comparison::Ne),
+        ("<=", Comparison::Le),
+        (">=", Comparison::Ge),
+        ("<", Comparison::Lt),
+        (">", Comparison::Gt),
+    ];
+
+    fn holds(self, actual: u32, expected: u32) -> bool {
+        match self {
+            Comparison::Eq => actual == expected,
+            Comparison::Ne => actual != expected,
+            Comparison::Lt => actual < expected,
+            Comparison::Le => actual <= expected,
+            Comparison::Gt => actual > expected,
+            Comparison::Ge => actual >= expected,
+        }
+    }
+}
+
+/// A single `; assert place op value` comment.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Assertion {
+    pub line: usize,
+    pub text: String,
+    pub place: Place,
+    pub comparison: Comparison,
+    pub expected: u32,
+}
+
+/// An assertion which didn't hold, `actual` is `None` if it looked past the end of memory.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Failure {
+    pub assertion: Assertion,
+    pub actual: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Report {
+    pub passed: usize,
+    pub failures: Vec<Failure>,
+}
+
+impl Report {
+    pub fn ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+fn parse_place(line_no: usize, text: &str) -> asm::Result<Place> {
+    if text.eq_ignore_ascii_case("pc") {
+        return Ok(Place::Counter);
+    }
+
+    let lower = text.to_ascii_lowercase();
+    if let Some(address) = lower.strip_prefix("mem[").and_then(|x| x.strip_suffix(']')) {
+        return Ok(Place::Mem(asm::parse_number(line_no, address.trim())?));
+    }
+
+    Ok(Place::Reg(asm::parse_register(line_no, text)?))
+}
+
+/// Find all the `; assert ...` comments of a source text.
+///
+/// An assertion compares a register (`r0`), a byte of memory (`mem[0x10]`) or the counter
+/// (`pc`) against a number with one of `==`, `!=`, `<`, `<=`, `>` or `>=`, like
+/// `; assert r0 == 23`.
+///
+/// It errors on the first assertion that couldn't be parsed.
+pub fn assertions(src: &str) -> Result<Vec<Assertion>> {
+    let mut out = vec![];
+
+    for (idx, line) in src.lines().enumerate() {
+        let line_no = idx + 1;
+
+        let comment = match asm::find_unquoted(line, ';') {
+            Some(at) => line[at + 1..].trim(),
+            None => continue,
+        };
+        let text = match comment.strip_prefix("assert ") {
+            Some(text) => text.trim(),
+            None => continue,
+        };
+
+        let bad = || TestError::BadAssertion(line_no, text.to_string());
+        let (at, op, comparison) = Comparison::ALL
+            .iter()
+            .filter_map(|&(op, comparison)| text.find(op).map(|at| (at, op, comparison)))
+            .min_by_key(|x| x.0)
+            .ok_or_else(bad)?;
+
+        let place = parse_place(line_no, text[..at].trim()).map_err(|_| bad())?;
+        let expected =
+            asm::parse_number(line_no, text[at + op.len()..].trim()).map_err(|_| bad())?;
+
+        out.push(Assertion {
+            line: line_no,
+            text: text.to_string(),
+            place,
+            comparison,
+            expected,
+        });
+    }
+
+    Ok(out)
+}
+
+/// Assemble a source text, run it until it halts (for at most `budget` instructions) and then
+/// check all of its assertions (see `assertions()`) against the final state of the program.
+///
+/// It errors if the source couldn't be assembled, has a malformed assertion, or if the program
+/// reached an invalid instruction or didn't halt in time. Assertions that don't hold aren't
+/// errors, they're the failures of the report.
+pub fn run(src: &str, budget: u64) -> Result<Report> {
+    let assertions = assertions(src)?;
+    let code = asm::assemble(src).map_err(TestError::Asm)?;
+
+    let mut program = Program::new(code);
+    for _ in 0..budget {
+        if program.halted() {
+            break;
+        }
+
+        program.step().map_err(TestError::Vm)?;
+    }
+
+    if !program.halted() {
+        return Err(TestError::Timeout(budget));
+    }
+
+    let mut report = Report::default();
+    for assertion in assertions {
+        let actual = match assertion.place {
+            Place::Reg(reg) => Some(program.regs()[reg as usize]),
+            Place::Mem(address) => program.code().get(address as usize).map(|&x| x as u32),
+            Place::Counter => Some(program.counter()),
+        };
+
+        match actual {
+            Some(actual) if assertion.comparison.holds(actual, assertion.expected) => {
+                report.passed += 1;
+            }
+            _ => report.failures.push(Failure { assertion, actual }),
+        }
+    }
+
+    Ok(report)
+}
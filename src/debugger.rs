@@ -0,0 +1,367 @@
+use std::fmt::Write;
+
+use crate::isa::{self, Instr};
+use crate::vm::{Program, Stop};
+
+mod watch;
+
+pub use watch::Watch;
+
+const HELP: &str = "commands:
+    s, step [count]        execute one (or `count`) instructions
+    c, continue            run until a breakpoint or until the program halts
+    b, break [address]     set a breakpoint, or list them without an address
+    b [address] if <expr>  stop at `address` (or anywhere) once `expr` isn't zero
+    d, delete <address|#n> remove a breakpoint, or the conditional breakpoint `n`
+    p, print <expr>        evaluate an expression
+    w, watch [expr]        print `expr` after every command, or list the watches
+    unwatch <n>            remove the watch `n`
+    r, regs                print the counter and the registers
+    x <address> [length]   hex dump memory (64 bytes by default)
+    q, quit                leave the debugger
+    h, help                print this
+
+addresses can also be symbols (`loop`, `loop+4`) if the image has a symbol table, an empty
+line repeats the last command
+
+expressions are made of numbers, symbols, r0-r3, `pc`, `mem8[..]`, `mem16[..]`, `mem32[..]`
+and the operators of C, like `r0 + mem16[0x40] > 100`";
+
+/// A debugger driving a `Program` through text commands, which is what the interactive `debug`
+/// mode of the CLI is built on.
+#[derive(Debug)]
+pub struct Debugger {
+    program: Program,
+    last: String,
+    watches: Vec<Watch>,
+    // conditional breakpoints, the ones without an address are checked after every step
+    conditions: Vec<(Option<u32>, Watch)>,
+}
+
+impl Debugger {
+    pub fn new(program: Program) -> Self {
+        Self {
+            program,
+            last: String::new(),
+            watches: vec![],
+            conditions: vec![],
+        }
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    // an address followed by the symbol it's in, if there's one
+    fn name(&self, address: u32) -> String {
+        match self.program.symbolize(address) {
+            Some(symbol) => format!("{:08x} <{}>", address, symbol),
+            None => format!("{:08x}", address),
+        }
+    }
+
+    /// The instruction at the counter, formatted as `address: instruction`, followed by the
+    /// source line it came from if the program has a line table. Both the counter and jump
+    /// targets are followed by the symbol they point into if the program has a symbol table.
+    pub fn current(&self) -> String {
+        let counter = self.program.counter();
+
+        let instr = match isa::decode(self.program.code(), counter) {
+            Ok(Instr::Jump(opcode, target)) if self.program.symbolize(target).is_some() => {
+                // safe to unwrap because only known opcodes get decoded
+                let mnemonic = isa::mnemonic(opcode).unwrap();
+                format!("{}: {} {}", self.name(counter), mnemonic, self.name(target))
+            }
+            Ok(instr) => format!("{}: {}", self.name(counter), instr),
+            Err(err) => format!("{}: <{}>", self.name(counter), err),
+        };
+
+        match self.program.line(counter) {
+            Some(line) => format!("{:24} ; {}:{}", instr, line.file, line.line),
+            None => instr,
+        }
+    }
+
+    /// Run a single command and return what it printed, or `None` if it asked to quit.
+    ///
+    /// Mistakes in the command (unknown names, bad numbers) and errors raised by the program are
+    /// reported in the output rather than stopping the debugger.
+    pub fn command(&mut self, line: &str) -> Option<String> {
+        let line = if line.trim().is_empty() {
+            self.last.clone()
+        } else {
+            line.trim().to_string()
+        };
+        self.last = line.clone();
+
+        let mut words = line.split_whitespace();
+        let cmd = words.next().unwrap_or("");
+        let args: Vec<&str> = words.collect();
+
+        let mut out = String::new();
+        let result = match cmd {
+            "s" | "step" => self.step(&args, &mut out),
+            "c" | "continue" => self.resume(&mut out),
+            "b" | "break" => self.add_breakpoint(&args, &mut out),
+            "d" | "delete" => self.remove_breakpoint(&args, &mut out),
+            "r" | "regs" => {
+                self.regs(&mut out);
+                Ok(())
+            }
+            "x" => self.hexdump(&args, &mut out),
+            "p" | "print" => self.print(&args, &mut out),
+            "w" | "watch" => self.watch(&args, &mut out),
+            "unwatch" => self.unwatch(&args, &mut out),
+            "q" | "quit" => return None,
+            "h" | "help" | "" => {
+                out.push_str(HELP);
+                Ok(())
+            }
+            _ => Err(format!("unknown command `{}`, try `help`", cmd)),
+        };
+
+        if let Err(err) = result {
+            out.push_str(&err);
+        }
+
+        Some(out.trim_end().to_string())
+    }
+
+    fn step(&mut self, args: &[&str], out: &mut String) -> Result<(), String> {
+        let count = match args {
+            [] => 1,
+            [count] => parse_number(count)?,
+            _ => return Err("usage: step [count]".to_string()),
+        };
+
+        for _ in 0..count {
+            if self.program.halted() {
+                break;
+            }
+
+            self.program.step().map_err(|err| err.to_string())?;
+        }
+
+        self.status(out);
+        Ok(())
+    }
+
+    fn resume(&mut self, out: &mut String) -> Result<(), String> {
+        // without any conditions the vm can look for the breakpoints on its own, which is a
+        // lot faster than checking after every step
+        let stop = if self.conditions.is_empty() {
+            Some(self.program.resume().map_err(|err| err.to_string())?)
+        } else {
+            self.resume_checking(out)?
+        };
+
+        match stop {
+            Some(Stop::Breakpoint(address)) => {
+                writeln!(out, "breakpoint at {}", self.name(address)).unwrap()
+            }
+            Some(Stop::Halted) | None => {}
+        }
+
+        self.status(out);
+        Ok(())
+    }
+
+    // `None` if it stopped on a condition, which is already reported in `out`
+    fn resume_checking(&mut self, out: &mut String) -> Result<Option<Stop>, String> {
+        loop {
+            self.program.step().map_err(|err| err.to_string())?;
+
+            if self.program.halted() {
+                return Ok(Some(Stop::Halted));
+            }
+
+            let counter = self.program.counter();
+            for (idx, (address, condition)) in self.conditions.iter().enumerate() {
+                if address.is_some_and(|x| x != counter) {
+                    continue;
+                }
+
+                let value = condition
+                    .eval(&self.program)
+                    .map_err(|err| format!("condition #{} `{}`: {}", idx + 1, condition, err))?;
+                if value != 0 {
+                    let name = self.name(counter);
+                    writeln!(out, "condition #{} `{}` at {}", idx + 1, condition, name).unwrap();
+                    return Ok(None);
+                }
+            }
+
+            if self.program.breakpoints().any(|x| x == counter) {
+                return Ok(Some(Stop::Breakpoint(counter)));
+            }
+        }
+    }
+
+    fn add_breakpoint(&mut self, args: &[&str], out: &mut String) -> Result<(), String> {
+        if let Some(at) = args.iter().position(|x| *x == "if") {
+            let address = match args[..at] {
+                [] => None,
+                [address] => Some(self.address(address)?),
+                _ => return Err("usage: break [address] if <expr>".to_string()),
+            };
+            let condition = Watch::parse(&args[at + 1..].join(" "))?;
+
+            self.conditions.push((address, condition));
+            let idx = self.conditions.len();
+            match address {
+                Some(address) => writeln!(out, "condition #{} set at {}", idx, self.name(address)),
+                None => writeln!(out, "condition #{} set", idx),
+            }
+            .unwrap();
+
+            return Ok(());
+        }
+
+        match args {
+            [] => {
+                for address in self.program.breakpoints() {
+                    writeln!(out, "{}", self.name(address)).unwrap();
+                }
+                for (idx, (address, condition)) in self.conditions.iter().enumerate() {
+                    match address {
+                        Some(address) => {
+                            let name = self.name(*address);
+                            writeln!(out, "#{} {} if {}", idx + 1, name, condition)
+                        }
+                        None => writeln!(out, "#{} if {}", idx + 1, condition),
+                    }
+                    .unwrap();
+                }
+            }
+            [address] => {
+                let address = self.address(address)?;
+                if self.program.add_breakpoint(address) {
+                    writeln!(out, "breakpoint set at {}", self.name(address)).unwrap();
+                } else {
+                    let name = self.name(address);
+                    writeln!(out, "there already is a breakpoint at {}", name).unwrap();
+                }
+            }
+            _ => return Err("usage: break [address]".to_string()),
+        }
+
+        Ok(())
+    }
+
+    fn remove_breakpoint(&mut self, args: &[&str], out: &mut String) -> Result<(), String> {
+        if let [idx] = args {
+            if let Some(idx) = idx.strip_prefix('#') {
+                let idx = index(idx, self.conditions.len())?;
+                let (_, condition) = self.conditions.remove(idx);
+                writeln!(out, "condition #{} `{}` removed", idx + 1, condition).unwrap();
+
+                return Ok(());
+            }
+        }
+
+        let address = match args {
+            [address] => self.address(address)?,
+            _ => return Err("usage: delete <address|#n>".to_string()),
+        };
+
+        if self.program.remove_breakpoint(address) {
+            writeln!(out, "breakpoint at {} removed", self.name(address)).unwrap();
+        } else {
+            writeln!(out, "there is no breakpoint at {}", self.name(address)).unwrap();
+        }
+
+        Ok(())
+    }
+
+    fn regs(&self, out: &mut String) {
+        writeln!(out, "counter: {}", self.name(self.program.counter())).unwrap();
+        for (idx, reg) in self.program.regs().iter().enumerate() {
+            writeln!(out, "r{}: {:#010x} ({})", idx, reg, reg).unwrap();
+        }
+    }
+
+    fn hexdump(&self, args: &[&str], out: &mut String) -> Result<(), String> {
+        let (address, len) = match args {
+            [address] => (self.address(address)?, 64),
+            [address, len] => (self.address(address)?, parse_number(len)?),
+            _ => return Err("usage: x <address> [length]".to_string()),
+        };
+
+        out.push_str(&self.program.dump(address..address.saturating_add(len)));
+        Ok(())
+    }
+
+    fn print(&self, args: &[&str], out: &mut String) -> Result<(), String> {
+        let value = Watch::parse(&args.join(" "))?.eval(&self.program)?;
+        writeln!(out, "{:#010x} ({})", value, value).unwrap();
+
+        Ok(())
+    }
+
+    fn watch(&mut self, args: &[&str], out: &mut String) -> Result<(), String> {
+        if !args.is_empty() {
+            self.watches.push(Watch::parse(&args.join(" "))?);
+        }
+
+        self.show_watches(out);
+        Ok(())
+    }
+
+    fn unwatch(&mut self, args: &[&str], out: &mut String) -> Result<(), String> {
+        let idx = match args {
+            [idx] => index(idx, self.watches.len())?,
+            _ => return Err("usage: unwatch <n>".to_string()),
+        };
+
+        let watch = self.watches.remove(idx);
+        writeln!(out, "watch #{} `{}` removed", idx + 1, watch).unwrap();
+        Ok(())
+    }
+
+    fn show_watches(&self, out: &mut String) {
+        for (idx, watch) in self.watches.iter().enumerate() {
+            match watch.eval(&self.program) {
+                Ok(value) => writeln!(out, "#{} {} = {:#x} ({})", idx + 1, watch, value, value),
+                Err(err) => writeln!(out, "#{} {} = <{}>", idx + 1, watch, err),
+            }
+            .unwrap();
+        }
+    }
+
+    // a number or a symbol
+    fn address(&self, text: &str) -> Result<u32, String> {
+        parse_number(text).or_else(|_| {
+            self.program
+                .lookup(text)
+                .ok_or_else(|| format!("`{}` is neither a number nor a symbol", text))
+        })
+    }
+
+    // what every command that moves the program prints afterwards
+    fn status(&self, out: &mut String) {
+        if self.program.halted() {
+            out.push_str("the program halted\n");
+        } else {
+            writeln!(out, "{}", self.current()).unwrap();
+        }
+
+        self.show_watches(out);
+    }
+}
+
+// a 1 based index from the user into a list of `len` things
+fn index(text: &str, len: usize) -> Result<usize, String> {
+    match parse_number(text)? as usize {
+        idx @ 1.. if idx <= len => Ok(idx - 1),
+        _ => Err(format!("there is no #{}", text)),
+    }
+}
+
+fn parse_number(text: &str) -> Result<u32, String> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse::<u32>(),
+    };
+
+    parsed.map_err(|_| format!("`{}` is not a valid number", text))
+}
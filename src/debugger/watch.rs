@@ -0,0 +1,304 @@
+use std::fmt;
+
+use crate::isa;
+use crate::vm::Program;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum UnOp {
+    Neg,
+    Not,
+    LogicalNot,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum BinOp {
+    LogicalOr,
+    LogicalAnd,
+    Or,
+    Xor,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+// (text, operator, precedence), longer operators come first so `<=` isn't read as `<`
+const BINARY: [(&str, BinOp, u8); 18] = [
+    ("||", BinOp::LogicalOr, 1),
+    ("&&", BinOp::LogicalAnd, 2),
+    ("==", BinOp::Eq, 6),
+    ("!=", BinOp::Ne, 6),
+    ("<=", BinOp::Le, 7),
+    (">=", BinOp::Ge, 7),
+    ("<<", BinOp::Shl, 8),
+    (">>", BinOp::Shr, 8),
+    ("|", BinOp::Or, 3),
+    ("^", BinOp::Xor, 4),
+    ("&", BinOp::And, 5),
+    ("<", BinOp::Lt, 7),
+    (">", BinOp::Gt, 7),
+    ("+", BinOp::Add, 9),
+    ("-", BinOp::Sub, 9),
+    ("*", BinOp::Mul, 10),
+    ("/", BinOp::Div, 10),
+    ("%", BinOp::Rem, 10),
+];
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Node {
+    Num(u32),
+    Reg(u8),
+    Counter,
+    Symbol(String),
+    // (size in bytes, address)
+    Mem(u32, Box<Node>),
+    Unary(UnOp, Box<Node>),
+    Binary(BinOp, Box<Node>, Box<Node>),
+}
+
+/// An expression over the state of a program, like `r0 + mem16[0x40] > 100`, which the
+/// debugger shows after every command or uses as the condition of a breakpoint.
+///
+/// It understands numbers, the registers, `pc`, symbols (standing for their address),
+/// `mem8[address]`, `mem16[address]` and `mem32[address]` (little endian) and the operators of
+/// C along with their precedence. Comparisons and the logical operators give 0 or 1 and
+/// arithmetic wraps around like it does on the machine.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Watch {
+    text: String,
+    root: Node,
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl Watch {
+    /// Parse an expression.
+    ///
+    /// It errors if `text` isn't a valid expression.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            rest: text.trim(),
+            text,
+        };
+
+        let root = parser.binary(0)?;
+        if !parser.rest.trim().is_empty() {
+            return Err(parser.bad());
+        }
+
+        Ok(Self {
+            text: text.trim().to_string(),
+            root,
+        })
+    }
+
+    /// Evaluate the expression against the current state of `program`.
+    ///
+    /// It errors on unknown symbols, memory accesses past the end of the memory or division
+    /// by zero.
+    pub fn eval(&self, program: &Program) -> Result<u32, String> {
+        eval(&self.root, program)
+    }
+}
+
+fn eval(node: &Node, program: &Program) -> Result<u32, String> {
+    let value = match node {
+        Node::Num(value) => *value,
+        Node::Reg(reg) => program.regs()[*reg as usize],
+        Node::Counter => program.counter(),
+        Node::Symbol(name) => program
+            .lookup(name)
+            .ok_or_else(|| format!("there is no symbol `{}`", name))?,
+        Node::Mem(size, address) => {
+            let address = eval(address, program)?;
+            let start = address as usize;
+            let bytes = program
+                .code()
+                .get(start..start + *size as usize)
+                .ok_or_else(|| format!("{:#x} is outside of memory", address))?;
+
+            bytes
+                .iter()
+                .rev()
+                .fold(0u32, |acc, x| (acc << 8) | *x as u32)
+        }
+        Node::Unary(op, node) => {
+            let value = eval(node, program)?;
+            match op {
+                UnOp::Neg => value.wrapping_neg(),
+                UnOp::Not => !value,
+                UnOp::LogicalNot => (value == 0) as u32,
+            }
+        }
+        // these two don't evaluate the right side if the left one already decides, so a
+        // guard like `r1 != 0 && r0 / r1 > 2` works
+        Node::Binary(BinOp::LogicalAnd, lhs, rhs) => {
+            (eval(lhs, program)? != 0 && eval(rhs, program)? != 0) as u32
+        }
+        Node::Binary(BinOp::LogicalOr, lhs, rhs) => {
+            (eval(lhs, program)? != 0 || eval(rhs, program)? != 0) as u32
+        }
+        Node::Binary(op, lhs, rhs) => {
+            let (lhs, rhs) = (eval(lhs, program)?, eval(rhs, program)?);
+            let by_zero = || "division by zero".to_string();
+
+            match op {
+                BinOp::Or => lhs | rhs,
+                BinOp::Xor => lhs ^ rhs,
+                BinOp::And => lhs & rhs,
+                BinOp::Eq => (lhs == rhs) as u32,
+                BinOp::Ne => (lhs != rhs) as u32,
+                BinOp::Lt => (lhs < rhs) as u32,
+                BinOp::Le => (lhs <= rhs) as u32,
+                BinOp::Gt => (lhs > rhs) as u32,
+                BinOp::Ge => (lhs >= rhs) as u32,
+                BinOp::Shl => lhs.checked_shl(rhs).unwrap_or(0),
+                BinOp::Shr => lhs.checked_shr(rhs).unwrap_or(0),
+                BinOp::Add => lhs.wrapping_add(rhs),
+                BinOp::Sub => lhs.wrapping_sub(rhs),
+                BinOp::Mul => lhs.wrapping_mul(rhs),
+                BinOp::Div => lhs.checked_div(rhs).ok_or_else(by_zero)?,
+                BinOp::Rem => lhs.checked_rem(rhs).ok_or_else(by_zero)?,
+                BinOp::LogicalAnd | BinOp::LogicalOr => unreachable!("handled above"),
+            }
+        }
+    };
+
+    Ok(value)
+}
+
+// a precedence climbing parser working straight on the text
+struct Parser<'a> {
+    rest: &'a str,
+    text: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn bad(&self) -> String {
+        format!("`{}` is not a valid expression", self.text.trim())
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.rest = self.rest.trim_start();
+        match self.rest.strip_prefix(token) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn word(&mut self) -> Option<&'a str> {
+        self.rest = self.rest.trim_start();
+        let len = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+            .unwrap_or(self.rest.len());
+
+        let (word, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        Some(word).filter(|x| !x.is_empty())
+    }
+
+    fn peek_op(&self) -> Option<(&'static str, BinOp, u8)> {
+        let rest = self.rest.trim_start();
+        BINARY
+            .iter()
+            .copied()
+            .find(|(text, _, _)| rest.starts_with(text))
+    }
+
+    fn binary(&mut self, min: u8) -> Result<Node, String> {
+        let mut lhs = self.unary()?;
+
+        while let Some((text, op, precedence)) = self.peek_op().filter(|x| x.2 >= min) {
+            self.eat(text);
+            let rhs = self.binary(precedence + 1)?;
+            lhs = Node::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.eat("-") {
+            return Ok(Node::Unary(UnOp::Neg, Box::new(self.unary()?)));
+        }
+        if self.eat("~") {
+            return Ok(Node::Unary(UnOp::Not, Box::new(self.unary()?)));
+        }
+        // `!=` is never at the start of an operand so this can't eat half of it
+        if self.eat("!") {
+            return Ok(Node::Unary(UnOp::LogicalNot, Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let node = self.binary(0)?;
+            return if self.eat(")") {
+                Ok(node)
+            } else {
+                Err(self.bad())
+            };
+        }
+
+        let word = self.word().ok_or_else(|| self.bad())?.to_string();
+        let lower = word.to_ascii_lowercase();
+
+        let size = match lower.as_str() {
+            "mem8" => Some(1),
+            "mem16" => Some(2),
+            "mem32" => Some(4),
+            _ => None,
+        };
+        if let Some(size) = size {
+            if !self.eat("[") {
+                return Err(self.bad());
+            }
+            let address = self.binary(0)?;
+            if !self.eat("]") {
+                return Err(self.bad());
+            }
+
+            return Ok(Node::Mem(size, Box::new(address)));
+        }
+
+        if lower == "pc" {
+            return Ok(Node::Counter);
+        }
+
+        if let Some(reg) = lower.strip_prefix('r').and_then(|x| x.parse::<u8>().ok()) {
+            return if reg < isa::REGISTERS {
+                Ok(Node::Reg(reg))
+            } else {
+                Err(format!("`{}` is not a valid register", word))
+            };
+        }
+
+        if word.starts_with(|c: char| c.is_ascii_digit()) {
+            let parsed = match lower.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => lower.parse::<u32>(),
+            };
+
+            return parsed
+                .map(Node::Num)
+                .map_err(|_| format!("`{}` is not a valid number", word));
+        }
+
+        Ok(Node::Symbol(word))
+    }
+}
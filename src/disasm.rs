@@ -0,0 +1,380 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::image::{Image, Segment, SegmentKind, Symbol};
+use crate::isa::{self, DecodeError, Instr};
+
+/// Disassemble bytecode into assembly text, one instruction per line.
+///
+/// Every line is followed by a comment holding the address of the instruction and its raw
+/// bytes, since comments are ignored by the assembler the output can be fed back into
+/// `asm::assemble` and it will produce the exact same bytes.
+///
+/// It errors on the first sequence of bytes which isn't a valid instruction.
+pub fn disassemble(code: &[u8]) -> Result<String, DecodeError> {
+    disassemble_at(code, 0)
+}
+
+/// Disassemble bytecode like `disassemble()`, for code which gets loaded at `base` instead of
+/// address zero (the code segments of an image), so the addresses in the comments are right.
+pub fn disassemble_at(code: &[u8], base: u32) -> Result<String, DecodeError> {
+    let mut out = String::new();
+    disassemble_into(&mut out, code, base, &[])?;
+
+    Ok(out)
+}
+
+/// Disassemble all the code segments of an image, using its symbol table to put labels in front
+/// of the instructions they point at and to name jump targets. Segments which don't start right
+/// where the one before them ended get an `.org` in front of them, so the output still assembles
+/// back into the same bytes at the same addresses as long as the symbol names are unique, with
+/// the gaps between the segments padded with `NOP`s.
+///
+/// It errors on the first sequence of bytes which isn't a valid instruction.
+pub fn disassemble_image(image: &Image) -> Result<String, DecodeError> {
+    let mut out = String::new();
+
+    // `.org` can't move backwards, so the segments go out in the order of their addresses
+    let mut code: Vec<_> = image
+        .segments
+        .iter()
+        .filter(|x| x.kind == SegmentKind::Code)
+        .collect();
+    code.sort_by_key(|x| x.address);
+
+    let mut end = 0;
+    for segment in code {
+        if segment.address != end {
+            // safe to unwrap because writing into a string can't fail
+            writeln!(out, ".org {:#x}", segment.address).unwrap();
+        }
+        disassemble_into(&mut out, &segment.data, segment.address, &image.symbols)?;
+        end = segment.address.wrapping_add(segment.data.len() as u32);
+    }
+
+    Ok(out)
+}
+
+// how many bytes go on a single `.byte` line of a data region
+const DATA_ROW: usize = 8;
+
+/// Disassemble all the code segments of an image following its control flow, so bytes which
+/// are never executed come out as `.byte` data instead of garbage instructions and any
+/// instruction can be disassembled, even in images with data mixed into their code.
+///
+/// Starting from the entry point, every instruction is followed into the next one and into its
+/// jump target, other than after `JMP` and `HLT` which never fall through. Jump targets without
+/// a symbol get a label of their own (`L_0000001c`), so the output still assembles back into
+/// the same bytes. Code which is only ever reached through computed addresses can't be found
+/// this way and shows up as data.
+pub fn disassemble_flow(image: &Image) -> String {
+    let code: Vec<&Segment> = image
+        .segments
+        .iter()
+        .filter(|x| x.kind == SegmentKind::Code)
+        // one reaching past the end of the address space can't be loaded anyway
+        .filter(|x| x.address as u64 + x.data.len() as u64 <= 1 << 32)
+        .collect();
+    let segment_of = |address: u32| {
+        code.iter()
+            .find(|x| address >= x.address && ((address - x.address) as usize) < x.data.len())
+    };
+
+    // every instruction reachable from the entry point by its address, and all the jump targets
+    let mut instrs = BTreeMap::new();
+    let mut targets = BTreeSet::new();
+    // the bytes taken by the instructions, so overlapping ones are left out
+    let mut taken = BTreeSet::new();
+    let mut pending = vec![image.entry];
+    while let Some(address) = pending.pop() {
+        if taken.contains(&address) {
+            continue;
+        }
+        let Some(segment) = segment_of(address) else {
+            continue;
+        };
+        let Ok(instr) = isa::decode(&segment.data, address - segment.address) else {
+            continue;
+        };
+        // an instruction running past the end of the address space ends the flow there
+        let Some(last) = address.checked_add(instr.size() - 1) else {
+            continue;
+        };
+        if (address..=last).any(|x| taken.contains(&x)) {
+            continue;
+        }
+
+        taken.extend(address..=last);
+        instrs.insert(address, instr);
+
+        // and nothing comes after one ending right at the end of it
+        let next = last.checked_add(1);
+        match instr {
+            Instr::Jump(isa::JMP, target) => {
+                targets.insert(target);
+                pending.push(target);
+            }
+            Instr::Jump(_, target) => {
+                targets.insert(target);
+                pending.extend(next.into_iter().chain([target]));
+            }
+            Instr::Bare(isa::HLT) => {}
+            _ => pending.extend(next),
+        }
+    }
+
+    let mut names: BTreeMap<u32, Vec<String>> = BTreeMap::new();
+    for symbol in image.symbols.iter() {
+        names
+            .entry(symbol.address)
+            .or_default()
+            .push(symbol.name.clone());
+    }
+    for target in targets {
+        if instrs.contains_key(&target) {
+            names
+                .entry(target)
+                .or_insert_with(|| vec![format!("L_{:08x}", target)]);
+        }
+    }
+
+    let mut rows = vec![];
+    for segment in code {
+        // as u64s since a segment can end right at the end of the address space, every address
+        // before the end fits in a u32
+        let end = segment.address as u64 + segment.data.len() as u64;
+        let mut next = segment.address as u64;
+
+        while next < end {
+            let address = next as u32;
+            for name in names.get(&address).into_iter().flatten() {
+                rows.push(Row {
+                    address,
+                    text: format!("{}:", name),
+                    bytes: vec![],
+                });
+            }
+
+            let (text, size) = match instrs.get(&address) {
+                Some(&instr) => {
+                    let text = match instr {
+                        Instr::Jump(opcode, target) => match names.get(&target) {
+                            // safe to unwrap because only known opcodes get decoded
+                            Some(name) => format!("{} {}", isa::mnemonic(opcode).unwrap(), name[0]),
+                            None => instr.to_string(),
+                        },
+                        _ => instr.to_string(),
+                    };
+
+                    (text, instr.size())
+                }
+                None => {
+                    // data runs up to the next instruction or label, whichever comes first
+                    let mut size = 0;
+                    while (address as u64 + size as u64) < end
+                        && (size as usize) < DATA_ROW
+                        && (size == 0
+                            || !(instrs.contains_key(&(address + size))
+                                || names.contains_key(&(address + size))))
+                    {
+                        size += 1;
+                    }
+
+                    let start = (address - segment.address) as usize;
+                    let bytes: Vec<String> = segment.data[start..start + size as usize]
+                        .iter()
+                        .map(|x| format!("{:#04x}", x))
+                        .collect();
+
+                    (format!(".byte {}", bytes.join(", ")), size)
+                }
+            };
+
+            let start = (address - segment.address) as usize;
+            rows.push(Row {
+                address,
+                text,
+                bytes: segment.data[start..start + size as usize].to_vec(),
+            });
+            next += size as u64;
+        }
+    }
+
+    let mut out = String::new();
+    write_rows(&mut out, rows);
+    out
+}
+
+/// Compare the code segments of two images instruction by instruction, to see what a rebuild
+/// actually changed, the result is empty if nothing did.
+///
+/// Instructions are compared by their text with jump targets named after symbols where there
+/// are any, so code which just moved around doesn't show up as changed as long as the images
+/// have symbol tables. Every run of changes is printed as a hunk headed by the address it
+/// starts at in both images, followed by the removed (`-`) and added (`+`) instructions.
+///
+/// It errors on the first sequence of bytes in either image which isn't a valid instruction.
+pub fn diff_images(old: &Image, new: &Image) -> Result<String, DecodeError> {
+    let old = image_rows(old)?;
+    let new = image_rows(new)?;
+
+    // only the part between the common prefix and suffix needs the quadratic table
+    let prefix = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(a, b)| a.text == b.text)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a.text == b.text)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    // lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i].text == b[j].text {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = String::new();
+    let mut hunk = String::new();
+    let (mut i, mut j) = (0, 0);
+    // where the first row of the hunk being built is, in the old and the new image
+    let start = |i: usize, j: usize| {
+        let old_at = a.get(i).or(old.get(prefix + i)).map_or(0, |x| x.address);
+        let new_at = b.get(j).or(new.get(prefix + j)).map_or(0, |x| x.address);
+        (old_at, new_at)
+    };
+    let mut at = start(0, 0);
+
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i].text == b[j].text {
+            flush(&mut out, &mut hunk, at);
+            i += 1;
+            j += 1;
+            at = start(i, j);
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            // safe to unwrap because writing into a string can't fail
+            writeln!(hunk, "- {:08x}: {}", a[i].address, a[i].text).unwrap();
+            i += 1;
+        } else {
+            writeln!(hunk, "+ {:08x}: {}", b[j].address, b[j].text).unwrap();
+            j += 1;
+        }
+    }
+    flush(&mut out, &mut hunk, at);
+
+    Ok(out)
+}
+
+fn flush(out: &mut String, hunk: &mut String, (old_at, new_at): (u32, u32)) {
+    if !hunk.is_empty() {
+        // safe to unwrap because writing into a string can't fail
+        writeln!(out, "@@ -{:08x} +{:08x} @@", old_at, new_at).unwrap();
+        out.push_str(hunk);
+        hunk.clear();
+    }
+}
+
+// a line of disassembly, labels being rows of their own without any bytes
+struct Row {
+    address: u32,
+    text: String,
+    bytes: Vec<u8>,
+}
+
+fn image_rows(image: &Image) -> Result<Vec<Row>, DecodeError> {
+    let mut rows = vec![];
+
+    for segment in image.segments.iter() {
+        if segment.kind == SegmentKind::Code {
+            rows.extend(rows_of(&segment.data, segment.address, &image.symbols)?);
+        }
+    }
+
+    Ok(rows)
+}
+
+fn rows_of(code: &[u8], base: u32, symbols: &[Symbol]) -> Result<Vec<Row>, DecodeError> {
+    let mut rows = vec![];
+    let mut address = 0u32;
+
+    while (address as usize) < code.len() {
+        let instr = isa::decode(code, address)?;
+        // an instruction running past the end of the address space ends the sweep there
+        let Some(at) = base
+            .checked_add(address)
+            .filter(|x| x.checked_add(instr.size() - 1).is_some())
+        else {
+            break;
+        };
+        let bytes = &code[address as usize..address as usize + instr.size() as usize];
+
+        for symbol in symbols.iter().filter(|x| x.address == at) {
+            rows.push(Row {
+                address: at,
+                text: format!("{}:", symbol.name),
+                bytes: vec![],
+            });
+        }
+
+        let text = match instr {
+            Instr::Jump(opcode, target) => match symbols.iter().find(|x| x.address == target) {
+                // safe to unwrap because only known opcodes get decoded
+                Some(symbol) => format!("{} {}", isa::mnemonic(opcode).unwrap(), symbol.name),
+                None => instr.to_string(),
+            },
+            _ => instr.to_string(),
+        };
+
+        rows.push(Row {
+            address: at,
+            text,
+            bytes: bytes.to_vec(),
+        });
+
+        // and nothing comes after one ending right at the end of it
+        match address.checked_add(instr.size()) {
+            Some(next) => address = next,
+            None => break,
+        }
+    }
+
+    Ok(rows)
+}
+
+fn disassemble_into(
+    out: &mut String,
+    code: &[u8],
+    base: u32,
+    symbols: &[Symbol],
+) -> Result<(), DecodeError> {
+    write_rows(out, rows_of(code, base, symbols)?);
+
+    Ok(())
+}
+
+fn write_rows(out: &mut String, rows: Vec<Row>) {
+    for row in rows {
+        if row.bytes.is_empty() {
+            // safe to unwrap because writing into a string can't fail
+            writeln!(out, "{}", row.text).unwrap();
+            continue;
+        }
+
+        write!(out, "{:<24} ; {:08x}:", row.text, row.address).unwrap();
+        for byte in row.bytes {
+            write!(out, " {:02x}", byte).unwrap();
+        }
+        out.push('\n');
+    }
+}
@@ -0,0 +1,61 @@
+// entry points for fuzzing harnesses (like cargo-fuzz targets), each one takes arbitrary input
+// and none of them panic or exit whatever it is, so a harness only has to forward its input:
+//
+//     fuzz_target!(|data: &[u8]| cpu_tset::fuzz::fuzz_execute(data, 10_000));
+
+use crate::image::Image;
+use crate::vm::Program;
+use crate::{asm, disasm, ihex, isa, verify};
+
+/// Decode an instruction at every offset of `data`, then run the disassembler and the verifier
+/// over all of it.
+pub fn fuzz_decode(data: &[u8]) {
+    for address in 0..data.len() as u32 {
+        if let Ok(instr) = isa::decode(data, address) {
+            // the encoder has to give back the exact bytes it was decoded from, and printing it
+            // must not fail either
+            let mut bytes = vec![];
+            instr.encode(&mut bytes);
+            debug_assert_eq!(bytes, data[address as usize..][..bytes.len()]);
+
+            let _ = instr.to_string();
+        }
+    }
+
+    let _ = disasm::disassemble(data);
+    let _ = verify::verify(data);
+}
+
+/// Run `data` as raw code for at most `budget` instructions, stopping early if it halts or hits
+/// an invalid instruction.
+pub fn fuzz_execute(data: &[u8], budget: u64) {
+    let mut program = Program::new(data.to_vec());
+
+    for _ in 0..budget {
+        if program.halted() || program.step().is_err() {
+            break;
+        }
+    }
+}
+
+/// Parse `data` as a serialized image and as Intel HEX text, without laying the segments out
+/// since the required memory in the header could be anything up to 4GiB.
+pub fn fuzz_load(data: &[u8]) {
+    if let Ok(image) = Image::from_bytes(data) {
+        let _ = image.to_bytes();
+        let _ = image.symbolize(image.entry);
+    }
+
+    if let Ok(text) = std::str::from_utf8(data) {
+        if let Ok(image) = ihex::from_ihex(text) {
+            let _ = ihex::to_ihex(&image);
+        }
+    }
+}
+
+/// Assemble `data` as source text, if it's valid utf-8.
+pub fn fuzz_assemble(data: &[u8]) {
+    if let Ok(src) = std::str::from_utf8(data) {
+        let _ = asm::assemble_listing(src);
+    }
+}
@@ -0,0 +1,59 @@
+use alloc::string::String;
+use core::fmt::Write;
+
+/// Format bytes as a classic hex dump, 16 bytes per row with the address of the row in front
+/// and the printable ASCII characters (dots for the rest) at the end:
+///
+/// ```text
+/// 00000010  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 00  |Hello, world!...|
+/// ```
+///
+/// `base` is the address of the first byte, rows always start at a multiple of 16 so a dump
+/// that doesn't start at one gets some blank columns in its first row.
+pub fn hexdump(bytes: &[u8], base: u32) -> String {
+    let mut out = String::new();
+
+    let skip = (base % 16) as usize;
+    let mut row_address = base - skip as u32;
+    let mut rest = bytes;
+    let mut first = true;
+
+    while !rest.is_empty() {
+        let lead = if first { skip } else { 0 };
+        let len = rest.len().min(16 - lead);
+        let (row, tail) = rest.split_at(len);
+
+        // safe to unwrap because writing into a string can't fail
+        write!(out, "{:08x} ", row_address).unwrap();
+        let mut ascii = String::new();
+        for column in 0..16usize {
+            if column == 8 {
+                out.push(' ');
+            }
+
+            match column.checked_sub(lead).and_then(|idx| row.get(idx)) {
+                Some(&byte) => {
+                    write!(out, " {:02x}", byte).unwrap();
+                    ascii.push(if byte.is_ascii_graphic() || byte == b' ' {
+                        byte as char
+                    } else {
+                        '.'
+                    });
+                }
+                None => {
+                    out.push_str("   ");
+                    if column < lead {
+                        ascii.push(' ');
+                    }
+                }
+            }
+        }
+        writeln!(out, "  |{}|", ascii).unwrap();
+
+        rest = tail;
+        row_address = row_address.wrapping_add(16);
+        first = false;
+    }
+
+    out
+}
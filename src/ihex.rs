@@ -0,0 +1,203 @@
+use std::fmt::{self, Write};
+
+use crate::image::{Image, Segment, SegmentKind};
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+// how many data bytes go into a single record when writing
+const RECORD_LEN: usize = 16;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum IhexError {
+    BadRecord(usize),
+    BadChecksum(usize),
+    UnknownRecordType(usize, u8),
+    MissingEndOfFile,
+}
+
+impl std::error::Error for IhexError {}
+
+pub type Result<T> = std::result::Result<T, IhexError>;
+
+impl fmt::Display for IhexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IhexError::BadRecord(line) => write!(f, "line {}: malformed record", line),
+            IhexError::BadChecksum(line) => write!(f, "line {}: checksum mismatch", line),
+            IhexError::UnknownRecordType(line, kind) => {
+                write!(f, "line {}: unknown record type {:#04x}", line, kind)
+            }
+            IhexError::MissingEndOfFile => write!(f, "the end of file record is missing"),
+        }
+    }
+}
+
+fn write_record(out: &mut String, kind: u8, address: u16, data: &[u8]) {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend_from_slice(data);
+
+    let checksum = bytes
+        .iter()
+        .fold(0u8, |acc, x| acc.wrapping_add(*x))
+        .wrapping_neg();
+    bytes.push(checksum);
+
+    out.push(':');
+    for byte in bytes {
+        // safe to unwrap because writing into a string can't fail
+        write!(out, "{:02X}", byte).unwrap();
+    }
+    out.push('\n');
+}
+
+/// Write all the segments of an image as Intel HEX records, using extended linear address
+/// records for anything above 64KiB and a start linear address record for the entry point.
+///
+/// The format has no notion of segment kinds, required memory, symbols or line tables, so those
+/// don't survive a round trip through `from_ihex()`.
+pub fn to_ihex(image: &Image) -> String {
+    let mut out = String::new();
+    let mut upper = 0u16;
+
+    for segment in image.segments.iter() {
+        let mut address = segment.address;
+
+        // records can't cross a 64KiB boundary, so split at those as well as every RECORD_LEN
+        let mut rest = &segment.data[..];
+        while !rest.is_empty() {
+            let high = (address >> 16) as u16;
+            if high != upper {
+                write_record(&mut out, EXTENDED_LINEAR_ADDRESS, 0, &high.to_be_bytes());
+                upper = high;
+            }
+
+            let to_boundary = 0x10000 - (address & 0xFFFF) as usize;
+            let len = rest.len().min(RECORD_LEN).min(to_boundary);
+
+            write_record(&mut out, DATA, address as u16, &rest[..len]);
+
+            rest = &rest[len..];
+            address += len as u32;
+        }
+    }
+
+    write_record(
+        &mut out,
+        START_LINEAR_ADDRESS,
+        0,
+        &image.entry.to_be_bytes(),
+    );
+    write_record(&mut out, END_OF_FILE, 0, &[]);
+
+    out
+}
+
+/// Read Intel HEX records into an image, contiguous data records are merged into a single code
+/// segment and the required memory is the end of the last one.
+///
+/// Both the linear and the segmented (8086 style) address records are understood, the entry
+/// point is taken from the start address records and defaults to zero.
+///
+/// It errors on malformed records, checksum mismatches, unknown record types or if the end of
+/// file record is missing.
+pub fn from_ihex(text: &str) -> Result<Image> {
+    let mut segments: Vec<Segment> = vec![];
+    let mut entry = 0;
+    let mut base = 0u32;
+
+    for (idx, line) in text.lines().enumerate() {
+        let line_no = idx + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let hex = line
+            .strip_prefix(':')
+            .ok_or(IhexError::BadRecord(line_no))?;
+        if hex.len() % 2 != 0 || hex.len() < 10 {
+            return Err(IhexError::BadRecord(line_no));
+        }
+
+        let mut bytes = vec![];
+        for pair in hex.as_bytes().chunks(2) {
+            let pair = std::str::from_utf8(pair).map_err(|_| IhexError::BadRecord(line_no))?;
+            bytes.push(u8::from_str_radix(pair, 16).map_err(|_| IhexError::BadRecord(line_no))?);
+        }
+
+        if bytes.iter().fold(0u8, |acc, x| acc.wrapping_add(*x)) != 0 {
+            return Err(IhexError::BadChecksum(line_no));
+        }
+
+        let len = bytes[0] as usize;
+        if bytes.len() != len + 5 {
+            return Err(IhexError::BadRecord(line_no));
+        }
+
+        let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let kind = bytes[3];
+        let data = &bytes[4..4 + len];
+
+        let be16 = || -> Result<u32> {
+            match data {
+                [high, low] => Ok(u16::from_be_bytes([*high, *low]) as u32),
+                _ => Err(IhexError::BadRecord(line_no)),
+            }
+        };
+        let be32 = || -> Result<u32> {
+            match data {
+                [a, b, c, d] => Ok(u32::from_be_bytes([*a, *b, *c, *d])),
+                _ => Err(IhexError::BadRecord(line_no)),
+            }
+        };
+
+        match kind {
+            DATA => {
+                let address = base.wrapping_add(offset);
+
+                match segments.last_mut() {
+                    Some(last) if last.address + last.data.len() as u32 == address => {
+                        last.data.extend_from_slice(data);
+                    }
+                    _ => segments.push(Segment {
+                        kind: SegmentKind::Code,
+                        address,
+                        data: data.to_vec(),
+                    }),
+                }
+            }
+            END_OF_FILE => {
+                let memory = segments
+                    .iter()
+                    .map(|x| x.address + x.data.len() as u32)
+                    .max()
+                    .unwrap_or(0);
+
+                return Ok(Image {
+                    entry,
+                    memory,
+                    segments,
+                    symbols: vec![],
+                    lines: vec![],
+                });
+            }
+            EXTENDED_SEGMENT_ADDRESS => base = be16()? << 4,
+            START_SEGMENT_ADDRESS => {
+                let cs_ip = be32()?;
+                entry = ((cs_ip >> 16) << 4) + (cs_ip & 0xFFFF);
+            }
+            EXTENDED_LINEAR_ADDRESS => base = be16()? << 16,
+            START_LINEAR_ADDRESS => entry = be32()?,
+            _ => return Err(IhexError::UnknownRecordType(line_no, kind)),
+        }
+    }
+
+    Err(IhexError::MissingEndOfFile)
+}
@@ -0,0 +1,518 @@
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt;
+
+#[cfg(feature = "sign")]
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+/// The first bytes of every image, files which don't start with these are raw code.
+pub const MAGIC: [u8; 4] = *b"L32X";
+const VERSION: u8 = 4;
+
+// magic, version, entry, memory, segment count
+const HEADER_V1_LEN: usize = 4 + 1 + 4 + 4 + 2;
+// the version 1 header, symbol count, string section size
+const HEADER_V2_LEN: usize = HEADER_V1_LEN + 4 + 4;
+// the version 2 header, line count
+const HEADER_V3_LEN: usize = HEADER_V2_LEN + 4;
+// the version 3 header, checksum, signature size
+const HEADER_LEN: usize = HEADER_V3_LEN + 4 + 4;
+// where the checksum is in the header
+const CHECKSUM_AT: usize = HEADER_V3_LEN;
+#[cfg(feature = "sign")]
+const SIGNATURE_LEN: usize = 64;
+// kind, address, file offset, size
+const SEGMENT_LEN: usize = 1 + 4 + 4 + 4;
+// offset of the name in the string section, address
+const SYMBOL_LEN: usize = 4 + 4;
+// address, offset of the file name in the string section, line
+const LINE_LEN: usize = 4 + 4 + 4;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ImageError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    BadSegmentKind(u8),
+    SegmentOutOfBounds(usize),
+    BadSymbolName(usize),
+    BadLineFile(usize),
+    /// (checksum in the header, checksum of the bytes)
+    BadChecksum(u32, u32),
+    Unsigned,
+    BadSignature,
+}
+
+impl core::error::Error for ImageError {}
+
+pub type Result<T> = core::result::Result<T, ImageError>;
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::BadMagic => write!(f, "this is not a lim32 image"),
+            ImageError::UnsupportedVersion(version) => {
+                write!(f, "image version {} is not supported", version)
+            }
+            ImageError::Truncated => write!(f, "the image ends unexpectedly"),
+            ImageError::BadSegmentKind(kind) => write!(f, "unknown segment kind {:#04x}", kind),
+            ImageError::SegmentOutOfBounds(idx) => {
+                write!(f, "segment {} points outside of the image", idx)
+            }
+            ImageError::BadSymbolName(idx) => {
+                write!(f, "the name of symbol {} is not in the string section", idx)
+            }
+            ImageError::BadLineFile(idx) => write!(
+                f,
+                "the file of line table entry {} is not in the string section",
+                idx
+            ),
+            ImageError::BadChecksum(expected, actual) => write!(
+                f,
+                "the image is corrupted, its checksum is {:#010x} but it should be {:#010x}",
+                actual, expected
+            ),
+            ImageError::Unsigned => write!(f, "the image is not signed"),
+            ImageError::BadSignature => write!(f, "the signature of the image doesn't match"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SegmentKind {
+    Code,
+    Data,
+}
+
+impl SegmentKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            SegmentKind::Code => 1,
+            SegmentKind::Data => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self> {
+        match byte {
+            1 => Ok(SegmentKind::Code),
+            2 => Ok(SegmentKind::Data),
+            _ => Err(ImageError::BadSegmentKind(byte)),
+        }
+    }
+}
+
+/// A chunk of bytes which gets loaded at `address` in the guest memory.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Segment {
+    pub kind: SegmentKind,
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+/// A name for an address, only used to make debuggers and traces readable.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u32,
+}
+
+/// The line of a source file the bytes starting at `address` were assembled from, it covers
+/// everything up to the address of the next entry.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Line {
+    pub address: u32,
+    pub file: String,
+    pub line: u32,
+}
+
+/// Find the closest symbol at or before `address` in a symbol table, along with how far past it
+/// the address is.
+pub fn symbol_at(symbols: &[Symbol], address: u32) -> Option<(&Symbol, u32)> {
+    symbols
+        .iter()
+        .filter(|x| x.address <= address)
+        .max_by_key(|x| x.address)
+        .map(|x| (x, address - x.address))
+}
+
+/// Find the entry of a line table (sorted by address) which covers `address`.
+pub fn line_at(lines: &[Line], address: u32) -> Option<&Line> {
+    let idx = lines.partition_point(|x| x.address <= address);
+    idx.checked_sub(1).map(|idx| &lines[idx])
+}
+
+/// An executable program, its segments along with where to start executing and how much memory
+/// it needs, optionally with a table of symbols and a line table.
+///
+/// The serialized form is a header (magic, version, entry point, required memory, the amount of
+/// segments and symbols, the size of the string section, the amount of lines, a CRC32 of the
+/// whole image with the CRC32 itself taken as zero and the size of the signature), followed by
+/// the segment table (kind, load address, file offset and size of every segment), the symbol
+/// table (offset of the name in the string section and address of every symbol), the line table
+/// (address, offset of the file name in the string section and line of every entry), the string
+/// section (the symbol and file names each ended by a zero byte), the bytes of the segments and
+/// then the signature if there's one, all the integers are little endian. Version 1 images,
+/// which end the header after the amount of segments and have no symbols, version 2 images,
+/// which end it after the size of the string section and have no line table, and version 3
+/// images, which end it after the amount of lines and have no checksum, can still be read.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Image {
+    pub entry: u32,
+    pub memory: u32,
+    pub segments: Vec<Segment>,
+    pub symbols: Vec<Symbol>,
+    /// Sorted by address, see `line_at()`.
+    pub lines: Vec<Line>,
+}
+
+impl Image {
+    /// Wrap raw code (loaded at address zero and started from there) in an `Image`.
+    pub fn raw(code: Vec<u8>) -> Self {
+        Self {
+            entry: 0,
+            memory: code.len() as u32,
+            segments: vec![Segment {
+                kind: SegmentKind::Code,
+                address: 0,
+                data: code,
+            }],
+            symbols: vec![],
+            lines: vec![],
+        }
+    }
+
+    /// Whether the bytes start like a serialized image, as opposed to being raw code.
+    pub fn is_image(bytes: &[u8]) -> bool {
+        bytes.starts_with(&MAGIC)
+    }
+
+    /// Load either a serialized image or raw code, depending on whether the bytes start with the
+    /// image magic.
+    ///
+    /// It errors if the bytes look like an image but couldn't be deserialized.
+    pub fn load(bytes: &[u8]) -> Result<Self> {
+        if Self::is_image(bytes) {
+            Self::from_bytes(bytes)
+        } else {
+            Ok(Self::raw(bytes.to_vec()))
+        }
+    }
+
+    /// Serialize the image, with a CRC32 of all of it in the header so `from_bytes()` can tell
+    /// if it got corrupted.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.serialize(0)
+    }
+
+    /// Serialize the image like `to_bytes()`, followed by an ed25519 signature of all of it
+    /// which `from_signed_bytes()` checks, so images can't be tampered with.
+    #[cfg(feature = "sign")]
+    pub fn to_signed_bytes(&self, key: &SigningKey) -> Vec<u8> {
+        let mut out = self.serialize(SIGNATURE_LEN as u32);
+        let signature = key.sign(&out);
+        out.extend_from_slice(&signature.to_bytes());
+
+        out
+    }
+
+    // the signature isn't covered by the checksum, which only leaves room for it at the end
+    fn serialize(&self, signature_len: u32) -> Vec<u8> {
+        let mut out = vec![];
+        out.extend_from_slice(&MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&self.entry.to_le_bytes());
+        out.extend_from_slice(&self.memory.to_le_bytes());
+        out.extend_from_slice(&(self.segments.len() as u16).to_le_bytes());
+
+        let mut strings = vec![];
+        let mut name_offsets = vec![];
+        for symbol in self.symbols.iter() {
+            name_offsets.push(strings.len() as u32);
+            strings.extend_from_slice(symbol.name.as_bytes());
+            strings.push(0);
+        }
+
+        // every file is only stored once, no matter how many lines point at it
+        let mut files: Vec<(&str, u32)> = vec![];
+        let mut file_offsets = vec![];
+        for line in self.lines.iter() {
+            let offset = match files.iter().find(|x| x.0 == line.file) {
+                Some(&(_, offset)) => offset,
+                None => {
+                    let offset = strings.len() as u32;
+                    strings.extend_from_slice(line.file.as_bytes());
+                    strings.push(0);
+                    files.push((&line.file, offset));
+
+                    offset
+                }
+            };
+            file_offsets.push(offset);
+        }
+
+        out.extend_from_slice(&(self.symbols.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(strings.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(self.lines.len() as u32).to_le_bytes());
+        // the checksum gets filled in once everything else is there
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&signature_len.to_le_bytes());
+
+        let mut offset = (HEADER_LEN
+            + SEGMENT_LEN * self.segments.len()
+            + SYMBOL_LEN * self.symbols.len()
+            + LINE_LEN * self.lines.len()
+            + strings.len()) as u32;
+        for segment in self.segments.iter() {
+            out.push(segment.kind.to_byte());
+            out.extend_from_slice(&segment.address.to_le_bytes());
+            out.extend_from_slice(&offset.to_le_bytes());
+            out.extend_from_slice(&(segment.data.len() as u32).to_le_bytes());
+
+            offset += segment.data.len() as u32;
+        }
+
+        for (symbol, name_offset) in self.symbols.iter().zip(name_offsets) {
+            out.extend_from_slice(&name_offset.to_le_bytes());
+            out.extend_from_slice(&symbol.address.to_le_bytes());
+        }
+
+        for (line, file_offset) in self.lines.iter().zip(file_offsets) {
+            out.extend_from_slice(&line.address.to_le_bytes());
+            out.extend_from_slice(&file_offset.to_le_bytes());
+            out.extend_from_slice(&line.line.to_le_bytes());
+        }
+        out.extend_from_slice(&strings);
+
+        for segment in self.segments.iter() {
+            out.extend_from_slice(&segment.data);
+        }
+
+        let checksum = crc32(&out);
+        out[CHECKSUM_AT..CHECKSUM_AT + 4].copy_from_slice(&checksum.to_le_bytes());
+
+        out
+    }
+
+    /// Deserialize an image written by `to_signed_bytes()`, making sure it was signed by the
+    /// owner of `key`.
+    ///
+    /// It errors like `from_bytes()`, if the image has no signature or if the signature
+    /// doesn't match.
+    #[cfg(feature = "sign")]
+    pub fn from_signed_bytes(bytes: &[u8], key: &VerifyingKey) -> Result<Self> {
+        let image = Self::from_bytes(bytes)?;
+
+        // images from before version 4 can't have a signature
+        if bytes[4] != VERSION || read_u32(bytes, CHECKSUM_AT + 4)? as usize != SIGNATURE_LEN {
+            return Err(ImageError::Unsigned);
+        }
+
+        let (signed, signature) = bytes.split_at(bytes.len() - SIGNATURE_LEN);
+        // safe to unwrap because `from_bytes()` already made sure the signature is all there
+        let signature = Signature::from_bytes(signature.try_into().unwrap());
+        key.verify(signed, &signature)
+            .map_err(|_| ImageError::BadSignature)?;
+
+        Ok(image)
+    }
+
+    /// Deserialize an image written by `to_bytes()`, a signature (see `to_signed_bytes()`) is
+    /// skipped without being checked.
+    ///
+    /// It errors if the bytes aren't an image of a supported version, if they end before the
+    /// header or the segment table do, if a segment points outside of the bytes or if the
+    /// checksum doesn't match (images from before version 4 don't have one).
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if !Self::is_image(bytes) {
+            return Err(ImageError::BadMagic);
+        }
+
+        if bytes.len() < HEADER_V1_LEN {
+            return Err(ImageError::Truncated);
+        }
+
+        let version = bytes[4];
+        let (header_len, symbol_count, strings_len, line_count) = match version {
+            1 => (HEADER_V1_LEN, 0, 0, 0),
+            2 => (
+                HEADER_V2_LEN,
+                read_u32(bytes, 15)? as usize,
+                read_u32(bytes, 19)? as usize,
+                0,
+            ),
+            3 | VERSION => (
+                if version == 3 {
+                    HEADER_V3_LEN
+                } else {
+                    HEADER_LEN
+                },
+                read_u32(bytes, 15)? as usize,
+                read_u32(bytes, 19)? as usize,
+                read_u32(bytes, 23)? as usize,
+            ),
+            _ => return Err(ImageError::UnsupportedVersion(version)),
+        };
+
+        let bytes = if version == VERSION {
+            let expected = read_u32(bytes, CHECKSUM_AT)?;
+            let signature_len = read_u32(bytes, CHECKSUM_AT + 4)? as usize;
+            let bytes = bytes
+                .len()
+                .checked_sub(signature_len)
+                .filter(|end| *end >= HEADER_LEN)
+                .map(|end| &bytes[..end])
+                .ok_or(ImageError::Truncated)?;
+
+            let mut covered = bytes.to_vec();
+            covered[CHECKSUM_AT..CHECKSUM_AT + 4].fill(0);
+            let actual = crc32(&covered);
+            if actual != expected {
+                return Err(ImageError::BadChecksum(expected, actual));
+            }
+
+            bytes
+        } else {
+            bytes
+        };
+
+        let entry = read_u32(bytes, 5)?;
+        let memory = read_u32(bytes, 9)?;
+        let count = u16::from_le_bytes([bytes[13], bytes[14]]) as usize;
+
+        let mut segments = vec![];
+        for idx in 0..count {
+            let at = header_len + SEGMENT_LEN * idx;
+
+            let kind = SegmentKind::from_byte(*bytes.get(at).ok_or(ImageError::Truncated)?)?;
+            let address = read_u32(bytes, at + 1)?;
+            let offset = read_u32(bytes, at + 5)? as usize;
+            let size = read_u32(bytes, at + 9)? as usize;
+
+            let data = offset
+                .checked_add(size)
+                .and_then(|end| bytes.get(offset..end))
+                .ok_or(ImageError::SegmentOutOfBounds(idx))?;
+
+            segments.push(Segment {
+                kind,
+                address,
+                data: data.to_vec(),
+            });
+        }
+
+        let symbols_at = header_len + SEGMENT_LEN * count;
+        let lines_at = symbols_at.saturating_add(SYMBOL_LEN.saturating_mul(symbol_count));
+        let strings_at = lines_at.saturating_add(LINE_LEN.saturating_mul(line_count));
+        let strings = strings_at
+            .checked_add(strings_len)
+            .and_then(|end| bytes.get(strings_at..end))
+            .ok_or(ImageError::Truncated)?;
+
+        let mut symbols = vec![];
+        for idx in 0..symbol_count {
+            let at = symbols_at + SYMBOL_LEN * idx;
+
+            let name_offset = read_u32(bytes, at)? as usize;
+            let address = read_u32(bytes, at + 4)?;
+
+            let name = read_string(strings, name_offset).ok_or(ImageError::BadSymbolName(idx))?;
+
+            symbols.push(Symbol {
+                name: name.to_string(),
+                address,
+            });
+        }
+
+        let mut lines = vec![];
+        for idx in 0..line_count {
+            let at = lines_at + LINE_LEN * idx;
+
+            let address = read_u32(bytes, at)?;
+            let file_offset = read_u32(bytes, at + 4)? as usize;
+            let line = read_u32(bytes, at + 8)?;
+
+            let file = read_string(strings, file_offset).ok_or(ImageError::BadLineFile(idx))?;
+
+            lines.push(Line {
+                address,
+                file: file.to_string(),
+                line,
+            });
+        }
+
+        Ok(Self {
+            entry,
+            memory,
+            segments,
+            symbols,
+            lines,
+        })
+    }
+
+    /// Find the address of a symbol by its name.
+    pub fn lookup(&self, name: &str) -> Option<u32> {
+        self.symbols
+            .iter()
+            .find(|x| x.name == name)
+            .map(|x| x.address)
+    }
+
+    /// Find the closest symbol at or before `address`, along with how far past it the address
+    /// is, so it can be shown as `name+offset`.
+    pub fn symbolize(&self, address: u32) -> Option<(&Symbol, u32)> {
+        symbol_at(&self.symbols, address)
+    }
+
+    /// Lay all the segments out in a single zero filled buffer, which is as big as the required
+    /// memory or the end of the last segment, whichever is bigger.
+    pub fn flatten(&self) -> Vec<u8> {
+        let end = self
+            .segments
+            .iter()
+            .map(|x| x.address as usize + x.data.len())
+            .max()
+            .unwrap_or(0)
+            .max(self.memory as usize);
+
+        let mut memory = vec![0; end];
+        for segment in self.segments.iter() {
+            let start = segment.address as usize;
+            memory[start..start + segment.data.len()].copy_from_slice(&segment.data);
+        }
+
+        memory
+    }
+}
+
+/// The CRC32 (the IEEE one, as used by zip and PNG) of `bytes`.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+// a zero terminated string starting at `offset` in the string section
+fn read_string(strings: &[u8], offset: usize) -> Option<&str> {
+    strings
+        .get(offset..)
+        .and_then(|x| x.split(|&b| b == 0).next())
+        .and_then(|x| core::str::from_utf8(x).ok())
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Result<u32> {
+    let slice = bytes.get(at..at + 4).ok_or(ImageError::Truncated)?;
+    Ok(u32::from_le_bytes([slice[0], slice[1], slice[2], slice[3]]))
+}
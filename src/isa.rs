@@ -0,0 +1,309 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+// opcodes
+pub const ADD: u8 = 0x01;
+pub const SUB: u8 = 0x02;
+pub const JMP: u8 = 0x03;
+pub const JZ: u8 = 0x04;
+pub const JLZ: u8 = 0x05;
+pub const JMZ: u8 = 0x06;
+pub const MOV: u8 = 0x07;
+pub const LDP: u8 = 0x08;
+pub const STP: u8 = 0x09;
+pub const AND: u8 = 0x0A;
+pub const NOT: u8 = 0x0B;
+pub const OR: u8 = 0x0C;
+pub const NOR: u8 = 0x0D;
+pub const NAND: u8 = 0x0E;
+pub const XOR: u8 = 0x0F;
+pub const XNOR: u8 = 0x10;
+pub const HLT: u8 = 0x11;
+pub const NOP: u8 = 0x12;
+pub const INT: u8 = 0x13;
+pub const CMP: u8 = 0x14;
+
+// addressing modes of the modded instructions
+pub const RR_MODE: u8 = 0x01;
+pub const RB_MODE: u8 = 0x02;
+pub const RW_MODE: u8 = 0x03;
+pub const RD_MODE: u8 = 0x04;
+
+/// Amount of general purpose registers, register ids go from 0 up to (but not including) this.
+pub const REGISTERS: u8 = 4;
+
+// (opcode, mnemonic), the mnemonics are what the assembler accepts (case insensitively) and what
+// the disassembler prints
+const MNEMONICS: [(u8, &str); 20] = [
+    (ADD, "ADD"),
+    (SUB, "SUB"),
+    (JMP, "JMP"),
+    (JZ, "JZ"),
+    (JLZ, "JLZ"),
+    (JMZ, "JMZ"),
+    (MOV, "MOV"),
+    (LDP, "LDP"),
+    (STP, "STP"),
+    (AND, "AND"),
+    (NOT, "NOT"),
+    (OR, "OR"),
+    (NOR, "NOR"),
+    (NAND, "NAND"),
+    (XOR, "XOR"),
+    (XNOR, "XNOR"),
+    (HLT, "HLT"),
+    (NOP, "NOP"),
+    (INT, "INT"),
+    (CMP, "CMP"),
+];
+
+/// Get the mnemonic of an opcode, `None` if the byte isn't a known opcode.
+pub fn mnemonic(opcode: u8) -> Option<&'static str> {
+    MNEMONICS.iter().find(|x| x.0 == opcode).map(|x| x.1)
+}
+
+/// Get the opcode of a mnemonic, the comparison ignores case.
+pub fn opcode(mnemonic: &str) -> Option<u8> {
+    MNEMONICS
+        .iter()
+        .find(|x| x.1.eq_ignore_ascii_case(mnemonic))
+        .map(|x| x.0)
+}
+
+/// Whether the opcode is one of the jumps, which take an absolute dword address.
+pub fn is_jump(opcode: u8) -> bool {
+    matches!(opcode, JMP | JZ | JLZ | JMZ)
+}
+
+/// Whether the opcode is one of the modded instructions, which take a mode byte, a target
+/// register and a source whose size depends on the mode.
+pub fn is_modded(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        AND | NAND | OR | NOR | XOR | XNOR | MOV | ADD | SUB | CMP
+    )
+}
+
+/// The source operand of a modded instruction, the variant decides the addressing mode.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Operand {
+    Reg(u8),
+    Byte(u8),
+    Word(u16),
+    Dword(u32),
+}
+
+impl Operand {
+    /// The smallest immediate operand `value` fits in.
+    pub fn imm(value: u32) -> Self {
+        if let Ok(byte) = u8::try_from(value) {
+            Operand::Byte(byte)
+        } else if let Ok(word) = u16::try_from(value) {
+            Operand::Word(word)
+        } else {
+            Operand::Dword(value)
+        }
+    }
+
+    /// The mode byte that selects this operand.
+    pub fn mode(&self) -> u8 {
+        match self {
+            Operand::Reg(_) => RR_MODE,
+            Operand::Byte(_) => RB_MODE,
+            Operand::Word(_) => RW_MODE,
+            Operand::Dword(_) => RD_MODE,
+        }
+    }
+
+    /// Amount of bytes the operand takes after the target register.
+    pub fn size(&self) -> u32 {
+        match self {
+            Operand::Reg(_) | Operand::Byte(_) => 1,
+            Operand::Word(_) => 2,
+            Operand::Dword(_) => 4,
+        }
+    }
+}
+
+/// A single decoded instruction.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Instr {
+    /// One of the jumps with its absolute target address.
+    Jump(u8, u32),
+    /// One of the modded instructions with its target register and source operand.
+    Modded(u8, u8, Operand),
+    /// `NOT` with its target register.
+    Not(u8),
+    /// Instructions which don't take any operands (`LDP`, `STP`, `HLT`, `NOP` and `INT`).
+    Bare(u8),
+}
+
+impl Instr {
+    pub fn opcode(&self) -> u8 {
+        match *self {
+            Instr::Jump(op, _) | Instr::Modded(op, _, _) | Instr::Bare(op) => op,
+            Instr::Not(_) => NOT,
+        }
+    }
+
+    /// Amount of bytes the encoded instruction takes, including the opcode.
+    pub fn size(&self) -> u32 {
+        match self {
+            Instr::Jump(_, _) => 5,
+            Instr::Modded(_, _, source) => 3 + source.size(),
+            Instr::Not(_) => 2,
+            Instr::Bare(_) => 1,
+        }
+    }
+
+    /// Append the byte encoding of the instruction to `out`, multi byte values are little endian
+    /// just like the interpreter reads them.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.push(self.opcode());
+
+        match *self {
+            Instr::Jump(_, address) => out.extend_from_slice(&address.to_le_bytes()),
+            Instr::Modded(_, target, source) => {
+                out.push(source.mode());
+                out.push(target);
+
+                match source {
+                    Operand::Reg(reg) => out.push(reg),
+                    Operand::Byte(byte) => out.push(byte),
+                    Operand::Word(word) => out.extend_from_slice(&word.to_le_bytes()),
+                    Operand::Dword(dword) => out.extend_from_slice(&dword.to_le_bytes()),
+                }
+            }
+            Instr::Not(reg) => out.push(reg),
+            Instr::Bare(_) => {}
+        }
+    }
+}
+
+impl fmt::Display for Instr {
+    /// Formats the instruction in the syntax the assembler takes, immediates only get a size
+    /// keyword when the assembler wouldn't pick that size by itself.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // safe to unwrap because every opcode an `Instr` can hold has a mnemonic
+        let name = mnemonic(self.opcode()).unwrap();
+
+        match *self {
+            Instr::Jump(_, address) => write!(f, "{} {:#010x}", name, address),
+            Instr::Modded(_, target, source) => {
+                write!(f, "{} r{}, ", name, target)?;
+
+                let value = match source {
+                    Operand::Reg(reg) => return write!(f, "r{}", reg),
+                    Operand::Byte(byte) => byte as u32,
+                    Operand::Word(word) => word as u32,
+                    Operand::Dword(dword) => dword,
+                };
+
+                if Operand::imm(value) != source {
+                    let size = match source {
+                        Operand::Word(_) => "word",
+                        _ => "dword",
+                    };
+
+                    write!(f, "{} ", size)?;
+                }
+
+                write!(f, "{}", value)
+            }
+            Instr::Not(reg) => write!(f, "{} r{}", name, reg),
+            Instr::Bare(_) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum DecodeError {
+    UnknownOpcode(u32, u8),
+    UnknownMode(u32, u8),
+    BadRegister(u32, u8),
+    Truncated(u32),
+}
+
+impl core::error::Error for DecodeError {}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnknownOpcode(address, byte) => {
+                write!(f, "{:#010x}: unknown opcode {:#04x}", address, byte)
+            }
+            DecodeError::UnknownMode(address, byte) => {
+                write!(
+                    f,
+                    "{:#010x}: unknown addressing mode {:#04x}",
+                    address, byte
+                )
+            }
+            DecodeError::BadRegister(address, byte) => {
+                write!(f, "{:#010x}: register id {} is out of range", address, byte)
+            }
+            DecodeError::Truncated(address) => {
+                write!(
+                    f,
+                    "{:#010x}: instruction runs past the end of the code",
+                    address
+                )
+            }
+        }
+    }
+}
+
+/// Decode the instruction starting at `address` in `code`.
+///
+/// It errors if the bytes there aren't a valid instruction, that is unknown opcodes or modes,
+/// register ids the interpreter would reject, and instructions cut off by the end of the code.
+pub fn decode(code: &[u8], address: u32) -> core::result::Result<Instr, DecodeError> {
+    let byte_at = |offset: u32| -> core::result::Result<u8, DecodeError> {
+        code.get((address as usize).saturating_add(offset as usize))
+            .copied()
+            .ok_or(DecodeError::Truncated(address))
+    };
+
+    let dword_at = |offset: u32| -> core::result::Result<u32, DecodeError> {
+        let mut bytes = [0u8; 4];
+        for (idx, byte) in bytes.iter_mut().enumerate() {
+            *byte = byte_at(offset + idx as u32)?;
+        }
+
+        Ok(u32::from_le_bytes(bytes))
+    };
+
+    let register_at = |offset: u32| -> core::result::Result<u8, DecodeError> {
+        let reg = byte_at(offset)?;
+        if reg < REGISTERS {
+            Ok(reg)
+        } else {
+            Err(DecodeError::BadRegister(address, reg))
+        }
+    };
+
+    let opcode = byte_at(0)?;
+
+    if is_jump(opcode) {
+        Ok(Instr::Jump(opcode, dword_at(1)?))
+    } else if is_modded(opcode) {
+        let mode = byte_at(1)?;
+        let target = register_at(2)?;
+
+        let source = match mode {
+            RR_MODE => Operand::Reg(register_at(3)?),
+            RB_MODE => Operand::Byte(byte_at(3)?),
+            RW_MODE => Operand::Word(u16::from_le_bytes([byte_at(3)?, byte_at(4)?])),
+            RD_MODE => Operand::Dword(dword_at(3)?),
+            _ => return Err(DecodeError::UnknownMode(address, mode)),
+        };
+
+        Ok(Instr::Modded(opcode, target, source))
+    } else if opcode == NOT {
+        Ok(Instr::Not(register_at(1)?))
+    } else if mnemonic(opcode).is_some() {
+        Ok(Instr::Bare(opcode))
+    } else {
+        Err(DecodeError::UnknownOpcode(address, opcode))
+    }
+}
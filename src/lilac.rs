@@ -1,7 +1,124 @@
+// reading, writing, copying and comparing the memory of processes
+mod access;
+
+// std::alloc::Allocator for a process of a `GlobalLilac`
+#[cfg(feature = "allocator_api")]
+pub mod alloc_api;
+
+// blocks freed when they go out of scope
+pub mod allocation;
+
 // impl Allocator
 pub mod allocator;
 
+// bump allocation inside a single block
+pub mod arena;
+
+// processes started by other processes
+mod children;
+
+// tracking borrows which outlive a borrow of the allocator
+#[cfg(feature = "std")]
+pub mod borrows;
+
+// power of two blocks for buddy mode
+mod buddy;
+
+// std::io over a single block
+#[cfg(feature = "std")]
+pub mod cursor;
+
+// std::alloc::GlobalAlloc on top of an allocator
+#[cfg(feature = "std")]
+pub mod global;
+
+// the bytes of the heap, in memory or mapped from a file
+mod heap;
+
+// waiting on and waking up addresses in shared blocks
+mod futex;
+
+// processes handled together
+mod groups;
+
+// guard bytes around blocks and filling of new and freed ones
+pub mod guard;
+
+// free blocks indexed by address and size
+mod free;
+
+// heaps of their own inside the heap, for keeping classes of memory apart
+mod named;
+
+// hooks for watching what the allocator does
+pub mod observer;
+
+// heap images on disk
+pub mod persist;
+
+// giving the embedder a chance to make room before running out
+pub mod pressure;
+
+// read, write and execute protection of blocks
+mod protect;
+
+// thread safe wrapper
+#[cfg(feature = "std")]
+pub mod parallel;
+
+// named shared memory segments
+mod shm;
+
+// zeroizing memory with writes the compiler can't leave out
+mod scrub;
+
+// checkpointing and restoring the whole allocator
+pub mod snapshot;
+
+// heap statistics and reports
+pub mod stats;
+
+// collecting what's left of blocks and processes which are gone
+pub mod sweep;
+
+// blocks of plain old data borrowed as slices of it
+pub mod typed;
+
 // types
 pub mod types;
 
-pub use types::{AllocError, Allocator, FreeBlock, MemRange, ProcBuilder, Process, Result};
+// checking the bookkeeping for consistency
+pub mod validate;
+
+// per process address spaces
+pub mod virt;
+
+// shares which don't keep the block alive
+mod weak;
+
+#[cfg(feature = "allocator_api")]
+pub use alloc_api::ProcessAlloc;
+pub use allocation::Allocation;
+pub use arena::Arena;
+#[cfg(feature = "std")]
+pub use borrows::BorrowToken;
+#[cfg(feature = "std")]
+pub use cursor::BlockCursor;
+#[cfg(feature = "std")]
+pub use global::GlobalLilac;
+#[cfg(feature = "std")]
+pub use observer::EventLog;
+pub use observer::{AllocObserver, Event};
+#[cfg(feature = "std")]
+pub use parallel::ParallelAlloc;
+pub use persist::HeapImageError;
+pub use pressure::{Pressure, PressureHandler};
+pub use snapshot::Snapshot;
+pub use stats::{BlockInfo, Leak, ProcessStats, Stats};
+pub use sweep::Sweep;
+pub use typed::{Pod, TypedHandle};
+pub use types::{
+    Access, AllocError, Allocator, BlockHandle, Compaction, FreeBlock, Group, Inherit, MemRange,
+    ParseProcessError, ProcBuilder, Process, Protection, Result, Strategy,
+};
+pub use validate::Violation;
@@ -0,0 +1,226 @@
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::ops::Range;
+
+use super::{Access, AllocError, Allocator, BlockHandle, MemRange, Process, Result};
+
+// the range of the `len` bytes starting at `addr`, which can't go past the end of the address
+// space
+fn span(process_id: Process, addr: u32, len: u32) -> Result<Range<u32>> {
+    addr.checked_add(len)
+        .map(|end| addr..end)
+        .ok_or(AllocError::NotOwned {
+            process_id,
+            range: addr..u32::MAX,
+        })
+}
+
+// whether every byte of `range` is in one of `blocks`
+fn covers<'a>(blocks: impl Iterator<Item = &'a MemRange>, range: &Range<u32>) -> bool {
+    let mut blocks: Vec<&Range<u32>> = blocks.map(|x| &x.range).collect();
+    blocks.sort_unstable_by_key(|x| x.start);
+
+    // the first byte not covered yet
+    let mut next = range.start;
+    for block in blocks {
+        if block.start > next {
+            break;
+        }
+        next = next.max(block.end);
+    }
+
+    next >= range.end
+}
+
+impl Allocator {
+    // error like `range_borrow_spanning()`, or `range_borrow_spanning_mut()` if the range is
+    // going to be written to, would for `range`
+    fn check_spanning(&self, process_id: Process, range: &Range<u32>, write: bool) -> Result<()> {
+        let allocated = self
+            .allocated
+            .get(&process_id)
+            .ok_or_else(|| self.no_such_process(process_id))?;
+
+        let touched = || {
+            allocated
+                .iter()
+                .filter(|x| x.range.start < range.end && range.start < x.range.end)
+        };
+
+        if range.is_empty() || !covers(touched(), range) {
+            return Err(self.not_owned(process_id, range.clone()));
+        }
+        let access = if write { Access::Write } else { Access::Read };
+        if !covers(touched().filter(|x| self.allows(x, access)), range) {
+            return Err(AllocError::PermissionDenied {
+                process_id,
+                range: range.clone(),
+                access,
+            });
+        }
+        if write {
+            self.check_frozen(process_id)?;
+        }
+
+        for block in touched() {
+            let handle = BlockHandle {
+                process_id,
+                id: block.id,
+            };
+            self.check_block(handle, &block.range)?;
+        }
+
+        self.check_conflicts(range, write, None)
+    }
+
+    /// Like `range_borrow()` but the range can span any number of back to back blocks of the
+    /// process instead of just one, for treating blocks that were allocated one after the other
+    /// as one buffer. Blocks with guards are never back to back, there's always a guard between
+    /// them.
+    ///
+    /// It errors like `range_borrow()` does, where the range has to be owned byte for byte.
+    pub fn range_borrow_spanning(&self, process_id: Process, range: Range<u32>) -> Result<&[u8]> {
+        self.check_spanning(process_id, &range, false)?;
+        Ok(&self.heap[range.start as usize..range.end as usize])
+    }
+
+    /// Like `range_borrow_mut()` but for a range spanning back to back blocks, see
+    /// `range_borrow_spanning()`.
+    ///
+    /// It errors like `range_borrow_mut()` does, where every block the range is in has to be
+    /// writable.
+    pub fn range_borrow_spanning_mut(
+        &mut self,
+        process_id: Process,
+        range: Range<u32>,
+    ) -> Result<&mut [u8]> {
+        self.check_spanning(process_id, &range, true)?;
+        Ok(&mut self.heap[range.start as usize..range.end as usize])
+    }
+
+    // the `N` bytes at `addr` of a process
+    fn read<const N: usize>(&self, process_id: Process, addr: u32) -> Result<[u8; N]> {
+        let range = span(process_id, addr, N as u32)?;
+        let bytes = self.range_borrow(process_id, range)?;
+
+        // safe to unwrap because the range is exactly `N` bytes long
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn write<const N: usize>(
+        &mut self,
+        process_id: Process,
+        addr: u32,
+        bytes: [u8; N],
+    ) -> Result<()> {
+        let range = span(process_id, addr, N as u32)?;
+        self.range_borrow_mut(process_id, range)?
+            .copy_from_slice(&bytes);
+
+        Ok(())
+    }
+
+    /// Read the byte at `addr` of a process.
+    ///
+    /// It errors like `range_borrow()` does.
+    pub fn read_u8(&self, process_id: Process, addr: u32) -> Result<u8> {
+        self.read(process_id, addr).map(u8::from_le_bytes)
+    }
+
+    /// Read the little endian `u16` starting at `addr` of a process, both bytes have to be in
+    /// the same block.
+    ///
+    /// It errors like `range_borrow()` does.
+    pub fn read_u16(&self, process_id: Process, addr: u32) -> Result<u16> {
+        self.read(process_id, addr).map(u16::from_le_bytes)
+    }
+
+    /// Read the little endian `u32` starting at `addr` of a process, all four bytes have to be
+    /// in the same block.
+    ///
+    /// It errors like `range_borrow()` does.
+    pub fn read_u32(&self, process_id: Process, addr: u32) -> Result<u32> {
+        self.read(process_id, addr).map(u32::from_le_bytes)
+    }
+
+    /// Write `value` to `addr` of a process.
+    ///
+    /// It errors like `range_borrow_mut()` does.
+    pub fn write_u8(&mut self, process_id: Process, addr: u32, value: u8) -> Result<()> {
+        self.write(process_id, addr, value.to_le_bytes())
+    }
+
+    /// Write `value` as little endian starting at `addr` of a process, see `read_u16()`.
+    ///
+    /// It errors like `range_borrow_mut()` does.
+    pub fn write_u16(&mut self, process_id: Process, addr: u32, value: u16) -> Result<()> {
+        self.write(process_id, addr, value.to_le_bytes())
+    }
+
+    /// Write `value` as little endian starting at `addr` of a process, see `read_u32()`.
+    ///
+    /// It errors like `range_borrow_mut()` does.
+    pub fn write_u32(&mut self, process_id: Process, addr: u32, value: u32) -> Result<()> {
+        self.write(process_id, addr, value.to_le_bytes())
+    }
+
+    /// Copy the bytes of `src_range` of one process into `dst_range` of another, or of the same
+    /// one. The ranges may overlap, the copy comes out as if the source was read in full before
+    /// anything was written.
+    ///
+    /// Both ranges are checked like `range_borrow()` and `range_borrow_mut()` check them, so
+    /// this is the same as borrowing both sides except it works when they're in the same heap.
+    ///
+    /// It errors like `range_borrow()` does for the source and like `range_borrow_mut()` does
+    /// for the destination, or if the ranges aren't equally long (`AllocError::LengthMismatch`).
+    pub fn copy(
+        &mut self,
+        src_pid: Process,
+        src_range: Range<u32>,
+        dst_pid: Process,
+        dst_range: Range<u32>,
+    ) -> Result<()> {
+        self.check_range(src_pid, &src_range, false)?;
+        self.check_range(dst_pid, &dst_range, true)?;
+
+        let (src, dst) = (
+            src_range.end - src_range.start,
+            dst_range.end - dst_range.start,
+        );
+        if src != dst {
+            return Err(AllocError::LengthMismatch { src, dst });
+        }
+
+        self.heap.copy_within(
+            src_range.start as usize..src_range.end as usize,
+            dst_range.start as usize,
+        );
+        Ok(())
+    }
+
+    /// Set every byte of `range` of a process to `byte`, like `memset()`.
+    ///
+    /// It errors like `range_borrow_mut()` does.
+    pub fn fill(&mut self, process_id: Process, range: Range<u32>, byte: u8) -> Result<()> {
+        self.range_borrow_mut(process_id, range)?.fill(byte);
+        Ok(())
+    }
+
+    /// Compare the bytes of range `a` of one process with those of range `b` of another, or of
+    /// the same one, like `memcmp()` does: byte by byte, and if one of them runs out first it's
+    /// the smaller one.
+    ///
+    /// It errors like `range_borrow()` does for either range.
+    pub fn compare(
+        &self,
+        process_id: Process,
+        a: Range<u32>,
+        other_process_id: Process,
+        b: Range<u32>,
+    ) -> Result<Ordering> {
+        let a = self.range_borrow(process_id, a)?;
+        let b = self.range_borrow(other_process_id, b)?;
+
+        Ok(a.cmp(b))
+    }
+}
@@ -0,0 +1,61 @@
+use std::alloc::{self, Layout};
+use std::ptr::{self, NonNull};
+
+use super::{AllocError, GlobalLilac, Process, Result};
+
+/// A handle allocating from a `GlobalLilac` under one process, implementing the unstable
+/// `std::alloc::Allocator` so collections like `Vec::new_in()` and `Box::new_in()` put their
+/// memory in the lilac heap, see `GlobalLilac::process()`.
+///
+/// Every process gets a handle of its own, so how the blocks of several collections end up
+/// laid out and shared can be looked at through `GlobalLilac::with()`.
+#[derive(Debug, Copy, Clone)]
+pub struct ProcessAlloc<'a> {
+    lilac: &'a GlobalLilac,
+    process_id: Process,
+}
+
+impl ProcessAlloc<'_> {
+    pub fn process_id(&self) -> Process {
+        self.process_id
+    }
+}
+
+impl GlobalLilac {
+    /// A `ProcessAlloc` allocating under the process with the id of `process_id`, which is
+    /// registered first if it isn't yet.
+    ///
+    /// It errors like `Allocator::register_process()` does, except for the process being
+    /// registered already.
+    pub fn process(&self, process_id: Process) -> Result<ProcessAlloc<'_>> {
+        let process_id = match self.lock().register_process(process_id) {
+            Ok(process_id) | Err(AllocError::AlreadyRegistered(process_id)) => process_id,
+            Err(err) => return Err(err),
+        };
+
+        Ok(ProcessAlloc {
+            lilac: self,
+            process_id,
+        })
+    }
+}
+
+unsafe impl alloc::Allocator for ProcessAlloc<'_> {
+    fn allocate(&self, layout: Layout) -> std::result::Result<NonNull<[u8]>, alloc::AllocError> {
+        // lilac has no zero sized blocks, an aligned pointer nobody can read through will do
+        let block = match layout.size() {
+            0 => ptr::without_provenance_mut(layout.align()),
+            _ => self.lilac.alloc_as(self.process_id, layout),
+        };
+
+        NonNull::new(block)
+            .map(|x| NonNull::slice_from_raw_parts(x, layout.size()))
+            .ok_or(alloc::AllocError)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            self.lilac.dealloc_as(self.process_id, ptr.as_ptr());
+        }
+    }
+}
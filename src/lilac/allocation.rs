@@ -0,0 +1,85 @@
+use core::mem;
+use core::ops::Range;
+
+use super::{Allocator, BlockHandle, FreeBlock, Process, Result};
+
+/// A block which is freed when it goes out of scope, see `Allocator::alloc_scoped()`.
+///
+/// Freeing it only lets go of the reference of its process like `Allocator::free()` does, a
+/// block shared with other processes stays around for them. Dropping it can't report anything,
+/// so a free going wrong (the guards of the block were overwritten, or it was freed through
+/// `allocator()` already) goes unnoticed, `free()` frees it and passes the error on instead.
+#[derive(Debug)]
+pub struct Allocation<'a> {
+    allocator: &'a mut Allocator,
+    handle: BlockHandle,
+}
+
+impl<'a> Allocation<'a> {
+    /// Allocate a block of `size` bytes under a process id which is freed when it's dropped.
+    ///
+    /// It errors like `Allocator::alloc()` does.
+    pub fn new(allocator: &'a mut Allocator, process_id: Process, size: u32) -> Result<Self> {
+        let handle = allocator.alloc(process_id, size)?;
+        Ok(Self { allocator, handle })
+    }
+
+    pub fn handle(&self) -> BlockHandle {
+        self.handle
+    }
+
+    /// See `Allocator::range()`.
+    pub fn range(&self) -> Result<Range<u32>> {
+        self.allocator.range(self.handle)
+    }
+
+    /// See `Allocator::borrow()`.
+    pub fn borrow(&self) -> Result<&[u8]> {
+        self.allocator.borrow(self.handle)
+    }
+
+    /// See `Allocator::borrow_mut()`.
+    pub fn borrow_mut(&mut self) -> Result<&mut [u8]> {
+        self.allocator.borrow_mut(self.handle)
+    }
+
+    /// The allocator the block is from, for everything else that can be done with it while
+    /// it's alive.
+    pub fn allocator(&mut self) -> &mut Allocator {
+        self.allocator
+    }
+
+    /// Keep the block around after all, it has to be freed by hand like any other from now on.
+    pub fn into_handle(self) -> BlockHandle {
+        let handle = self.handle;
+        mem::forget(self);
+        handle
+    }
+
+    /// Free the block right away, see `Allocator::free()`.
+    ///
+    /// It errors like `Allocator::free()` does.
+    pub fn free(self) -> Result<FreeBlock> {
+        let freed = self.allocator.free(self.handle);
+        // it's freed already, dropping it would free it again
+        mem::forget(self);
+        freed
+    }
+}
+
+impl Drop for Allocation<'_> {
+    fn drop(&mut self) {
+        // there's nowhere to pass an error on to
+        let _ = self.allocator.free(self.handle);
+    }
+}
+
+impl Allocator {
+    /// Allocate a block of `size` bytes under a process id which is freed once the returned
+    /// `Allocation` goes out of scope, so host code can't leak it by forgetting to `free()` it.
+    ///
+    /// It errors like `alloc()` does.
+    pub fn alloc_scoped(&mut self, process_id: Process, size: u32) -> Result<Allocation<'_>> {
+        Allocation::new(self, process_id, size)
+    }
+}
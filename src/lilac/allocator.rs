@@ -1,268 +1,941 @@
-use std::collections::HashMap;
-use std::ops::Range;
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::Arc;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+use core::ops::Range;
+use core::sync::atomic::{AtomicU32, Ordering};
 
-use super::{AllocError, Allocator, FreeBlock, MemRange, Process, Result};
+use super::buddy::Buddy;
+use super::free::FreeList;
+use super::guard::POISON_BYTE;
+use super::heap::Heap;
+use super::scrub;
+use super::{
+    Access, AllocError, AllocObserver, Allocator, Arena, BlockHandle, Compaction, FreeBlock,
+    MemRange, Process, Result, Strategy,
+};
+use crate::hexdump;
+
+// how many freed handles are remembered for telling a double free apart from a handle that
+// never existed, ids are never reused so the only cost of forgetting one is a vaguer error
+const RECENTLY_FREED: usize = 64;
+
+// what happens to the bytes of a block once nobody holds it anymore
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Clear {
+    Keep,
+    Zero,
+    // zero them with volatile writes
+    Secure,
+}
 
 impl Allocator {
     /// Create a new `Allocator`.
     pub fn new() -> Self {
         Self {
-            heap: vec![],
-            allocated: HashMap::new(),
-            free: vec![],
+            heap: Heap::new(),
+            allocated: BTreeMap::new(),
+            free: FreeList::new(),
+            strategy: Strategy::FirstFit,
+            buddy: None,
+            next_id: 0,
+            limit: None,
+            tags: BTreeMap::new(),
+            observers: vec![],
+            guard: 0,
+            guards: BTreeMap::new(),
+            poison: false,
+            zero_on_alloc: false,
+            fill_fresh: false,
+            parents: BTreeMap::new(),
+            recently_freed: VecDeque::with_capacity(RECENTLY_FREED),
+            weak: BTreeMap::new(),
+            segments: BTreeMap::new(),
+            futexes: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            next_group: 0,
+            spawned_by: BTreeMap::new(),
+            generations: BTreeMap::new(),
+            protections: BTreeMap::new(),
+            aligns: BTreeMap::new(),
+            spaces: BTreeMap::new(),
+            heaps: BTreeMap::new(),
+            pressure_handler: None,
+            low_water: None,
+            under_pressure: false,
+            #[cfg(feature = "std")]
+            borrows: Arc::default(),
         }
     }
 
-    pub fn register_process(&mut self, process_id: Process) -> Result<()> {
-        if self.allocated.contains_key(&process_id) {
-            return Err(AllocError::AlreadyRegistered);
+    /// Create a new `Allocator` whose heap never grows past `limit` bytes.
+    pub fn with_limit(limit: u32) -> Self {
+        Self {
+            limit: Some(limit),
+            ..Self::new()
         }
+    }
 
-        if self.allocated.insert(process_id, vec![]).is_none() {
-            Ok(())
-        } else {
-            unreachable!();
+    pub fn limit(&self) -> Option<u32> {
+        self.limit
+    }
+
+    /// Change how long the heap may get from now on, `None` lets it grow as much as it wants. A
+    /// heap already past the new limit is left as is, it just won't grow any further.
+    ///
+    /// A mapped heap (see `with_mmap()`) can never grow past its mapping, the limit is capped
+    /// at that.
+    pub fn set_limit(&mut self, limit: Option<u32>) {
+        self.limit = match self.heap.max_len() {
+            Some(max) => Some(limit.map_or(max as u32, |x| x.min(max as u32))),
+            None => limit,
+        };
+    }
+
+    /// Whether every block is zeroed before it's handed out, see `set_zero_on_alloc()`.
+    pub fn zero_on_alloc(&self) -> bool {
+        self.zero_on_alloc
+    }
+
+    /// Make every allocation from now on hand out zeroed memory, including the bytes a block
+    /// gains in `realloc()`. Otherwise a block reusing freed memory starts out with whatever the
+    /// last block there left behind, unless it was freed with `free_clear()`.
+    ///
+    /// `alloc_zeroed()` does the same for a single allocation.
+    pub fn set_zero_on_alloc(&mut self, zero_on_alloc: bool) {
+        self.zero_on_alloc = zero_on_alloc;
+    }
+
+    /// Create a new `Allocator` with room for a heap of `capacity` bytes before it has to
+    /// reallocate, see `reserve()`.
+    pub fn with_capacity(capacity: u32) -> Self {
+        let mut allocator = Self::new();
+        allocator.heap.reserve_exact(capacity as usize);
+
+        allocator
+    }
+
+    /// How many bytes the heap currently spans, free blocks included.
+    pub fn heap_len(&self) -> u32 {
+        self.heap.len() as u32
+    }
+
+    /// How many bytes the heap can span before it has to reallocate.
+    pub fn heap_capacity(&self) -> u32 {
+        self.heap.capacity().min(u32::MAX as usize) as u32
+    }
+
+    /// Make room for the heap to grow by `additional` bytes without reallocating, so a burst of
+    /// allocations doesn't keep moving it around. It never reserves past the limit of the heap,
+    /// and `trim()` gives the room back.
+    pub fn reserve(&mut self, additional: u32) {
+        let mut wanted = self.heap.len() + additional as usize;
+        if let Some(limit) = self.limit {
+            wanted = wanted.min(limit as usize);
+        }
+
+        self.heap
+            .reserve_exact(wanted.saturating_sub(self.heap.len()));
+    }
+
+    // the error for when there's no room for `size` more bytes
+    pub(super) fn out_of_memory(&self, size: u32) -> AllocError {
+        AllocError::OutOfMemory {
+            size,
+            heap: self.heap.len() as u32,
+            limit: self.limit,
+        }
+    }
+
+    // whether the heap may grow to `len` bytes
+    fn fits(&self, len: usize) -> bool {
+        self.limit.is_none_or(|limit| len <= limit as usize)
+    }
+
+    /// Create a new `Allocator` in buddy mode, which rounds every block up to a power of two
+    /// (of at least 8 bytes) and splits and merges them in halves.
+    ///
+    /// Allocating and freeing are both O(log n) and fragmentation stays bounded, at the cost of
+    /// wasting up to half of every block, so it suits workloads with lots of blocks of similar
+    /// sizes best. The `Strategy` doesn't matter in this mode and `merge()` and `split()` aren't
+    /// supported, since the halves of a block have to stay where they are.
+    pub fn buddy() -> Self {
+        Self {
+            buddy: Some(Buddy::new()),
+            ..Self::new()
+        }
+    }
+
+    /// Whether this `Allocator` was created with `buddy()`.
+    pub fn is_buddy(&self) -> bool {
+        self.buddy.is_some()
+    }
+
+    /// Create a new `Allocator` which picks free blocks using `strategy`.
+    pub fn with_strategy(strategy: Strategy) -> Self {
+        Self {
+            strategy,
+            ..Self::new()
+        }
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    /// Change how free blocks are picked from now on, the blocks already allocated stay where
+    /// they are.
+    pub fn set_strategy(&mut self, strategy: Strategy) {
+        self.strategy = strategy;
+    }
+
+    /// Tell `observer` about everything this `Allocator` does from now on, see
+    /// `AllocObserver`. Observers are called in the order they were added.
+    pub fn observe(&mut self, observer: impl AllocObserver + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    // tell every observer about something which just happened
+    pub(super) fn notify(&mut self, f: impl Fn(&mut dyn AllocObserver)) {
+        for observer in &mut self.observers {
+            f(observer.as_mut());
+        }
+    }
+
+    /// Register a process with the id of `process_id`, so it can allocate.
+    ///
+    /// An id can be used again once the process which had it was cleaned up, the new process
+    /// gets the next generation of it so the `Process` of the old one can't reach the memory of
+    /// the new one, everything given it errors with `AllocError::StaleProcess` instead. It
+    /// returns the process with the generation it got, which is what has to be used from now
+    /// on, the generation of `process_id` doesn't matter.
+    ///
+    /// It errors if a process with the id is registered already (`AllocError::AlreadyRegistered`,
+    /// with that process).
+    pub fn register_process(&mut self, process_id: Process) -> Result<Process> {
+        let id = process_id.id;
+        let live = Process { id, generation: 0 }..=Process {
+            id,
+            generation: u32::MAX,
+        };
+        if let Some(live) = self.allocated.range(live).next() {
+            return Err(AllocError::AlreadyRegistered(*live.0));
+        }
+
+        let generation = match self.generations.get(&id) {
+            Some(generation) => generation + 1,
+            None => 0,
+        };
+        self.generations.insert(id, generation);
+
+        let process_id = Process { id, generation };
+        self.allocated.insert(process_id, vec![]);
+
+        Ok(process_id)
+    }
+
+    // what to error with for a process which isn't registered, which is only a process that
+    // doesn't exist if its id wasn't reused
+    pub(super) fn no_such_process(&self, process_id: Process) -> AllocError {
+        match self.generations.get(&process_id.id) {
+            Some(&generation) if generation > process_id.generation => AllocError::StaleProcess {
+                process_id,
+                current: Process {
+                    id: process_id.id,
+                    generation,
+                },
+            },
+            _ => AllocError::NoSuchProcess(process_id),
+        }
+    }
+
+    // the index of the block `handle` refers to in the blocks of its process
+    pub(super) fn find(&self, handle: BlockHandle) -> Result<usize> {
+        let allocated = match self.allocated.get(&handle.process_id) {
+            Some(allocated) => allocated,
+            None => return Err(self.no_such_process(handle.process_id)),
+        };
+
+        allocated
+            .iter()
+            .position(|x| x.id == handle.id)
+            .ok_or(AllocError::BlockNotFound(handle))
+    }
+
+    // like `find()` but also errors if another process holds the block too, for everything which
+    // changes where the block is or how long it is
+    fn find_owned(&self, handle: BlockHandle) -> Result<usize> {
+        let idx = self.find(handle)?;
+        let block = &self.allocated[&handle.process_id][idx];
+
+        // a window is part of a block somebody shared, even if nobody else holds it anymore
+        if (*block.refcount).load(Ordering::SeqCst) > 1 || block.parent.is_some() {
+            return Err(AllocError::BlockShared(handle));
         }
+
+        Ok(idx)
     }
 
-    fn alloc_new(&mut self, process_id: Process, size: u32) -> Range<u32> {
-        let last_elem = self.heap.len() as u32;
-        for _ in 0..size {
-            self.heap.push(0);
+    // the id and range of the whole block `block` is part of, which is only another block than
+    // its own for a window from `share_range()`
+    pub(super) fn block_of(&self, block: &MemRange) -> (u64, Range<u32>) {
+        match block.parent {
+            // the parent stays in `parents` for as long as a window into it exists
+            Some(parent) => (parent, self.parents[&parent].clone()),
+            None => (block.id, block.range.clone()),
         }
-        let new_last_elem = self.heap.len() as u32;
+    }
 
-        let range = last_elem..(new_last_elem - 1);
+    // hand out the id for a new block, they're never reused so an old handle can't end up
+    // pointing at some other block
+    fn add_block(&mut self, process_id: Process, range: Range<u32>) -> BlockHandle {
+        let id = self.next_id;
+        self.next_id += 1;
 
         let entry = self.allocated.entry(process_id).or_insert(vec![]);
-        entry.push(MemRange::new(Arc::new(AtomicU32::new(1)), range));
+        entry.push(MemRange::new(id, Arc::new(AtomicU32::new(1)), range));
 
-        last_elem..(new_last_elem - 1)
+        BlockHandle { process_id, id }
     }
 
-    fn alloc_free(
+    // like `add_block()` for a block placed at `outer` with room for `before` and `after` guard
+    // bytes, and return its handle along with where the block itself is
+    pub(super) fn add_guarded(
         &mut self,
         process_id: Process,
-        size: u32,
-        free: (u32, Range<u32>),
-    ) -> Range<u32> {
-        // the start will be the start of the free block, but the end will be the start plus the
-        // size but subtracting one, because of how vectors are indexed, for example a 4 element
-        // range is 0..3, not 0..4, if we were to not subtract it would treat a 4 element range as
-        // 0..4 which is actually 5 elements
-        //
-        // NOTE: in alloc_new() this was done when initializing the range, however here we do it
-        // beforehand.
-        let start = free.1.start;
-        let end = free.1.start + size - 1;
-
-        let new_cap = free.0 - size;
-        if new_cap != 0 {
-            // if there is still free memory left that we don't need to allocate, we'll just start
-            // from the end of the last used block and declare the rest as free.
-            let start_of_rest = end + 1;
-            let end_of_rest = free.1.end;
+        outer: Range<u32>,
+        before: u32,
+        after: u32,
+    ) -> (BlockHandle, Range<u32>) {
+        let range = outer.start + before..outer.end - after;
+        let handle = self.add_block(process_id, range.clone());
+        self.fill_new(range.clone());
 
-            self.free.push((new_cap, start_of_rest..end_of_rest));
+        if before + after > 0 {
+            self.write_guards(&outer, &range);
+            self.guards.insert(handle.id, outer);
         }
 
-        let range = start..end;
-        let entry = self.allocated.entry(process_id).or_insert(vec![]);
-        entry.push(MemRange::new(Arc::new(AtomicU32::new(1)), range));
+        (handle, range)
+    }
+
+    fn alloc_new(&mut self, size: u32) -> Option<Range<u32>> {
+        // a free block at the very end of the heap is taken along, so the heap only grows by the
+        // bytes that are missing
+        let trailing = self.free.ending_at(self.heap.len() as u32);
+        let last_elem = match &trailing {
+            Some(free) => free.start,
+            None => self.heap.len() as u32,
+        };
+
+        let new_len = last_elem as usize + size as usize;
+        if !self.fits(new_len) || !self.heap.resize(new_len) {
+            return None;
+        }
+
+        if trailing.is_some() {
+            self.free.remove(last_elem);
+        }
+
+        Some(last_elem..new_len as u32)
+    }
+
+    fn alloc_free(&mut self, size: u32, free: Range<u32>) -> Range<u32> {
+        // the block takes the start of the free block, and since ranges are half-open it ends
+        // right where the rest of the free block starts
+        let start = free.start;
+        let end = free.start + size;
+
+        if free.end > end {
+            // if there is still free memory left that we don't need to allocate, we'll just start
+            // from the end of the block and declare the rest as free.
+            self.free.insert(end..free.end);
+        }
 
         start..end
     }
 
+    // find room for `size` bytes on the heap, growing it if needed, without giving it to anyone
+    pub(super) fn place(&mut self, size: u32) -> Result<Range<u32>> {
+        if let Some(buddy) = &mut self.buddy {
+            // the process only gets the bytes it asked for, the rest of the block is worked out
+            // again from the size of the range when it's freed
+            let start = buddy
+                .alloc(&mut self.heap, Buddy::order(size), self.limit)
+                .ok_or_else(|| self.out_of_memory(size))?;
+            return Ok(start..start + size);
+        }
+
+        if let Some(free) = self.free.take(size, self.strategy) {
+            Ok(self.alloc_free(size, free))
+        } else {
+            self.alloc_new(size).ok_or_else(|| self.out_of_memory(size))
+        }
+    }
+
     /// Allocates a certain `size` of bytes on the heap of the `Allocator` under a process id; if
     /// there aren't enough free bytes it will add more space on the heap.
     ///
-    /// It will return a `Range<u32>` where you can later use the start index of that range as the
-    /// value to free this memory later, using the `free()` function.
+    /// Which free block it takes when there's more than one big enough depends on the
+    /// `Strategy` of the `Allocator`.
+    ///
+    /// It will return a `BlockHandle` which is what you later give to `free()` to free this
+    /// memory, `range()` tells where the block currently is.
     ///
-    /// This function will error if the process id hasn't been registered before.
-    pub fn alloc(&mut self, process_id: Process, size: u32) -> Result<Range<u32>> {
+    /// This function will error if the process id hasn't been registered before, if `size` is
+    /// zero (`AllocError::ZeroSize`) or if there's no free block big enough and the heap can't
+    /// grow past its limit (`AllocError::OutOfMemory`), which the pressure handler gets a chance
+    /// to make room for first, see `set_pressure_handler()`.
+    pub fn alloc(&mut self, process_id: Process, size: u32) -> Result<BlockHandle> {
+        self.relieve(|x| x.alloc_inner(process_id, size))
+    }
+
+    fn alloc_inner(&mut self, process_id: Process, size: u32) -> Result<BlockHandle> {
         if !self.allocated.contains_key(&process_id) {
-            return Err(AllocError::NoSuchProcess);
+            return Err(self.no_such_process(process_id));
         }
+        if size == 0 {
+            return Err(AllocError::ZeroSize);
+        }
+        self.check_group(process_id, size)?;
 
-        let has_free = self.free.iter().enumerate().find(|x| x.1 .0 >= size);
-        if let Some(free) = has_free {
-            let free = self.free.swap_remove(free.0);
-            Ok(self.alloc_free(process_id, size, free))
-        } else {
-            Ok(self.alloc_new(process_id, size))
+        let guard = self.guard;
+        let outer = size
+            .checked_add(guard * 2)
+            .ok_or_else(|| self.out_of_memory(size))
+            .and_then(|x| self.place(x))?;
+        let (handle, range) = self.add_guarded(process_id, outer, guard, guard);
+        self.notify(|x| x.on_alloc(handle, &range));
+
+        Ok(handle)
+    }
+
+    /// Like `alloc()` but the block is always zeroed, whether or not the `Allocator` zeroes
+    /// every allocation.
+    ///
+    /// It errors like `alloc()` does.
+    pub fn alloc_zeroed(&mut self, process_id: Process, size: u32) -> Result<BlockHandle> {
+        let handle = self.alloc(process_id, size)?;
+        if !self.zero_on_alloc {
+            // safe to unwrap because the block was just allocated
+            let range = self.range(handle).unwrap();
+            self.heap[range.start as usize..range.end as usize].fill(0);
+        }
+
+        Ok(handle)
+    }
+
+    // like `place()` but the block starts at a multiple of `align`
+    fn place_aligned(&mut self, size: u32, align: u32) -> Result<Range<u32>> {
+        if let Some(buddy) = &mut self.buddy {
+            let start = buddy
+                .alloc_aligned(
+                    &mut self.heap,
+                    Buddy::order(size),
+                    align.trailing_zeros(),
+                    self.limit,
+                )
+                .ok_or_else(|| self.out_of_memory(size))?;
+            return Ok(start..start + size);
+        }
+
+        let end_at = |start: u32| start + size;
+
+        if let Some((free, start)) = self.free.take_aligned(size, align) {
+            // the slack before the aligned start and whatever is left after the block stay free,
+            // neither of them touches another free block
+            if start > free.start {
+                self.free.insert(free.start..start);
+            }
+            if free.end > end_at(start) {
+                self.free.insert(end_at(start)..free.end);
+            }
+
+            return Ok(start..end_at(start));
+        }
+
+        // grow the heap, the slack before the aligned start is given back like a freed block so
+        // it merges with a free block at the end of the heap
+        let len = self.heap.len() as u32;
+        let start = len.next_multiple_of(align);
+        if !self.fits(end_at(start) as usize) || !self.heap.resize(end_at(start) as usize) {
+            return Err(self.out_of_memory(size));
+        }
+
+        if start > len {
+            self.release(len..start);
+        }
+
+        Ok(start..end_at(start))
+    }
+
+    /// Like `alloc()` but the block starts at a multiple of `align`, the bytes skipped to get
+    /// there stay free.
+    ///
+    /// Free blocks are looked at in address order whatever the `Strategy` is, since the best
+    /// fitting block isn't necessarily one which can be aligned.
+    ///
+    /// It errors like `alloc()` does, or if `align` isn't a power of two
+    /// (`AllocError::BadAlignment`).
+    pub fn alloc_aligned(
+        &mut self,
+        process_id: Process,
+        size: u32,
+        align: u32,
+    ) -> Result<BlockHandle> {
+        self.relieve(|x| x.alloc_aligned_inner(process_id, size, align))
+    }
+
+    fn alloc_aligned_inner(
+        &mut self,
+        process_id: Process,
+        size: u32,
+        align: u32,
+    ) -> Result<BlockHandle> {
+        if !self.allocated.contains_key(&process_id) {
+            return Err(self.no_such_process(process_id));
+        }
+        if size == 0 {
+            return Err(AllocError::ZeroSize);
+        }
+        if !align.is_power_of_two() {
+            return Err(AllocError::BadAlignment(align));
+        }
+        self.check_group(process_id, size)?;
+
+        // the guard before the block is made a multiple of `align` too, so the block itself
+        // still starts aligned
+        let (before, after) = (self.guard.next_multiple_of(align), self.guard);
+        let outer = size
+            .checked_add(before + after)
+            .ok_or_else(|| self.out_of_memory(size))
+            .and_then(|x| self.place_aligned(x, align))?;
+        let (handle, range) = self.add_guarded(process_id, outer, before, after);
+        if align > 1 {
+            self.aligns.insert(handle.id, align);
         }
+        self.notify(|x| x.on_alloc(handle, &range));
+
+        Ok(handle)
+    }
+
+    /// The range of the heap the block of `handle` is in right now. Like every range in lilac it's
+    /// half-open, the end is the first byte after the block.
+    ///
+    /// It errors if the process doesn't exist (`AllocError::NoSuchProcess`) or if it doesn't
+    /// hold the block (`AllocError::BlockNotFound`).
+    pub fn range(&self, handle: BlockHandle) -> Result<Range<u32>> {
+        let handle = self.strong(handle)?;
+        let idx = self.find(handle)?;
+        Ok(self.allocated[&handle.process_id][idx].range.clone())
+    }
+
+    /// How many processes hold the block of `handle`, including the one of the handle.
+    ///
+    /// It errors like `range()` does.
+    pub fn refcount(&self, handle: BlockHandle) -> Result<u32> {
+        let idx = self.find(handle)?;
+        Ok((*self.allocated[&handle.process_id][idx].refcount).load(Ordering::SeqCst))
+    }
+
+    /// Whether another process holds the block of `handle` too, in which case `merge()`,
+    /// `split()` and `realloc()` error with `AllocError::BlockShared`.
+    ///
+    /// It errors like `range()` does.
+    pub fn is_shared(&self, handle: BlockHandle) -> Result<bool> {
+        Ok(self.refcount(handle)? > 1)
+    }
+
+    /// The handle of the block of a process which `addr` is in, for going from an address a
+    /// guest holds, which may point anywhere inside the block, back to the block.
+    ///
+    /// It errors if the process doesn't exist (`AllocError::NoSuchProcess`) or if none of its
+    /// blocks has `addr` in it (`AllocError::NotOwned`).
+    pub fn handle_at(&self, process_id: Process, addr: u32) -> Result<BlockHandle> {
+        let allocated = match self.allocated.get(&process_id) {
+            Some(allocated) => allocated,
+            None => return Err(self.no_such_process(process_id)),
+        };
+
+        allocated
+            .iter()
+            .find(|x| x.range.contains(&addr))
+            .map(|x| BlockHandle {
+                process_id,
+                id: x.id,
+            })
+            .ok_or(AllocError::NotOwned {
+                process_id,
+                range: addr..addr.saturating_add(1),
+            })
+    }
+
+    /// Whether `addr` is in any of the blocks a process holds, shared ones included, for memory
+    /// protection checks on every access. A process which doesn't exist owns nothing.
+    pub fn owns(&self, process_id: Process, addr: u32) -> bool {
+        self.allocated
+            .get(&process_id)
+            .is_some_and(|allocated| allocated.iter().any(|x| x.range.contains(&addr)))
+    }
+
+    /// Label the block of `handle` with a short name like `"stack"` or `"framebuffer"`, which
+    /// shows up in `stats()` and `leak_report()`. Every process sharing the block sees the same
+    /// label, and it replaces the one the block had before.
+    ///
+    /// It errors if the process doesn't exist (`AllocError::NoSuchProcess`) or if it doesn't
+    /// hold the block (`AllocError::BlockNotFound`).
+    pub fn set_tag(&mut self, handle: BlockHandle, tag: impl Into<String>) -> Result<()> {
+        self.find(handle)?;
+        self.tags.insert(handle.id, tag.into());
+
+        Ok(())
+    }
+
+    /// The label of the block of `handle`, if it has one.
+    ///
+    /// It errors like `set_tag()` does.
+    pub fn tag(&self, handle: BlockHandle) -> Result<Option<&str>> {
+        self.find(handle)?;
+        Ok(self.tags.get(&handle.id).map(|x| x.as_str()))
+    }
+
+    /// Take the label off the block of `handle` and return it.
+    ///
+    /// It errors like `set_tag()` does.
+    pub fn remove_tag(&mut self, handle: BlockHandle) -> Result<Option<String>> {
+        self.find(handle)?;
+        Ok(self.tags.remove(&handle.id))
     }
 
     // this function frees the block if and only if the refcount becomes zero in this free, meaning
     // that it will only remove the memory block from the access list and not put it into the free
     // vector, this means that if a process just holds to a shared memory infinitely it will never
     // free and be a memory leak, very cool!
-    fn free_inner(
-        &mut self,
-        process_id: Process,
-        start_idx: u32,
-        zeroize: bool,
-    ) -> Result<FreeBlock> {
-        let allocated = {
-            if !self.allocated.contains_key(&process_id) {
-                return Err(AllocError::NoSuchProcess);
+    fn free_inner(&mut self, handle: BlockHandle, clear: Clear) -> Result<FreeBlock> {
+        let block_idx = match self.find(handle) {
+            Err(AllocError::BlockNotFound(_)) => {
+                return Err(match self.recently_freed.iter().find(|x| x.0 == handle) {
+                    Some((_, start)) => AllocError::DoubleFree {
+                        process_id: handle.process_id,
+                        start: *start,
+                    },
+                    None => AllocError::BlockNotFound(handle),
+                })
             }
-
-            self.allocated.entry(process_id).or_insert(vec![])
+            found => found?,
         };
 
-        let block = allocated
-            .iter()
-            .enumerate()
-            .find(|x| x.1.range.start == start_idx);
-
-        // because of enumerate the index is .0 and the block is .1
-        if let Some(block_real) = block {
-            let refcount = (*(block_real.1.refcount)).load(Ordering::Relaxed);
-            let block_idx = block_real.0;
-
-            // decrease refcount by 1
-            (*(allocated[block_idx].refcount)).fetch_sub(1, Ordering::SeqCst);
-            let refcount = refcount - 1;
-
-            // remove block from process' access list
-            let block = allocated.swap_remove(block_idx);
-
-            // if the refcount became zero (aka this was the last process holding a reference) then
-            // move it into the free vec
-            if refcount == 0 {
-                if zeroize {
-                    for i in block.range.start..=block.range.end {
+        // the last process holding the block gives its bytes back to be handed out again (and
+        // maybe zeroes them), which would pull them out from under any token claiming them.
+        // otherwise it only can't claim the bytes it lets go of
+        let block = &self.allocated[&handle.process_id][block_idx];
+        if (*block.refcount).load(Ordering::SeqCst) == 1 {
+            let (_, range) = self.block_of(block);
+            self.check_conflicts(&range, true, None)?;
+        } else {
+            self.check_claimed(handle.process_id, &block.range)?;
+        }
+
+        let checked =
+            self.check_block(handle, &self.allocated[&handle.process_id][block_idx].range);
+        // safe to unwrap because `find()` checked that the process exists
+        let allocated = self.allocated.get_mut(&handle.process_id).unwrap();
+
+        // decrease refcount by 1
+        let refcount = (*(allocated[block_idx].refcount)).fetch_sub(1, Ordering::SeqCst) - 1;
+
+        // remove block from process' access list
+        let block = allocated.swap_remove(block_idx);
+        self.unmap_block(handle);
+
+        if self.recently_freed.len() == RECENTLY_FREED {
+            self.recently_freed.pop_front();
+        }
+        self.recently_freed.push_back((handle, block.range.start));
+
+        // other processes can hold the same window, its label only goes once none of them do
+        if block.parent.is_some() && !self.allocated.values().flatten().any(|x| x.id == block.id) {
+            self.tags.remove(&block.id);
+        }
+        // and the range of the block it's part of is only kept for as long as there are windows
+        // into it, the block itself doesn't need it
+        let (id, range) = self.block_of(&block);
+        if let Some(parent) = block.parent {
+            if !self
+                .allocated
+                .values()
+                .flatten()
+                .any(|x| x.parent == Some(parent))
+            {
+                self.parents.remove(&parent);
+            }
+        }
+
+        // if the refcount became zero (aka this was the last process holding a reference) then
+        // move it into the free vec
+        let freed = if refcount == 0 {
+            // the last window to go takes the whole block it's part of along
+            self.tags.remove(&block.id);
+            self.tags.remove(&id);
+            self.protections.remove(&id);
+            self.aligns.remove(&id);
+            self.drop_segment(id);
+
+            // the guards go along with the block
+            let extent = self.extent(id, &range);
+            self.guards.remove(&id);
+
+            match clear {
+                Clear::Keep => {}
+                Clear::Zero => {
+                    for i in extent.clone() {
                         self.heap[i as usize] = 0;
                     }
                 }
-
-                // add the freed block into the free vec
-                let blocklen = block.range.len() as u32 + 1;
-                self.free.push((blocklen, block.range));
-
-                // sort the free vec before checking to merge
-                self.free.sort_unstable_by(|a, b| a.1.start.cmp(&b.1.start));
-
-                // NOTE: this, somehow in some arcane fucking way, checks all the ranges in this
-                // vector to see if they connect (this is possible because we sorted the vector
-                // beforehand, the sort was also unstable because our key would NEVER repeat as it
-                // is the index of a vector) after checking if they connect it adds the indices to
-                // a vector and deduplicates them because in my shitty implementation duplication
-                // is a thing.
-                let mut last_end = 0;
-                let mut indices = vec![];
-                for i in self.free.iter().enumerate() {
-                    let old_last = last_end;
-                    last_end = i.1 .1.start + i.1 .0;
-
-                    if (old_last > 0) && (old_last == i.1 .1.start) {
-                        indices.push(i.0 - 1);
-                        indices.push(i.0);
-                    }
+                Clear::Secure => {
+                    scrub::zeroize(&mut self.heap[extent.start as usize..extent.end as usize])
                 }
-                indices.dedup();
-
-                if !indices.is_empty() {
-                    // safe to unwrap because we know indices is NOT empty, and we can do both first()
-                    // and last() because we know if indices is NOT empty there are at least 2 elements
-                    // because of the last code block which fills indices
-                    let start = self.free[*indices.first().unwrap()].1.start;
-                    let end = self.free[*indices.last().unwrap()].1.end;
-                    let cap = end + 1;
-
-                    // we dont swap remove because it will take the sorted free array and ruin it,
-                    // instead we remove and keep the order, we can't use the indices because the array
-                    // is shifted, so instead we remove the first index with the count of however many
-                    // indices we had (3 works too!)
-                    //
-                    // example with 3 merged at the same time:
-                    // alloc 4 bytes under 0
-                    // alloc 4 bytes under 1
-                    // alloc 4 bytes under 2
-                    //
-                    // [0][0][0][0][1][1][1][1][2][2][2][2]
-                    //
-                    // free 4 bytes under 0
-                    // free 4 bytes under 2
-                    //
-                    // [/][/][/][/][1][1][1][1][/][/][/][/]
-                    //
-                    // free 4 bytes under 1
-                    //
-                    // (memory will be merged as they are all contiguous)
-                    // [-][-][-][-][-][-][-][-][-][-][-][-]
-                    //
-                    // alloc 6 bytes under 0
-                    //
-                    // [0][0][0][0][0][0][-][-][-][-][-][-]
-                    //
-                    // ---
-                    //
-                    // i believe 3 is the most amount of contiguous blocks possible that we would have
-                    // to merge, as this code is run on every free() call there can never be more than
-                    // 3 mergable blocks together at the same time.
-                    for _ in 0..indices.len() {
-                        self.free.remove(indices[0]);
-                    }
+            }
 
-                    self.free.push((cap, start..end));
+            self.release(extent)
+        } else {
+            FreeBlock::RefcountDecreased
+        };
 
-                    return Ok(FreeBlock::FreeMerge(cap));
-                }
+        self.notify(|x| x.on_free(handle, freed));
+        checked.map(|_| freed)
+    }
+
+    // put a block which nobody holds anymore into the free list, merging it with every free block
+    // it's back to back with, walking outwards in address order. freeing always merges so there
+    // shouldn't ever be more than one on each side, but this doesn't rely on it. a block in a
+    // named heap goes back to the free list of that heap, so it never merges across its edge
+    pub(super) fn release(&mut self, range: Range<u32>) -> FreeBlock {
+        if self.poison {
+            self.heap[range.start as usize..range.end as usize].fill(POISON_BYTE);
+        }
+
+        if let Some(buddy) = &mut self.buddy {
+            let order = Buddy::order(range.end - range.start);
+            let merged = buddy.free(self.heap.len() as u32, range.start, order);
 
-                Ok(FreeBlock::Free(blocklen))
+            return if merged == order {
+                FreeBlock::Free(1 << order)
             } else {
-                Ok(FreeBlock::RefcountDecreased)
-            }
+                FreeBlock::FreeMerge(1 << merged)
+            };
+        }
+
+        let (mut start, mut end) = (range.start, range.end);
+        let mut merged = false;
+        let free = self.free_list_at(start);
+
+        while let Some(before) = free.ending_at(start) {
+            free.remove(before.start);
+            start = before.start;
+            merged = true;
+        }
+        while let Some(after) = free.remove(end) {
+            end = after.end;
+            merged = true;
+        }
+
+        free.insert(start..end);
+
+        let cap = end - start;
+        if merged {
+            FreeBlock::FreeMerge(cap)
         } else {
-            Err(AllocError::BlockNotFound)
+            FreeBlock::Free(cap)
         }
     }
 
-    /// Free a block of memory under a process id (but don't zeroize the underlying memory), this
-    /// will need the starting index of the block.
+    /// Free the block of `handle` (but don't zeroize the underlying memory), the handle isn't
+    /// valid anymore afterwards.
     ///
-    /// It errors if it couldn't find the block from the starting index (`AllocError::BlockNotFound`).
-    pub fn free(&mut self, process_id: Process, start_idx: u32) -> Result<FreeBlock> {
-        self.free_inner(process_id, start_idx, false)
+    /// It errors if the process doesn't exist (`AllocError::NoSuchProcess`), if it doesn't
+    /// hold the block (`AllocError::BlockNotFound`), or `AllocError::DoubleFree` if it already
+    /// freed it not too long ago, if a `BorrowToken` of the process claims some of the block,
+    /// or any token does and this is the last process holding it (`AllocError::BorrowConflict`),
+    /// in which case it isn't freed, or if the guards around the block were overwritten
+    /// (`AllocError::Corruption`), in which case the block is still freed since it's the bytes
+    /// around it that are broken.
+    pub fn free(&mut self, handle: BlockHandle) -> Result<FreeBlock> {
+        self.free_inner(handle, Clear::Keep)
+    }
+
+    /// Free the block of a process which `addr` is in, which can be any address inside the
+    /// block, see `handle_at()`.
+    ///
+    /// It errors like `handle_at()` and `free()` do.
+    pub fn free_containing(&mut self, process_id: Process, addr: u32) -> Result<FreeBlock> {
+        let handle = self.handle_at(process_id, addr)?;
+        self.free(handle)
+    }
+
+    /// Free the block of `handle` (and zeroize the underlying memory), the handle isn't valid
+    /// anymore afterwards. In poison mode the memory ends up poisoned rather than zeroed.
+    ///
+    /// The zeroes are plain writes to memory nothing reads afterwards, which the compiler is
+    /// free to leave out, use `free_secure()` for blocks holding secrets.
+    ///
+    /// It errors like `free()` does.
+    pub fn free_clear(&mut self, handle: BlockHandle) -> Result<FreeBlock> {
+        self.free_inner(handle, Clear::Zero)
+    }
+
+    /// Like `free_clear()` but the memory is zeroed with volatile writes, which the compiler
+    /// can't leave out. Only the block's current bytes are zeroed, `scrub_free()` gets the copies
+    /// `realloc()` and `compact()` leave behind when they move it.
+    ///
+    /// It errors like `free_clear()` does.
+    pub fn free_secure(&mut self, handle: BlockHandle) -> Result<FreeBlock> {
+        self.free_inner(handle, Clear::Secure)
+    }
+
+    /// Immutably borrow the whole block of `handle`.
+    ///
+    /// It errors if the process doesn't exist (`AllocError::NoSuchProcess`), if it doesn't
+    /// hold the block (`AllocError::BlockNotFound`), if the block isn't readable
+    /// (`AllocError::PermissionDenied`, see `protect()`), if the guards around the block were
+    /// overwritten (`AllocError::Corruption`) or if a `BorrowToken` claimed some of it
+    /// (`AllocError::BorrowConflict`).
+    pub fn borrow(&self, handle: BlockHandle) -> Result<&[u8]> {
+        let strong = self.strong(handle)?;
+        // `find()` goes first, it's what checks that the process exists
+        let idx = self.find(strong)?;
+        let block = &self.allocated[&strong.process_id][idx];
+        let range = block.range.clone();
+        if !self.allows(block, Access::Read) {
+            return Err(AllocError::PermissionDenied {
+                process_id: handle.process_id,
+                range,
+                access: Access::Read,
+            });
+        }
+
+        self.check_block(handle, &range)?;
+        self.check_conflicts(&range, false, None)?;
+        Ok(&self.heap[range.start as usize..range.end as usize])
     }
 
-    /// Free a block of memory under a process id (and zeroize the underlying memory), this will
-    /// need the starting index of the block.
+    /// Mutably borrow the whole block of `handle`.
     ///
-    /// It errors if it couldn't find the block from the starting index (`AllocError::BlockNotFound`).
-    pub fn free_clear(&mut self, process_id: Process, start_idx: u32) -> Result<FreeBlock> {
-        self.free_inner(process_id, start_idx, true)
+    /// It errors like `borrow()` does, or if the block was shared with the process read-only or
+    /// isn't writable (`AllocError::PermissionDenied`).
+    pub fn borrow_mut(&mut self, handle: BlockHandle) -> Result<&mut [u8]> {
+        let strong = self.strong(handle)?;
+        let idx = self.find(strong)?;
+        let block = &self.allocated[&strong.process_id][idx];
+        let range = block.range.clone();
+        if !self.allows(block, Access::Write) || self.weak_read_only(handle) {
+            return Err(AllocError::PermissionDenied {
+                process_id: handle.process_id,
+                range,
+                access: Access::Write,
+            });
+        }
+
+        self.check_frozen(handle.process_id)?;
+        self.check_block(handle, &range)?;
+        self.check_conflicts(&range, true, None)?;
+        Ok(&mut self.heap[range.start as usize..range.end as usize])
     }
 
     /// Immutably borrow a certain range of the heap from a process, the process must have already
     /// allocated memory beforehand and the range specified must also be within the allocated
     /// memory space of the process.
     ///
-    /// It errors if the process doesn't exist (`AllocError::NoSuchProcess`) and if the specified
-    /// range isn't owned by the process (`AllocError::NotOwned`).
-    pub fn range_borrow(&mut self, process_id: Process, range: Range<u32>) -> Result<&[u8]> {
-        let allocated = {
-            if !self.allocated.contains_key(&process_id) {
-                return Err(AllocError::NoSuchProcess);
-            }
+    /// It errors if the process doesn't exist (`AllocError::NoSuchProcess`), if the specified
+    /// range isn't owned by the process (`AllocError::NotOwned`), if the block isn't readable
+    /// (`AllocError::PermissionDenied`, see `protect()`), or in poison mode if some of
+    /// it was freed (`AllocError::UseAfterFree`), if the guards around the block it's in
+    /// were overwritten (`AllocError::Corruption`) and if a `BorrowToken` claimed some of it
+    /// (`AllocError::BorrowConflict`).
+    pub fn range_borrow(&self, process_id: Process, range: Range<u32>) -> Result<&[u8]> {
+        self.check_range(process_id, &range, false)?;
+
+        Ok(&self.heap[range.start as usize..range.end as usize])
+    }
+
+    // error like `range_borrow()`, or `range_borrow_mut()` if the range is going to be written
+    // to, would for `range`
+    pub(super) fn check_range(
+        &self,
+        process_id: Process,
+        range: &Range<u32>,
+        write: bool,
+    ) -> Result<()> {
+        self.check_owned(process_id, range, write)?;
+        self.check_conflicts(range, write, None)
+    }
+
+    // without std there are no `BorrowToken`s, so nothing can conflict
+    #[cfg(not(feature = "std"))]
+    pub(super) fn check_conflicts(
+        &self,
+        _range: &Range<u32>,
+        _write: bool,
+        _except: Option<u64>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    #[cfg(not(feature = "std"))]
+    pub(super) fn check_claimed(&self, _process_id: Process, _range: &Range<u32>) -> Result<()> {
+        Ok(())
+    }
 
-            self.allocated.entry(process_id).or_insert(vec![])
+    // `check_range()` without looking at the borrow tokens
+    pub(super) fn check_owned(
+        &self,
+        process_id: Process,
+        range: &Range<u32>,
+        write: bool,
+    ) -> Result<()> {
+        let access = if write { Access::Write } else { Access::Read };
+        self.check_access(process_id, range, access)
+    }
+
+    // `check_owned()` for any kind of access
+    pub(super) fn check_access(
+        &self,
+        process_id: Process,
+        range: &Range<u32>,
+        access: Access,
+    ) -> Result<()> {
+        let allocated = match self.allocated.get(&process_id) {
+            Some(allocated) => allocated,
+            None => return Err(self.no_such_process(process_id)),
         };
 
-        if let Some(_found_range) = allocated
+        // the process can hold the range twice through a window, one it may write to is enough
+        let found = allocated
             .iter()
-            .find(|&x| (x.range.start <= range.start) && (x.range.end >= range.end))
-        {
-            // as range end is exclusive we have to add 1 to it, because
-            // all indexable types start from 0 instead of 1
-            Ok(&self.heap[range.start as usize..range.end as usize + 1])
-        } else {
-            Err(AllocError::NotOwned)
+            .filter(|&x| (x.range.start <= range.start) && (x.range.end >= range.end))
+            .max_by_key(|x| self.allows(x, access));
+
+        match found {
+            // nobody owns an empty range, there's no byte in it to tell whose it is
+            Some(_) if range.is_empty() => Err(AllocError::NotOwned {
+                process_id,
+                range: range.clone(),
+            }),
+            Some(found) if !self.allows(found, access) => Err(AllocError::PermissionDenied {
+                process_id,
+                range: range.clone(),
+                access,
+            }),
+            Some(_) if access == Access::Write && self.is_frozen(process_id) => {
+                Err(AllocError::Frozen(process_id))
+            }
+            Some(found) => self.check_block(
+                BlockHandle {
+                    process_id,
+                    id: found.id,
+                },
+                &found.range,
+            ),
+            None => Err(self.not_owned(process_id, range.clone())),
         }
     }
 
@@ -270,92 +943,734 @@ impl Allocator {
     /// allocated memory beforehand and the range specified must also be within the allocated
     /// memory space of the process.
     ///
-    /// It errors if the process doesn't exist (`AllocError::NoSuchProcess`) and if the specified
-    /// range isn't owned by the process (`AllocError::NotOwned`).
+    /// It errors like `range_borrow()` does, or if the process can only read the range or the
+    /// block isn't writable (`AllocError::PermissionDenied`).
     ///
     /// NOTE: The given range **must** be within a single allocated block, be it shared or owned.
-    /// If you would like to have one contiguous range, either free all the back to back blocks and
-    /// allocate them again, or call `realloc`.
+    /// If you would like to have one contiguous range, either use `range_borrow_spanning_mut()`,
+    /// free all the back to back blocks and allocate them again, or call `realloc`.
     pub fn range_borrow_mut(
         &mut self,
         process_id: Process,
         range: Range<u32>,
     ) -> Result<&mut [u8]> {
-        let allocated = {
-            if !self.allocated.contains_key(&process_id) {
-                return Err(AllocError::NoSuchProcess);
+        self.check_range(process_id, &range, true)?;
+
+        Ok(&mut self.heap[range.start as usize..range.end as usize])
+    }
+
+    /// Hex dump a range of the heap owned by a process, see `range_borrow()` for which ranges
+    /// are accepted.
+    ///
+    /// It errors like `range_borrow()` does.
+    pub fn dump(&self, process_id: Process, range: Range<u32>) -> Result<String> {
+        let start = range.start;
+        let bytes = self.range_borrow(process_id, range)?;
+
+        Ok(hexdump::hexdump(bytes, start))
+    }
+
+    /// Share the block of `handle` with another process, which gets a handle of its own to it.
+    /// The block is only freed once every process holding it freed it.
+    ///
+    /// It errors if either process doesn't exist (`AllocError::NoSuchProcess`), if the source
+    /// process doesn't hold the block (`AllocError::BlockNotFound`), if the target is the source
+    /// process itself (`AllocError::ShareWithSelf`) or if it already holds the block
+    /// (`AllocError::AlreadyShared`, with the handle it has), since a second reference would
+    /// only keep the block alive after the process thinks it freed it.
+    pub fn share(&mut self, handle: BlockHandle, target_process: Process) -> Result<BlockHandle> {
+        self.share_inner(handle, target_process, false)
+    }
+
+    /// Like `share()` but the other process can only read the block, `borrow_mut()` and
+    /// `range_borrow_mut()` refuse to hand it out to it (`AllocError::PermissionDenied`).
+    ///
+    /// It errors like `share()` does.
+    pub fn share_read_only(
+        &mut self,
+        handle: BlockHandle,
+        target_process: Process,
+    ) -> Result<BlockHandle> {
+        self.share_inner(handle, target_process, true)
+    }
+
+    pub(super) fn share_inner(
+        &mut self,
+        handle: BlockHandle,
+        target_process: Process,
+        read_only: bool,
+    ) -> Result<BlockHandle> {
+        // instead of cloning the vec we clone the memrange, less overhead this way
+        let idx = self.find(handle)?;
+        let memrange = self.allocated[&handle.process_id][idx].clone();
+
+        if target_process == handle.process_id {
+            return Err(AllocError::ShareWithSelf(handle));
+        }
+
+        let allocated_target = {
+            if !self.allocated.contains_key(&target_process) {
+                return Err(self.no_such_process(target_process));
             }
 
-            self.allocated.entry(process_id).or_insert(vec![])
+            self.allocated.entry(target_process).or_insert(vec![])
         };
 
-        if let Some(_found_range) = allocated
-            .iter()
-            .find(|&x| (x.range.start <= range.start) && (x.range.end >= range.end))
-        {
-            // as range end is exclusive we have to add 1 to it, because
-            // all indexable types start from 0 instead of 1
-            Ok(&mut self.heap[range.start as usize..range.end as usize + 1])
-        } else {
-            Err(AllocError::NotOwned)
+        if allocated_target.iter().any(|x| x.id == memrange.id) {
+            return Err(AllocError::AlreadyShared(BlockHandle {
+                process_id: target_process,
+                id: memrange.id,
+            }));
         }
+
+        (*memrange.refcount).fetch_add(1, Ordering::SeqCst);
+        let refcount = Arc::clone(&memrange.refcount);
+
+        // sharing a window on shares the same window, and a process that can only read the
+        // block can't hand out more than that
+        let mut shared = MemRange::new(memrange.id, refcount, memrange.range);
+        shared.read_only = read_only || memrange.read_only;
+        shared.parent = memrange.parent;
+        allocated_target.push(shared);
+
+        let shared = BlockHandle {
+            process_id: target_process,
+            id: memrange.id,
+        };
+        // a weak share the process had is superseded by the real one
+        self.weak.remove(&shared);
+        self.notify(|x| x.on_share(handle, shared));
+
+        Ok(shared)
     }
 
-    pub fn share(
+    /// Share the bytes of `range` of the block of `handle` with another
+    /// process, which gets a handle to a window into the block, so a process can hand out part
+    /// of a buffer without exposing all of it. The window can be shared on like any other block.
+    ///
+    /// The window keeps the whole block alive, it's only freed once the block and all of the
+    /// windows into it were freed. It can't be merged, split or resized, and neither can the
+    /// block as long as there are windows into it.
+    ///
+    /// It errors like `share()` does, except that the target holding the block already is fine,
+    /// or if `range` isn't inside the block (`AllocError::NotOwned`).
+    pub fn share_range(
         &mut self,
-        source_process: Process,
+        handle: BlockHandle,
         target_process: Process,
-        start_idx: u32,
-    ) -> Result<()> {
-        let allocated_source = {
-            if !self.allocated.contains_key(&source_process) {
-                return Err(AllocError::NoSuchProcess);
-            }
+        range: Range<u32>,
+    ) -> Result<BlockHandle> {
+        let idx = self.find(handle)?;
+        let memrange = self.allocated[&handle.process_id][idx].clone();
+
+        if range.is_empty() || range.start < memrange.range.start || range.end > memrange.range.end
+        {
+            return Err(AllocError::NotOwned {
+                process_id: handle.process_id,
+                range,
+            });
+        }
+        if target_process == handle.process_id {
+            return Err(AllocError::ShareWithSelf(handle));
+        }
+        if !self.allocated.contains_key(&target_process) {
+            return Err(self.no_such_process(target_process));
+        }
+
+        // a window into a window is a window into the same block
+        let (parent, parent_range) = self.block_of(&memrange);
+        self.parents.insert(parent, parent_range);
+
+        (*memrange.refcount).fetch_add(1, Ordering::SeqCst);
+        let refcount = Arc::clone(&memrange.refcount);
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut window = MemRange::new(id, refcount, range);
+        window.parent = Some(parent);
+        window.read_only = memrange.read_only;
+        // safe to unwrap because we just checked that the process exists
+        self.allocated
+            .get_mut(&target_process)
+            .unwrap()
+            .push(window);
 
-            // safe to unwrap because we checked whether it exists
-            self.allocated.get(&source_process).unwrap()
+        let shared = BlockHandle {
+            process_id: target_process,
+            id,
         };
+        self.notify(|x| x.on_share(handle, shared));
 
-        // instead of cloning the vec we clone the memrange, less overhead this way
-        let memrange = {
-            if let Some(found_range) = allocated_source
-                .iter()
-                .find(|&x| x.range.start <= start_idx)
-            {
-                found_range.clone()
+        Ok(shared)
+    }
+
+    /// Move the block starting at `start` from one process to another, for handing a buffer
+    /// off in a message passing design. Unlike sharing and freeing it the refcount stays the
+    /// same, the source process just doesn't hold the block anymore and the target holds it
+    /// instead, with the same permissions. It returns the handle the target got for it.
+    ///
+    /// It errors if either process doesn't exist (`AllocError::NoSuchProcess`), if no block of
+    /// the source process starts at `start` (`AllocError::NotOwned`), if the target already
+    /// holds the block (`AllocError::AlreadyShared`, with the handle it has) or if a
+    /// `BorrowToken` of the source process claims some of it (`AllocError::BorrowConflict`).
+    pub fn transfer(&mut self, src: Process, dst: Process, start: u32) -> Result<BlockHandle> {
+        let idx = self
+            .allocated
+            .get(&src)
+            .ok_or_else(|| self.no_such_process(src))?
+            .iter()
+            .position(|x| x.range.start == start)
+            .ok_or(AllocError::NotOwned {
+                process_id: src,
+                range: start..start.saturating_add(1),
+            })?;
+        let id = self.allocated[&src][idx].id;
+
+        let target = self
+            .allocated
+            .get(&dst)
+            .ok_or_else(|| self.no_such_process(dst))?;
+        let handle = BlockHandle {
+            process_id: src,
+            id,
+        };
+        let transferred = BlockHandle {
+            process_id: dst,
+            id,
+        };
+
+        if src == dst {
+            return Ok(handle);
+        }
+        if target.iter().any(|x| x.id == id) {
+            return Err(AllocError::AlreadyShared(transferred));
+        }
+        // the tokens of the source would go on claiming the block from under the target
+        self.check_claimed(src, &self.allocated[&src][idx].range)?;
+
+        // safe to unwrap both because we just checked that the processes exist
+        let block = self.allocated.get_mut(&src).unwrap().swap_remove(idx);
+        self.allocated.get_mut(&dst).unwrap().push(block);
+        self.unmap_block(handle);
+        // a weak share the target had is superseded by the block itself
+        self.weak.remove(&transferred);
+
+        self.notify(|x| x.on_transfer(handle, transferred));
+        Ok(transferred)
+    }
+
+    /// Merge two back to back blocks held by the same process into a single block, the blocks
+    /// can be given in any order. The handle of the one with the lower address now refers to
+    /// the merged block and keeps its label and protection, the other one isn't valid anymore.
+    ///
+    /// It will return the `Range<u32>` of the merged block, which has to be freed as a whole
+    /// from now on.
+    ///
+    /// It errors if the process doesn't exist (`AllocError::NoSuchProcess`), if either of the
+    /// blocks isn't one of its blocks (`AllocError::BlockNotFound`), if either of them is shared
+    /// with another process (`AllocError::BlockShared`), since merging would hand the other
+    /// process memory it never had, or if they aren't back to back (`AllocError::NotContiguous`),
+    /// which blocks in different named heaps never are, in which case `realloc` is how you get
+    /// one contiguous block. In buddy mode it always errors (`AllocError::Unsupported`).
+    pub fn merge(&mut self, first: BlockHandle, second: BlockHandle) -> Result<Range<u32>> {
+        if self.buddy.is_some() {
+            return Err(AllocError::Unsupported);
+        }
+
+        let second = BlockHandle {
+            process_id: first.process_id,
+            ..second
+        };
+        let (first_idx, second_idx) = (self.find_owned(first)?, self.find_owned(second)?);
+
+        let allocated = &self.allocated[&first.process_id];
+        let (first_idx, second_idx) =
+            if allocated[first_idx].range.start < allocated[second_idx].range.start {
+                (first_idx, second_idx)
             } else {
-                return Err(AllocError::NotOwned);
+                (second_idx, first_idx)
+            };
+        let (low, high) = (allocated[first_idx].clone(), allocated[second_idx].clone());
+
+        // the guards between the blocks end up inside the merged block, so they're checked
+        // while they still mean something
+        for block in [&low, &high] {
+            let handle = BlockHandle {
+                process_id: first.process_id,
+                id: block.id,
+            };
+            self.check_block(handle, &block.range)?;
+        }
+
+        // the second block has to start right where the first one ends, this also rules out
+        // merging a block with itself
+        let (low_extent, high_extent) = (
+            self.extent(low.id, &low.range),
+            self.extent(high.id, &high.range),
+        );
+        // and a block can't reach out of the named heap it's in
+        if low_extent.end != high_extent.start
+            || self.heap_at(low_extent.start) != self.heap_at(high_extent.start)
+        {
+            return Err(AllocError::NotContiguous {
+                first: low.range,
+                second: high.range,
+            });
+        }
+
+        let range = low.range.start..high.range.end;
+        if self.guards.remove(&high.id).is_some() || self.guards.contains_key(&low.id) {
+            self.guards
+                .insert(low.id, low_extent.start..high_extent.end);
+        }
+
+        // safe to unwrap because `find()` checked that the process exists
+        let allocated = self.allocated.get_mut(&first.process_id).unwrap();
+        allocated[first_idx].range = range.clone();
+        let removed = allocated.swap_remove(second_idx);
+        self.tags.remove(&removed.id);
+        self.protections.remove(&removed.id);
+        self.aligns.remove(&removed.id);
+        self.drop_segment(removed.id);
+        self.unmap_block(BlockHandle {
+            process_id: first.process_id,
+            id: removed.id,
+        });
+
+        // the handles were given in any order, the observer gets told which one survived
+        let (first, second) = if first.id == removed.id {
+            (second, first)
+        } else {
+            (first, second)
+        };
+        self.notify(|x| x.on_merge(first, second, &range));
+
+        Ok(range)
+    }
+
+    /// Split the block of `handle` in two at `offset` bytes from its start, the inverse of
+    /// `merge()`. Both halves are blocks of their own from then on and get freed separately.
+    ///
+    /// The handle now refers to the first half, it will return the handle of the second half.
+    /// Both halves keep the label and the protection of the block, only the first one keeps its
+    /// alignment (see `alloc_aligned()`).
+    ///
+    /// It errors if the process doesn't exist (`AllocError::NoSuchProcess`), if the block isn't
+    /// one of its blocks (`AllocError::BlockNotFound`), if it's shared with another process
+    /// (`AllocError::BlockShared`), since the other process still holds the whole range, or if
+    /// either half would be empty (`AllocError::BadSplit`). In buddy mode it always errors
+    /// (`AllocError::Unsupported`).
+    pub fn split(&mut self, handle: BlockHandle, offset: u32) -> Result<BlockHandle> {
+        if self.buddy.is_some() {
+            return Err(AllocError::Unsupported);
+        }
+
+        let idx = self.find_owned(handle)?;
+
+        // the last offset which still leaves a byte for the second half is one before the size
+        let range = self.allocated[&handle.process_id][idx].range.clone();
+        if offset == 0 || offset >= range.end - range.start {
+            return Err(AllocError::BadSplit {
+                size: range.end - range.start,
+                offset,
+            });
+        }
+
+        // safe to unwrap because `find()` checked that the process exists
+        let allocated = self.allocated.get_mut(&handle.process_id).unwrap();
+        allocated[idx].range = range.start..range.start + offset;
+
+        let second = self.add_block(handle.process_id, range.start + offset..range.end);
+        if let Some(tag) = self.tags.get(&handle.id).cloned() {
+            self.tags.insert(second.id, tag);
+        }
+        if let Some(protection) = self.protections.get(&handle.id).copied() {
+            self.protections.insert(second.id, protection);
+        }
+
+        // there are no guards between the halves, each one keeps the guard on its own side. a
+        // half of a block that was split before may have no guard on either side, which is just
+        // a block without guards
+        if let Some(outer) = self.guards.remove(&handle.id) {
+            let mid = range.start + offset;
+            if outer.start < range.start {
+                self.guards.insert(handle.id, outer.start..mid);
+            }
+            if outer.end > range.end {
+                self.guards.insert(second.id, mid..outer.end);
+            }
+        }
+
+        self.notify(|x| x.on_split(handle, second));
+        Ok(second)
+    }
+
+    /// Resize the block of `handle` to `size` bytes, keeping its contents up to the smaller of
+    /// the two sizes.
+    ///
+    /// Shrinking always happens in place and the bytes past the new end are freed. Growing
+    /// happens in place too if the block is followed by a free block with enough room for the
+    /// extra bytes, or by the end of the heap, otherwise a new block is allocated and the contents
+    /// are copied over. It will return the new `Range<u32>` of the block along with whether it
+    /// moved, the handle stays the same either way. A block in a named heap only ever moves
+    /// inside of it, see `create_heap()`. In buddy mode it only stays in place if `size` rounds up
+    /// to the same power of two as the old size.
+    ///
+    /// It errors if the process doesn't exist (`AllocError::NoSuchProcess`), if the block isn't
+    /// one of its blocks (`AllocError::BlockNotFound`), if it's shared with another process
+    /// (`AllocError::BlockShared`), since the other process would be left holding the old range,
+    /// if `size` is zero (`AllocError::ZeroSize`), which is what `free()` is for, or if the block
+    /// has to move and there's no room for it (`AllocError::OutOfMemory`, or
+    /// `AllocError::HeapFull` in a named heap), or if a `BorrowToken` claims any of the bytes it
+    /// moves away from or shrinks off (`AllocError::BorrowConflict`), in which case it's left as
+    /// it was.
+    pub fn realloc(&mut self, handle: BlockHandle, size: u32) -> Result<(Range<u32>, bool)> {
+        self.relieve(|x| x.realloc_inner(handle, size))
+    }
+
+    fn realloc_inner(&mut self, handle: BlockHandle, size: u32) -> Result<(Range<u32>, bool)> {
+        if size == 0 {
+            return Err(AllocError::ZeroSize);
+        }
+
+        let idx = self.find_owned(handle)?;
+
+        let old_range = self.allocated[&handle.process_id][idx].range.clone();
+        self.check_block(handle, &old_range)?;
+        self.check_group(
+            handle.process_id,
+            size.saturating_sub(old_range.end - old_range.start),
+        )?;
+
+        // everything below works on the block along with its guards, which get written again
+        // wherever it ends up
+        let old = self.extent(handle.id, &old_range);
+        let (before, after) = (old_range.start - old.start, old.end - old_range.end);
+        let size = size
+            .checked_add(before + after)
+            .ok_or_else(|| self.out_of_memory(size))?;
+        let old_size = old.end - old.start;
+        let end = old.start + size;
+        // a block in a named heap only ever grows into free bytes of that heap
+        let heap = self.heap_at(old.start).map(String::from);
+
+        let in_place = if self.buddy.is_some() {
+            // the block is really a power of two long, so anything rounding up to the same one
+            // still fits, but a smaller one would leave the rest of the block behind on free
+            Buddy::order(size) == Buddy::order(old_size)
+        } else if size <= old_size {
+            if size < old_size {
+                // a token would be left claiming the bytes given back
+                self.check_conflicts(&(end..old.end), true, None)?;
+                self.release(end..old.end);
+            }
+
+            true
+        } else {
+            let extra = size - old_size;
+            let heap_end = self.heap.len() as u32;
+            let at_end = heap.is_none() && self.fits(end as usize);
+
+            match self.free_list_at(old.start).get(old.end) {
+                Some(next) if next.end - next.start >= extra => {
+                    // keep what we don't need as a smaller free block
+                    let free = self.free_list_at(old.start);
+                    free.remove(next.start);
+                    if next.end > end {
+                        free.insert(end..next.end);
+                    }
+                    true
+                }
+                // the free block is too small but it's the last one on the heap, so we take all
+                // of it and push the rest
+                // if the heap can't grow after all the block has to move
+                Some(next) if next.end == heap_end && at_end && self.heap.resize(end as usize) => {
+                    self.free.remove(next.start);
+                    true
+                }
+                None if old.end == heap_end && at_end => self.heap.resize(end as usize),
+                _ => false,
             }
         };
 
-        let allocated_target = {
-            if !self.allocated.contains_key(&target_process) {
-                return Err(AllocError::NoSuchProcess);
+        let range = if in_place {
+            old.start..end
+        } else {
+            // a token would be left claiming the bytes the block moved away from
+            self.check_conflicts(&old_range, true, None)?;
+            // place the new block before giving back the old one so they can't overlap. aligned
+            // blocks never come from a named heap
+            let range = match (heap.as_deref(), self.aligns.get(&handle.id)) {
+                (None, Some(&align)) => self.place_aligned(size, align)?,
+                (heap, _) => self.place_in(heap, size)?,
+            };
+            // the guard after the block is left behind, it's written again at the new end
+            let len = old_size.min(size) - after;
+            self.heap.copy_within(
+                old.start as usize..(old.start + len) as usize,
+                range.start as usize,
+            );
+            self.release(old);
+
+            range
+        };
+
+        // the old extent is on the free list now, so the guards have to go with the block
+        let outer = range;
+        let range = outer.start + before..outer.end - after;
+        self.guards.remove(&handle.id);
+        if before + after > 0 {
+            self.write_guards(&outer, &range);
+            self.guards.insert(handle.id, outer);
+        }
+
+        let old_len = old_range.end - old_range.start;
+        if range.end - range.start > old_len {
+            self.fill_new(range.start + old_len..range.end);
+        }
+
+        // safe to unwrap because `find()` checked that the process exists
+        let allocated = self.allocated.get_mut(&handle.process_id).unwrap();
+        allocated[idx].range = range.clone();
+
+        self.notify(|x| x.on_realloc(handle, &old_range, &range));
+        Ok((range, !in_place))
+    }
+
+    /// Allocate a block of `size` bytes under a process id and hand it out in pieces with a
+    /// bump pointer, see `Arena`.
+    ///
+    /// It errors like `alloc()` does.
+    pub fn arena(&mut self, process_id: Process, size: u32) -> Result<Arena> {
+        Arena::new(self, process_id, size)
+    }
+
+    /// Move every allocated block as far towards the start of the heap as it goes, keeping their
+    /// order, so all the free holes between them become one free block at the end of the heap.
+    /// Blocks in a named heap only move towards the start of it instead, which leaves a free
+    /// block at the end of each named heap and one before each of them too, see `create_heap()`.
+    ///
+    /// The contents of the blocks move along with them and handles stay valid, but ranges taken
+    /// from before have to be looked up again with `range()` (or `Arena::relocate()`), the
+    /// returned `Compaction` tells which blocks moved.
+    ///
+    /// It errors in buddy mode (`AllocError::Unsupported`), where blocks can't move freely, or if
+    /// a `BorrowToken` claims some of a block which would have to move
+    /// (`AllocError::BorrowConflict`), in which case nothing moves.
+    pub fn compact(&mut self) -> Result<Compaction> {
+        if self.buddy.is_some() {
+            return Err(AllocError::Unsupported);
+        }
+
+        // shared blocks show up once for every process holding them, they still only move once.
+        // blocks move along with their guards, but the starts in `moved` are of the blocks
+        let mut blocks: Vec<(Range<u32>, u32, u64)> = self
+            .allocated
+            .values()
+            .flatten()
+            .map(|x| {
+                let (id, range) = self.block_of(x);
+                (self.extent(id, &range), range.start, id)
+            })
+            .collect();
+        blocks.sort_unstable_by_key(|x| x.0.start);
+        blocks.dedup();
+
+        // blocks never leave the named heap they're in, so every named heap and every stretch
+        // between them is compacted on its own and ends up with a free block of its own. where
+        // everything goes is worked out first, since nothing may move while a token claims it
+        let mut compaction = Compaction::default();
+        // the blocks which move along with where to, and the free blocks left, which are the one
+        // at the end of each zone and the bytes aligned blocks skip to stay aligned
+        let (mut moves, mut free) = (vec![], vec![]);
+        let mut blocks = blocks.into_iter().peekable();
+        for zone in self.zones() {
+            // where the next block goes and where the last one ended before moving it
+            let (mut next, mut old_next) = (zone.start, zone.start);
+            while let Some((block, start, id)) = blocks.next_if(|x| x.0.start < zone.end) {
+                // the block is aligned where it is, so this never goes past its start
+                let aligned = next.next_multiple_of(self.aligns.get(&id).copied().unwrap_or(1));
+                if aligned > next {
+                    free.push(next..aligned);
+                }
+                compaction.reclaimed += block.start - old_next - (aligned - next);
+                old_next = block.end;
+                next = aligned;
+
+                if block.start != next {
+                    self.check_conflicts(&block, true, None)?;
+                    compaction.moved.push((start, next + (start - block.start)));
+                    moves.push((block.clone(), next, id));
+                }
+
+                next += block.end - block.start;
             }
 
-            self.allocated.entry(target_process).or_insert(vec![])
+            if next < zone.end {
+                free.push(next..zone.end);
+            }
+        }
+
+        self.free = FreeList::new();
+        for heap in self.heaps.values_mut() {
+            heap.free = FreeList::new();
+        }
+
+        for (block, next, id) in moves {
+            // copy_within is fine with the ranges overlapping
+            self.heap
+                .copy_within(block.start as usize..block.end as usize, next as usize);
+            if let Some(outer) = self.guards.get_mut(&id) {
+                *outer = next..next + (block.end - block.start);
+            }
+        }
+
+        for range in free {
+            // what's left are the old copies of the blocks which moved
+            if self.poison {
+                self.heap[range.start as usize..range.end as usize].fill(POISON_BYTE);
+            }
+            self.free_list_at(range.start).insert(range);
+        }
+
+        // windows move by as much as the block they're part of
+        for block in self.allocated.values_mut().flatten() {
+            let start = match block.parent {
+                Some(parent) => self.parents[&parent].start,
+                None => block.range.start,
+            };
+            let moved_by = start - compaction.new_start(start);
+            block.range = block.range.start - moved_by..block.range.end - moved_by;
+        }
+        for range in self.parents.values_mut() {
+            let start = compaction.new_start(range.start);
+            *range = start..start + (range.end - range.start);
+        }
+
+        self.notify(|x| x.on_compact(&compaction));
+        Ok(compaction)
+    }
+
+    /// Cut the free space at the end of the heap off and give the memory back, so the heap
+    /// doesn't keep its peak size forever. `compact()` first to get all the free space there.
+    ///
+    /// It will return how many bytes the heap got shorter by. In buddy mode the heap stays a
+    /// power of two long, so it's only ever halved.
+    pub fn trim(&mut self) -> u32 {
+        let len = self.heap.len() as u32;
+
+        let new_len = if let Some(buddy) = &mut self.buddy {
+            buddy.trim(len)
+        } else {
+            match self.free.ending_at(len) {
+                Some(free) => {
+                    self.free.remove(free.start);
+                    free.start
+                }
+                None => len,
+            }
         };
 
-        (*memrange.refcount).fetch_add(1, Ordering::SeqCst);
-        let refcount = Arc::clone(&memrange.refcount);
+        self.heap.truncate(new_len as usize);
+        self.heap.shrink_to_fit();
 
-        allocated_target.push(MemRange::new(refcount, memrange.range));
-        //Ok(&self.heap[range.start as usize..range.end as usize + 1])
-        Ok(())
+        let trimmed = len - new_len;
+        self.notify(|x| x.on_trim(trimmed));
+
+        trimmed
     }
 
+    /// Free every block the process holds and unregister it, for when the process exits.
+    ///
+    /// Shared blocks work like freeing them one by one does: the process lets go of its
+    /// reference and the refcount goes down, but the block is only reclaimed if nobody else
+    /// holds it anymore, so other processes never lose memory they still hold. Its weak shares
+    /// are dropped along with its address space, it stops waiting in `futex_wait()` and it
+    /// leaves its group. The processes it started with `register_child()` are cleaned up first,
+    /// along with theirs.
+    ///
+    /// It errors if the process doesn't exist (`AllocError::NoSuchProcess`), if a `BorrowToken`
+    /// claims any of the bytes freeing its blocks one by one would give up
+    /// (`AllocError::BorrowConflict`, see `free()`), in which case nothing is freed and the
+    /// process stays registered, or if the guards around any of its blocks were overwritten
+    /// (`AllocError::Corruption`), in which case every block is still freed and the process is
+    /// still unregistered.
     pub fn clean_process(&mut self, process_id: Process) -> Result<()> {
-        if !self.allocated.contains_key(&process_id) {
-            return Err(AllocError::NoSuchProcess);
-        }
+        self.clean_process_inner(process_id, Clear::Keep)
+    }
+
+    /// Like `clean_process()` but the blocks which get reclaimed are zeroed like `free_clear()`
+    /// does, the shared ones other processes still hold are left alone.
+    ///
+    /// It errors like `clean_process()` does.
+    pub fn clean_process_clear(&mut self, process_id: Process) -> Result<()> {
+        self.clean_process_inner(process_id, Clear::Zero)
+    }
 
-        let vec = self.allocated[&process_id].clone();
+    fn clean_process_inner(&mut self, process_id: Process, clear: Clear) -> Result<()> {
+        let handles: Vec<BlockHandle> = self.blocks(process_id)?.map(|x| x.handle).collect();
+        self.check_clean(process_id)?;
+
+        // a block with broken guards is still freed, so the rest of them are freed too before
+        // passing the error on
+        let mut result = Ok(());
+
+        // the children go first, so the blocks they share with the process are reclaimed along
+        // with the ones of the process
+        let children: Vec<Process> = self.children(process_id).collect();
+        for child in children {
+            result = result.and(self.clean_process_inner(child, clear));
+        }
 
-        for block in vec {
-            self.free(process_id, block.range.start)?;
+        for handle in handles {
+            let freed = self.free_inner(handle, clear);
+            result = result.and(freed.map(|_| ()));
         }
 
         self.allocated.remove(&process_id);
+        self.spawned_by.remove(&process_id);
+        self.spaces.remove(&process_id);
+        self.weak.retain(|x, _| x.process_id != process_id);
+        self.cancel_wait(process_id);
+        for group in self.groups.values_mut() {
+            group.members.remove(&process_id);
+        }
+        result
+    }
+
+    // error like `free()` would for any of the blocks cleaning up the process and its children
+    // lets go of, before a single one of them is freed
+    fn check_clean(&self, process_id: Process) -> Result<()> {
+        let mut cleaned = vec![process_id];
+        let mut idx = 0;
+        while idx < cleaned.len() {
+            cleaned.extend(self.children(cleaned[idx]));
+            idx += 1;
+        }
+
+        // a block is given back once all of its holders are cleaned up, windows count towards
+        // the block they're part of like they do in the refcount
+        let mut holders: BTreeMap<u64, u32> = BTreeMap::new();
+        for block in cleaned
+            .iter()
+            .filter_map(|x| self.allocated.get(x))
+            .flatten()
+        {
+            *holders.entry(self.block_of(block).0).or_default() += 1;
+        }
+
+        for process_id in &cleaned {
+            for block in self.allocated.get(process_id).into_iter().flatten() {
+                let (id, range) = self.block_of(block);
+                if holders[&id] == (*block.refcount).load(Ordering::SeqCst) {
+                    self.check_conflicts(&range, true, None)?;
+                } else {
+                    self.check_claimed(*process_id, &block.range)?;
+                }
+            }
+        }
+
         Ok(())
     }
 }
@@ -0,0 +1,109 @@
+use core::ops::Range;
+
+use super::{AllocError, Allocator, BlockHandle, FreeBlock, Process, Result};
+
+/// One block owned by a process which gets handed out in pieces by bumping a pointer, all of
+/// them are given back at once with `reset()` or `release()`.
+///
+/// Allocating from an arena is just an addition and a compare, so it's much faster than going
+/// through the `Allocator` for lots of short lived scratch allocations. The pieces are ranges
+/// inside the block, so they are borrowed through `Allocator::range_borrow()` like any other
+/// range the process owns.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Arena {
+    handle: BlockHandle,
+    // where the block was the last time we looked, so handing out pieces doesn't need the
+    // allocator
+    block: Range<u32>,
+    // the next byte to hand out, as an offset into the block
+    next: u32,
+}
+
+impl Arena {
+    /// Allocate a block of `size` bytes under a process id for the arena.
+    ///
+    /// It errors like `Allocator::alloc()` does.
+    pub fn new(allocator: &mut Allocator, process_id: Process, size: u32) -> Result<Self> {
+        let handle = allocator.alloc(process_id, size)?;
+        // safe to unwrap because the block was just allocated
+        let block = allocator.range(handle).unwrap();
+
+        Ok(Self {
+            handle,
+            block,
+            next: 0,
+        })
+    }
+
+    pub fn process_id(&self) -> Process {
+        self.handle.process_id()
+    }
+
+    /// The handle of the block of the arena.
+    pub fn handle(&self) -> BlockHandle {
+        self.handle
+    }
+
+    /// The whole block of the arena.
+    pub fn block(&self) -> Range<u32> {
+        self.block.clone()
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.block.end - self.block.start
+    }
+
+    /// How many bytes were handed out since the last reset.
+    pub fn used(&self) -> u32 {
+        self.next
+    }
+
+    pub fn remaining(&self) -> u32 {
+        self.capacity() - self.next
+    }
+
+    /// Hand out the next `size` bytes of the block.
+    ///
+    /// It errors if `size` is zero (`AllocError::ZeroSize`) or if there aren't that many bytes
+    /// left (`AllocError::ArenaFull`), in which case nothing is handed out.
+    pub fn alloc(&mut self, size: u32) -> Result<Range<u32>> {
+        if size == 0 {
+            return Err(AllocError::ZeroSize);
+        }
+        if size > self.remaining() {
+            return Err(AllocError::ArenaFull {
+                size,
+                remaining: self.remaining(),
+            });
+        }
+
+        let start = self.block.start + self.next;
+        self.next += size;
+
+        Ok(start..start + size)
+    }
+
+    /// Take back everything handed out so far, the block stays allocated so the arena can be
+    /// used again. The ranges handed out before are still inside the block, but they'll overlap
+    /// with the ones handed out next.
+    pub fn reset(&mut self) {
+        self.next = 0;
+    }
+
+    /// Follow the block of the arena to wherever `Allocator::compact()` moved it, the pieces
+    /// handed out before moved by the same amount.
+    ///
+    /// It errors like `Allocator::range()` does, if the block was freed behind the back of the
+    /// arena.
+    pub fn relocate(&mut self, allocator: &Allocator) -> Result<()> {
+        self.block = allocator.range(self.handle)?;
+        Ok(())
+    }
+
+    /// Free the block of the arena, and with it everything handed out from it.
+    ///
+    /// It errors like `Allocator::free()` does.
+    pub fn release(self, allocator: &mut Allocator) -> Result<FreeBlock> {
+        allocator.free(self.handle)
+    }
+}
@@ -0,0 +1,172 @@
+use core::ops::Range;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::{Access, AllocError, Allocator, Process, Result};
+
+// the borrows of an `Allocator` which are still live, shared with every `BorrowToken` so a
+// token can take itself out when it's dropped
+#[derive(Debug, Default)]
+pub(super) struct Borrows {
+    next_id: u64,
+    // the id, process, range and whether it's mutable of every live token
+    live: Vec<(u64, Process, Range<u32>, bool)>,
+}
+
+// a poisoned lock only means something panicked while holding it, none of the changes made
+// under it can be left halfway
+fn lock(borrows: &Mutex<Borrows>) -> MutexGuard<'_, Borrows> {
+    borrows.lock().unwrap_or_else(|x| x.into_inner())
+}
+
+/// A borrow of a range of a process which is tracked until the token is dropped, see
+/// `Allocator::track_borrow()`.
+///
+/// The borrow checker only keeps borrows from aliasing for as long as they're borrowing the
+/// allocator, a token lets one component claim a range for longer than that and keeps every
+/// other component from writing to it (or from reading it, for a mutable token) in the
+/// meantime. The bytes are borrowed through the token with `Allocator::borrow_token()`.
+#[derive(Debug)]
+pub struct BorrowToken {
+    borrows: Arc<Mutex<Borrows>>,
+    id: u64,
+    process_id: Process,
+    range: Range<u32>,
+    mutable: bool,
+}
+
+impl BorrowToken {
+    pub fn process_id(&self) -> Process {
+        self.process_id
+    }
+
+    pub fn range(&self) -> Range<u32> {
+        self.range.clone()
+    }
+
+    /// Whether the token can write to the range, see `Allocator::track_borrow_mut()`.
+    pub fn is_mut(&self) -> bool {
+        self.mutable
+    }
+}
+
+impl Drop for BorrowToken {
+    fn drop(&mut self) {
+        lock(&self.borrows).live.retain(|x| x.0 != self.id);
+    }
+}
+
+impl Allocator {
+    // error if a live token overlaps `range` which a borrow of it can't coexist with, which is
+    // any token for writing and a mutable one for reading. `except` is the token doing the
+    // borrowing itself
+    pub(super) fn check_conflicts(
+        &self,
+        range: &Range<u32>,
+        write: bool,
+        except: Option<u64>,
+    ) -> Result<()> {
+        let borrows = lock(&self.borrows);
+        let conflict = borrows.live.iter().find(|(id, _, live, mutable)| {
+            Some(*id) != except
+                && (write || *mutable)
+                && live.start < range.end
+                && range.start < live.end
+        });
+
+        match conflict {
+            Some((_, _, live, _)) => Err(AllocError::BorrowConflict {
+                range: range.clone(),
+                with: live.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // error if a live token of the process overlaps `range`, which it's about to let go of. the
+    // token would go on claiming bytes the process doesn't hold anymore, keeping whoever holds
+    // them next from writing to them
+    pub(super) fn check_claimed(&self, process_id: Process, range: &Range<u32>) -> Result<()> {
+        let borrows = lock(&self.borrows);
+        let claimed = borrows.live.iter().find(|(_, holder, live, _)| {
+            *holder == process_id && live.start < range.end && range.start < live.end
+        });
+
+        match claimed {
+            Some((_, _, live, _)) => Err(AllocError::BorrowConflict {
+                range: range.clone(),
+                with: live.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn track(&self, process_id: Process, range: Range<u32>, mutable: bool) -> Result<BorrowToken> {
+        self.check_range(process_id, &range, mutable)?;
+
+        let mut borrows = lock(&self.borrows);
+        let id = borrows.next_id;
+        borrows.next_id += 1;
+        borrows.live.push((id, process_id, range.clone(), mutable));
+
+        Ok(BorrowToken {
+            borrows: Arc::clone(&self.borrows),
+            id,
+            process_id,
+            range,
+            mutable,
+        })
+    }
+
+    /// Claim `range` of a process for reading until the returned token is dropped, in the
+    /// meantime nothing can borrow any of it mutably, a token or not.
+    ///
+    /// It errors like `range_borrow()` does, or if some of the range is claimed by a mutable
+    /// token (`AllocError::BorrowConflict`).
+    pub fn track_borrow(&self, process_id: Process, range: Range<u32>) -> Result<BorrowToken> {
+        self.track(process_id, range, false)
+    }
+
+    /// Claim `range` of a process for writing until the returned token is dropped, in the
+    /// meantime nothing can borrow any of it at all except through this token.
+    ///
+    /// It errors like `range_borrow_mut()` does, or if some of the range is claimed by any
+    /// token (`AllocError::BorrowConflict`).
+    pub fn track_borrow_mut(&self, process_id: Process, range: Range<u32>) -> Result<BorrowToken> {
+        self.track(process_id, range, true)
+    }
+
+    // the id of `token` if it's a token of this allocator
+    fn token_id(&self, token: &BorrowToken) -> Option<u64> {
+        Arc::ptr_eq(&self.borrows, &token.borrows).then_some(token.id)
+    }
+
+    /// Borrow the range of `token`, which is checked again in case the range was freed in the
+    /// meantime.
+    ///
+    /// It errors like `range_borrow()` does.
+    pub fn borrow_token(&self, token: &BorrowToken) -> Result<&[u8]> {
+        self.check_owned(token.process_id, &token.range, false)?;
+        self.check_conflicts(&token.range, false, self.token_id(token))?;
+
+        Ok(&self.heap[token.range.start as usize..token.range.end as usize])
+    }
+
+    /// Mutably borrow the range of `token`.
+    ///
+    /// It errors like `range_borrow_mut()` does, where a token from `track_borrow()` can't
+    /// write (`AllocError::PermissionDenied`).
+    pub fn borrow_token_mut(&mut self, token: &BorrowToken) -> Result<&mut [u8]> {
+        if !token.mutable {
+            return Err(AllocError::PermissionDenied {
+                process_id: token.process_id,
+                range: token.range(),
+                access: Access::Write,
+            });
+        }
+
+        self.check_owned(token.process_id, &token.range, true)?;
+        self.check_conflicts(&token.range, true, self.token_id(token))?;
+
+        Ok(&mut self.heap[token.range.start as usize..token.range.end as usize])
+    }
+}
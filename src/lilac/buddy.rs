@@ -0,0 +1,150 @@
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use super::heap::Heap;
+
+// the smallest block handed out is 8 bytes, anything smaller would just make the per order sets
+// bigger for no real gain
+const MIN_ORDER: u32 = 3;
+
+/// The bookkeeping of an `Allocator` in buddy mode, where every block is a power of two long and
+/// starts at a multiple of its own size.
+///
+/// A block of order `k` is `1 << k` bytes long and its buddy is the other half of the block of
+/// order `k + 1` they were split from, which is found by flipping bit `k` of the start address.
+/// The heap is always a power of two long, so the whole of it is the biggest block there is.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Buddy {
+    // the start of every free block, by order
+    free: Vec<BTreeSet<u32>>,
+}
+
+impl Buddy {
+    pub(super) fn new() -> Self {
+        Self::default()
+    }
+
+    /// All the free blocks, in no particular order.
+    pub(super) fn iter(&self) -> impl Iterator<Item = Range<u32>> + '_ {
+        self.free
+            .iter()
+            .enumerate()
+            .flat_map(|(order, set)| set.iter().map(move |start| *start..*start + (1 << order)))
+    }
+
+    /// The order of the smallest block which fits `size` bytes.
+    pub(super) fn order(size: u32) -> u32 {
+        size.next_power_of_two().trailing_zeros().max(MIN_ORDER)
+    }
+
+    fn set(&mut self, order: u32) -> &mut BTreeSet<u32> {
+        let order = order as usize;
+        if self.free.len() <= order {
+            self.free.resize(order + 1, BTreeSet::new());
+        }
+
+        &mut self.free[order]
+    }
+
+    /// Take a free block of `order`, splitting a bigger one or doubling the heap until there is
+    /// one, and return its start, or `None` if the heap would have to grow past `limit`.
+    pub(super) fn alloc(&mut self, heap: &mut Heap, order: u32, limit: Option<u32>) -> Option<u32> {
+        loop {
+            let found = (order as usize..self.free.len()).find(|x| !self.free[*x].is_empty());
+            if let Some(found) = found {
+                // safe to unwrap because we just checked that the set isn't empty
+                let start = self.free[found].pop_first().unwrap();
+
+                // keep the second half of every split as a free block of its own
+                for k in (order..found as u32).rev() {
+                    self.set(k).insert(start + (1 << k));
+                }
+
+                return Some(start);
+            }
+
+            let len = heap.len();
+            let grown = if len == 0 { 1 << order } else { len * 2 };
+            if limit.is_some_and(|limit| grown > limit as usize) || !heap.resize(grown) {
+                return None;
+            }
+
+            if len == 0 {
+                return Some(0);
+            }
+
+            // the new top half is the buddy of the whole old heap, which may be free too
+            self.free(grown as u32, len as u32, len.trailing_zeros());
+        }
+    }
+
+    /// Like `alloc()` but the block starts at a multiple of `1 << align`, by taking a block of
+    /// order `align` and keeping only its first block of `order`, the rest are free blocks whose
+    /// buddies are the part that's in use so they can't merge.
+    pub(super) fn alloc_aligned(
+        &mut self,
+        heap: &mut Heap,
+        order: u32,
+        align: u32,
+        limit: Option<u32>,
+    ) -> Option<u32> {
+        if align <= order {
+            return self.alloc(heap, order, limit);
+        }
+
+        let start = self.alloc(heap, align, limit)?;
+        for k in order..align {
+            self.set(k).insert(start + (1 << k));
+        }
+
+        Some(start)
+    }
+
+    /// Add `range` to the free sets as it is, without merging it with its buddy, for rebuilding
+    /// them from a snapshot. It returns false if the block isn't a power of two long.
+    pub(super) fn restore(&mut self, range: &Range<u32>) -> bool {
+        let len = range.end.wrapping_sub(range.start);
+        if range.start >= range.end || !len.is_power_of_two() {
+            return false;
+        }
+
+        self.set(len.trailing_zeros()).insert(range.start);
+        true
+    }
+
+    /// Take the free blocks making up the top halves of the heap out of the free sets, and
+    /// return how long the heap is without them.
+    pub(super) fn trim(&mut self, heap_len: u32) -> u32 {
+        if heap_len == 0 || self.set(heap_len.trailing_zeros()).remove(&0) {
+            return 0;
+        }
+
+        let mut len = heap_len;
+        while len > 1 && self.set((len / 2).trailing_zeros()).remove(&(len / 2)) {
+            len /= 2;
+        }
+
+        len
+    }
+
+    /// Give back the block of `order` at `start`, merging it with its buddy for as long as the
+    /// buddy is free, and return the order of the block it ended up in.
+    pub(super) fn free(&mut self, heap_len: u32, start: u32, order: u32) -> u32 {
+        let top = heap_len.trailing_zeros();
+        let (mut start, mut order) = (start, order);
+
+        while order < top {
+            let buddy = start ^ (1 << order);
+            if !self.set(order).remove(&buddy) {
+                break;
+            }
+
+            start = start.min(buddy);
+            order += 1;
+        }
+
+        self.set(order).insert(start);
+        order
+    }
+}
@@ -0,0 +1,114 @@
+use alloc::{string::String, vec::Vec};
+
+use super::{Allocator, BlockHandle, Inherit, Process, Result};
+
+impl Allocator {
+    /// Register `child` as a process started by `parent`, which gets every block the parent
+    /// holds right away, like a process forked off another one. With `Inherit::Share` it
+    /// shares them (with the same permissions the parent has), with `Inherit::Copy` it gets
+    /// copies of them of its own.
+    ///
+    /// The child goes in the group the parent is in, if any, and cleaning up the parent with
+    /// `clean_process()` cleans up the child (and its children) too.
+    ///
+    /// It returns the child with the generation it got like `register_process()` does, and the
+    /// handle of every block of the parent along with the handle the child got for it, sorted
+    /// by the handle of the parent. Since addresses are the same for every process the copies
+    /// can't be made lazily on the first write like a real fork does, they end up wherever
+    /// there's room, so their handles (and `range()`) are how to find them.
+    ///
+    /// It errors if the parent doesn't exist (`AllocError::NoSuchProcess`), if the child is
+    /// registered already (`AllocError::AlreadyRegistered`) or like `share()` or `alloc()` do
+    /// for the blocks, in which case the child is cleaned up again.
+    pub fn register_child(
+        &mut self,
+        parent: Process,
+        child: Process,
+        inherit: Inherit,
+    ) -> Result<(Process, Vec<(BlockHandle, BlockHandle)>)> {
+        if !self.allocated.contains_key(&parent) {
+            return Err(self.no_such_process(parent));
+        }
+        let child = self.register_process(child)?;
+        self.spawned_by.insert(child, parent);
+
+        match self.inherit(parent, child, inherit) {
+            Ok(handles) => Ok((child, handles)),
+            Err(e) => {
+                // the child never got to run, so nothing of it is worth keeping around
+                let _ = self.clean_process(child);
+                Err(e)
+            }
+        }
+    }
+
+    fn inherit(
+        &mut self,
+        parent: Process,
+        child: Process,
+        inherit: Inherit,
+    ) -> Result<Vec<(BlockHandle, BlockHandle)>> {
+        if let Some(group) = self.group_of(parent) {
+            self.add_to_group(group, child)?;
+        }
+
+        let mut handles: Vec<BlockHandle> = self.allocated[&parent]
+            .iter()
+            .map(|x| BlockHandle {
+                process_id: parent,
+                id: x.id,
+            })
+            .collect();
+        handles.sort_unstable();
+
+        let mut inherited = Vec::with_capacity(handles.len());
+        for handle in handles {
+            let got = match inherit {
+                Inherit::Share => self.share(handle, child)?,
+                Inherit::Copy => self.copy_block(handle, child)?,
+            };
+            inherited.push((handle, got));
+        }
+
+        Ok(inherited)
+    }
+
+    // give `process_id` a block of its own with the same bytes, label and protection as the
+    // block of `handle`, in the same named heap
+    fn copy_block(&mut self, handle: BlockHandle, process_id: Process) -> Result<BlockHandle> {
+        let range = self.range(handle)?;
+        self.check_conflicts(&range, false, None)?;
+
+        let size = range.end - range.start;
+        let copy = match self.heap_at(range.start).map(String::from) {
+            Some(heap) => self.alloc_in(process_id, &heap, size)?,
+            None => self.alloc(process_id, size)?,
+        };
+        // safe to unwrap because the block was just allocated
+        let start = self.range(copy).unwrap().start;
+        self.heap
+            .copy_within(range.start as usize..range.end as usize, start as usize);
+
+        if let Some(tag) = self.tags.get(&handle.id).cloned() {
+            self.tags.insert(copy.id, tag);
+        }
+        let protection = self.protection(handle)?;
+        self.protect(copy, protection)?;
+
+        Ok(copy)
+    }
+
+    /// The process which started the process with `register_child()`, if any.
+    pub fn parent_of(&self, process_id: Process) -> Option<Process> {
+        self.spawned_by.get(&process_id).copied()
+    }
+
+    /// The processes the process started with `register_child()` that are still around,
+    /// sorted.
+    pub fn children(&self, process_id: Process) -> impl Iterator<Item = Process> + '_ {
+        self.spawned_by
+            .iter()
+            .filter(move |x| *x.1 == process_id)
+            .map(|x| *x.0)
+    }
+}
@@ -0,0 +1,131 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use super::{Allocator, BlockHandle, Result};
+
+// the offset into a block `len` bytes long that `position` is at, the end if it's past it
+fn offset(position: u64, len: usize) -> usize {
+    position.min(len as u64) as usize
+}
+
+/// A `std::io` view of one block, for streaming data straight into or out of lilac memory with
+/// code that works on any `Read`, `Write` or `Seek`, like serializers and parsers.
+///
+/// It works like `std::io::Cursor` over a slice: reads and writes start at the position and
+/// move it along, reads stop at the end of the block and writes only write as much as fits.
+/// Seeking past the end is fine, reading and writing there just don't do anything.
+///
+/// Every read and write borrows the block anew, so errors like a broken guard or a block that
+/// was shared read-only come back as `io::Error`s with the `AllocError` inside.
+#[derive(Debug)]
+pub struct BlockCursor<'a> {
+    allocator: &'a mut Allocator,
+    handle: BlockHandle,
+    // an offset into the block, which may be past its end
+    position: u64,
+}
+
+impl<'a> BlockCursor<'a> {
+    /// Create a new `BlockCursor` at the start of the block of `handle`.
+    ///
+    /// It errors like `Allocator::range()` does.
+    pub fn new(allocator: &'a mut Allocator, handle: BlockHandle) -> Result<Self> {
+        allocator.range(handle)?;
+
+        Ok(Self {
+            allocator,
+            handle,
+            position: 0,
+        })
+    }
+
+    pub fn handle(&self) -> BlockHandle {
+        self.handle
+    }
+
+    /// The position as an offset into the block.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: u64) {
+        self.position = position;
+    }
+}
+
+impl Read for BlockCursor<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let block = self
+            .allocator
+            .borrow(self.handle)
+            .map_err(io::Error::other)?;
+        let start = offset(self.position, block.len());
+        let rest = &block[start..];
+
+        let len = rest.len().min(buf.len());
+        buf[..len].copy_from_slice(&rest[..len]);
+        self.position += len as u64;
+
+        Ok(len)
+    }
+}
+
+impl Write for BlockCursor<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let block = self
+            .allocator
+            .borrow_mut(self.handle)
+            .map_err(io::Error::other)?;
+        let start = offset(self.position, block.len());
+        let rest = &mut block[start..];
+
+        let len = rest.len().min(buf.len());
+        rest[..len].copy_from_slice(&buf[..len]);
+        self.position += len as u64;
+
+        Ok(len)
+    }
+
+    // everything is written straight into the heap
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for BlockCursor<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(position) => {
+                self.position = position;
+                return Ok(position);
+            }
+            SeekFrom::Current(offset) => (self.position, offset),
+            SeekFrom::End(offset) => {
+                let range = self
+                    .allocator
+                    .range(self.handle)
+                    .map_err(io::Error::other)?;
+                ((range.end - range.start) as u64, offset)
+            }
+        };
+
+        match base.checked_add_signed(offset) {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "can't seek to before the start of the block",
+            )),
+        }
+    }
+}
+
+impl Allocator {
+    /// Get a `BlockCursor` at the start of the block of `handle`.
+    ///
+    /// It errors like `range()` does.
+    pub fn cursor(&mut self, handle: BlockHandle) -> Result<BlockCursor<'_>> {
+        BlockCursor::new(self, handle)
+    }
+}
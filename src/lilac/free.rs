@@ -0,0 +1,119 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::{vec, vec::Vec};
+use core::ops::Range;
+
+use super::Strategy;
+
+/// The free blocks of an `Allocator`, indexed both by address and by size so that finding a
+/// block big enough for an allocation, or the neighbours of a block being freed, doesn't have to
+/// walk all of them.
+///
+/// The ranges are half-open like everywhere else in lilac, and no two of them ever touch (one
+/// ending where the next one starts), since freeing merges back to back blocks.
+#[derive(Debug, Clone, Default)]
+pub(super) struct FreeList {
+    // start -> end
+    by_start: BTreeMap<u32, u32>,
+    // (size, start)
+    by_size: BTreeSet<(u32, u32)>,
+}
+
+fn size(start: u32, end: u32) -> u32 {
+    end - start
+}
+
+impl FreeList {
+    pub(super) fn new() -> Self {
+        Self::default()
+    }
+
+    /// All the free blocks, sorted by address.
+    pub(super) fn iter(&self) -> impl Iterator<Item = Range<u32>> + '_ {
+        self.by_start.iter().map(|(start, end)| *start..*end)
+    }
+
+    /// The free block starting at `start`.
+    pub(super) fn get(&self, start: u32) -> Option<Range<u32>> {
+        self.by_start.get(&start).map(|end| start..*end)
+    }
+
+    /// The free block ending at `end`, so its last byte is right before it.
+    pub(super) fn ending_at(&self, end: u32) -> Option<Range<u32>> {
+        self.by_start
+            .range(..end)
+            .next_back()
+            .filter(|x| *x.1 == end)
+            .map(|(start, end)| *start..*end)
+    }
+
+    pub(super) fn insert(&mut self, range: Range<u32>) {
+        self.by_start.insert(range.start, range.end);
+        self.by_size
+            .insert((size(range.start, range.end), range.start));
+    }
+
+    /// Remove the free block starting at `start`, if there's one.
+    pub(super) fn remove(&mut self, start: u32) -> Option<Range<u32>> {
+        let end = self.by_start.remove(&start)?;
+        self.by_size.remove(&(size(start, end), start));
+
+        Some(start..end)
+    }
+
+    /// Every entry of the size index which doesn't match the free block it's for, and every free
+    /// block missing from it, as `(start, indexed size, actual size)`. It's always empty unless
+    /// the two indexes got out of sync somehow.
+    pub(super) fn mismatches(&self) -> Vec<(u32, Option<u32>, Option<u32>)> {
+        let mut mismatches = vec![];
+
+        for (indexed, start) in &self.by_size {
+            let actual = self.by_start.get(start).map(|end| size(*start, *end));
+            if actual != Some(*indexed) {
+                mismatches.push((*start, Some(*indexed), actual));
+            }
+        }
+
+        let indexed: BTreeSet<u32> = self.by_size.iter().map(|x| x.1).collect();
+        for (start, end) in &self.by_start {
+            if !indexed.contains(start) {
+                mismatches.push((*start, None, Some(size(*start, *end))));
+            }
+        }
+
+        mismatches
+    }
+
+    /// Remove and return the first free block, in address order, with room for `wanted` bytes
+    /// starting at a multiple of `align`, along with where they start.
+    pub(super) fn take_aligned(&mut self, wanted: u32, align: u32) -> Option<(Range<u32>, u32)> {
+        let (start, aligned) = self.by_start.iter().find_map(|(start, end)| {
+            let aligned = start.checked_next_multiple_of(align)?;
+            (aligned.checked_add(wanted)? <= *end).then_some((*start, aligned))
+        })?;
+
+        Some((self.remove(start)?, aligned))
+    }
+
+    /// Remove and return a free block of at least `wanted` bytes, picked according to `strategy`.
+    ///
+    /// Best and worst fit only look at the size index, first fit has to walk the blocks in
+    /// address order until one is big enough.
+    pub(super) fn take(&mut self, wanted: u32, strategy: Strategy) -> Option<Range<u32>> {
+        let start = match strategy {
+            Strategy::FirstFit => self
+                .by_start
+                .iter()
+                .find(|(start, end)| size(**start, **end) >= wanted)
+                .map(|x| *x.0),
+            Strategy::BestFit => self.by_size.range((wanted, 0)..).next().map(|x| x.1),
+            Strategy::WorstFit => self
+                .by_size
+                .iter()
+                .next_back()
+                .filter(|x| x.0 >= wanted)
+                .map(|x| x.1),
+        }?;
+
+        self.remove(start)
+    }
+}
@@ -0,0 +1,85 @@
+use alloc::{vec, vec::Vec};
+
+use super::{AllocError, Allocator, Process, Result};
+
+impl Allocator {
+    // what the waiters on `addr` of a process are told apart by, the block it's in (the whole
+    // block for a window) and how far into that block it is. every process sharing the block
+    // agrees on it and it stays the same when the block moves
+    pub(super) fn futex_key(&self, process_id: Process, addr: u32) -> Result<(u64, u32)> {
+        let end = addr.checked_add(4).ok_or(AllocError::NotOwned {
+            process_id,
+            range: addr..u32::MAX,
+        })?;
+        self.range_borrow(process_id, addr..end)?;
+
+        let handle = self.handle_at(process_id, addr)?;
+        // safe to index because `handle_at()` already checked that the process exists
+        let block = &self.allocated[&process_id][self.find(handle)?];
+        let (id, range) = self.block_of(block);
+
+        Ok((id, addr - range.start))
+    }
+
+    /// Put the process to sleep on `addr` if the little endian `u32` there is still `expected`,
+    /// for building mutexes and condition variables in shared blocks the way Linux futexes do.
+    /// The scheduler of the VM is expected to stop running the process until `futex_wake()`
+    /// hands it back, the allocator only keeps track of who waits where.
+    ///
+    /// It returns false without putting the process to sleep if the value isn't `expected`
+    /// anymore, another process changed it in the meantime and the process should look again. A
+    /// process only ever waits on one address, waiting again moves it.
+    ///
+    /// It errors like `read_u32()` does.
+    pub fn futex_wait(&mut self, process_id: Process, addr: u32, expected: u32) -> Result<bool> {
+        if self.read_u32(process_id, addr)? != expected {
+            return Ok(false);
+        }
+
+        let key = self.futex_key(process_id, addr)?;
+        self.cancel_wait(process_id);
+        self.futexes.entry(key).or_default().push_back(process_id);
+
+        Ok(true)
+    }
+
+    /// Wake up to `count` of the processes waiting on `addr` of a process, the ones which have
+    /// been waiting the longest first, and return them so the scheduler can run them again.
+    /// Every process sharing the block can wake the waiters on it.
+    ///
+    /// It errors like `read_u32()` does.
+    pub fn futex_wake(
+        &mut self,
+        process_id: Process,
+        addr: u32,
+        count: u32,
+    ) -> Result<Vec<Process>> {
+        let key = self.futex_key(process_id, addr)?;
+        let mut woken = vec![];
+
+        if let Some(waiting) = self.futexes.get_mut(&key) {
+            let count = (count as usize).min(waiting.len());
+            woken.extend(waiting.drain(..count));
+
+            if waiting.is_empty() {
+                self.futexes.remove(&key);
+            }
+        }
+
+        Ok(woken)
+    }
+
+    /// Whether the process is asleep in `futex_wait()`.
+    pub fn is_waiting(&self, process_id: Process) -> bool {
+        self.futexes.values().any(|x| x.contains(&process_id))
+    }
+
+    // stop the process from waiting on whatever it waits on, when it waits somewhere else or
+    // goes away
+    pub(super) fn cancel_wait(&mut self, process_id: Process) {
+        self.futexes.retain(|_, waiting| {
+            waiting.retain(|x| *x != process_id);
+            !waiting.is_empty()
+        });
+    }
+}
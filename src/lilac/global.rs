@@ -0,0 +1,107 @@
+use std::alloc::{GlobalAlloc, Layout};
+use std::ptr;
+use std::sync::{Mutex, MutexGuard};
+
+use super::{Allocator, ProcBuilder, Process};
+
+/// An `Allocator` behind `std::alloc::GlobalAlloc`, so Rust collections can be run against
+/// lilac to see how it behaves with them, in tests and demos.
+///
+/// Everything is allocated under a single process, `process_id()`, and the heap has a fixed
+/// capacity which it's never allowed to grow past, since a heap that reallocates would move
+/// every block that was handed out. Allocations which don't fit return a null pointer like
+/// running out of memory does.
+///
+/// It can't be the `#[global_allocator]` itself, the bookkeeping of the allocator lives in
+/// `HashMap`s and `Vec`s which allocate from the global allocator, so it's meant for calling
+/// directly (or through the `allocator_api` adapter) instead.
+#[derive(Debug)]
+pub struct GlobalLilac {
+    allocator: Mutex<Allocator>,
+    process_id: Process,
+}
+
+impl GlobalLilac {
+    /// Create a new `GlobalLilac` with a heap of at most `capacity` bytes.
+    pub fn new(capacity: u32) -> Self {
+        let mut allocator = Allocator::with_capacity(capacity);
+        allocator.set_limit(Some(capacity));
+
+        // safe to unwrap because the allocator is brand new
+        let process_id = allocator
+            .register_process(ProcBuilder::new().count())
+            .unwrap();
+
+        Self {
+            allocator: Mutex::new(allocator),
+            process_id,
+        }
+    }
+
+    /// The process every block is allocated under.
+    pub fn process_id(&self) -> Process {
+        self.process_id
+    }
+
+    // a poisoned lock only means something panicked while holding it, and nothing in here
+    // panics halfway through changing the allocator
+    pub(super) fn lock(&self) -> MutexGuard<'_, Allocator> {
+        self.allocator.lock().unwrap_or_else(|x| x.into_inner())
+    }
+
+    /// Run `f` with the allocator locked, for looking at its stats or leaks. It only gets to
+    /// look since moving any of the blocks around would pull them out from under their
+    /// pointers.
+    pub fn with<R>(&self, f: impl FnOnce(&Allocator) -> R) -> R {
+        f(&self.lock())
+    }
+
+    // allocate a block for `layout` under a process and return a pointer to it, or null if
+    // there's no room for it
+    pub(super) fn alloc_as(&self, process_id: Process, layout: Layout) -> *mut u8 {
+        let (size, align) = match (u32::try_from(layout.size()), u32::try_from(layout.align())) {
+            (Ok(size), Ok(align)) => (size, align),
+            _ => return ptr::null_mut(),
+        };
+
+        let mut allocator = self.lock();
+        let handle = match allocator.alloc_aligned(process_id, size, align) {
+            Ok(handle) => handle,
+            Err(_) => return ptr::null_mut(),
+        };
+
+        // safe to unwrap because the block was just allocated
+        let start = allocator.range(handle).unwrap().start;
+        // the heap never reallocates so the pointer stays good for as long as the block lives,
+        // and it's inside the heap since the block is
+        let block = unsafe { allocator.heap.as_mut_ptr().add(start as usize) };
+
+        // the block is aligned inside the heap, the heap itself may not be aligned that much
+        if block.align_offset(layout.align()) != 0 {
+            // safe to unwrap because the block was just allocated
+            allocator.free(handle).unwrap();
+            return ptr::null_mut();
+        }
+
+        block
+    }
+
+    // free the block of a process `ptr` points to
+    pub(super) fn dealloc_as(&self, process_id: Process, ptr: *mut u8) {
+        let mut allocator = self.lock();
+        let start = ptr as usize - allocator.heap.as_ptr() as usize;
+
+        // a pointer this didn't hand out is a bug, but there's no way to report it from here
+        let _ = allocator.free_containing(process_id, start as u32);
+    }
+}
+
+unsafe impl GlobalAlloc for GlobalLilac {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.alloc_as(self.process_id, layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        self.dealloc_as(self.process_id, ptr)
+    }
+}
@@ -0,0 +1,215 @@
+use alloc::collections::BTreeSet;
+use alloc::{vec, vec::Vec};
+
+use super::{AllocError, Allocator, Group, Process, Result};
+
+/// The processes of a `Group` and what applies to all of them together.
+#[derive(Debug, Clone, Default)]
+pub(super) struct GroupState {
+    pub(super) members: BTreeSet<Process>,
+    // how many bytes the members may hold together, see `Allocator::set_group_quota()`
+    pub(super) quota: Option<u32>,
+    pub(super) frozen: bool,
+}
+
+impl Allocator {
+    /// Create a new empty group, for handling a set of processes all at once, like a job of the
+    /// VM along with the helpers it spawned. Groups can be cleaned up, limited to a quota and
+    /// frozen as a whole.
+    pub fn create_group(&mut self) -> Group {
+        let group = Group(self.next_group);
+        self.next_group += 1;
+        self.groups.insert(group, GroupState::default());
+
+        group
+    }
+
+    fn group(&self, group: Group) -> Result<&GroupState> {
+        self.groups
+            .get(&group)
+            .ok_or(AllocError::NoSuchGroup(group))
+    }
+
+    /// Put the process in `group`, a process can only be in one group at a time.
+    ///
+    /// It errors if the group or the process doesn't exist (`AllocError::NoSuchGroup` and
+    /// `AllocError::NoSuchProcess`) or if the process is in a group already
+    /// (`AllocError::AlreadyInGroup`, with the group it's in).
+    pub fn add_to_group(&mut self, group: Group, process_id: Process) -> Result<()> {
+        self.group(group)?;
+        if !self.allocated.contains_key(&process_id) {
+            return Err(self.no_such_process(process_id));
+        }
+        if let Some(group) = self.group_of(process_id) {
+            return Err(AllocError::AlreadyInGroup { process_id, group });
+        }
+
+        // safe to unwrap because we just checked that the group exists
+        self.groups
+            .get_mut(&group)
+            .unwrap()
+            .members
+            .insert(process_id);
+
+        Ok(())
+    }
+
+    /// Take the process out of `group`, it keeps all of its blocks.
+    ///
+    /// It errors if the group doesn't exist (`AllocError::NoSuchGroup`) or the process isn't in
+    /// it (`AllocError::NoSuchProcess`).
+    pub fn remove_from_group(&mut self, group: Group, process_id: Process) -> Result<()> {
+        self.group(group)?;
+
+        // safe to unwrap because we just checked that the group exists
+        if self
+            .groups
+            .get_mut(&group)
+            .unwrap()
+            .members
+            .remove(&process_id)
+        {
+            Ok(())
+        } else {
+            Err(AllocError::NoSuchProcess(process_id))
+        }
+    }
+
+    /// The group the process is in, if it's in one.
+    pub fn group_of(&self, process_id: Process) -> Option<Group> {
+        self.groups
+            .iter()
+            .find(|x| x.1.members.contains(&process_id))
+            .map(|x| *x.0)
+    }
+
+    /// The processes in `group`, sorted.
+    ///
+    /// It errors if the group doesn't exist (`AllocError::NoSuchGroup`).
+    pub fn members(&self, group: Group) -> Result<impl Iterator<Item = Process> + '_> {
+        Ok(self.group(group)?.members.iter().copied())
+    }
+
+    /// How many bytes the processes of `group` hold together, a block several of them share
+    /// only counts once, like in `stats()`.
+    ///
+    /// It errors if the group doesn't exist (`AllocError::NoSuchGroup`).
+    pub fn group_usage(&self, group: Group) -> Result<u32> {
+        let members = &self.group(group)?.members;
+
+        // shared blocks show up once for every process holding them, they're told apart by id
+        let mut seen = vec![];
+        for process_id in members {
+            for block in self.allocated.get(process_id).into_iter().flatten() {
+                let (id, range) = self.block_of(block);
+                seen.push((id, range.end - range.start));
+            }
+        }
+
+        seen.sort_unstable();
+        seen.dedup();
+        Ok(seen.iter().map(|x| x.1).sum())
+    }
+
+    /// Limit how many bytes the processes of `group` may hold together (see `group_usage()`),
+    /// `None` takes the limit off. Allocations which would go past it fail, the blocks they
+    /// already hold are left alone even if they're over the new quota.
+    ///
+    /// It errors if the group doesn't exist (`AllocError::NoSuchGroup`).
+    pub fn set_group_quota(&mut self, group: Group, quota: Option<u32>) -> Result<()> {
+        self.group(group)?;
+        // safe to unwrap because we just checked that the group exists
+        self.groups.get_mut(&group).unwrap().quota = quota;
+
+        Ok(())
+    }
+
+    /// How many bytes the processes of `group` may hold together, see `set_group_quota()`.
+    ///
+    /// It errors if the group doesn't exist (`AllocError::NoSuchGroup`).
+    pub fn group_quota(&self, group: Group) -> Result<Option<u32>> {
+        Ok(self.group(group)?.quota)
+    }
+
+    /// Freeze every process of `group` so their memory stays exactly as it is, for pausing a
+    /// job to inspect it. Frozen processes can still read their memory and free their blocks,
+    /// but allocating, resizing and writing fail (`AllocError::Frozen`) until `thaw_group()`.
+    ///
+    /// It errors if the group doesn't exist (`AllocError::NoSuchGroup`).
+    pub fn freeze_group(&mut self, group: Group) -> Result<()> {
+        self.set_frozen(group, true)
+    }
+
+    /// Let the processes of a group frozen with `freeze_group()` change their memory again.
+    ///
+    /// It errors like `freeze_group()` does.
+    pub fn thaw_group(&mut self, group: Group) -> Result<()> {
+        self.set_frozen(group, false)
+    }
+
+    fn set_frozen(&mut self, group: Group, frozen: bool) -> Result<()> {
+        self.group(group)?;
+        // safe to unwrap because we just checked that the group exists
+        self.groups.get_mut(&group).unwrap().frozen = frozen;
+
+        Ok(())
+    }
+
+    /// Whether the process is in a frozen group, see `freeze_group()`.
+    pub fn is_frozen(&self, process_id: Process) -> bool {
+        self.groups
+            .values()
+            .any(|x| x.frozen && x.members.contains(&process_id))
+    }
+
+    // error if the process is frozen
+    pub(super) fn check_frozen(&self, process_id: Process) -> Result<()> {
+        if self.is_frozen(process_id) {
+            Err(AllocError::Frozen(process_id))
+        } else {
+            Ok(())
+        }
+    }
+
+    // error if the process can't get `size` more bytes, since it's frozen or its group would go
+    // over its quota
+    pub(super) fn check_group(&self, process_id: Process, size: u32) -> Result<()> {
+        let group = match self.group_of(process_id) {
+            Some(group) => group,
+            None => return Ok(()),
+        };
+
+        let state = self.group(group)?;
+        if state.frozen {
+            return Err(AllocError::Frozen(process_id));
+        }
+
+        if let Some(quota) = state.quota {
+            if self.group_usage(group)?.saturating_add(size) > quota {
+                return Err(AllocError::QuotaExceeded { group, size, quota });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Clean up every process of `group` like `clean_process()` does, all at once, and get rid
+    /// of the group.
+    ///
+    /// It errors if the group doesn't exist (`AllocError::NoSuchGroup`) or like
+    /// `clean_process()` does, in which case all the processes are still cleaned up.
+    pub fn clean_group(&mut self, group: Group) -> Result<()> {
+        let members: Vec<Process> = self.group(group)?.members.iter().copied().collect();
+        self.groups.remove(&group);
+
+        let mut result = Ok(());
+        for process_id in members {
+            // children are cleaned up along with their parent, which may have come first
+            if self.allocated.contains_key(&process_id) {
+                result = result.and(self.clean_process(process_id));
+            }
+        }
+
+        result
+    }
+}
@@ -0,0 +1,139 @@
+use core::ops::Range;
+
+use super::{AllocError, Allocator, BlockHandle, Process, Result};
+
+/// What the guards around a block are filled with, the same byte the MSVC debug heap uses for
+/// the "no man's land" around its blocks.
+pub const GUARD_BYTE: u8 = 0xfd;
+
+/// What freed blocks are filled with in poison mode, the same byte the MSVC debug heap uses for
+/// freed memory.
+pub const POISON_BYTE: u8 = 0xdd;
+
+/// What new blocks are filled with in debug fill mode, see `Allocator::set_debug_fill()`.
+pub const FRESH_BYTE: u8 = 0xaa;
+
+impl Allocator {
+    /// Create a new `Allocator` which surrounds every block with `guard` bytes on each side,
+    /// filled with `GUARD_BYTE`. They're checked on every free and borrow of the block, so a
+    /// process writing past either end of its block gets caught the next time the block is
+    /// touched instead of silently corrupting its neighbour.
+    ///
+    /// The guards don't count towards the size of the block, `range()` and the borrows only
+    /// ever see the bytes in between.
+    pub fn with_guards(guard: u32) -> Self {
+        Self {
+            guard,
+            ..Self::new()
+        }
+    }
+
+    /// How many guard bytes go on each side of a new block, 0 means none.
+    pub fn guards(&self) -> u32 {
+        self.guard
+    }
+
+    /// Change how many guard bytes go on each side of the blocks allocated from now on, the
+    /// blocks already allocated keep the guards they have.
+    pub fn set_guards(&mut self, guard: u32) {
+        self.guard = guard;
+    }
+
+    /// Whether freed blocks get poisoned, see `set_poison()`.
+    pub fn poison(&self) -> bool {
+        self.poison
+    }
+
+    /// Turn poison mode on or off. In poison mode every byte that gets freed is filled with
+    /// `POISON_BYTE`, so stale data read through a dangling range stands out, and
+    /// `range_borrow()` tells a range that was freed and not handed out again apart from one
+    /// that just isn't the process' (`AllocError::UseAfterFree`).
+    ///
+    /// It's meant for debugging, since freeing has to write over every byte and a failed
+    /// borrow has to look through the free blocks.
+    pub fn set_poison(&mut self, poison: bool) {
+        self.poison = poison;
+    }
+
+    /// Whether new blocks get filled with `FRESH_BYTE`, see `set_debug_fill()`.
+    pub fn debug_fill(&self) -> bool {
+        self.fill_fresh
+    }
+
+    /// Turn debug fill mode on or off, which fills every new block (and the bytes a block gains
+    /// in `realloc()`) with `FRESH_BYTE` and turns poison mode on or off along with it, see
+    /// `set_poison()`. A guest program reading memory it never wrote then sees `0xaa`s, or
+    /// `0xdd`s for memory it already freed, which stand out in a `dump()`.
+    ///
+    /// `set_zero_on_alloc()` wins over it, zeroed memory is what the program asked for then.
+    pub fn set_debug_fill(&mut self, debug_fill: bool) {
+        self.fill_fresh = debug_fill;
+        self.poison = debug_fill;
+    }
+
+    // fill the bytes a block just got, which are zeroed or filled with the fresh byte if the
+    // allocator does either, and left as they were otherwise
+    pub(super) fn fill_new(&mut self, range: Range<u32>) {
+        let bytes = &mut self.heap[range.start as usize..range.end as usize];
+        if self.zero_on_alloc {
+            bytes.fill(0);
+        } else if self.fill_fresh {
+            bytes.fill(FRESH_BYTE);
+        }
+    }
+
+    // the error for a borrow of a range the process doesn't own
+    pub(super) fn not_owned(&self, process_id: Process, range: Range<u32>) -> AllocError {
+        let freed = self.poison
+            && self
+                .free_ranges()
+                .iter()
+                .any(|x| x.start < range.end && range.start < x.end);
+
+        if freed {
+            AllocError::UseAfterFree { process_id, range }
+        } else {
+            AllocError::NotOwned { process_id, range }
+        }
+    }
+
+    // the bytes the block with `id` takes up on the heap, its guards included
+    pub(super) fn extent(&self, id: u64, range: &Range<u32>) -> Range<u32> {
+        match self.guards.get(&id) {
+            Some(outer) => outer.clone(),
+            None => range.clone(),
+        }
+    }
+
+    // fill everything of `outer` around `range` with the guard byte
+    pub(super) fn write_guards(&mut self, outer: &Range<u32>, range: &Range<u32>) {
+        self.heap[outer.start as usize..range.start as usize].fill(GUARD_BYTE);
+        self.heap[range.end as usize..outer.end as usize].fill(GUARD_BYTE);
+    }
+
+    // error if the guards of the block of `handle`, which is at `range`, were overwritten
+    pub(super) fn check_block(&self, handle: BlockHandle, range: &Range<u32>) -> Result<()> {
+        let outer = match self.guards.get(&handle.id) {
+            Some(outer) => outer,
+            None => return Ok(()),
+        };
+
+        let before = &self.heap[outer.start as usize..range.start as usize];
+        let after = &self.heap[range.end as usize..outer.end as usize];
+        if before.iter().chain(after).all(|x| *x == GUARD_BYTE) {
+            Ok(())
+        } else {
+            Err(AllocError::Corruption(handle))
+        }
+    }
+
+    /// Check that the guards around the block of `handle` are still intact, like every free
+    /// and borrow of it does. A block allocated without guards always passes.
+    ///
+    /// It errors like `range()` does, or if something wrote over the guards
+    /// (`AllocError::Corruption`).
+    pub fn check_guards(&self, handle: BlockHandle) -> Result<()> {
+        let range = self.range(handle)?;
+        self.check_block(handle, &range)
+    }
+}
@@ -0,0 +1,274 @@
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+#[cfg(all(feature = "std", unix))]
+use core::{ffi::c_void, ptr, slice};
+#[cfg(all(feature = "std", unix))]
+use std::fs::{File, OpenOptions};
+#[cfg(all(feature = "std", unix))]
+use std::io;
+#[cfg(all(feature = "std", unix))]
+use std::os::unix::io::AsRawFd;
+#[cfg(all(feature = "std", unix))]
+use std::path::Path;
+
+#[cfg(all(feature = "std", unix))]
+use libc::{mmap, munmap, MAP_FAILED, MAP_SHARED, PROT_READ, PROT_WRITE};
+
+#[cfg(all(feature = "std", unix))]
+use super::Allocator;
+
+/// The bytes of the heap of an `Allocator`, either in a `Vec` or mapped from a file, see
+/// `Allocator::with_mmap()`. It derefs to the bytes either way.
+pub(super) struct Heap {
+    backing: Backing,
+}
+
+enum Backing {
+    Vec(Vec<u8>),
+    #[cfg(all(feature = "std", unix))]
+    Mapped(Mapping),
+}
+
+// a file mapped into memory, `len` bytes of which are the heap. the mapping is `capacity` bytes
+// long from the start so it never has to move, but only the bytes before `len` are in the file,
+// touching the rest would be a SIGBUS
+#[cfg(all(feature = "std", unix))]
+struct Mapping {
+    file: File,
+    ptr: *mut u8,
+    len: usize,
+    capacity: usize,
+}
+
+// safe because the mapping is only ever reached through the `Heap` owning it, like the buffer
+// of a `Vec`
+#[cfg(all(feature = "std", unix))]
+unsafe impl Send for Mapping {}
+#[cfg(all(feature = "std", unix))]
+unsafe impl Sync for Mapping {}
+
+#[cfg(all(feature = "std", unix))]
+impl Mapping {
+    fn new(file: File, capacity: usize) -> io::Result<Self> {
+        file.set_len(0)?;
+
+        // safe because the kernel picks the address and checks everything else
+        let ptr = unsafe {
+            mmap(
+                ptr::null_mut(),
+                capacity,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            file,
+            ptr: ptr as *mut u8,
+            len: 0,
+            capacity,
+        })
+    }
+
+    // growing the file fills it with zeroes, the heap only gets longer if it did. shrinking it
+    // only gives the bytes back to the file system, the heap is shorter even if that fails and
+    // the file just stays longer than it
+    fn resize(&mut self, len: usize) -> io::Result<()> {
+        if len > self.capacity {
+            return Err(io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                "the heap can't grow past its mapping",
+            ));
+        }
+
+        let resized = self.file.set_len(len as u64);
+        if resized.is_ok() || len < self.len {
+            self.len = len;
+        }
+
+        resized
+    }
+}
+
+#[cfg(all(feature = "std", unix))]
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // safe because the mapping was created with exactly this pointer and length, and
+        // nothing can borrow the heap anymore
+        unsafe { munmap(self.ptr as *mut c_void, self.capacity) };
+    }
+}
+
+impl Heap {
+    pub(super) fn new() -> Self {
+        Self {
+            backing: Backing::Vec(Vec::new()),
+        }
+    }
+
+    /// How many bytes the heap can span before it has to reallocate, or for a mapped heap at
+    /// all.
+    pub(super) fn capacity(&self) -> usize {
+        match &self.backing {
+            Backing::Vec(vec) => vec.capacity(),
+            #[cfg(all(feature = "std", unix))]
+            Backing::Mapped(mapping) => mapping.capacity,
+        }
+    }
+
+    /// How long the heap can ever get, `None` if there's no limit other than the memory of the
+    /// host.
+    pub(super) fn max_len(&self) -> Option<usize> {
+        match &self.backing {
+            Backing::Vec(_) => None,
+            #[cfg(all(feature = "std", unix))]
+            Backing::Mapped(mapping) => Some(mapping.capacity),
+        }
+    }
+
+    pub(super) fn is_mapped(&self) -> bool {
+        match &self.backing {
+            Backing::Vec(_) => false,
+            #[cfg(all(feature = "std", unix))]
+            Backing::Mapped(_) => true,
+        }
+    }
+
+    // a mapped heap has all of its room from the start
+    pub(super) fn reserve_exact(&mut self, additional: usize) {
+        match &mut self.backing {
+            Backing::Vec(vec) => vec.reserve_exact(additional),
+            #[cfg(all(feature = "std", unix))]
+            Backing::Mapped(_) => {}
+        }
+    }
+
+    /// Grow or shrink the heap to `len` bytes, the new ones are all zero. It returns whether the
+    /// heap is `len` bytes long now, a mapped heap can't grow if its file can't (the disk is
+    /// full, or it would grow past the mapping), which is like a `Vec` running out of memory.
+    #[must_use]
+    pub(super) fn resize(&mut self, len: usize) -> bool {
+        match &mut self.backing {
+            Backing::Vec(vec) => {
+                vec.resize(len, 0);
+                true
+            }
+            #[cfg(all(feature = "std", unix))]
+            Backing::Mapped(mapping) => mapping.resize(len).is_ok() || mapping.len == len,
+        }
+    }
+
+    pub(super) fn truncate(&mut self, len: usize) {
+        if len < self.len() {
+            // shrinking always works, see `Mapping::resize()`
+            let _ = self.resize(len);
+        }
+    }
+
+    // truncating a mapped heap already gave the bytes back to the file system
+    pub(super) fn shrink_to_fit(&mut self) {
+        match &mut self.backing {
+            Backing::Vec(vec) => vec.shrink_to_fit(),
+            #[cfg(all(feature = "std", unix))]
+            Backing::Mapped(_) => {}
+        }
+    }
+}
+
+impl From<Vec<u8>> for Heap {
+    fn from(vec: Vec<u8>) -> Self {
+        Self {
+            backing: Backing::Vec(vec),
+        }
+    }
+}
+
+impl Deref for Heap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.backing {
+            Backing::Vec(vec) => vec,
+            // safe because the first `len` bytes of the mapping are in the file
+            #[cfg(all(feature = "std", unix))]
+            Backing::Mapped(mapping) => unsafe { slice::from_raw_parts(mapping.ptr, mapping.len) },
+        }
+    }
+}
+
+impl DerefMut for Heap {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match &mut self.backing {
+            Backing::Vec(vec) => vec,
+            // safe because the first `len` bytes of the mapping are in the file, and the mapping
+            // is only reached through this `Heap`
+            #[cfg(all(feature = "std", unix))]
+            Backing::Mapped(mapping) => unsafe {
+                slice::from_raw_parts_mut(mapping.ptr, mapping.len)
+            },
+        }
+    }
+}
+
+// the bytes themselves are way too much to print
+impl fmt::Debug for Heap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Heap")
+            .field("len", &self.len())
+            .field("mapped", &self.is_mapped())
+            .finish()
+    }
+}
+
+#[cfg(all(feature = "std", unix))]
+impl Allocator {
+    /// Create a new `Allocator` whose heap lives in the file at `path` instead of in memory,
+    /// mapped with `mmap()`, so a guest memory far bigger than the RAM of the host only takes up
+    /// the pages that are actually touched, and the bytes of the heap end up on disk on their
+    /// own. The file is created if it doesn't exist and emptied if it does.
+    ///
+    /// The whole `capacity` is mapped right away so the heap never moves, which makes it the
+    /// limit of the heap as well (see `set_limit()`, which can't raise it past `capacity`). The
+    /// file only ever holds the heap, `save_image()` is still what saves the bookkeeping.
+    ///
+    /// It errors if the file couldn't be opened or mapped.
+    pub fn with_mmap(path: impl AsRef<Path>, capacity: u32) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        Ok(Self {
+            heap: Heap {
+                backing: Backing::Mapped(Mapping::new(file, capacity as usize)?),
+            },
+            limit: Some(capacity),
+            ..Self::new()
+        })
+    }
+
+    /// Whether the heap is mapped from a file, see `with_mmap()`.
+    pub fn is_mapped(&self) -> bool {
+        self.heap.is_mapped()
+    }
+
+    /// Make sure everything written to a mapped heap is on disk, it does nothing for a heap in
+    /// memory.
+    ///
+    /// It errors if the file couldn't be synced.
+    pub fn sync(&self) -> io::Result<()> {
+        match &self.heap.backing {
+            Backing::Vec(_) => Ok(()),
+            // the mapping is shared, so its dirty pages are the page cache of the file
+            Backing::Mapped(mapping) => mapping.file.sync_data(),
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use super::free::FreeList;
+use super::{AllocError, Allocator, BlockHandle, Process, Result};
+
+/// A stretch of the heap set aside with `Allocator::create_heap()`, with free blocks of its own.
+#[derive(Debug, Clone)]
+pub(super) struct NamedHeap {
+    pub(super) range: Range<u32>,
+    pub(super) free: FreeList,
+}
+
+impl NamedHeap {
+    fn contains(&self, addr: u32) -> bool {
+        self.range.contains(&addr)
+    }
+}
+
+impl Allocator {
+    /// Set `size` bytes of the heap aside as a heap of its own called `name`, which only
+    /// `alloc_in()` allocates from, and return where it is. Keeping classes of memory apart
+    /// like this (code, data, memory for a device) means blocks of one class coming and going
+    /// can't fragment the others.
+    ///
+    /// The named heap never grows or moves, it's taken from the free blocks like an allocation
+    /// would be (or from the end of the heap). Blocks in it stay in it when they're resized or
+    /// the heap is compacted, and freeing them gives the bytes back to it, not to the rest of the
+    /// heap.
+    ///
+    /// It errors if there's a named heap called `name` already (`AllocError::HeapExists`), if
+    /// `size` is zero (`AllocError::ZeroSize`), if there's no room for it
+    /// (`AllocError::OutOfMemory`) or in buddy mode (`AllocError::Unsupported`).
+    pub fn create_heap(&mut self, name: impl Into<String>, size: u32) -> Result<Range<u32>> {
+        let name = name.into();
+        self.relieve(|x| x.create_heap_inner(&name, size))
+    }
+
+    fn create_heap_inner(&mut self, name: &str, size: u32) -> Result<Range<u32>> {
+        if self.buddy.is_some() {
+            return Err(AllocError::Unsupported);
+        }
+        if self.heaps.contains_key(name) {
+            return Err(AllocError::HeapExists(name.into()));
+        }
+        if size == 0 {
+            return Err(AllocError::ZeroSize);
+        }
+
+        let range = self.place(size)?;
+        let mut free = FreeList::new();
+        free.insert(range.clone());
+        self.heaps.insert(
+            name.into(),
+            NamedHeap {
+                range: range.clone(),
+                free,
+            },
+        );
+
+        Ok(range)
+    }
+
+    /// Give the bytes of the named heap back to the rest of the heap.
+    ///
+    /// It errors if there's no named heap called `name` (`AllocError::NoSuchHeap`) or if any
+    /// block is still in it (`AllocError::HeapInUse`).
+    pub fn remove_heap(&mut self, name: &str) -> Result<()> {
+        let heap = self
+            .heaps
+            .get(name)
+            .ok_or_else(|| AllocError::NoSuchHeap(name.into()))?;
+
+        // everything of it is free exactly when it's one free block spanning all of it
+        if heap.free.get(heap.range.start) != Some(heap.range.clone()) {
+            return Err(AllocError::HeapInUse(name.into()));
+        }
+
+        // safe to unwrap because we just checked that the heap exists
+        let heap = self.heaps.remove(name).unwrap();
+        self.release(heap.range);
+
+        Ok(())
+    }
+
+    /// Every named heap along with where it is, sorted by name.
+    pub fn heaps(&self) -> impl Iterator<Item = (&str, Range<u32>)> + '_ {
+        self.heaps
+            .iter()
+            .map(|(x, y)| (x.as_str(), y.range.clone()))
+    }
+
+    /// How many bytes of the named heap are free.
+    ///
+    /// It errors if there's no named heap called `name` (`AllocError::NoSuchHeap`).
+    pub fn heap_free(&self, name: &str) -> Result<u32> {
+        let heap = self
+            .heaps
+            .get(name)
+            .ok_or_else(|| AllocError::NoSuchHeap(name.into()))?;
+
+        Ok(heap.free.iter().map(|x| x.end - x.start).sum())
+    }
+
+    /// The named heap the block of `handle` is in, `None` if it's in the rest of the heap.
+    ///
+    /// It errors like `range()` does.
+    pub fn heap_of(&self, handle: BlockHandle) -> Result<Option<&str>> {
+        let start = self.range(handle)?.start;
+        Ok(self.heap_at(start))
+    }
+
+    /// Like `alloc()` but the block goes in the named heap `heap`, see `create_heap()`.
+    ///
+    /// It errors like `alloc()` does, if there's no named heap called `heap`
+    /// (`AllocError::NoSuchHeap`) or if it has no free block big enough
+    /// (`AllocError::HeapFull`), since it doesn't grow.
+    pub fn alloc_in(&mut self, process_id: Process, heap: &str, size: u32) -> Result<BlockHandle> {
+        self.relieve(|x| x.alloc_in_inner(process_id, heap, size))
+    }
+
+    fn alloc_in_inner(
+        &mut self,
+        process_id: Process,
+        heap: &str,
+        size: u32,
+    ) -> Result<BlockHandle> {
+        if !self.allocated.contains_key(&process_id) {
+            return Err(self.no_such_process(process_id));
+        }
+        if size == 0 {
+            return Err(AllocError::ZeroSize);
+        }
+        self.check_group(process_id, size)?;
+
+        let guard = self.guard;
+        let outer = size
+            .checked_add(guard * 2)
+            .ok_or_else(|| AllocError::HeapFull {
+                heap: heap.into(),
+                size,
+            })
+            .and_then(|x| self.place_in(Some(heap), x))?;
+
+        let (handle, range) = self.add_guarded(process_id, outer, guard, guard);
+        self.notify(|x| x.on_alloc(handle, &range));
+
+        Ok(handle)
+    }
+
+    // the named heap `addr` is in, if any
+    pub(super) fn heap_at(&self, addr: u32) -> Option<&str> {
+        self.heaps
+            .iter()
+            .find(|x| x.1.contains(addr))
+            .map(|x| x.0.as_str())
+    }
+
+    // the free blocks `addr` goes back to once it's free, the ones of the named heap it's in or
+    // the ones of the rest of the heap
+    pub(super) fn free_list_at(&mut self, addr: u32) -> &mut FreeList {
+        match self.heaps.values_mut().find(|x| x.contains(addr)) {
+            Some(heap) => &mut heap.free,
+            None => &mut self.free,
+        }
+    }
+
+    // like `place()` but in the named heap `heap`, which never grows, `None` is the rest of the
+    // heap
+    pub(super) fn place_in(&mut self, heap: Option<&str>, size: u32) -> Result<Range<u32>> {
+        let name = match heap {
+            Some(name) => name,
+            None => return self.place(size),
+        };
+
+        let heap = self
+            .heaps
+            .get_mut(name)
+            .ok_or_else(|| AllocError::NoSuchHeap(name.into()))?;
+        let free = heap
+            .free
+            .take(size, self.strategy)
+            .ok_or_else(|| AllocError::HeapFull {
+                heap: name.into(),
+                size,
+            })?;
+
+        let end = free.start + size;
+        if free.end > end {
+            heap.free.insert(end..free.end);
+        }
+
+        Ok(free.start..end)
+    }
+
+    // the stretches of the heap `compact()` can move blocks around in without them leaving the
+    // named heap they're in or moving into one, every named heap and everything between them,
+    // sorted by address
+    pub(super) fn zones(&self) -> Vec<Range<u32>> {
+        let mut heaps: Vec<Range<u32>> = self.heaps.values().map(|x| x.range.clone()).collect();
+        heaps.sort_unstable_by_key(|x| x.start);
+
+        let mut zones = Vec::with_capacity(heaps.len() * 2 + 1);
+        let mut next = 0;
+        for range in heaps {
+            if range.start > next {
+                zones.push(next..range.start);
+            }
+            next = range.end;
+            zones.push(range);
+        }
+
+        let len = self.heap.len() as u32;
+        if len > next {
+            zones.push(next..len);
+        }
+
+        zones
+    }
+}
@@ -0,0 +1,188 @@
+#[cfg(feature = "std")]
+use alloc::collections::VecDeque;
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::{BlockHandle, Compaction, FreeBlock};
+
+/// Something that wants to hear about everything an `Allocator` does, to audit it or mirror
+/// it somewhere else, see `Allocator::observe()`.
+///
+/// Every method does nothing by default so an observer only has to implement the ones it
+/// cares about. They're called after the operation went through, never for one that errored.
+pub trait AllocObserver: fmt::Debug + Send + Sync {
+    /// A block was allocated, with `alloc()`, `alloc_aligned()` or `arena()`.
+    fn on_alloc(&mut self, _handle: BlockHandle, _range: &Range<u32>) {}
+
+    /// A process let go of a block, which is only really freed if nobody else holds it.
+    fn on_free(&mut self, _handle: BlockHandle, _freed: FreeBlock) {}
+
+    /// The block of `handle` was shared with another process, which got `shared` for it.
+    fn on_share(&mut self, _handle: BlockHandle, _shared: BlockHandle) {}
+
+    /// The block of `handle` was moved to another process, which got `transferred` for it.
+    fn on_transfer(&mut self, _handle: BlockHandle, _transferred: BlockHandle) {}
+
+    /// `second` was merged into `first`, which now spans `range`.
+    fn on_merge(&mut self, _first: BlockHandle, _second: BlockHandle, _range: &Range<u32>) {}
+
+    /// The block of `handle` was split and `second` is the second half.
+    fn on_split(&mut self, _handle: BlockHandle, _second: BlockHandle) {}
+
+    /// The block of `handle` was resized from `old` to `new`, which may be somewhere else.
+    fn on_realloc(&mut self, _handle: BlockHandle, _old: &Range<u32>, _new: &Range<u32>) {}
+
+    fn on_compact(&mut self, _compaction: &Compaction) {}
+
+    /// The heap got `trimmed` bytes shorter.
+    fn on_trim(&mut self, _trimmed: u32) {}
+}
+
+/// One thing an `Allocator` did, as recorded by an `EventLog`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Event {
+    Alloc {
+        handle: BlockHandle,
+        range: Range<u32>,
+    },
+    Free {
+        handle: BlockHandle,
+        freed: FreeBlock,
+    },
+    Share {
+        handle: BlockHandle,
+        shared: BlockHandle,
+    },
+    Transfer {
+        handle: BlockHandle,
+        transferred: BlockHandle,
+    },
+    Merge {
+        first: BlockHandle,
+        second: BlockHandle,
+        range: Range<u32>,
+    },
+    Split {
+        handle: BlockHandle,
+        second: BlockHandle,
+    },
+    Realloc {
+        handle: BlockHandle,
+        old: Range<u32>,
+        new: Range<u32>,
+    },
+    Compact(Compaction),
+    Trim(u32),
+}
+
+/// An `AllocObserver` keeping the last few events in a ring buffer, the oldest one is dropped
+/// once it's full.
+///
+/// Cloning it gives another handle to the same buffer, so one clone goes to
+/// `Allocator::observe()` and the other one is kept around to read the events.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct EventLog {
+    events: Arc<Mutex<VecDeque<Event>>>,
+    capacity: usize,
+}
+
+#[cfg(feature = "std")]
+impl EventLog {
+    /// Create a new `EventLog` which keeps the last `capacity` events.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // a poisoned lock only means an observer call panicked halfway through a push, the buffer
+    // itself is still fine
+    fn lock(&self) -> MutexGuard<'_, VecDeque<Event>> {
+        self.events.lock().unwrap_or_else(|x| x.into_inner())
+    }
+
+    fn push(&self, event: Event) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut events = self.lock();
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// The events still in the buffer, oldest first.
+    pub fn events(&self) -> Vec<Event> {
+        self.lock().iter().cloned().collect()
+    }
+
+    /// Empty the buffer and return what was in it, oldest first.
+    pub fn drain(&self) -> Vec<Event> {
+        self.lock().drain(..).collect()
+    }
+}
+
+#[cfg(feature = "std")]
+impl AllocObserver for EventLog {
+    fn on_alloc(&mut self, handle: BlockHandle, range: &Range<u32>) {
+        self.push(Event::Alloc {
+            handle,
+            range: range.clone(),
+        });
+    }
+
+    fn on_free(&mut self, handle: BlockHandle, freed: FreeBlock) {
+        self.push(Event::Free { handle, freed });
+    }
+
+    fn on_share(&mut self, handle: BlockHandle, shared: BlockHandle) {
+        self.push(Event::Share { handle, shared });
+    }
+
+    fn on_transfer(&mut self, handle: BlockHandle, transferred: BlockHandle) {
+        self.push(Event::Transfer {
+            handle,
+            transferred,
+        });
+    }
+
+    fn on_merge(&mut self, first: BlockHandle, second: BlockHandle, range: &Range<u32>) {
+        self.push(Event::Merge {
+            first,
+            second,
+            range: range.clone(),
+        });
+    }
+
+    fn on_split(&mut self, handle: BlockHandle, second: BlockHandle) {
+        self.push(Event::Split { handle, second });
+    }
+
+    fn on_realloc(&mut self, handle: BlockHandle, old: &Range<u32>, new: &Range<u32>) {
+        self.push(Event::Realloc {
+            handle,
+            old: old.clone(),
+            new: new.clone(),
+        });
+    }
+
+    fn on_compact(&mut self, compaction: &Compaction) {
+        self.push(Event::Compact(compaction.clone()));
+    }
+
+    fn on_trim(&mut self, trimmed: u32) {
+        self.push(Event::Trim(trimmed));
+    }
+}
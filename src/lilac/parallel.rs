@@ -0,0 +1,233 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+
+use super::{AllocError, Allocator, BlockHandle, FreeBlock, Process, Result};
+
+/// A thread safe handle to an `Allocator`, cloning it gives another handle to the same
+/// allocator so several host threads can each service their own processes.
+///
+/// Everything an `Allocator` can do is done through `lock()` (or `with()`), which holds the lock
+/// for as long as the guard lives, or through `read()` for the calls which don't change the
+/// allocator, those can happen at the same time as each other and anything changing the
+/// allocator waits for all of them. The most common calls have shortcuts which take the lock
+/// just for themselves.
+///
+/// Threads can also sleep on an address until another thread wakes them up, see `futex_wait()`.
+#[derive(Debug, Clone, Default)]
+pub struct ParallelAlloc(Arc<RwLock<Allocator>>, Arc<Futexes>);
+
+// the threads sleeping in `ParallelAlloc::futex_wait()`, which can't wait on the lock of the
+// allocator itself since a condvar needs a mutex
+#[derive(Debug, Default)]
+struct Futexes {
+    state: Mutex<FutexState>,
+    woken: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct FutexState {
+    // the id of the next wait
+    next_id: u64,
+    // the waits on every address, oldest first, by block id and offset like the waiters of an
+    // `Allocator`
+    waiting: BTreeMap<(u64, u32), VecDeque<u64>>,
+    // the waits which were woken up but didn't notice yet
+    woken: BTreeSet<u64>,
+}
+
+impl ParallelAlloc {
+    /// Create a new `ParallelAlloc` around an empty `Allocator`.
+    pub fn new() -> Self {
+        Self::from_allocator(Allocator::new())
+    }
+
+    /// Share an existing `Allocator` between threads.
+    pub fn from_allocator(allocator: Allocator) -> Self {
+        Self(Arc::new(RwLock::new(allocator)), Arc::default())
+    }
+
+    /// Lock the allocator for reading, other threads can still read it at the same time but
+    /// can't change it until the guard is dropped.
+    ///
+    /// It errors if another thread panicked while holding the lock (`AllocError::Poisoned`),
+    /// which leaves the bookkeeping in a state nobody should keep using.
+    pub fn read(&self) -> Result<RwLockReadGuard<'_, Allocator>> {
+        self.0.read().map_err(|_| AllocError::Poisoned)
+    }
+
+    /// Lock the whole allocator until the guard is dropped, for changing it or for doing several
+    /// operations without other threads getting in between.
+    ///
+    /// It errors like `read()` does.
+    pub fn lock(&self) -> Result<RwLockWriteGuard<'_, Allocator>> {
+        self.0.write().map_err(|_| AllocError::Poisoned)
+    }
+
+    /// Run `f` with the whole allocator locked, see `lock()`.
+    ///
+    /// It errors like `read()` does.
+    pub fn with<R>(&self, f: impl FnOnce(&mut Allocator) -> R) -> Result<R> {
+        Ok(f(&mut *self.lock()?))
+    }
+
+    // the waits are only ever changed all at once, a thread panicking while holding them can't
+    // leave them half done. it's always taken before the allocator, never the other way around
+    fn futexes(&self) -> MutexGuard<'_, FutexState> {
+        self.1.state.lock().unwrap_or_else(|x| x.into_inner())
+    }
+
+    /// See `Allocator::register_process()`.
+    pub fn register_process(&self, process_id: Process) -> Result<Process> {
+        self.lock()?.register_process(process_id)
+    }
+
+    /// See `Allocator::alloc()`.
+    pub fn alloc(&self, process_id: Process, size: u32) -> Result<BlockHandle> {
+        self.lock()?.alloc(process_id, size)
+    }
+
+    /// See `Allocator::free()`.
+    pub fn free(&self, handle: BlockHandle) -> Result<FreeBlock> {
+        self.lock()?.free(handle)
+    }
+
+    /// See `Allocator::read_u32()`.
+    pub fn read_u32(&self, process_id: Process, addr: u32) -> Result<u32> {
+        self.read()?.read_u32(process_id, addr)
+    }
+
+    /// See `Allocator::write_u32()`.
+    pub fn write_u32(&self, process_id: Process, addr: u32, value: u32) -> Result<()> {
+        self.lock()?.write_u32(process_id, addr, value)
+    }
+
+    /// Immutably borrow a whole block like `Allocator::borrow()`, handing it to `f` so the lock
+    /// is only taken for as long as it runs.
+    ///
+    /// It errors like `read()` and `Allocator::borrow()` do.
+    pub fn borrow<R>(&self, handle: BlockHandle, f: impl FnOnce(&[u8]) -> R) -> Result<R> {
+        Ok(f(self.read()?.borrow(handle)?))
+    }
+
+    /// Mutably borrow a whole block like `Allocator::borrow_mut()`, handing it to `f` so the
+    /// lock is only taken for as long as it runs.
+    ///
+    /// It errors like `read()` and `Allocator::borrow_mut()` do.
+    pub fn borrow_mut<R>(&self, handle: BlockHandle, f: impl FnOnce(&mut [u8]) -> R) -> Result<R> {
+        Ok(f(self.lock()?.borrow_mut(handle)?))
+    }
+
+    /// Block the calling thread for as long as the little endian `u32` at `addr` of a process
+    /// is `expected` and nobody called `futex_wake()` on it, for building mutexes and condition
+    /// variables in shared blocks between host threads. It's the blocking version of
+    /// `Allocator::futex_wait()`, and the two don't see each other's waiters.
+    ///
+    /// It returns whether it was woken up, false means the value wasn't `expected` to begin
+    /// with. A wake can't get lost between checking the value and going to sleep, as long as the
+    /// value is changed before `futex_wake()` is called.
+    ///
+    /// It errors like `read()` and `Allocator::read_u32()` do.
+    pub fn futex_wait(&self, process_id: Process, addr: u32, expected: u32) -> Result<bool> {
+        self.wait(process_id, addr, expected, None)
+    }
+
+    /// Like `futex_wait()` but gives up after `timeout`, returning false like it does for a value
+    /// which wasn't `expected`.
+    ///
+    /// It errors like `futex_wait()` does.
+    pub fn futex_wait_timeout(
+        &self,
+        process_id: Process,
+        addr: u32,
+        expected: u32,
+        timeout: Duration,
+    ) -> Result<bool> {
+        self.wait(process_id, addr, expected, Some(Instant::now() + timeout))
+    }
+
+    fn wait(
+        &self,
+        process_id: Process,
+        addr: u32,
+        expected: u32,
+        deadline: Option<Instant>,
+    ) -> Result<bool> {
+        // the value is checked with the waits locked, so a wake can't come in before this one
+        // is in there
+        let mut state = self.futexes();
+        let key = {
+            let allocator = self.read()?;
+            if allocator.read_u32(process_id, addr)? != expected {
+                return Ok(false);
+            }
+            allocator.futex_key(process_id, addr)?
+        };
+
+        let id = state.next_id;
+        state.next_id += 1;
+        state.waiting.entry(key).or_default().push_back(id);
+
+        while !state.woken.remove(&id) {
+            let timeout = match deadline {
+                Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+                None => {
+                    state = self.1.woken.wait(state).unwrap_or_else(|x| x.into_inner());
+                    continue;
+                }
+            };
+
+            if timeout.is_zero() {
+                // nobody woke it up in time, so it's still waiting
+                if let Some(waiting) = state.waiting.get_mut(&key) {
+                    waiting.retain(|x| *x != id);
+                    if waiting.is_empty() {
+                        state.waiting.remove(&key);
+                    }
+                }
+
+                return Ok(false);
+            }
+
+            state = self
+                .1
+                .woken
+                .wait_timeout(state, timeout)
+                .unwrap_or_else(|x| x.into_inner())
+                .0;
+        }
+
+        Ok(true)
+    }
+
+    /// Wake up to `count` of the threads waiting on `addr` of a process in `futex_wait()`, the
+    /// ones which have been waiting the longest first, and return how many were woken up.
+    ///
+    /// It errors like `read()` and `Allocator::futex_wake()` do.
+    pub fn futex_wake(&self, process_id: Process, addr: u32, count: u32) -> Result<u32> {
+        let mut guard = self.futexes();
+        let key = self.read()?.futex_key(process_id, addr)?;
+
+        let state = &mut *guard;
+        let mut woken = 0;
+        if let Some(waiting) = state.waiting.get_mut(&key) {
+            while woken < count {
+                match waiting.pop_front() {
+                    Some(id) => state.woken.insert(id),
+                    None => break,
+                };
+                woken += 1;
+            }
+
+            if waiting.is_empty() {
+                state.waiting.remove(&key);
+            }
+        }
+
+        if woken > 0 {
+            self.1.woken.notify_all();
+        }
+
+        Ok(woken)
+    }
+}
@@ -0,0 +1,603 @@
+use alloc::collections::BTreeMap;
+use alloc::{string::String, vec, vec::Vec};
+use core::fmt;
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::fs::{self, File};
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+use super::snapshot::BlockState;
+use super::virt::Mapping;
+#[cfg(feature = "std")]
+use super::Allocator;
+use super::{BlockHandle, Process, Protection, Snapshot, Strategy};
+use crate::image::crc32;
+
+/// The first bytes of every heap image.
+pub const HEAP_MAGIC: [u8; 4] = *b"LHEP";
+const VERSION: u8 = 9;
+
+// magic, version, flags, strategy, checksum, limit, guard, next id, heap length, and the
+// process, free block, tag, guard, parent, freed handle and weak share counts
+const HEADER_V1_LEN: usize = 4 + 1 + 1 + 1 + 4 + 4 + 4 + 8 + 4 + 4 * 7;
+// the version 1 header, segment count
+const HEADER_V2_LEN: usize = HEADER_V1_LEN + 4;
+// the version 2 header, generation count
+const HEADER_V3_LEN: usize = HEADER_V2_LEN + 4;
+// the version 3 header, protection count
+const HEADER_V4_LEN: usize = HEADER_V3_LEN + 4;
+// the version 4 header, mapping count
+const HEADER_V5_LEN: usize = HEADER_V4_LEN + 4;
+// the version 5 header, named heap count
+const HEADER_V6_LEN: usize = HEADER_V5_LEN + 4;
+// the version 6 header, low water mark
+const HEADER_V7_LEN: usize = HEADER_V6_LEN + 4;
+// the version 7 header, alignment count
+const HEADER_LEN: usize = HEADER_V7_LEN + 4;
+// where the checksum is in the header
+const CHECKSUM_AT: usize = 7;
+
+// the bits of the flags byte
+const BUDDY: u8 = 1;
+const POISON: u8 = 1 << 1;
+const ZERO_ON_ALLOC: u8 = 1 << 2;
+const LIMIT: u8 = 1 << 3;
+const LOW_WATER: u8 = 1 << 4;
+const FILL_FRESH: u8 = 1 << 5;
+
+// the bits of the flags byte of a block
+const READ_ONLY: u8 = 1;
+const WINDOW: u8 = 1 << 1;
+
+// the bits of a protection
+const READ: u8 = 1;
+const WRITE: u8 = 1 << 1;
+const EXECUTE: u8 = 1 << 2;
+
+/// Everything that can go wrong reading a heap image, see `Snapshot::from_bytes()`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum HeapImageError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    /// (checksum in the header, checksum of the bytes)
+    BadChecksum(u32, u32),
+    BadStrategy(u8),
+    /// The label of the block with this id isn't UTF-8.
+    BadTag(u64),
+    /// The name of the segment of the block with this id isn't UTF-8.
+    BadSegmentName(u64),
+    /// The name of the named heap at this address isn't UTF-8.
+    BadHeapName(u32),
+}
+
+impl core::error::Error for HeapImageError {}
+
+impl fmt::Display for HeapImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeapImageError::BadMagic => write!(f, "this is not a lilac heap image"),
+            HeapImageError::UnsupportedVersion(version) => {
+                write!(f, "heap image version {} is not supported", version)
+            }
+            HeapImageError::Truncated => write!(f, "the heap image ends unexpectedly"),
+            HeapImageError::BadChecksum(expected, actual) => write!(
+                f,
+                "the heap image is corrupted, its checksum is {:#010x} but it should be {:#010x}",
+                actual, expected
+            ),
+            HeapImageError::BadStrategy(strategy) => {
+                write!(f, "unknown allocation strategy {:#04x}", strategy)
+            }
+            HeapImageError::BadTag(id) => write!(f, "the label of block {} isn't UTF-8", id),
+            HeapImageError::BadSegmentName(id) => {
+                write!(f, "the segment name of block {} isn't UTF-8", id)
+            }
+            HeapImageError::BadHeapName(start) => {
+                write!(f, "the name of the named heap at {:#x} isn't UTF-8", start)
+            }
+        }
+    }
+}
+
+fn strategy_to_byte(strategy: Strategy) -> u8 {
+    match strategy {
+        Strategy::FirstFit => 0,
+        Strategy::BestFit => 1,
+        Strategy::WorstFit => 2,
+    }
+}
+
+fn strategy_from_byte(byte: u8) -> Result<Strategy, HeapImageError> {
+    match byte {
+        0 => Ok(Strategy::FirstFit),
+        1 => Ok(Strategy::BestFit),
+        2 => Ok(Strategy::WorstFit),
+        _ => Err(HeapImageError::BadStrategy(byte)),
+    }
+}
+
+fn write_range(out: &mut Vec<u8>, range: &Range<u32>) {
+    out.extend_from_slice(&range.start.to_le_bytes());
+    out.extend_from_slice(&range.end.to_le_bytes());
+}
+
+fn write_string(out: &mut Vec<u8>, string: &str) {
+    out.extend_from_slice(&(string.len() as u32).to_le_bytes());
+    out.extend_from_slice(string.as_bytes());
+}
+
+fn write_process(out: &mut Vec<u8>, process_id: &Process) {
+    out.extend_from_slice(&process_id.id.to_le_bytes());
+    out.extend_from_slice(&process_id.generation.to_le_bytes());
+}
+
+fn write_handle(out: &mut Vec<u8>, handle: &BlockHandle) {
+    write_process(out, &handle.process_id);
+    out.extend_from_slice(&handle.id.to_le_bytes());
+}
+
+// reads the image front to back, everything running past the end of it is `Truncated`
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+    // whether processes have a generation, which they only do since version 3
+    generations: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], HeapImageError> {
+        let bytes = self
+            .at
+            .checked_add(len)
+            .and_then(|end| self.bytes.get(self.at..end))
+            .ok_or(HeapImageError::Truncated)?;
+        self.at += len;
+
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, HeapImageError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, HeapImageError> {
+        // safe to unwrap because `bytes()` returns exactly as many bytes as asked for
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, HeapImageError> {
+        // safe to unwrap because `bytes()` returns exactly as many bytes as asked for
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    // `err` is what to error with if it isn't UTF-8
+    fn string(&mut self, err: HeapImageError) -> Result<String, HeapImageError> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| err)
+    }
+
+    fn range(&mut self) -> Result<Range<u32>, HeapImageError> {
+        Ok(self.u32()?..self.u32()?)
+    }
+
+    fn process(&mut self) -> Result<Process, HeapImageError> {
+        let id = self.u32()?;
+        let generation = if self.generations { self.u32()? } else { 0 };
+
+        Ok(Process { id, generation })
+    }
+
+    fn handle(&mut self) -> Result<BlockHandle, HeapImageError> {
+        Ok(BlockHandle {
+            process_id: self.process()?,
+            id: self.u64()?,
+        })
+    }
+
+    // how many entries of at least `len` bytes follow, a count the rest of the image can't
+    // possibly hold is `Truncated` right away instead of a huge allocation
+    fn count(&mut self, len: usize) -> Result<usize, HeapImageError> {
+        let count = self.u32()? as usize;
+        if count.saturating_mul(len) > self.bytes.len() {
+            return Err(HeapImageError::Truncated);
+        }
+
+        Ok(count)
+    }
+}
+
+impl Snapshot {
+    /// Serialize the snapshot into a heap image, all the integers are little endian and there's
+    /// a CRC32 of all of it in the header so `from_bytes()` can tell if it got corrupted.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = 0;
+        if self.buddy {
+            flags |= BUDDY;
+        }
+        if self.poison {
+            flags |= POISON;
+        }
+        if self.zero_on_alloc {
+            flags |= ZERO_ON_ALLOC;
+        }
+        if self.fill_fresh {
+            flags |= FILL_FRESH;
+        }
+        if self.limit.is_some() {
+            flags |= LIMIT;
+        }
+        if self.low_water.is_some() {
+            flags |= LOW_WATER;
+        }
+
+        let mut out = vec![];
+        out.extend_from_slice(&HEAP_MAGIC);
+        out.push(VERSION);
+        out.push(flags);
+        out.push(strategy_to_byte(self.strategy));
+        // the checksum gets filled in once everything else is there
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&self.limit.unwrap_or(0).to_le_bytes());
+        out.extend_from_slice(&self.guard.to_le_bytes());
+        out.extend_from_slice(&self.next_id.to_le_bytes());
+        out.extend_from_slice(&(self.heap.len() as u32).to_le_bytes());
+        for count in [
+            self.blocks.len(),
+            self.free.len(),
+            self.tags.len(),
+            self.guards.len(),
+            self.parents.len(),
+            self.recently_freed.len(),
+            self.weak.len(),
+            self.segments.len(),
+            self.generations.len(),
+            self.protections.len(),
+            self.spaces.values().map(|x| x.len()).sum(),
+            self.heaps.len(),
+        ] {
+            out.extend_from_slice(&(count as u32).to_le_bytes());
+        }
+        out.extend_from_slice(&self.low_water.unwrap_or(0).to_le_bytes());
+        out.extend_from_slice(&(self.aligns.len() as u32).to_le_bytes());
+
+        out.extend_from_slice(&self.heap);
+
+        for (process_id, blocks) in &self.blocks {
+            write_process(&mut out, process_id);
+            out.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+
+            for block in blocks {
+                let mut flags = 0;
+                if block.read_only {
+                    flags |= READ_ONLY;
+                }
+                if block.parent.is_some() {
+                    flags |= WINDOW;
+                }
+
+                out.extend_from_slice(&block.id.to_le_bytes());
+                write_range(&mut out, &block.range);
+                out.push(flags);
+                out.extend_from_slice(&block.parent.unwrap_or(0).to_le_bytes());
+            }
+        }
+
+        for range in &self.free {
+            write_range(&mut out, range);
+        }
+
+        for (id, tag) in &self.tags {
+            out.extend_from_slice(&id.to_le_bytes());
+            write_string(&mut out, tag);
+        }
+
+        for map in [&self.guards, &self.parents] {
+            for (id, range) in map {
+                out.extend_from_slice(&id.to_le_bytes());
+                write_range(&mut out, range);
+            }
+        }
+
+        for (handle, start) in &self.recently_freed {
+            write_handle(&mut out, handle);
+            out.extend_from_slice(&start.to_le_bytes());
+        }
+
+        for (handle, read_only) in &self.weak {
+            write_handle(&mut out, handle);
+            out.push(*read_only as u8);
+        }
+
+        for (name, id) in &self.segments {
+            out.extend_from_slice(&id.to_le_bytes());
+            write_string(&mut out, name);
+        }
+
+        for (id, generation) in &self.generations {
+            out.extend_from_slice(&id.to_le_bytes());
+            out.extend_from_slice(&generation.to_le_bytes());
+        }
+
+        for (id, protection) in &self.protections {
+            let mut bits = 0;
+            if protection.read {
+                bits |= READ;
+            }
+            if protection.write {
+                bits |= WRITE;
+            }
+            if protection.execute {
+                bits |= EXECUTE;
+            }
+
+            out.extend_from_slice(&id.to_le_bytes());
+            out.push(bits);
+        }
+
+        for (process_id, space) in &self.spaces {
+            for (addr, mapping) in space {
+                write_process(&mut out, process_id);
+                out.extend_from_slice(&addr.to_le_bytes());
+                out.extend_from_slice(&mapping.id.to_le_bytes());
+                out.extend_from_slice(&mapping.len.to_le_bytes());
+            }
+        }
+
+        for (name, range) in &self.heaps {
+            write_range(&mut out, range);
+            write_string(&mut out, name);
+        }
+
+        for (id, align) in &self.aligns {
+            out.extend_from_slice(&id.to_le_bytes());
+            out.extend_from_slice(&align.to_le_bytes());
+        }
+
+        let checksum = crc32(&out);
+        out[CHECKSUM_AT..CHECKSUM_AT + 4].copy_from_slice(&checksum.to_le_bytes());
+
+        out
+    }
+
+    /// Deserialize a heap image written by `to_bytes()`, images from before version 2 don't have
+    /// any segments, ones from before version 3 only have processes of the first generation
+    /// (see `Allocator::register_process()`), ones from before version 4 only have blocks with
+    /// the default protection, ones from before version 5 don't have any mappings, ones from
+    /// before version 6 don't have any named heaps, ones from before version 7 don't have a
+    /// low water mark, the ranges in ones from before version 8 end on their last byte
+    /// instead of after it (`Allocator::restore()` turns them into half-open ones) and ones from
+    /// before version 9 don't know the alignment of any block.
+    ///
+    /// It errors if the bytes aren't a heap image of a supported version, if they end before
+    /// everything the header says is there, if the checksum doesn't match or if the strategy, a
+    /// label, a segment name or a heap name can't be made sense of. Whether the bookkeeping in it
+    /// holds together is only checked by `Allocator::restore()`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, HeapImageError> {
+        if !bytes.starts_with(&HEAP_MAGIC) {
+            return Err(HeapImageError::BadMagic);
+        }
+        if bytes.len() < HEADER_V1_LEN {
+            return Err(HeapImageError::Truncated);
+        }
+
+        let version = bytes[4];
+        let header_len = match version {
+            1 => HEADER_V1_LEN,
+            2 => HEADER_V2_LEN,
+            3 => HEADER_V3_LEN,
+            4 => HEADER_V4_LEN,
+            5 => HEADER_V5_LEN,
+            6 => HEADER_V6_LEN,
+            // only the ranges changed in version 8, the header didn't
+            7 | 8 => HEADER_V7_LEN,
+            VERSION => HEADER_LEN,
+            _ => return Err(HeapImageError::UnsupportedVersion(version)),
+        };
+        if bytes.len() < header_len {
+            return Err(HeapImageError::Truncated);
+        }
+
+        let mut reader = Reader {
+            bytes,
+            at: 5,
+            generations: version >= 3,
+        };
+        let flags = reader.u8()?;
+        let strategy = strategy_from_byte(reader.u8()?)?;
+
+        let expected = reader.u32()?;
+        let mut covered = bytes.to_vec();
+        covered[CHECKSUM_AT..CHECKSUM_AT + 4].fill(0);
+        let actual = crc32(&covered);
+        if actual != expected {
+            return Err(HeapImageError::BadChecksum(expected, actual));
+        }
+
+        let limit = reader.u32()?;
+        let guard = reader.u32()?;
+        let next_id = reader.u64()?;
+        let heap_len = reader.u32()? as usize;
+        let process_count = reader.count(8)?;
+        let free_count = reader.count(8)?;
+        let tag_count = reader.count(12)?;
+        let guard_count = reader.count(16)?;
+        let parent_count = reader.count(16)?;
+        let freed_count = reader.count(16)?;
+        let weak_count = reader.count(13)?;
+        let segment_count = if version == 1 { 0 } else { reader.count(12)? };
+        let generation_count = if version < 3 { 0 } else { reader.count(8)? };
+        let protection_count = if version < 4 { 0 } else { reader.count(9)? };
+        let mapping_count = if version < 5 { 0 } else { reader.count(24)? };
+        let heap_count = if version < 6 { 0 } else { reader.count(12)? };
+        let low_water = if version < 7 { 0 } else { reader.u32()? };
+        let align_count = if version < 9 { 0 } else { reader.count(12)? };
+
+        let heap = reader.bytes(heap_len)?.to_vec();
+
+        let mut blocks = BTreeMap::new();
+        for _ in 0..process_count {
+            let process_id = reader.process()?;
+            let block_count = reader.count(25)?;
+
+            let mut states = Vec::with_capacity(block_count);
+            for _ in 0..block_count {
+                let id = reader.u64()?;
+                let range = reader.range()?;
+                let flags = reader.u8()?;
+                let parent = reader.u64()?;
+
+                states.push(BlockState {
+                    id,
+                    range,
+                    parent: (flags & WINDOW != 0).then_some(parent),
+                    read_only: flags & READ_ONLY != 0,
+                });
+            }
+            blocks.insert(process_id, states);
+        }
+
+        let free = (0..free_count)
+            .map(|_| reader.range())
+            .collect::<Result<_, _>>()?;
+
+        let mut tags = BTreeMap::new();
+        for _ in 0..tag_count {
+            let id = reader.u64()?;
+            tags.insert(id, reader.string(HeapImageError::BadTag(id))?);
+        }
+
+        let mut guards = BTreeMap::new();
+        for _ in 0..guard_count {
+            guards.insert(reader.u64()?, reader.range()?);
+        }
+
+        let mut parents = BTreeMap::new();
+        for _ in 0..parent_count {
+            parents.insert(reader.u64()?, reader.range()?);
+        }
+
+        let recently_freed = (0..freed_count)
+            .map(|_| Ok((reader.handle()?, reader.u32()?)))
+            .collect::<Result<_, _>>()?;
+
+        let weak = (0..weak_count)
+            .map(|_| Ok((reader.handle()?, reader.u8()? != 0)))
+            .collect::<Result<_, _>>()?;
+
+        let mut segments = BTreeMap::new();
+        for _ in 0..segment_count {
+            let id = reader.u64()?;
+            segments.insert(reader.string(HeapImageError::BadSegmentName(id))?, id);
+        }
+
+        let mut generations = BTreeMap::new();
+        for _ in 0..generation_count {
+            generations.insert(reader.u32()?, reader.u32()?);
+        }
+
+        let mut protections = BTreeMap::new();
+        for _ in 0..protection_count {
+            let id = reader.u64()?;
+            let bits = reader.u8()?;
+            protections.insert(
+                id,
+                Protection {
+                    read: bits & READ != 0,
+                    write: bits & WRITE != 0,
+                    execute: bits & EXECUTE != 0,
+                },
+            );
+        }
+
+        let mut spaces: BTreeMap<Process, BTreeMap<u32, Mapping>> = BTreeMap::new();
+        for _ in 0..mapping_count {
+            let process_id = reader.process()?;
+            let addr = reader.u32()?;
+            let mapping = Mapping {
+                id: reader.u64()?,
+                len: reader.u32()?,
+            };
+            spaces.entry(process_id).or_default().insert(addr, mapping);
+        }
+
+        let mut heaps = BTreeMap::new();
+        for _ in 0..heap_count {
+            let range = reader.range()?;
+            let name = reader.string(HeapImageError::BadHeapName(range.start))?;
+            heaps.insert(name, range);
+        }
+
+        let mut aligns = BTreeMap::new();
+        for _ in 0..align_count {
+            aligns.insert(reader.u64()?, reader.u32()?);
+        }
+
+        Ok(Snapshot {
+            heap,
+            blocks,
+            free,
+            buddy: flags & BUDDY != 0,
+            strategy,
+            next_id,
+            limit: (flags & LIMIT != 0).then_some(limit),
+            low_water: (flags & LOW_WATER != 0).then_some(low_water),
+            tags,
+            guard,
+            guards,
+            poison: flags & POISON != 0,
+            zero_on_alloc: flags & ZERO_ON_ALLOC != 0,
+            fill_fresh: flags & FILL_FRESH != 0,
+            parents,
+            recently_freed,
+            weak,
+            segments,
+            generations,
+            protections,
+            aligns,
+            spaces,
+            heaps,
+            // the ranges of images from before version 8 end on their last byte, they're moved
+            // over by `Allocator::restore()`
+            half_open: version >= 8,
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl Allocator {
+    /// Write a heap image of the allocator (see `Snapshot::to_bytes()`) to `path`, so a long
+    /// running simulation can carry on from it after the host restarts, see `load_image()`.
+    ///
+    /// The image is written next to `path` first and only renamed over it once it's all on
+    /// disk, a crash halfway through leaves the last image as it was.
+    ///
+    /// It errors if the file couldn't be written.
+    pub fn save_image(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+
+        let mut file = File::create(&tmp)?;
+        file.write_all(&self.snapshot().to_bytes())?;
+        file.sync_all()?;
+
+        fs::rename(&tmp, path)
+    }
+
+    /// Create an `Allocator` from a heap image written by `save_image()`.
+    ///
+    /// It errors if the file couldn't be read, or with `io::ErrorKind::InvalidData` if it isn't
+    /// a valid heap image (with the `HeapImageError` inside) or the allocator in it doesn't
+    /// hold together (with the `AllocError` from `restore()` inside).
+    pub fn load_image(path: impl AsRef<Path>) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let snapshot = Snapshot::from_bytes(&bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        Self::restore(snapshot).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
@@ -0,0 +1,141 @@
+use alloc::{boxed::Box, string::String};
+use core::fmt;
+
+use super::{AllocError, Allocator, Result};
+
+/// Why a `PressureHandler` was called.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Pressure {
+    /// An allocation of `size` bytes found no room, even after growing the heap as far as it
+    /// goes.
+    OutOfMemory { size: u32 },
+    /// An allocation of `size` bytes found no room in the named heap `heap`.
+    HeapFull { heap: String, size: u32 },
+    /// The headroom dropped below the low water mark, see `Allocator::set_low_water()`.
+    Low { headroom: u32 },
+}
+
+/// Something that gets a chance to make room when an `Allocator` runs short on memory, by
+/// dropping caches of the VM, freeing blocks or compacting the heap, see
+/// `Allocator::set_pressure_handler()`.
+pub trait PressureHandler: fmt::Debug + Send + Sync {
+    /// Make room if it can, and return whether it's worth trying the allocation again. The
+    /// return value doesn't matter for `Pressure::Low`, since nothing failed.
+    ///
+    /// The handler isn't called again while it runs, an allocation failing in here just fails.
+    fn on_pressure(&mut self, allocator: &mut Allocator, pressure: Pressure) -> bool;
+}
+
+impl Allocator {
+    /// Have `handler` called whenever an allocation fails for lack of room, before the error
+    /// is handed back, and whenever the headroom drops below the low water mark. If it says so
+    /// the allocation is tried once more, so freeing or compacting in there makes it go through.
+    ///
+    /// Failing is `AllocError::OutOfMemory` or `AllocError::HeapFull` from `alloc()`,
+    /// `alloc_aligned()`, `alloc_in()`, `realloc()` or `create_heap()`, or anything built on
+    /// them. There's only ever one handler, this replaces the one there was.
+    pub fn set_pressure_handler(&mut self, handler: impl PressureHandler + 'static) {
+        self.pressure_handler = Some(Box::new(handler));
+    }
+
+    /// Take the handler set with `set_pressure_handler()` off, and return it.
+    pub fn remove_pressure_handler(&mut self) -> Option<Box<dyn PressureHandler>> {
+        self.pressure_handler.take()
+    }
+
+    /// The headroom below which the pressure handler is told about it, see `set_low_water()`.
+    pub fn low_water(&self) -> Option<u32> {
+        self.low_water
+    }
+
+    /// Have the pressure handler called with `Pressure::Low` once an allocation leaves less
+    /// than `low_water` bytes of headroom (see `headroom()`), `None` turns it off. It's called
+    /// once every time the headroom drops below it, not for every allocation while it stays
+    /// below.
+    ///
+    /// Working out the headroom walks the free blocks, so every allocation gets a bit slower
+    /// while there's a low water mark.
+    pub fn set_low_water(&mut self, low_water: Option<u32>) {
+        self.low_water = low_water;
+        self.under_pressure = false;
+    }
+
+    /// How many more bytes can be allocated before running out, the free bytes along with how
+    /// much further the heap can grow. It's `None` for an allocator without a limit, which
+    /// never runs out. The free bytes of named heaps don't count, see `heap_free()` for those.
+    pub fn headroom(&self) -> Option<u32> {
+        let limit = self.limit?;
+        let free: u32 = match &self.buddy {
+            Some(buddy) => buddy.iter().map(|x| x.end - x.start).sum(),
+            None => self.free.iter().map(|x| x.end - x.start).sum(),
+        };
+
+        Some(free.saturating_add(limit.saturating_sub(self.heap.len() as u32)))
+    }
+
+    // run the allocation `f`, and if it fails for lack of room tell the handler and run it once
+    // more if the handler made room. it's only ever run at the top of a public call, so the
+    // handler can move blocks around without pulling them out from under an allocation
+    pub(super) fn relieve<T>(&mut self, mut f: impl FnMut(&mut Self) -> Result<T>) -> Result<T> {
+        let result = f(self);
+        let pressure = match &result {
+            Ok(_) => {
+                self.check_low_water();
+                return result;
+            }
+            Err(AllocError::OutOfMemory { size, .. }) => Pressure::OutOfMemory { size: *size },
+            Err(AllocError::HeapFull { heap, size }) => Pressure::HeapFull {
+                heap: heap.clone(),
+                size: *size,
+            },
+            Err(_) => return result,
+        };
+
+        if self.call_pressure_handler(pressure) {
+            let result = f(self);
+            if result.is_ok() {
+                self.check_low_water();
+            }
+            result
+        } else {
+            result
+        }
+    }
+
+    // tell the handler about the headroom once it drops below the low water mark
+    fn check_low_water(&mut self) {
+        let low_water = match self.low_water {
+            Some(low_water) => low_water,
+            None => return,
+        };
+        // an allocator without a limit never runs low
+        let headroom = match self.headroom() {
+            Some(headroom) => headroom,
+            None => return,
+        };
+
+        if headroom >= low_water {
+            self.under_pressure = false;
+        } else if !self.under_pressure && self.pressure_handler.is_some() {
+            self.under_pressure = true;
+            self.call_pressure_handler(Pressure::Low { headroom });
+        }
+    }
+
+    // the handler is taken out while it runs, so it can have the allocator and isn't called
+    // again by whatever it allocates
+    fn call_pressure_handler(&mut self, pressure: Pressure) -> bool {
+        let mut handler = match self.pressure_handler.take() {
+            Some(handler) => handler,
+            None => return false,
+        };
+
+        let retry = handler.on_pressure(self, pressure);
+        // unless it put another handler in its place
+        if self.pressure_handler.is_none() {
+            self.pressure_handler = Some(handler);
+        }
+
+        retry
+    }
+}
@@ -0,0 +1,81 @@
+use core::ops::Range;
+
+use super::{Access, AllocError, Allocator, BlockHandle, MemRange, Process, Protection, Result};
+
+impl Allocator {
+    /// Set what the processes holding the block of `handle` may do with it, like `mprotect()`
+    /// does for a page. Every process sharing the block is held to it, along with the windows
+    /// into it, on top of what they were shared with (a read only share stays read only).
+    ///
+    /// Blocks start out `Protection::READ_WRITE`, so nothing on the heap can be run as code
+    /// until it's made executable, see `fetch()`.
+    ///
+    /// It errors like `range()` does, or if the process can only read the block, only holds a
+    /// window into it or only has a weak share of it (`AllocError::PermissionDenied`), since any
+    /// of them would let it grant itself more than it was given.
+    pub fn protect(&mut self, handle: BlockHandle, protection: Protection) -> Result<()> {
+        // a weak share doesn't hold the block, so it has no say over it either
+        if self.is_weak(handle) {
+            return Err(AllocError::PermissionDenied {
+                process_id: handle.process_id,
+                range: self.range(handle)?,
+                access: Access::Write,
+            });
+        }
+
+        let idx = self.find(handle)?;
+        let block = &self.allocated[&handle.process_id][idx];
+        if block.read_only || block.parent.is_some() {
+            return Err(AllocError::PermissionDenied {
+                process_id: handle.process_id,
+                range: block.range.clone(),
+                access: Access::Write,
+            });
+        }
+
+        if protection == Protection::default() {
+            self.protections.remove(&handle.id);
+        } else {
+            self.protections.insert(handle.id, protection);
+        }
+
+        Ok(())
+    }
+
+    /// What the processes holding the block of `handle` may do with it, see `protect()`.
+    ///
+    /// It errors like `range()` does.
+    pub fn protection(&self, handle: BlockHandle) -> Result<Protection> {
+        let handle = self.strong(handle)?;
+        let idx = self.find(handle)?;
+        let (id, _) = self.block_of(&self.allocated[&handle.process_id][idx]);
+
+        Ok(self.protections.get(&id).copied().unwrap_or_default())
+    }
+
+    // whether the process holding `block` may do `access` to it, which is up to the protection
+    // of the whole block and, for writing, whether it was shared read only
+    pub(super) fn allows(&self, block: &MemRange, access: Access) -> bool {
+        let (id, _) = self.block_of(block);
+        let protection = self.protections.get(&id).copied().unwrap_or_default();
+
+        match access {
+            Access::Read => protection.read,
+            Access::Write => protection.write && !block.read_only,
+            Access::Execute => protection.execute,
+        }
+    }
+
+    /// Borrow `range` of a process to run it as code, which is how `Program::step_heap()` fetches
+    /// its instructions. Unlike `range_borrow()` the block has to be executable and doesn't have
+    /// to be readable, see `protect()`.
+    ///
+    /// It errors like `range_borrow()` does, where the block not being executable is
+    /// `AllocError::PermissionDenied`.
+    pub fn fetch(&self, process_id: Process, range: Range<u32>) -> Result<&[u8]> {
+        self.check_access(process_id, &range, Access::Execute)?;
+        self.check_conflicts(&range, false, None)?;
+
+        Ok(&self.heap[range.start as usize..range.end as usize])
+    }
+}
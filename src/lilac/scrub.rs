@@ -0,0 +1,34 @@
+use core::ptr;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use super::Allocator;
+
+// overwrite `bytes` with zeroes in a way the compiler isn't allowed to leave out, even when it
+// can tell that nothing reads them before they're written again
+pub(super) fn zeroize(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        // safe because the pointer comes from a mutable reference, so it's valid and aligned
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+
+    // and keep whatever gets written to the heap next from being moved before the zeroes
+    compiler_fence(Ordering::SeqCst);
+}
+
+impl Allocator {
+    /// Zero every free byte of the heap with volatile writes like `free_secure()` does, and
+    /// return how many bytes that was.
+    ///
+    /// Blocks moved by `realloc()` or `compact()` leave a copy of their old contents behind in
+    /// what's now free memory, and so do blocks freed with just `free()`, this gets rid of all of
+    /// them at once.
+    pub fn scrub_free(&mut self) -> u32 {
+        let mut scrubbed = 0;
+        for range in self.free_ranges() {
+            zeroize(&mut self.heap[range.start as usize..range.end as usize]);
+            scrubbed += range.end - range.start;
+        }
+
+        scrubbed
+    }
+}
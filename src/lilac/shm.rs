@@ -0,0 +1,104 @@
+use alloc::string::ToString;
+
+use super::{AllocError, Allocator, BlockHandle, Process, Result};
+
+impl Allocator {
+    /// Allocate a block of `size` bytes for the process and give it a `name`, like `"vram"`, so
+    /// other processes can get at it with `attach_segment()` without having to be told where
+    /// it is. The block is labeled with the name too (see `set_tag()`).
+    ///
+    /// The name stays around until it's unlinked with `unlink_segment()` or every process
+    /// holding the block freed it.
+    ///
+    /// It errors like `alloc()` does, or if there's a segment with that name already
+    /// (`AllocError::SegmentExists`).
+    pub fn create_segment(
+        &mut self,
+        process_id: Process,
+        name: &str,
+        size: u32,
+    ) -> Result<BlockHandle> {
+        if self.segments.contains_key(name) {
+            return Err(AllocError::SegmentExists(name.to_string()));
+        }
+
+        let handle = self.alloc(process_id, size)?;
+        self.segments.insert(name.to_string(), handle.id);
+        self.tags.insert(handle.id, name.to_string());
+
+        Ok(handle)
+    }
+
+    /// Share the segment called `name` with the process, see `create_segment()`.
+    ///
+    /// It errors like `share()` does, or if there's no segment with that name
+    /// (`AllocError::NoSuchSegment`).
+    pub fn attach_segment(&mut self, process_id: Process, name: &str) -> Result<BlockHandle> {
+        self.attach(process_id, name, false)
+    }
+
+    /// Like `attach_segment()` but the process can only read the segment, see
+    /// `share_read_only()`.
+    ///
+    /// It errors like `attach_segment()` does.
+    pub fn attach_segment_read_only(
+        &mut self,
+        process_id: Process,
+        name: &str,
+    ) -> Result<BlockHandle> {
+        self.attach(process_id, name, true)
+    }
+
+    fn attach(&mut self, process_id: Process, name: &str, read_only: bool) -> Result<BlockHandle> {
+        let id = *self
+            .segments
+            .get(name)
+            .ok_or_else(|| AllocError::NoSuchSegment(name.to_string()))?;
+
+        // share it from a process which may write to it if there is one, so the new share isn't
+        // stuck being read only
+        let holder = self
+            .allocated
+            .iter()
+            .flat_map(|(process_id, blocks)| blocks.iter().map(move |x| (*process_id, x)))
+            .filter(|(_, x)| x.id == id)
+            .min_by_key(|(_, x)| x.read_only)
+            .map(|(process_id, _)| BlockHandle { process_id, id })
+            .ok_or_else(|| AllocError::NoSuchSegment(name.to_string()))?;
+
+        self.share_inner(holder, process_id, read_only)
+    }
+
+    /// Take the name off a segment, so it can't be attached to anymore and the name can be used
+    /// for a new one. The processes already holding the block keep it until they free it.
+    ///
+    /// It errors if there's no segment with that name (`AllocError::NoSuchSegment`).
+    pub fn unlink_segment(&mut self, name: &str) -> Result<()> {
+        match self.segments.remove(name) {
+            Some(_) => Ok(()),
+            None => Err(AllocError::NoSuchSegment(name.to_string())),
+        }
+    }
+
+    /// The names of all the segments, sorted.
+    pub fn segments(&self) -> impl Iterator<Item = &str> + '_ {
+        self.segments.keys().map(|x| x.as_str())
+    }
+
+    /// The segment name of the block of `handle`, if it has one.
+    ///
+    /// It errors like `range()` does.
+    pub fn segment_name(&self, handle: BlockHandle) -> Result<Option<&str>> {
+        self.range(handle)?;
+        Ok(self
+            .segments
+            .iter()
+            .find(|x| *x.1 == handle.id)
+            .map(|x| x.0.as_str()))
+    }
+
+    // forget the name of the block with `id`, if it has one, once nobody holds it anymore
+    pub(super) fn drop_segment(&mut self, id: u64) {
+        self.segments.retain(|_, x| *x != id);
+    }
+}
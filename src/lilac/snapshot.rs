@@ -0,0 +1,283 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::{string::String, vec::Vec};
+use core::ops::Range;
+use core::sync::atomic::{AtomicU32, Ordering};
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use super::buddy::Buddy;
+use super::free::FreeList;
+use super::named::NamedHeap;
+use super::virt::Mapping;
+use super::{
+    AllocError, Allocator, BlockHandle, MemRange, Process, Protection, Result, Strategy, Violation,
+};
+
+/// One block a process holds, as kept in a `Snapshot`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub(super) struct BlockState {
+    pub(super) id: u64,
+    pub(super) range: Range<u32>,
+    pub(super) parent: Option<u64>,
+    pub(super) read_only: bool,
+}
+
+/// Everything an `Allocator` knows, the heap and all of its bookkeeping, for checkpointing it
+/// alongside the rest of the VM and picking up where it left off later, see
+/// `Allocator::snapshot()`.
+///
+/// Refcounts aren't kept, they're however many processes hold a block so `Allocator::restore()`
+/// counts them again. Observers, the pressure handler, live `BorrowToken`s, processes waiting in
+/// `Allocator::futex_wait()`, process groups and which process started which aren't part of it
+/// either.
+///
+/// With the `serde` feature it (and `Allocator` itself, through it) can be serialized with any
+/// serde format.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Snapshot {
+    pub(super) heap: Vec<u8>,
+    pub(super) blocks: BTreeMap<Process, Vec<BlockState>>,
+    // sorted by address
+    pub(super) free: Vec<Range<u32>>,
+    pub(super) buddy: bool,
+    pub(super) strategy: Strategy,
+    pub(super) next_id: u64,
+    pub(super) limit: Option<u32>,
+    pub(super) low_water: Option<u32>,
+    pub(super) tags: BTreeMap<u64, String>,
+    pub(super) guard: u32,
+    pub(super) guards: BTreeMap<u64, Range<u32>>,
+    pub(super) poison: bool,
+    pub(super) zero_on_alloc: bool,
+    pub(super) fill_fresh: bool,
+    pub(super) parents: BTreeMap<u64, Range<u32>>,
+    // oldest first
+    pub(super) recently_freed: Vec<(BlockHandle, u32)>,
+    // a list rather than a map, since plenty of formats only take strings as map keys
+    pub(super) weak: Vec<(BlockHandle, bool)>,
+    pub(super) segments: BTreeMap<String, u64>,
+    // the latest generation of every id, see `Allocator::register_process()`
+    pub(super) generations: BTreeMap<u32, u32>,
+    pub(super) protections: BTreeMap<u64, Protection>,
+    // snapshots from before blocks kept their alignment don't have any
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(super) aligns: BTreeMap<u64, u32>,
+    pub(super) spaces: BTreeMap<Process, BTreeMap<u32, Mapping>>,
+    // where every named heap is, its free blocks are in `free` along with the rest
+    pub(super) heaps: BTreeMap<String, Range<u32>>,
+    // whether the ranges end after their last byte, snapshots from before lilac went over to
+    // half-open ranges don't have it so it's false for them, see `migrate()`
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(super) half_open: bool,
+}
+
+impl Snapshot {
+    /// How many bytes the heap of the snapshot spans.
+    pub fn heap_len(&self) -> u32 {
+        self.heap.len() as u32
+    }
+
+    /// Every process in the snapshot, sorted.
+    pub fn processes(&self) -> impl Iterator<Item = Process> + '_ {
+        self.blocks.keys().copied()
+    }
+
+    // the ranges of a snapshot from before lilac went over to half-open ranges end on their last
+    // byte instead of the one after it, so all of their ends move one up. a range ending on the
+    // very last address can't be made half-open and would never have fit in a heap anyway
+    fn migrate(&mut self) -> Result<()> {
+        if self.half_open {
+            return Ok(());
+        }
+
+        let ranges = self
+            .blocks
+            .values_mut()
+            .flatten()
+            .map(|x| &mut x.range)
+            .chain(self.free.iter_mut())
+            .chain(self.guards.values_mut())
+            .chain(self.parents.values_mut())
+            .chain(self.heaps.values_mut());
+        for range in ranges {
+            match range.end.checked_add(1) {
+                Some(end) => range.end = end,
+                None => {
+                    return Err(AllocError::BadSnapshot(Violation::OutOfBounds(
+                        range.clone(),
+                    )))
+                }
+            }
+        }
+        self.half_open = true;
+
+        Ok(())
+    }
+}
+
+impl Allocator {
+    /// Take a copy of the heap and all of the bookkeeping, which `restore()` turns back into an
+    /// `Allocator` in exactly the same state, handles and all.
+    pub fn snapshot(&self) -> Snapshot {
+        let blocks = self
+            .allocated
+            .iter()
+            .map(|(process_id, blocks)| {
+                let blocks = blocks
+                    .iter()
+                    .map(|x| BlockState {
+                        id: x.id,
+                        range: x.range.clone(),
+                        parent: x.parent,
+                        read_only: x.read_only,
+                    })
+                    .collect();
+
+                (*process_id, blocks)
+            })
+            .collect();
+
+        Snapshot {
+            heap: self.heap.to_vec(),
+            blocks,
+            free: self.free_ranges(),
+            buddy: self.buddy.is_some(),
+            strategy: self.strategy,
+            next_id: self.next_id,
+            limit: self.limit,
+            low_water: self.low_water,
+            tags: self.tags.clone(),
+            guard: self.guard,
+            guards: self.guards.clone(),
+            poison: self.poison,
+            zero_on_alloc: self.zero_on_alloc,
+            fill_fresh: self.fill_fresh,
+            parents: self.parents.clone(),
+            recently_freed: self.recently_freed.iter().copied().collect(),
+            weak: self.weak.iter().map(|(x, y)| (*x, *y)).collect(),
+            segments: self.segments.clone(),
+            generations: self.generations.clone(),
+            protections: self.protections.clone(),
+            aligns: self.aligns.clone(),
+            spaces: self.spaces.clone(),
+            heaps: self
+                .heaps
+                .iter()
+                .map(|(x, y)| (x.clone(), y.range.clone()))
+                .collect(),
+            half_open: true,
+        }
+    }
+
+    /// Create an `Allocator` from a snapshot taken with `snapshot()`, with the refcounts worked
+    /// out from how many processes hold each block and no observers.
+    ///
+    /// A snapshot serialized before lilac went over to half-open ranges has ranges which end on
+    /// their last byte, they're moved over to ending after it first.
+    ///
+    /// A snapshot may have come from anywhere once it was serialized, so the allocator is checked
+    /// with `validate()` before it's handed out. It errors with the first violation found
+    /// (`AllocError::BadSnapshot`).
+    pub fn restore(mut snapshot: Snapshot) -> Result<Self> {
+        snapshot.migrate()?;
+        let mut allocator = Self::new();
+
+        // every process sharing a block shares its refcount too, windows share the one of the
+        // block they're part of
+        let mut refcounts: BTreeMap<u64, Arc<AtomicU32>> = BTreeMap::new();
+        for block in snapshot.blocks.values().flatten() {
+            let refcount = refcounts
+                .entry(block.parent.unwrap_or(block.id))
+                .or_default();
+            (**refcount).fetch_add(1, Ordering::SeqCst);
+        }
+
+        for (process_id, blocks) in snapshot.blocks {
+            let blocks = blocks
+                .into_iter()
+                .map(|x| {
+                    let refcount = Arc::clone(&refcounts[&x.parent.unwrap_or(x.id)]);
+                    let mut memrange = MemRange::new(x.id, refcount, x.range);
+                    memrange.parent = x.parent;
+                    memrange.read_only = x.read_only;
+                    memrange
+                })
+                .collect();
+
+            allocator.allocated.insert(process_id, blocks);
+        }
+
+        // the free blocks go back to the named heap they're in, so the heaps have to be there
+        // first
+        for (name, range) in snapshot.heaps {
+            let free = FreeList::new();
+            allocator.heaps.insert(name, NamedHeap { range, free });
+        }
+
+        if snapshot.buddy {
+            let mut buddy = Buddy::new();
+            for range in snapshot.free {
+                if !buddy.restore(&range) {
+                    return Err(AllocError::BadSnapshot(Violation::Misaligned(range)));
+                }
+            }
+            allocator.buddy = Some(buddy);
+        } else {
+            for range in snapshot.free {
+                allocator.free_list_at(range.start).insert(range);
+            }
+        }
+
+        allocator.heap = snapshot.heap.into();
+        allocator.strategy = snapshot.strategy;
+        allocator.next_id = snapshot.next_id;
+        allocator.limit = snapshot.limit;
+        allocator.low_water = snapshot.low_water;
+        allocator.tags = snapshot.tags;
+        allocator.guard = snapshot.guard;
+        allocator.guards = snapshot.guards;
+        allocator.poison = snapshot.poison;
+        allocator.zero_on_alloc = snapshot.zero_on_alloc;
+        allocator.fill_fresh = snapshot.fill_fresh;
+        allocator.parents = snapshot.parents;
+        allocator.recently_freed.extend(snapshot.recently_freed);
+        allocator.weak = snapshot.weak.into_iter().collect();
+        allocator.segments = snapshot.segments;
+        allocator.generations = snapshot.generations;
+        allocator.protections = snapshot.protections;
+        allocator.aligns = snapshot.aligns;
+        allocator.spaces = snapshot.spaces;
+
+        // a snapshot from before there were generations only has the processes to go by
+        for process_id in allocator.allocated.keys() {
+            let latest = allocator
+                .generations
+                .entry(process_id.id)
+                .or_insert(process_id.generation);
+            *latest = (*latest).max(process_id.generation);
+        }
+
+        match allocator.validate().into_iter().next() {
+            Some(violation) => Err(AllocError::BadSnapshot(violation)),
+            None => Ok(allocator),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Allocator {
+    fn serialize<S: Serializer>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error> {
+        self.snapshot().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Allocator {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> core::result::Result<Self, D::Error> {
+        let snapshot = Snapshot::deserialize(deserializer)?;
+        Allocator::restore(snapshot).map_err(de::Error::custom)
+    }
+}
@@ -0,0 +1,349 @@
+use alloc::collections::BTreeMap;
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt::Write;
+use core::ops::Range;
+use core::sync::atomic::Ordering;
+
+use super::{Allocator, BlockHandle, MemRange, Process, Protection, Result};
+
+/// How much of the heap one process holds, see `Stats`.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct ProcessStats {
+    /// Bytes in the blocks the process holds, including the shared ones.
+    pub allocated: u32,
+    pub blocks: usize,
+    /// How many of its blocks other processes hold too.
+    pub shared: usize,
+}
+
+/// A snapshot of how the heap of an `Allocator` is used, see `Allocator::stats()`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Stats {
+    /// How many bytes the heap spans.
+    pub heap: u32,
+    /// Bytes in blocks somebody holds, a shared block only counts once.
+    pub allocated: u32,
+    /// Bytes in free blocks, in buddy mode this and `allocated` don't add up to `heap` since
+    /// the rounding of every block isn't part of either, and neither are guards (see
+    /// `Allocator::with_guards()`). The free bytes of named heaps count too, even though only
+    /// `Allocator::alloc_in()` can use them.
+    pub free: u32,
+    pub largest_free: u32,
+    /// Blocks somebody holds, a shared block only counts once.
+    pub blocks: usize,
+    pub free_blocks: usize,
+    pub processes: BTreeMap<Process, ProcessStats>,
+    /// Bytes in the blocks with each label, see `Allocator::set_tag()`.
+    pub tags: BTreeMap<String, u32>,
+}
+
+impl Stats {
+    /// How scattered the free bytes are, from 0 when they're all in one block to almost 1 when
+    /// they're spread over lots of small holes, worked out as `1 - largest_free / free`.
+    ///
+    /// A high value means allocations can fail to find a block even though there are enough
+    /// free bytes overall, which is when `Allocator::compact()` helps.
+    pub fn fragmentation(&self) -> f64 {
+        if self.free == 0 {
+            return 0.0;
+        }
+
+        1.0 - self.largest_free as f64 / self.free as f64
+    }
+}
+
+/// A block somebody still holds, see `Allocator::leaks()`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Leak {
+    pub range: Range<u32>,
+    pub size: u32,
+    pub refcount: u32,
+    /// Every process holding the block, in no particular order.
+    pub processes: Vec<Process>,
+    pub tag: Option<String>,
+}
+
+/// Everything there is to know about one block, see `Allocator::block_info()`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BlockInfo {
+    pub handle: BlockHandle,
+    pub range: Range<u32>,
+    pub size: u32,
+    /// How many processes hold the block, including the one of the handle.
+    pub refcount: u32,
+    pub shared: bool,
+    /// Whether the process can only read the block, see `Allocator::share_read_only()`.
+    pub read_only: bool,
+    /// What every process holding the block may do with it, see `Allocator::protect()`.
+    pub protection: Protection,
+    pub tag: Option<String>,
+}
+
+fn len(range: &Range<u32>) -> u32 {
+    range.end - range.start
+}
+
+impl Allocator {
+    // all the free blocks, whichever mode the allocator is in and the ones of the named heaps
+    // too, sorted by address
+    pub(super) fn free_ranges(&self) -> Vec<Range<u32>> {
+        let mut ranges: Vec<Range<u32>> = match &self.buddy {
+            Some(buddy) => buddy.iter().collect(),
+            None => self.free.iter().collect(),
+        };
+        ranges.extend(self.heaps.values().flat_map(|x| x.free.iter()));
+        ranges.sort_unstable_by_key(|x| x.start);
+
+        ranges
+    }
+
+    // what `block_info()` says about a block of a process
+    pub(super) fn info(&self, process_id: Process, block: &MemRange) -> BlockInfo {
+        let refcount = (*block.refcount).load(Ordering::SeqCst);
+
+        BlockInfo {
+            handle: BlockHandle {
+                process_id,
+                id: block.id,
+            },
+            range: block.range.clone(),
+            size: len(&block.range),
+            refcount,
+            shared: refcount > 1,
+            read_only: block.read_only,
+            protection: self
+                .protections
+                .get(&self.block_of(block).0)
+                .copied()
+                .unwrap_or_default(),
+            tag: self.tags.get(&block.id).cloned(),
+        }
+    }
+
+    /// Where the block of `handle` is, how long it is, who else holds it and what it's labeled,
+    /// `handle_at()` gives the handle for an address.
+    ///
+    /// It errors like `range()` does.
+    pub fn block_info(&self, handle: BlockHandle) -> Result<BlockInfo> {
+        let idx = self.find(handle)?;
+        Ok(self.info(handle.process_id, &self.allocated[&handle.process_id][idx]))
+    }
+
+    /// Every block the process holds, in no particular order, for walking its allocations
+    /// without going through them one handle at a time.
+    ///
+    /// It errors if the process doesn't exist (`AllocError::NoSuchProcess`).
+    pub fn blocks(&self, process_id: Process) -> Result<impl Iterator<Item = BlockInfo> + '_> {
+        let allocated = self
+            .allocated
+            .get(&process_id)
+            .ok_or_else(|| self.no_such_process(process_id))?;
+
+        Ok(allocated.iter().map(move |x| self.info(process_id, x)))
+    }
+
+    /// Take a snapshot of how the heap is used, for keeping an eye on it while the allocator
+    /// runs.
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats {
+            heap: self.heap.len() as u32,
+            ..Stats::default()
+        };
+
+        for range in self.free_ranges() {
+            stats.free += len(&range);
+            stats.largest_free = stats.largest_free.max(len(&range));
+            stats.free_blocks += 1;
+        }
+
+        // shared blocks show up once for every process holding them, they're told apart by id
+        let mut seen = vec![];
+        for (process_id, blocks) in &self.allocated {
+            let process = stats.processes.entry(*process_id).or_default();
+
+            for block in blocks {
+                process.allocated += len(&block.range);
+                process.blocks += 1;
+                if (*block.refcount).load(Ordering::SeqCst) > 1 {
+                    process.shared += 1;
+                }
+
+                // a window counts as the block it's part of
+                let (id, range) = self.block_of(block);
+                seen.push((id, len(&range)));
+            }
+        }
+
+        seen.sort_unstable();
+        seen.dedup();
+        stats.blocks = seen.len();
+        stats.allocated = seen.iter().map(|x| x.1).sum();
+
+        for (id, size) in seen {
+            if let Some(tag) = self.tags.get(&id) {
+                *stats.tags.entry(tag.clone()).or_default() += size;
+            }
+        }
+
+        stats
+    }
+
+    /// Every block still held by a process, sorted by address. Calling it right before dropping
+    /// the allocator shows which processes never freed their memory.
+    pub fn leaks(&self) -> Vec<Leak> {
+        let mut leaks: Vec<(u64, Leak)> = vec![];
+        for (process_id, blocks) in &self.allocated {
+            for block in blocks {
+                match leaks.iter_mut().find(|x| x.0 == block.id) {
+                    Some((_, leak)) => leak.processes.push(*process_id),
+                    None => leaks.push((
+                        block.id,
+                        Leak {
+                            range: block.range.clone(),
+                            size: len(&block.range),
+                            refcount: (*block.refcount).load(Ordering::SeqCst),
+                            processes: vec![*process_id],
+                            tag: self.tags.get(&block.id).cloned(),
+                        },
+                    )),
+                }
+            }
+        }
+
+        leaks.sort_unstable_by_key(|x| x.1.range.start);
+        leaks.into_iter().map(|x| x.1).collect()
+    }
+
+    /// List every block still held by a process along with its size, label, refcount and the
+    /// processes holding it, one block per line.
+    pub fn leak_report(&self) -> String {
+        let leaks = self.leaks();
+        let mut report = format!(
+            "{} blocks ({} bytes) are still allocated\n",
+            leaks.len(),
+            leaks.iter().map(|x| x.size).sum::<u32>()
+        );
+
+        for leak in leaks {
+            let processes: Vec<String> = leak.processes.iter().map(|x| x.to_string()).collect();
+            let tag = match &leak.tag {
+                Some(tag) => format!(", {}", tag),
+                None => String::new(),
+            };
+
+            // safe to unwrap because writing into a string can't fail
+            writeln!(
+                report,
+                "  {:08x}..{:08x} ({} bytes{}) refcount {}, held by {}",
+                leak.range.start,
+                leak.range.end,
+                leak.size,
+                tag,
+                leak.refcount,
+                processes.join(", ")
+            )
+            .unwrap();
+        }
+
+        report
+    }
+
+    /// A Graphviz DOT graph of which process holds which block, for seeing at a glance how the
+    /// processes share memory. Processes are boxes, blocks are ellipses labeled with their
+    /// range, size, label and refcount, and shared blocks are drawn bold.
+    ///
+    /// Render it with something like `dot -Tsvg sharing.dot -o sharing.svg`.
+    pub fn sharing_graph(&self) -> String {
+        let mut processes: Vec<&Process> = self.allocated.keys().collect();
+        processes.sort_unstable();
+
+        let mut graph = String::from("digraph sharing {\n");
+
+        // safe to unwrap every write because writing into a string can't fail
+        for process_id in &processes {
+            writeln!(
+                graph,
+                "    \"p{0}\" [shape=box, label=\"process {0}\"];",
+                process_id
+            )
+            .unwrap();
+        }
+
+        for leak in self.leaks() {
+            let tag = match &leak.tag {
+                // the label ends up inside a quoted string
+                Some(tag) => format!("\\n{}", tag.replace('\\', "\\\\").replace('"', "\\\"")),
+                None => String::new(),
+            };
+            let bold = leak.refcount > 1;
+            let style = if bold { ", style=bold" } else { "" };
+
+            writeln!(
+                graph,
+                "    \"b{0:x}\" [label=\"{0:08x}..{1:08x}\\n{2} bytes{3}\\nrefcount {4}\"{5}];",
+                leak.range.start, leak.range.end, leak.size, tag, leak.refcount, style
+            )
+            .unwrap();
+
+            let mut holders = leak.processes.clone();
+            holders.sort_unstable();
+            for process_id in holders {
+                writeln!(
+                    graph,
+                    "    \"p{}\" -> \"b{:x}\"{};",
+                    process_id,
+                    leak.range.start,
+                    if bold { " [style=bold]" } else { "" }
+                )
+                .unwrap();
+            }
+        }
+
+        graph.push_str("}\n");
+        graph
+    }
+
+    /// See `Stats::fragmentation()`.
+    pub fn fragmentation(&self) -> f64 {
+        self.stats().fragmentation()
+    }
+
+    /// List every free hole of the heap along with the overall fragmentation, one hole per line
+    /// as `start..end (size)`.
+    pub fn free_report(&self) -> String {
+        let stats = self.stats();
+        let mut report = format!(
+            "{} free bytes in {} holes of a {} byte heap, {:.1}% fragmented\n",
+            stats.free,
+            stats.free_blocks,
+            stats.heap,
+            stats.fragmentation() * 100.0
+        );
+
+        for range in self.free_ranges() {
+            let end = if range.end == stats.heap {
+                ", at the end of the heap"
+            } else {
+                ""
+            };
+
+            // safe to unwrap because writing into a string can't fail
+            writeln!(
+                report,
+                "  {:08x}..{:08x} ({} bytes{})",
+                range.start,
+                range.end,
+                len(&range),
+                end
+            )
+            .unwrap();
+        }
+
+        report
+    }
+}
@@ -0,0 +1,229 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::{vec, vec::Vec};
+use core::ops::Range;
+use core::sync::atomic::Ordering;
+
+use super::buddy::Buddy;
+use super::{Allocator, Process};
+
+/// What `Allocator::sweep()` collected.
+///
+/// It's all empty unless something went wrong with the bookkeeping, every one of these is a
+/// bug in lilac or the result of a snapshot somebody messed with.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Sweep {
+    /// The bytes of the heap nobody held which weren't free either, they're free now. Sorted by
+    /// address.
+    pub reclaimed: Vec<Range<u32>>,
+    /// Every block whose refcount didn't match how many processes hold it, as (id, refcount,
+    /// holders), its refcount is how many processes hold it now.
+    pub refcounts: Vec<(u64, u32, u32)>,
+    /// How many labels, guards, protections, alignments, segments, mappings and ranges of blocks
+    /// with windows into them were left of blocks which are gone.
+    pub stale: usize,
+    /// The processes which aren't registered anymore but were still in a group, an address
+    /// space, a weak share, a futex queue or the record of which process started which, sorted.
+    pub processes: Vec<Process>,
+}
+
+impl Sweep {
+    /// How many bytes went back to the free blocks.
+    pub fn reclaimed_bytes(&self) -> u32 {
+        self.reclaimed.iter().map(|x| x.end - x.start).sum()
+    }
+
+    /// Whether there was nothing to collect.
+    pub fn is_clean(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Allocator {
+    /// Collect everything the bookkeeping still has of blocks and processes which are gone,
+    /// the way a garbage collector would, and return what it found. Bytes of the heap which
+    /// neither a process holds nor are free go back to the free blocks, refcounts which don't
+    /// match how many processes hold the block are set to it, and whatever is still kept for
+    /// blocks nobody holds or processes that aren't registered is dropped.
+    ///
+    /// None of it should ever be there, so this is for recovering from a bug (in lilac or in how
+    /// the heap was restored) without starting over. It walks everything the allocator knows,
+    /// so it's meant for running now and then, not for every allocation. `validate()` is what
+    /// finds the problems it can't fix, like blocks overlapping.
+    pub fn sweep(&mut self) -> Sweep {
+        let mut sweep = Sweep::default();
+
+        // the refcount of a block is shared by every process holding it, windows count towards
+        // the one of the block they're part of
+        let mut holders: BTreeMap<u64, u32> = BTreeMap::new();
+        for block in self.allocated.values().flatten() {
+            let (id, _) = self.block_of(block);
+            *holders.entry(id).or_default() += 1;
+        }
+        for block in self.allocated.values().flatten() {
+            let (id, _) = self.block_of(block);
+            let refcount = (*block.refcount).load(Ordering::SeqCst);
+            if refcount != holders[&id] {
+                if !sweep.refcounts.iter().any(|x| x.0 == id) {
+                    sweep.refcounts.push((id, refcount, holders[&id]));
+                }
+                (*block.refcount).store(holders[&id], Ordering::SeqCst);
+            }
+        }
+        sweep.refcounts.sort_unstable();
+
+        self.sweep_stale(&mut sweep);
+        self.sweep_processes(&mut sweep);
+
+        // everything that's either held by somebody or free, whatever is left in between is
+        // held by nobody
+        let mut covered = self.free_ranges();
+        for block in self.allocated.values().flatten() {
+            let (id, range) = self.block_of(block);
+            let extent = self.extent(id, &range);
+            covered.push(match self.buddy {
+                // the block takes up the whole power of two it was rounded up to
+                Some(_) => {
+                    let order = Buddy::order(extent.end - extent.start);
+                    extent.start..extent.start + (1 << order)
+                }
+                None => extent,
+            });
+        }
+        covered.sort_unstable_by_key(|x| x.start);
+
+        let mut next = 0;
+        let mut holes = vec![];
+        for range in covered {
+            if range.start > next {
+                holes.push(next..range.start);
+            }
+            next = next.max(range.end);
+        }
+        let len = self.heap.len() as u32;
+        if len > next {
+            holes.push(next..len);
+        }
+
+        for hole in holes {
+            match self.buddy {
+                Some(_) => self.release_buddy(&hole),
+                None => {
+                    self.release(hole.clone());
+                }
+            }
+            sweep.reclaimed.push(hole);
+        }
+
+        sweep
+    }
+
+    // drop everything kept by block id for blocks nobody holds, and the mappings of blocks the
+    // process doesn't hold anymore
+    fn sweep_stale(&mut self, sweep: &mut Sweep) {
+        let mut ids = BTreeSet::new();
+        let mut roots = BTreeSet::new();
+        for block in self.allocated.values().flatten() {
+            ids.insert(block.id);
+            roots.insert(self.block_of(block).0);
+        }
+        let windowed: BTreeSet<u64> = self
+            .allocated
+            .values()
+            .flatten()
+            .filter_map(|x| x.parent)
+            .collect();
+
+        let before = self.tags.len()
+            + self.guards.len()
+            + self.protections.len()
+            + self.aligns.len()
+            + self.segments.len()
+            + self.parents.len();
+        // windows have labels of their own, everything else goes by the whole block
+        self.tags
+            .retain(|id, _| ids.contains(id) || roots.contains(id));
+        self.guards.retain(|id, _| roots.contains(id));
+        self.protections.retain(|id, _| roots.contains(id));
+        self.aligns.retain(|id, _| roots.contains(id));
+        self.segments.retain(|_, id| roots.contains(id));
+        self.parents.retain(|id, _| windowed.contains(id));
+        sweep.stale += before
+            - (self.tags.len()
+                + self.guards.len()
+                + self.protections.len()
+                + self.aligns.len()
+                + self.segments.len()
+                + self.parents.len());
+
+        for (process_id, space) in &mut self.spaces {
+            let held: BTreeSet<u64> = self
+                .allocated
+                .get(process_id)
+                .into_iter()
+                .flatten()
+                .map(|x| x.id)
+                .collect();
+
+            let before = space.len();
+            space.retain(|_, x| held.contains(&x.id));
+            sweep.stale += before - space.len();
+        }
+    }
+
+    // forget the processes which aren't registered anymore wherever they were left behind
+    fn sweep_processes(&mut self, sweep: &mut Sweep) {
+        let mut gone = BTreeSet::new();
+        let registered = |x: &Process| self.allocated.contains_key(x);
+
+        gone.extend(self.spaces.keys().filter(|x| !registered(x)));
+        gone.extend(
+            self.weak
+                .keys()
+                .map(|x| x.process_id)
+                .filter(|x| !registered(x)),
+        );
+        gone.extend(self.futexes.values().flatten().filter(|x| !registered(x)));
+        for (child, parent) in &self.spawned_by {
+            gone.extend([child, parent].into_iter().filter(|x| !registered(x)));
+        }
+        for group in self.groups.values() {
+            gone.extend(group.members.iter().filter(|x| !registered(x)));
+        }
+
+        for process_id in &gone {
+            self.spaces.remove(process_id);
+            self.weak.retain(|x, _| x.process_id != *process_id);
+            self.cancel_wait(*process_id);
+            self.spawned_by
+                .retain(|child, parent| child != process_id && parent != process_id);
+            for group in self.groups.values_mut() {
+                group.members.remove(process_id);
+            }
+        }
+
+        sweep.processes = gone.into_iter().collect();
+    }
+
+    // free `range` in buddy mode, where it has to go back in blocks which are a power of two
+    // long and start at a multiple of their size. holes between buddy blocks always split up
+    // like that since every block in buddy mode does, except for pieces smaller than the
+    // smallest block, which only a heap messed with from the outside has and are left alone
+    fn release_buddy(&mut self, range: &Range<u32>) {
+        let mut start = range.start;
+        while start < range.end {
+            let left = range.end - start;
+            // the biggest block starting at `start` which still fits
+            let aligned = if start == 0 {
+                u32::MAX
+            } else {
+                1 << start.trailing_zeros()
+            };
+            let size = aligned.min(1 << (31 - left.leading_zeros()));
+
+            if size >= 1 << Buddy::order(1) {
+                self.release(start..start + size);
+            }
+            start += size;
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use core::marker::PhantomData;
+use core::mem;
+
+use super::{AllocError, Allocator, BlockHandle, Process, Result};
+
+/// Plain old data, types which can be viewed as raw bytes and back, so a block can be borrowed
+/// as a slice of them, see `Allocator::alloc_typed()`.
+///
+/// # Safety
+///
+/// Every bit pattern has to be a valid value of the type and it can't have any padding, since
+/// the bytes of the heap can be anything and a process can read every one of them.
+pub unsafe trait Pod: Copy + 'static {}
+
+unsafe impl Pod for u8 {}
+unsafe impl Pod for u16 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for u64 {}
+unsafe impl Pod for u128 {}
+unsafe impl Pod for usize {}
+unsafe impl Pod for i8 {}
+unsafe impl Pod for i16 {}
+unsafe impl Pod for i32 {}
+unsafe impl Pod for i64 {}
+unsafe impl Pod for i128 {}
+unsafe impl Pod for isize {}
+unsafe impl Pod for f32 {}
+unsafe impl Pod for f64 {}
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+/// The handle of a block holding values of `T`, see `Allocator::alloc_typed()`.
+///
+/// It's a `BlockHandle` which remembers the type, `handle()` gives the plain one for anything
+/// else done with the block.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct TypedHandle<T: Pod> {
+    handle: BlockHandle,
+    _type: PhantomData<T>,
+}
+
+impl<T: Pod> TypedHandle<T> {
+    pub fn handle(&self) -> BlockHandle {
+        self.handle
+    }
+
+    /// Borrow the block as a slice of `T`, a block which got resized to somewhere in between
+    /// two values leaves the last bytes out.
+    ///
+    /// It errors like `Allocator::borrow()` does, or if the block isn't aligned for `T` in
+    /// host memory (`AllocError::Misaligned`).
+    pub fn as_slice<'a>(&self, allocator: &'a Allocator) -> Result<&'a [T]> {
+        let start = allocator.range(self.handle)?.start;
+        let bytes = allocator.borrow(self.handle)?;
+
+        // safe because `T` is `Pod`, so any bytes are a valid `T`
+        let (before, values, _) = unsafe { bytes.align_to::<T>() };
+        if !before.is_empty() {
+            return Err(misaligned::<T>(start));
+        }
+
+        Ok(values)
+    }
+
+    /// Mutably borrow the block as a slice of `T`, see `as_slice()`.
+    ///
+    /// It errors like `Allocator::borrow_mut()` does, or if the block isn't aligned for `T` in
+    /// host memory (`AllocError::Misaligned`).
+    pub fn as_mut_slice<'a>(&self, allocator: &'a mut Allocator) -> Result<&'a mut [T]> {
+        let start = allocator.range(self.handle)?.start;
+        let bytes = allocator.borrow_mut(self.handle)?;
+
+        // safe because `T` is `Pod`, so any bytes are a valid `T`, and every `T` written ends
+        // up as bytes which are valid anyway
+        let (before, values, _) = unsafe { bytes.align_to_mut::<T>() };
+        if !before.is_empty() {
+            return Err(misaligned::<T>(start));
+        }
+
+        Ok(values)
+    }
+}
+
+// the error for a block at `start` which isn't aligned for `T` in host memory
+fn misaligned<T>(start: u32) -> AllocError {
+    AllocError::Misaligned {
+        start,
+        align: mem::align_of::<T>() as u32,
+    }
+}
+
+impl Allocator {
+    /// Allocate a block for `count` values of `T` under a process id, for structured data in
+    /// memory the guest can see. The block starts at a multiple of the alignment of `T`.
+    ///
+    /// The heap itself may sit at any address of host memory, so borrowing the block as a slice
+    /// checks that it's aligned there too. The global allocator aligns the heap for anything
+    /// up to `u128` on all the usual platforms, so that's only ever an issue for values which
+    /// have to be aligned more than that.
+    ///
+    /// It errors like `alloc_aligned()` does, with `count` of zero or a zero sized `T` being
+    /// `AllocError::ZeroSize`.
+    pub fn alloc_typed<T: Pod>(
+        &mut self,
+        process_id: Process,
+        count: u32,
+    ) -> Result<TypedHandle<T>> {
+        let size = count
+            .checked_mul(mem::size_of::<T>() as u32)
+            .ok_or_else(|| self.out_of_memory(u32::MAX))?;
+        let handle = self.alloc_aligned(process_id, size, mem::align_of::<T>() as u32)?;
+
+        Ok(TypedHandle {
+            handle,
+            _type: PhantomData,
+        })
+    }
+}
@@ -1,97 +1,755 @@
-use std::collections::HashMap;
-use std::fmt;
-use std::ops::Range;
-use std::sync::atomic::AtomicU32;
-use std::sync::Arc;
-use xorshift::{thread_rng, Rng, Xorshift1024};
+#[cfg(feature = "std")]
+use super::borrows::Borrows;
+use super::buddy::Buddy;
+use super::free::FreeList;
+use super::groups::GroupState;
+use super::heap::Heap;
+use super::named::NamedHeap;
+use super::virt::Mapping;
+use super::{AllocObserver, PressureHandler, Violation};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::{boxed::Box, string::String, string::ToString, vec::Vec};
+use core::fmt;
+use core::ops::Range;
+use core::str::FromStr;
+use core::sync::atomic::AtomicU32;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "std")]
+use std::sync::Mutex;
+#[cfg(feature = "std")]
+use xorshift::{thread_rng, Rand, Rng, SeedableRng, SplitMix64, Xorshift1024};
 
-#[derive(Debug, Clone)]
+/// Everything that can go wrong in lilac, the variants carry whatever was involved so a failure
+/// can be made sense of without a debugger.
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum AllocError {
-    AlreadyRegistered,
-    NoSuchProcess,
-    NotOwned,
-    BlockNotFound,
+    /// The process which has the id already.
+    AlreadyRegistered(Process),
+    NoSuchProcess(Process),
+    /// The id of the process was given to a newer one since, `current`, see
+    /// `Allocator::register_process()`.
+    StaleProcess {
+        process_id: Process,
+        current: Process,
+    },
+    NotOwned {
+        process_id: Process,
+        range: Range<u32>,
+    },
+    BlockNotFound(BlockHandle),
+    BlockShared(BlockHandle),
+    /// The ranges of the two blocks, in address order.
+    NotContiguous {
+        first: Range<u32>,
+        second: Range<u32>,
+    },
+    ZeroSize,
+    BadSplit {
+        size: u32,
+        offset: u32,
+    },
+    Unsupported,
+    ArenaFull {
+        size: u32,
+        remaining: u32,
+    },
+    BadAlignment(u32),
+    /// `size` is how many bytes there was no room for, guards included.
+    OutOfMemory {
+        size: u32,
+        heap: u32,
+        limit: Option<u32>,
+    },
+    Corruption(BlockHandle),
+    UseAfterFree {
+        process_id: Process,
+        range: Range<u32>,
+    },
+    DoubleFree {
+        process_id: Process,
+        start: u32,
+    },
+    ShareWithSelf(BlockHandle),
+    /// The handle the target process already has for the block.
+    AlreadyShared(BlockHandle),
+    /// The process may not do `access` to the range, since it only got it to read (see
+    /// `Allocator::share_read_only()`) or the block isn't protected for it (see
+    /// `Allocator::protect()`).
+    PermissionDenied {
+        process_id: Process,
+        range: Range<u32>,
+        access: Access,
+    },
+    /// The block the weak share pointed to was freed, see `Allocator::share_weak()`.
+    Dangling(BlockHandle),
+    /// The two ranges given to `Allocator::copy()` are `src` and `dst` bytes long.
+    LengthMismatch {
+        src: u32,
+        dst: u32,
+    },
+    /// The block at `start` isn't aligned to `align` bytes in host memory, see
+    /// `Allocator::alloc_typed()`.
+    Misaligned {
+        start: u32,
+        align: u32,
+    },
+    /// There's a segment with this name already, see `Allocator::create_segment()`.
+    SegmentExists(String),
+    NoSuchSegment(String),
+    NoSuchGroup(Group),
+    /// The group the process is in already.
+    AlreadyInGroup {
+        process_id: Process,
+        group: Group,
+    },
+    /// `size` more bytes would take the group past its quota, see
+    /// `Allocator::set_group_quota()`.
+    QuotaExceeded {
+        group: Group,
+        size: u32,
+        quota: u32,
+    },
+    /// The process is in a frozen group, see `Allocator::freeze_group()`.
+    Frozen(Process),
+    /// Where the process mapped the block already, see `Allocator::map()`.
+    AlreadyMapped {
+        handle: BlockHandle,
+        addr: u32,
+    },
+    /// The virtual range overlaps another mapping of the process or runs past the end of its
+    /// address space, see `Allocator::map_at()`.
+    BadMapping {
+        process_id: Process,
+        range: Range<u32>,
+    },
+    /// Nothing is mapped at the virtual address `addr` of the process.
+    Unmapped {
+        process_id: Process,
+        addr: u32,
+    },
+    /// There's no named heap with this name, see `Allocator::create_heap()`.
+    NoSuchHeap(String),
+    HeapExists(String),
+    /// The named heap still has blocks in it.
+    HeapInUse(String),
+    /// The named heap has no free block of `size` bytes left, it never grows.
+    HeapFull {
+        heap: String,
+        size: u32,
+    },
+    /// The range overlaps `with`, which a live `BorrowToken` has claimed.
+    BorrowConflict {
+        range: Range<u32>,
+        with: Range<u32>,
+    },
+    /// What's wrong with the allocator the snapshot would give, see `Allocator::restore()`.
+    BadSnapshot(Violation),
+    /// A thread panicked while it had the allocator locked, see `ParallelAlloc::lock()`.
+    Poisoned,
 }
 
-impl std::error::Error for AllocError {}
+impl core::error::Error for AllocError {}
 
-pub type Result<T> = std::result::Result<T, AllocError>;
+pub type Result<T> = core::result::Result<T, AllocError>;
 
 impl fmt::Display for AllocError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            AllocError::AlreadyRegistered => write!(f, "this process id is already registered"),
-            AllocError::NoSuchProcess => write!(f, "the given process id does not exist"),
-            AllocError::NotOwned => write!(f, "the memory range is not owned by the process"),
-            AllocError::BlockNotFound => write!(
+            AllocError::AlreadyRegistered(process_id) => {
+                write!(f, "process {} is already registered", process_id)
+            }
+            AllocError::NoSuchProcess(process_id) => {
+                write!(f, "process {} does not exist", process_id)
+            }
+            AllocError::StaleProcess {
+                process_id,
+                current,
+            } => write!(
+                f,
+                "process {} is gone, its id belongs to process {} now",
+                process_id, current
+            ),
+            AllocError::NotOwned { process_id, range } => write!(
+                f,
+                "the memory range {:#x}..{:#x} is not owned by process {}",
+                range.start, range.end, process_id
+            ),
+            AllocError::BlockNotFound(handle) => write!(
+                f,
+                "block {} was not found for process {}",
+                handle.id, handle.process_id
+            ),
+            AllocError::BlockShared(handle) => write!(
+                f,
+                "block {} of process {} is shared with another process and can't be changed",
+                handle.id, handle.process_id
+            ),
+            AllocError::NotContiguous { first, second } => write!(
+                f,
+                "the blocks at {:#x}..{:#x} and {:#x}..{:#x} are not back to back",
+                first.start, first.end, second.start, second.end
+            ),
+            AllocError::ZeroSize => write!(f, "a block can't be zero bytes long"),
+            AllocError::BadSplit { size, offset } => write!(
+                f,
+                "splitting a {} byte block at {} doesn't leave a byte on both sides",
+                size, offset
+            ),
+            AllocError::Unsupported => write!(f, "this isn't supported in the allocator's mode"),
+            AllocError::ArenaFull { size, remaining } => write!(
+                f,
+                "{} bytes don't fit in the {} bytes left in the arena",
+                size, remaining
+            ),
+            AllocError::BadAlignment(align) => {
+                write!(f, "the alignment {} isn't a power of two", align)
+            }
+            AllocError::OutOfMemory { size, heap, limit } => match limit {
+                Some(limit) => write!(
+                    f,
+                    "there's no room for {} bytes in the {} byte heap and it can't grow past {}",
+                    size, heap, limit
+                ),
+                None => write!(
+                    f,
+                    "there's no room for {} bytes in the {} byte heap",
+                    size, heap
+                ),
+            },
+            AllocError::Corruption(handle) => write!(
+                f,
+                "the guard bytes around block {} of process {} were overwritten",
+                handle.id, handle.process_id
+            ),
+            AllocError::UseAfterFree { process_id, range } => write!(
+                f,
+                "process {} used the memory range {:#x}..{:#x} after it was freed",
+                process_id, range.start, range.end
+            ),
+            AllocError::DoubleFree { process_id, start } => write!(
+                f,
+                "process {} already freed the block at {:#x}",
+                process_id, start
+            ),
+            AllocError::ShareWithSelf(handle) => write!(
+                f,
+                "process {} can't share block {} with itself",
+                handle.process_id, handle.id
+            ),
+            AllocError::AlreadyShared(handle) => write!(
+                f,
+                "process {} already holds block {}",
+                handle.process_id, handle.id
+            ),
+            AllocError::PermissionDenied {
+                process_id,
+                range,
+                access,
+            } => write!(
+                f,
+                "process {} may not {} the memory range {:#x}..{:#x}",
+                process_id,
+                match access {
+                    Access::Read => "read",
+                    Access::Write => "write to",
+                    Access::Execute => "execute",
+                },
+                range.start,
+                range.end
+            ),
+            AllocError::Dangling(handle) => write!(
+                f,
+                "block {} of the weak share of process {} was freed",
+                handle.id, handle.process_id
+            ),
+            AllocError::LengthMismatch { src, dst } => {
+                write!(f, "can't copy {} bytes into {} bytes", src, dst)
+            }
+            AllocError::Misaligned { start, align } => write!(
+                f,
+                "the block at {:#x} isn't aligned to {} bytes in host memory",
+                start, align
+            ),
+            AllocError::SegmentExists(name) => {
+                write!(
+                    f,
+                    "there's a shared memory segment called `{}` already",
+                    name
+                )
+            }
+            AllocError::NoSuchSegment(name) => {
+                write!(f, "there's no shared memory segment called `{}`", name)
+            }
+            AllocError::NoSuchGroup(group) => write!(f, "group {} does not exist", group),
+            AllocError::AlreadyInGroup { process_id, group } => {
+                write!(f, "process {} is in group {} already", process_id, group)
+            }
+            AllocError::QuotaExceeded { group, size, quota } => write!(
+                f,
+                "{} more bytes would take group {} past its quota of {} bytes",
+                size, group, quota
+            ),
+            AllocError::Frozen(process_id) => {
+                write!(
+                    f,
+                    "process {} is frozen and can't change its memory",
+                    process_id
+                )
+            }
+            AllocError::AlreadyMapped { handle, addr } => write!(
                 f,
-                "the block at the given start address was not found for this process"
+                "block {} is mapped at {:#x} of process {} already",
+                handle.id, addr, handle.process_id
+            ),
+            AllocError::BadMapping { process_id, range } => write!(
+                f,
+                "the virtual range {:#x}..{:#x} of process {} is taken or out of bounds",
+                range.start, range.end, process_id
+            ),
+            AllocError::Unmapped { process_id, addr } => write!(
+                f,
+                "nothing is mapped at {:#x} of process {}",
+                addr, process_id
+            ),
+            AllocError::NoSuchHeap(name) => write!(f, "there's no named heap called `{}`", name),
+            AllocError::HeapExists(name) => {
+                write!(f, "there's a named heap called `{}` already", name)
+            }
+            AllocError::HeapInUse(name) => {
+                write!(f, "the named heap `{}` still has blocks in it", name)
+            }
+            AllocError::HeapFull { heap, size } => write!(
+                f,
+                "the named heap `{}` has no room left for {} bytes",
+                heap, size
+            ),
+            AllocError::BorrowConflict { range, with } => write!(
+                f,
+                "the memory range {:#x}..{:#x} overlaps {:#x}..{:#x}, which is borrowed already",
+                range.start, range.end, with.start, with.end
+            ),
+            AllocError::BadSnapshot(violation) => {
+                write!(f, "the snapshot is inconsistent, {}", violation)
+            }
+            AllocError::Poisoned => write!(
+                f,
+                "a thread panicked while holding the allocator, its bookkeeping can't be trusted"
             ),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum FreeBlock {
     Free(u32),
     FreeMerge(u32),
     RefcountDecreased,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
-pub struct Process(u32);
+/// What `Allocator::compact()` did.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Compaction {
+    /// How many bytes of free holes between blocks were closed, they're all part of one free
+    /// block at the end of the heap (or of the named heap they were in) now. Only the bytes an
+    /// aligned block needs before it to stay aligned are left where they are, see
+    /// `Allocator::alloc_aligned()`.
+    pub reclaimed: u32,
+    /// (old start, new start) of every block which moved, sorted by address.
+    pub moved: Vec<(u32, u32)>,
+}
+
+impl Compaction {
+    /// Where the block which started at `start` before the compaction starts now.
+    pub fn new_start(&self, start: u32) -> u32 {
+        match self.moved.binary_search_by_key(&start, |x| x.0) {
+            Ok(idx) => self.moved[idx].1,
+            Err(_) => start,
+        }
+    }
+}
 
+/// A process of the VM, which `ProcBuilder` hands out and `Allocator::register_process()` gives
+/// a generation.
+///
+/// It prints as its id, with the generation after it if the id was reused, and parses back from
+/// that. Processes are ordered by id and then by generation, the newest last. For keeping one
+/// around as a single number there's `to_bits()`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Process {
+    pub(super) id: u32,
+    // how many processes had the id before this one, see `Allocator::register_process()`
+    pub(super) generation: u32,
+}
+
+impl fmt::Display for Process {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.generation {
+            0 => write!(f, "{}", self.id),
+            generation => write!(f, "{} (generation {})", self.id, generation),
+        }
+    }
+}
+
+impl Process {
+    /// The id of the process, what `ProcBuilder` handed out.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// How many processes had the id before this one, see `Allocator::register_process()`.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// The id and generation packed into a `u64`, the generation in the upper half, which
+    /// `from_bits()` turns back into the same process.
+    pub fn to_bits(&self) -> u64 {
+        (self.generation as u64) << 32 | self.id as u64
+    }
+
+    /// The process `to_bits()` gave `bits` for.
+    pub fn from_bits(bits: u64) -> Self {
+        Self {
+            id: bits as u32,
+            generation: (bits >> 32) as u32,
+        }
+    }
+}
+
+// as a single number, since plenty of formats only take strings and numbers as map keys and
+// processes are the keys of a few maps in a `Snapshot`
+#[cfg(feature = "serde")]
+impl Serialize for Process {
+    fn serialize<S: Serializer>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error> {
+        self.to_bits().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Process {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> core::result::Result<Self, D::Error> {
+        u64::deserialize(deserializer).map(Self::from_bits)
+    }
+}
+
+/// The first process with the id.
+impl From<u32> for Process {
+    fn from(id: u32) -> Self {
+        Self { id, generation: 0 }
+    }
+}
+
+/// The string given to `Process::from_str()` isn't a process the way it's displayed.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseProcessError(pub String);
+
+impl core::error::Error for ParseProcessError {}
+
+impl fmt::Display for ParseProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} is not a process", self.0)
+    }
+}
+
+impl FromStr for Process {
+    type Err = ParseProcessError;
+
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        let err = || ParseProcessError(s.to_string());
+
+        // either just the id or `<id> (generation <generation>)`
+        let (id, generation) = match s.split_once(" (generation ") {
+            Some((id, rest)) => (id, rest.strip_suffix(')').ok_or_else(err)?),
+            None => (s, "0"),
+        };
+
+        Ok(Self {
+            id: id.parse().map_err(|_| err())?,
+            generation: generation.parse().map_err(|_| err())?,
+        })
+    }
+}
+
+impl TryFrom<&str> for Process {
+    type Error = ParseProcessError;
+
+    fn try_from(s: &str) -> core::result::Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// A set of processes which are cleaned up, limited and frozen together, see
+/// `Allocator::create_group()`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Group(pub(super) u32);
+
+impl fmt::Display for Group {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Hands out process ids, either counting up with `count()` or scrambled with `xorshift()`.
+/// Every id of a builder is new as long as only one of the two is used with it.
 #[derive(Copy, Clone)]
 pub struct ProcBuilder {
-    xorshift: Xorshift1024,
+    // what the ids of `xorshift()` are scrambled with, drawn from an rng seeded from the thread
+    // rng or `from_seed()`, which there's only with std
+    #[cfg(feature = "std")]
+    keys: [u32; 2],
     counter: u32,
+    // how many ids `xorshift()` handed out
+    #[cfg(feature = "std")]
+    scrambled: u32,
 }
 
 impl ProcBuilder {
     pub fn new() -> Self {
+        #[cfg(feature = "std")]
+        return Self::with_rng(thread_rng());
+
+        #[cfg(not(feature = "std"))]
+        Self { counter: 0 }
+    }
+
+    /// Create a `ProcBuilder` whose `xorshift()` ids only depend on `seed`, so tests and replays
+    /// get the same ones every run.
+    #[cfg(feature = "std")]
+    pub fn from_seed(seed: u64) -> Self {
+        let mut seeder: SplitMix64 = SeedableRng::from_seed(seed);
+        Self::with_rng(Rand::rand(&mut seeder))
+    }
+
+    #[cfg(feature = "std")]
+    fn with_rng(mut rng: Xorshift1024) -> Self {
         Self {
-            xorshift: thread_rng(),
+            keys: [rng.next_u32(), rng.next_u32()],
             counter: 0,
+            scrambled: 0,
         }
     }
 
     pub fn count(&mut self) -> Process {
         self.counter += 1;
-        Process(self.counter - 1)
+        Process {
+            id: self.counter - 1,
+            generation: 0,
+        }
     }
 
+    /// A process id that looks random, for catching code which assumes ids are small or handed
+    /// out in order. They're 0, 1, 2 and so on scrambled in a way that can be undone, so unlike
+    /// drawing them from the rng none of them comes up twice before all 2^32 were handed out.
+    #[cfg(feature = "std")]
     pub fn xorshift(&mut self) -> Process {
-        let mut num: [u8; 4] = [0; 4];
-        self.xorshift.fill_bytes(&mut num);
-        Process(u32::from_le_bytes(num))
+        self.scrambled += 1;
+        Process {
+            id: scramble(self.scrambled - 1, self.keys),
+            generation: 0,
+        }
     }
 }
 
+// a bijection on u32 which looks random, a xorshift with a key and a multiply by an odd number
+// can all be undone so no two ids turn into the same one
+#[cfg(feature = "std")]
+fn scramble(id: u32, keys: [u32; 2]) -> u32 {
+    let mut x = id.wrapping_add(keys[0]);
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+
+    x ^ keys[1]
+}
+
 impl Default for ProcBuilder {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// What the processes holding a block may do with it, see `Allocator::protect()`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Protection {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Protection {
+    pub const NONE: Self = Self {
+        read: false,
+        write: false,
+        execute: false,
+    };
+    pub const READ_ONLY: Self = Self {
+        read: true,
+        ..Self::NONE
+    };
+    pub const READ_WRITE: Self = Self {
+        write: true,
+        ..Self::READ_ONLY
+    };
+    pub const READ_EXECUTE: Self = Self {
+        execute: true,
+        ..Self::READ_ONLY
+    };
+}
+
+/// What every block starts out with.
+impl Default for Protection {
+    fn default() -> Self {
+        Self::READ_WRITE
+    }
+}
+
+/// What a process wanted to do with memory, see `AllocError::PermissionDenied`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+/// What a child process gets of the blocks of its parent, see `Allocator::register_child()`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Inherit {
+    /// The same blocks, shared with the parent.
+    Share,
+    /// Copies of the blocks which only the child holds.
+    Copy,
+}
+
+/// An opaque reference to a block held by a process, which `Allocator::alloc()` returns and
+/// everything acting on a whole block takes.
+///
+/// Unlike the start index of the block it stays the same when the block moves (`compact()`,
+/// `realloc()`), and it always knows which process it belongs to.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BlockHandle {
+    pub(super) process_id: Process,
+    pub(super) id: u64,
+}
+
+impl BlockHandle {
+    pub fn process_id(&self) -> Process {
+        self.process_id
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MemRange {
+    // the same for every process sharing the block
+    pub(super) id: u64,
     pub(super) refcount: Arc<AtomicU32>,
+    // half-open, the end is the first byte after the block
     pub(super) range: Range<u32>,
+    // the id of the block this is a window into, see `Allocator::share_range()`
+    pub(super) parent: Option<u64>,
+    // whether the process holding this can only read it, see `Allocator::share_read_only()`
+    pub(super) read_only: bool,
 }
 
 impl MemRange {
-    pub fn new(refcount: Arc<AtomicU32>, range: Range<u32>) -> Self {
-        Self { refcount, range }
+    pub fn new(id: u64, refcount: Arc<AtomicU32>, range: Range<u32>) -> Self {
+        Self {
+            id,
+            refcount,
+            range,
+            parent: None,
+            read_only: false,
+        }
     }
 }
 
+/// Which free block `alloc()` picks when more than one of them is big enough.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Strategy {
+    /// The one at the lowest address, which has to walk the free blocks in address order,
+    /// the other two only look at an index sorted by size.
+    #[default]
+    FirstFit,
+    /// The smallest one, which leaves the smallest leftovers behind.
+    BestFit,
+    /// The biggest one, which leaves leftovers big enough to still be useful.
+    WorstFit,
+}
+
 #[derive(Debug)]
 pub struct Allocator {
-    pub(super) heap: Vec<u8>,
-    // hashmap<pid, vec<(refcount, range)>>
-    pub(super) allocated: HashMap<Process, Vec<MemRange>>,
-    // (size, range)
-    pub(super) free: Vec<(u32, Range<u32>)>,
+    pub(super) heap: Heap,
+    // the blocks every process holds, with their half-open ranges on the heap
+    pub(super) allocated: BTreeMap<Process, Vec<MemRange>>,
+    pub(super) free: FreeList,
+    pub(super) strategy: Strategy,
+    // only there in buddy mode, in which case `free` stays empty
+    pub(super) buddy: Option<Buddy>,
+    // the id of the next block handed out
+    pub(super) next_id: u64,
+    // how long the heap may get, in bytes
+    pub(super) limit: Option<u32>,
+    // the labels of the blocks which have one, by block id so every process sharing a block
+    // sees the same one
+    pub(super) tags: BTreeMap<u64, String>,
+    // told about everything the allocator does, in the order they were added
+    pub(super) observers: Vec<Box<dyn AllocObserver>>,
+    // how many guard bytes go on each side of a new block
+    pub(super) guard: u32,
+    // the whole range of every block with guards, guards included, by block id
+    pub(super) guards: BTreeMap<u64, Range<u32>>,
+    // whether freed blocks get filled with the poison byte
+    pub(super) poison: bool,
+    // the range of every block with windows into it, by block id, so it's still known once
+    // only the windows are left
+    pub(super) parents: BTreeMap<u64, Range<u32>>,
+    // whether new blocks are zeroed before they're handed out
+    pub(super) zero_on_alloc: bool,
+    // whether new blocks get filled with the fresh byte, see `Allocator::set_debug_fill()`
+    pub(super) fill_fresh: bool,
+    // the last few handles that were freed along with where their block started, oldest first
+    pub(super) recently_freed: VecDeque<(BlockHandle, u32)>,
+    // every weak share and whether it can only read the block, see `Allocator::share_weak()`
+    pub(super) weak: BTreeMap<BlockHandle, bool>,
+    // the block id of every named segment, see `Allocator::create_segment()`
+    pub(super) segments: BTreeMap<String, u64>,
+    // the processes waiting in `Allocator::futex_wait()`, oldest first, by block id and offset
+    pub(super) futexes: BTreeMap<(u64, u32), VecDeque<Process>>,
+    // every process group, see `Allocator::create_group()`
+    pub(super) groups: BTreeMap<Group, GroupState>,
+    // the id of the next group
+    pub(super) next_group: u32,
+    // the parent of every process started with `Allocator::register_child()`, by child
+    pub(super) spawned_by: BTreeMap<Process, Process>,
+    // the generation of the latest process with each id that was ever registered, so a stale
+    // `Process` can be told apart from one that never existed
+    pub(super) generations: BTreeMap<u32, u32>,
+    // the protection of every block which doesn't have the default one, by block id, see
+    // `Allocator::protect()`
+    pub(super) protections: BTreeMap<u64, Protection>,
+    // the alignment of every block allocated with `Allocator::alloc_aligned()`, by block id, so
+    // it stays aligned when it moves
+    pub(super) aligns: BTreeMap<u64, u32>,
+    // the blocks every process mapped into its address space by virtual address, see
+    // `Allocator::map()`
+    pub(super) spaces: BTreeMap<Process, BTreeMap<u32, Mapping>>,
+    // every named heap by name, see `Allocator::create_heap()`
+    pub(super) heaps: BTreeMap<String, NamedHeap>,
+    // told when allocations run out of room, see `Allocator::set_pressure_handler()`
+    pub(super) pressure_handler: Option<Box<dyn PressureHandler>>,
+    // the headroom below which the pressure handler is told, see `Allocator::set_low_water()`
+    pub(super) low_water: Option<u32>,
+    // whether the headroom is below the low water mark and the handler was told already
+    pub(super) under_pressure: bool,
+    // the ranges claimed by live `BorrowToken`s
+    #[cfg(feature = "std")]
+    pub(super) borrows: Arc<Mutex<Borrows>>,
 }
 
 impl Default for Allocator {
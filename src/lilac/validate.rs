@@ -0,0 +1,206 @@
+use alloc::collections::BTreeMap;
+use alloc::{string::String, vec, vec::Vec};
+use core::fmt;
+use core::ops::Range;
+use core::sync::atomic::Ordering;
+
+use super::Allocator;
+
+/// Something wrong with the bookkeeping of an `Allocator`, see `Allocator::validate()`.
+///
+/// None of these should ever happen, finding one means there's a bug in lilac or the heap was
+/// messed with from the outside.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Violation {
+    /// Two blocks share some bytes, either of them can be allocated or free.
+    Overlap(Range<u32>, Range<u32>),
+    /// A block reaches past the end of the heap or doesn't end after it starts.
+    OutOfBounds(Range<u32>),
+    /// The size index of the free blocks disagrees with the free block at `start`, `None` means
+    /// either the index has no entry for it or there is no such block.
+    SizeMismatch {
+        start: u32,
+        indexed: Option<u32>,
+        actual: Option<u32>,
+    },
+    /// A free block in buddy mode which doesn't start at a multiple of its own size.
+    Misaligned(Range<u32>),
+    /// The refcount of a block is zero or doesn't match how many processes hold it.
+    Refcount {
+        id: u64,
+        refcount: u32,
+        holders: u32,
+    },
+    /// The window `id` is part of the block `parent`, which nothing knows the range of.
+    MissingParent { id: u64, parent: u64 },
+    /// A block, allocated or free, which is partly inside the named heap `heap` and partly
+    /// outside of it.
+    HeapEdge { heap: String, range: Range<u32> },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::Overlap(first, second) => write!(
+                f,
+                "the blocks at {:#x}..{:#x} and {:#x}..{:#x} overlap",
+                first.start, first.end, second.start, second.end
+            ),
+            Violation::OutOfBounds(range) => write!(
+                f,
+                "the block at {:#x}..{:#x} isn't inside the heap",
+                range.start, range.end
+            ),
+            Violation::SizeMismatch {
+                start,
+                indexed,
+                actual,
+            } => write!(
+                f,
+                "the free block at {:#x} is indexed as {:?} bytes but is {:?} bytes",
+                start, indexed, actual
+            ),
+            Violation::Misaligned(range) => write!(
+                f,
+                "the free block at {:#x}..{:#x} isn't aligned to its size",
+                range.start, range.end
+            ),
+            Violation::Refcount {
+                id,
+                refcount,
+                holders,
+            } => write!(
+                f,
+                "block {} has a refcount of {} but {} processes hold it",
+                id, refcount, holders
+            ),
+            Violation::MissingParent { id, parent } => write!(
+                f,
+                "window {} is part of block {}, which doesn't exist",
+                id, parent
+            ),
+            Violation::HeapEdge { heap, range } => write!(
+                f,
+                "the block at {:#x}..{:#x} reaches across the edge of the named heap `{}`",
+                range.start, range.end, heap
+            ),
+        }
+    }
+}
+
+impl Allocator {
+    /// Check the bookkeeping for everything that should always hold: no two blocks overlap,
+    /// every block is inside the heap and either all in a named heap or not in one at all, the
+    /// free blocks are indexed with the right sizes, every window is into a block that exists
+    /// and the refcount of every block is how many processes hold it.
+    ///
+    /// It will return every violation it found, an empty `Vec` means the heap is fine. It walks
+    /// all the blocks so it's meant for debugging and tests, not for every allocation.
+    pub fn validate(&self) -> Vec<Violation> {
+        let mut violations = vec![];
+        let heap_len = self.heap.len() as u32;
+
+        // shared blocks show up once for every process holding them, they're told apart by id
+        let mut blocks: BTreeMap<u64, (Range<u32>, u32, u32)> = BTreeMap::new();
+        // with their guards, which nothing else may overlap either. windows are part of their
+        // block and count towards its refcount
+        for block in self.allocated.values().flatten() {
+            if let Some(parent) = block.parent.filter(|x| !self.parents.contains_key(x)) {
+                violations.push(Violation::MissingParent {
+                    id: block.id,
+                    parent,
+                });
+                continue;
+            }
+
+            let refcount = (*block.refcount).load(Ordering::SeqCst);
+            let (id, range) = self.block_of(block);
+            let entry = blocks
+                .entry(id)
+                .or_insert((self.extent(id, &range), refcount, 0));
+            entry.2 += 1;
+        }
+
+        for (id, (_, refcount, holders)) in &blocks {
+            if *refcount == 0 || refcount != holders {
+                violations.push(Violation::Refcount {
+                    id: *id,
+                    refcount: *refcount,
+                    holders: *holders,
+                });
+            }
+        }
+
+        let free = self.free_ranges();
+        match &self.buddy {
+            Some(_) => {
+                for range in &free {
+                    if range.start % (range.end - range.start) != 0 {
+                        violations.push(Violation::Misaligned(range.clone()));
+                    }
+                }
+            }
+            None => {
+                let lists =
+                    core::iter::once(&self.free).chain(self.heaps.values().map(|x| &x.free));
+                for (start, indexed, actual) in lists.flat_map(|x| x.mismatches()) {
+                    violations.push(Violation::SizeMismatch {
+                        start,
+                        indexed,
+                        actual,
+                    });
+                }
+            }
+        }
+
+        let mut ranges: Vec<Range<u32>> = blocks.into_values().map(|x| x.0).collect();
+        ranges.extend(free);
+        ranges.sort_unstable_by_key(|x| (x.start, x.end));
+
+        for range in &ranges {
+            if range.start >= range.end || range.end > heap_len {
+                violations.push(Violation::OutOfBounds(range.clone()));
+            }
+
+            for (name, heap) in &self.heaps {
+                let overlaps = range.start < heap.range.end && heap.range.start < range.end;
+                let inside = heap.range.start <= range.start && range.end <= heap.range.end;
+                if overlaps && !inside {
+                    violations.push(Violation::HeapEdge {
+                        heap: name.clone(),
+                        range: range.clone(),
+                    });
+                }
+            }
+        }
+
+        // in address order a block overlaps something before it if it starts before the
+        // furthest end seen so far
+        let mut furthest: Option<&Range<u32>> = None;
+        for range in &ranges {
+            match furthest {
+                Some(prev) if range.start < prev.end => {
+                    violations.push(Violation::Overlap(prev.clone(), range.clone()));
+                    if range.end > prev.end {
+                        furthest = Some(range);
+                    }
+                }
+                _ => furthest = Some(range),
+            }
+        }
+
+        // the named heaps themselves have to be inside the heap and apart from each other
+        let mut heaps: Vec<Range<u32>> = self.heaps.values().map(|x| x.range.clone()).collect();
+        heaps.sort_unstable_by_key(|x| x.start);
+        for (i, range) in heaps.iter().enumerate() {
+            if range.start >= range.end || range.end > heap_len {
+                violations.push(Violation::OutOfBounds(range.clone()));
+            }
+            if let Some(next) = heaps.get(i + 1).filter(|x| x.start < range.end) {
+                violations.push(Violation::Overlap(range.clone(), next.clone()));
+            }
+        }
+
+        violations
+    }
+}
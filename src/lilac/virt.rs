@@ -0,0 +1,243 @@
+use core::ops::Range;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::{AllocError, Allocator, BlockHandle, Process, Result};
+
+/// What the virtual addresses `Allocator::map()` picks are multiples of, so there's a gap
+/// between two mappings which running off the end of one of them falls into.
+pub const PAGE_SIZE: u32 = 4096;
+
+// a block mapped into the address space of a process. `len` is how long the block was when it
+// was mapped, which is as much of it as the mapping reaches even if it grew since
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub(super) struct Mapping {
+    pub(super) id: u64,
+    pub(super) len: u32,
+}
+
+impl Allocator {
+    /// Map the block of `handle` into the address space of its process, at the first page after
+    /// everything it has mapped already, and return the virtual address it got. The first block
+    /// a process maps is at 0.
+    ///
+    /// Every process has an address space of its own which only holds what it mapped, so a
+    /// guest running on the VM can be handed virtual addresses instead of indices into the
+    /// heap, which tell it nothing about where the memory of other processes is. The mapping
+    /// follows the block when it moves, see `range_borrow_virtual()` for using it.
+    ///
+    /// It errors like `map_at()` does.
+    pub fn map(&mut self, handle: BlockHandle) -> Result<u32> {
+        // the end of the last mapping rounded up to a page, as a u64 since it may be past the
+        // end of the address space
+        let next = match self
+            .spaces
+            .get(&handle.process_id)
+            .and_then(|x| x.iter().next_back())
+        {
+            Some((start, mapping)) => {
+                (*start as u64 + mapping.len as u64).next_multiple_of(PAGE_SIZE as u64)
+            }
+            None => 0,
+        };
+
+        let addr = u32::try_from(next).map_err(|_| AllocError::BadMapping {
+            process_id: handle.process_id,
+            range: u32::MAX..u32::MAX,
+        })?;
+        self.map_at(handle, addr)?;
+
+        Ok(addr)
+    }
+
+    /// Map the block of `handle` into the address space of its process at the virtual address
+    /// `addr`, see `map()`.
+    ///
+    /// It errors like `range()` does, for a weak share too, if the process already mapped the
+    /// block (`AllocError::AlreadyMapped`, with where) or if it doesn't fit at `addr`, since it
+    /// overlaps another mapping or runs past the end of the address space
+    /// (`AllocError::BadMapping`).
+    pub fn map_at(&mut self, handle: BlockHandle, addr: u32) -> Result<()> {
+        // only a block the process really holds, a weak share can go away under the mapping
+        let idx = self.find(handle)?;
+        let block = self.allocated[&handle.process_id][idx].range.clone();
+        let process_id = handle.process_id;
+        let space = self.spaces.entry(process_id).or_default();
+
+        if let Some((mapped, _)) = space.iter().find(|x| x.1.id == handle.id) {
+            return Err(AllocError::AlreadyMapped {
+                handle,
+                addr: *mapped,
+            });
+        }
+
+        // the end as a u64, since a mapping may end right at the end of the address space. the
+        // range in the errors stops at the last address there is
+        let len = block.end - block.start;
+        let end = addr as u64 + len as u64;
+        let range = addr..u32::try_from(end).unwrap_or(u32::MAX);
+        if end > 1 << 32 {
+            return Err(AllocError::BadMapping { process_id, range });
+        }
+
+        // the only mapping that can overlap is the last one starting before the end, blocks are
+        // never empty so there's a last byte to look up to
+        if let Some((start, mapping)) = space.range(..=(end - 1) as u32).next_back() {
+            if *start as u64 + mapping.len as u64 > addr as u64 {
+                return Err(AllocError::BadMapping { process_id, range });
+            }
+        }
+
+        space.insert(addr, Mapping { id: handle.id, len });
+        Ok(())
+    }
+
+    /// Take the mapping starting at the virtual address `addr` out of the address space of the
+    /// process, and return the handle of the block it was, which the process still holds.
+    ///
+    /// It errors if no mapping of the process starts at `addr` (`AllocError::Unmapped`).
+    pub fn unmap(&mut self, process_id: Process, addr: u32) -> Result<BlockHandle> {
+        match self
+            .spaces
+            .get_mut(&process_id)
+            .and_then(|x| x.remove(&addr))
+        {
+            Some(mapping) => Ok(BlockHandle {
+                process_id,
+                id: mapping.id,
+            }),
+            None => Err(AllocError::Unmapped { process_id, addr }),
+        }
+    }
+
+    /// Every mapping of the process, its virtual address along with the handle of the block,
+    /// sorted by address.
+    pub fn mappings(&self, process_id: Process) -> impl Iterator<Item = (u32, BlockHandle)> + '_ {
+        self.spaces
+            .get(&process_id)
+            .into_iter()
+            .flatten()
+            .map(move |(addr, mapping)| {
+                (
+                    *addr,
+                    BlockHandle {
+                        process_id,
+                        id: mapping.id,
+                    },
+                )
+            })
+    }
+
+    /// The index into the heap the virtual address `addr` of the process is at right now.
+    ///
+    /// It errors if nothing is mapped at `addr` (`AllocError::Unmapped`), which includes the
+    /// process not holding the block anymore.
+    pub fn translate(&self, process_id: Process, addr: u32) -> Result<u32> {
+        Ok(self.resolve(process_id, addr)?.0)
+    }
+
+    // `translate()` along with how many bytes from `addr` on are mapped in one go
+    fn resolve(&self, process_id: Process, addr: u32) -> Result<(u32, u32)> {
+        let unmapped = AllocError::Unmapped { process_id, addr };
+        let (start, mapping) = self
+            .spaces
+            .get(&process_id)
+            .and_then(|x| x.range(..=addr).next_back())
+            .ok_or(unmapped.clone())?;
+
+        let offset = addr - start;
+        let block = self
+            .allocated
+            .get(&process_id)
+            .and_then(|x| x.iter().find(|x| x.id == mapping.id))
+            .ok_or(unmapped.clone())?;
+
+        // the block may have shrunk since it was mapped
+        let len = mapping.len.min(block.range.end - block.range.start);
+        if offset >= len {
+            return Err(unmapped);
+        }
+
+        Ok((block.range.start + offset, len - offset))
+    }
+
+    // the range of the heap the virtual `range` of a process is at, which has to be inside a
+    // single mapping
+    fn translate_range(&self, process_id: Process, range: &Range<u32>) -> Result<Range<u32>> {
+        if range.is_empty() {
+            return Err(AllocError::NotOwned {
+                process_id,
+                range: range.clone(),
+            });
+        }
+
+        let (start, mapped) = self.resolve(process_id, range.start)?;
+        if range.end - range.start > mapped {
+            return Err(AllocError::Unmapped {
+                process_id,
+                addr: range.start + mapped,
+            });
+        }
+
+        Ok(start..start + (range.end - range.start))
+    }
+
+    // put the virtual range back into an error about the range it translated to, so the
+    // process is never told where its memory actually is
+    fn virtual_error(err: AllocError, virt: &Range<u32>) -> AllocError {
+        match err {
+            AllocError::NotOwned { process_id, .. } => AllocError::NotOwned {
+                process_id,
+                range: virt.clone(),
+            },
+            AllocError::PermissionDenied {
+                process_id, access, ..
+            } => AllocError::PermissionDenied {
+                process_id,
+                range: virt.clone(),
+                access,
+            },
+            AllocError::BorrowConflict { with, .. } => AllocError::BorrowConflict {
+                range: virt.clone(),
+                with,
+            },
+            err => err,
+        }
+    }
+
+    /// Like `range_borrow()` but `range` is in the address space of the process (see `map()`)
+    /// and has to be inside a single mapping.
+    ///
+    /// It errors like `range_borrow()` does, with the virtual range in the error, or if some of
+    /// the range isn't mapped (`AllocError::Unmapped`).
+    pub fn range_borrow_virtual(&self, process_id: Process, range: Range<u32>) -> Result<&[u8]> {
+        let physical = self.translate_range(process_id, &range)?;
+        self.range_borrow(process_id, physical)
+            .map_err(|err| Self::virtual_error(err, &range))
+    }
+
+    /// Like `range_borrow_mut()` but `range` is in the address space of the process, see
+    /// `range_borrow_virtual()`.
+    ///
+    /// It errors like `range_borrow_virtual()` and `range_borrow_mut()` do.
+    pub fn range_borrow_virtual_mut(
+        &mut self,
+        process_id: Process,
+        range: Range<u32>,
+    ) -> Result<&mut [u8]> {
+        let physical = self.translate_range(process_id, &range)?;
+        self.check_range(process_id, &physical, true)
+            .map_err(|err| Self::virtual_error(err, &range))?;
+
+        Ok(&mut self.heap[physical.start as usize..physical.end as usize])
+    }
+
+    // forget the mapping of the block of `handle`, if the process has one, once it doesn't hold
+    // the block anymore
+    pub(super) fn unmap_block(&mut self, handle: BlockHandle) {
+        if let Some(space) = self.spaces.get_mut(&handle.process_id) {
+            space.retain(|_, x| x.id != handle.id);
+        }
+    }
+}
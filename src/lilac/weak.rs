@@ -0,0 +1,97 @@
+use super::{AllocError, Allocator, BlockHandle, Process, Result};
+
+impl Allocator {
+    /// Share the block of `handle` with another process without keeping the block alive, the
+    /// block is freed once every process holding it for real freed it and the weak share is
+    /// left dangling.
+    ///
+    /// The weak handle can be given to `range()`, `borrow()` and `borrow_mut()` like any other,
+    /// which error once it's dangling instead of handing out whatever took the block's place.
+    /// `upgrade()` turns it into a normal share and `drop_weak()` gets rid of it.
+    ///
+    /// It errors like `share()` does, a weak share is only ever as writable as the one it's
+    /// made from.
+    pub fn share_weak(
+        &mut self,
+        handle: BlockHandle,
+        target_process: Process,
+    ) -> Result<BlockHandle> {
+        let idx = self.find(handle)?;
+        let read_only = self.allocated[&handle.process_id][idx].read_only;
+
+        if target_process == handle.process_id {
+            return Err(AllocError::ShareWithSelf(handle));
+        }
+
+        let target = self
+            .allocated
+            .get(&target_process)
+            .ok_or_else(|| self.no_such_process(target_process))?;
+
+        let weak = BlockHandle {
+            process_id: target_process,
+            id: handle.id,
+        };
+        if target.iter().any(|x| x.id == handle.id) || self.weak.contains_key(&weak) {
+            return Err(AllocError::AlreadyShared(weak));
+        }
+
+        self.weak.insert(weak, read_only);
+        Ok(weak)
+    }
+
+    /// Whether `handle` is a weak share, see `share_weak()`, dangling or not.
+    pub fn is_weak(&self, handle: BlockHandle) -> bool {
+        self.weak.contains_key(&handle)
+    }
+
+    // the handle of a process still holding the block a weak share points to, one that may
+    // write to it if there is one, or `handle` itself if it isn't a weak share. ids are never
+    // reused so the block is gone for good once nobody holds it anymore
+    pub(super) fn strong(&self, handle: BlockHandle) -> Result<BlockHandle> {
+        if !self.weak.contains_key(&handle) {
+            return Ok(handle);
+        }
+
+        self.allocated
+            .iter()
+            .flat_map(|(process_id, blocks)| blocks.iter().map(move |x| (*process_id, x)))
+            .filter(|(_, x)| x.id == handle.id)
+            .min_by_key(|(_, x)| x.read_only)
+            .map(|(process_id, _)| BlockHandle {
+                process_id,
+                id: handle.id,
+            })
+            .ok_or(AllocError::Dangling(handle))
+    }
+
+    // whether a weak share was made from one that can only read the block
+    pub(super) fn weak_read_only(&self, handle: BlockHandle) -> bool {
+        self.weak.get(&handle).copied().unwrap_or(false)
+    }
+
+    /// Turn the weak share of `handle` into a normal one which keeps the block alive, the
+    /// handle stays the same.
+    ///
+    /// It errors if `handle` isn't a weak share (`AllocError::BlockNotFound`) or if the block
+    /// was freed already (`AllocError::Dangling`).
+    pub fn upgrade(&mut self, handle: BlockHandle) -> Result<BlockHandle> {
+        if !self.weak.contains_key(&handle) {
+            return Err(AllocError::BlockNotFound(handle));
+        }
+
+        // sharing it for real takes the weak share out of `weak`
+        let strong = self.strong(handle)?;
+        self.share_inner(strong, handle.process_id, self.weak_read_only(handle))
+    }
+
+    /// Get rid of the weak share of `handle`, dangling or not.
+    ///
+    /// It errors if `handle` isn't a weak share (`AllocError::BlockNotFound`).
+    pub fn drop_weak(&mut self, handle: BlockHandle) -> Result<()> {
+        match self.weak.remove(&handle) {
+            Some(_) => Ok(()),
+            None => Err(AllocError::BlockNotFound(handle)),
+        }
+    }
+}
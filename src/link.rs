@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::image::{self, Image, Segment, SegmentKind};
+use crate::object::{Object, RelocKind};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LinkError {
+    DuplicateSymbol(String),
+    UndefinedSymbol(String),
+    RelocationOverflow(String, u32),
+    BadObject(usize),
+}
+
+impl std::error::Error for LinkError {}
+
+pub type Result<T> = std::result::Result<T, LinkError>;
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkError::DuplicateSymbol(name) => {
+                write!(f, "symbol `{}` is defined by more than one object", name)
+            }
+            LinkError::UndefinedSymbol(name) => {
+                write!(f, "symbol `{}` is not defined by any object", name)
+            }
+            LinkError::RelocationOverflow(name, value) => write!(
+                f,
+                "the address of `{}` ({:#010x}) does not fit in its operand",
+                name, value
+            ),
+            LinkError::BadObject(idx) => write!(
+                f,
+                "object {} refers to a section or symbol it doesn't have",
+                idx
+            ),
+        }
+    }
+}
+
+/// Figure out where every section of every object goes in the linked image, the returned value
+/// is indexed by object and then by section.
+///
+/// Sections with the same name are placed back to back in the order of the objects, `.text`
+/// sections go first and all the other names follow in the order they first show up in. Every
+/// section starts at a multiple of its alignment, the gaps this leaves are zero filled.
+pub fn layout(objects: &[Object]) -> Vec<Vec<u32>> {
+    // every section name in the order it will be laid out in
+    let mut names: Vec<&str> = vec![".text"];
+    for object in objects {
+        for section in object.sections.iter() {
+            if !names.contains(&section.name.as_str()) {
+                names.push(&section.name);
+            }
+        }
+    }
+
+    let mut bases: Vec<Vec<u32>> = objects.iter().map(|x| vec![0; x.sections.len()]).collect();
+
+    let mut address = 0u32;
+    for name in names {
+        for (obj_idx, object) in objects.iter().enumerate() {
+            for (sec_idx, section) in object.sections.iter().enumerate() {
+                if section.name == name {
+                    let align = section.align.max(1);
+                    address = address.div_ceil(align) * align;
+
+                    bases[obj_idx][sec_idx] = address;
+                    address += section.data.len() as u32;
+                }
+            }
+        }
+    }
+
+    bases
+}
+
+/// Link objects into a single executable image, which is loaded and run starting from address
+/// zero.
+///
+/// The sections are placed as described in `layout()`, after that every relocation is patched
+/// with the final address of its symbol, local symbols are only looked up in their own object
+/// while undefined ones are taken from the `global` symbols of all the objects.
+///
+/// It errors if a global symbol is defined twice, a symbol is never defined, an address doesn't
+/// fit in the operand it's written to or if an object refers to something it doesn't have.
+pub fn link(objects: &[Object]) -> Result<Vec<u8>> {
+    let bases = layout(objects);
+
+    let total = objects
+        .iter()
+        .zip(bases.iter())
+        .flat_map(|(object, bases)| object.sections.iter().zip(bases.iter()))
+        .map(|(section, base)| *base as usize + section.data.len())
+        .max()
+        .unwrap_or(0);
+
+    let mut image = vec![0; total];
+    for (obj_idx, object) in objects.iter().enumerate() {
+        for (sec_idx, section) in object.sections.iter().enumerate() {
+            let start = bases[obj_idx][sec_idx] as usize;
+            image[start..start + section.data.len()].copy_from_slice(&section.data);
+        }
+    }
+
+    let address_of = |obj_idx: usize, sym_idx: usize| -> Result<Option<u32>> {
+        let object = &objects[obj_idx];
+        let symbol = object
+            .symbols
+            .get(sym_idx)
+            .ok_or(LinkError::BadObject(obj_idx))?;
+
+        match symbol.section {
+            Some(section) => {
+                let base = bases[obj_idx]
+                    .get(section)
+                    .ok_or(LinkError::BadObject(obj_idx))?;
+
+                Ok(Some(base.wrapping_add(symbol.offset)))
+            }
+            None => Ok(None),
+        }
+    };
+
+    let mut globals = HashMap::new();
+    for (obj_idx, object) in objects.iter().enumerate() {
+        for (sym_idx, symbol) in object.symbols.iter().enumerate() {
+            if !symbol.global {
+                continue;
+            }
+
+            if let Some(address) = address_of(obj_idx, sym_idx)? {
+                if globals.insert(symbol.name.as_str(), address).is_some() {
+                    return Err(LinkError::DuplicateSymbol(symbol.name.clone()));
+                }
+            }
+        }
+    }
+
+    for (obj_idx, object) in objects.iter().enumerate() {
+        for reloc in object.relocations.iter() {
+            let address = match address_of(obj_idx, reloc.symbol)? {
+                Some(address) => address,
+                None => {
+                    // safe to index because `address_of` checked the symbol exists
+                    let name = &object.symbols[reloc.symbol].name;
+
+                    *globals
+                        .get(name.as_str())
+                        .ok_or_else(|| LinkError::UndefinedSymbol(name.clone()))?
+                }
+            };
+
+            let value = address.wrapping_add(reloc.addend);
+            let name = || object.symbols[reloc.symbol].name.clone();
+
+            let bytes = match reloc.kind {
+                RelocKind::Abs8 => u8::try_from(value)
+                    .map_err(|_| LinkError::RelocationOverflow(name(), value))?
+                    .to_le_bytes()
+                    .to_vec(),
+                RelocKind::Abs16 => u16::try_from(value)
+                    .map_err(|_| LinkError::RelocationOverflow(name(), value))?
+                    .to_le_bytes()
+                    .to_vec(),
+                RelocKind::Abs32 => value.to_le_bytes().to_vec(),
+            };
+
+            let base = bases[obj_idx]
+                .get(reloc.section)
+                .ok_or(LinkError::BadObject(obj_idx))?;
+            let start = base.wrapping_add(reloc.offset) as usize;
+
+            // safe to index because the base lookup above checked the section exists
+            let section_len = object.sections[reloc.section].data.len();
+            if reloc.offset as usize + bytes.len() > section_len {
+                return Err(LinkError::BadObject(obj_idx));
+            }
+
+            image[start..start + bytes.len()].copy_from_slice(&bytes);
+        }
+    }
+
+    Ok(image)
+}
+
+/// Link objects like `link()`, but wrap the result in an `Image` with the `.text` sections as
+/// its code segment and everything after them as its data segment.
+///
+/// The entry point is the global `_start` symbol if any object defines it, and the start of the
+/// image otherwise. Every symbol defined by the objects, local ones included, ends up in the
+/// symbol table of the image sorted by address, and so do the line tables of objects which have
+/// one.
+pub fn link_image(objects: &[Object]) -> Result<Image> {
+    let flat = link(objects)?;
+    let bases = layout(objects);
+
+    let mut text_end = 0;
+    let mut entry = 0;
+    let mut symbols = vec![];
+    let mut lines = vec![];
+    for (obj_idx, object) in objects.iter().enumerate() {
+        for (sec_idx, section) in object.sections.iter().enumerate() {
+            if section.name == ".text" {
+                text_end = text_end.max(bases[obj_idx][sec_idx] as usize + section.data.len());
+            }
+        }
+
+        for symbol in object.symbols.iter() {
+            if let Some(section) = symbol.section {
+                symbols.push(image::Symbol {
+                    name: symbol.name.clone(),
+                    address: bases[obj_idx][section] + symbol.offset,
+                });
+            }
+
+            if let (true, "_start", Some(section)) =
+                (symbol.global, symbol.name.as_str(), symbol.section)
+            {
+                entry = bases[obj_idx][section] + symbol.offset;
+            }
+        }
+
+        for line in object.lines.iter() {
+            let base = bases[obj_idx]
+                .get(line.section)
+                .ok_or(LinkError::BadObject(obj_idx))?;
+            let file = object
+                .files
+                .get(line.file)
+                .ok_or(LinkError::BadObject(obj_idx))?;
+
+            lines.push(image::Line {
+                address: base + line.offset,
+                file: file.clone(),
+                line: line.line,
+            });
+        }
+    }
+
+    let mut segments = vec![Segment {
+        kind: SegmentKind::Code,
+        address: 0,
+        data: flat[..text_end].to_vec(),
+    }];
+
+    if flat.len() > text_end {
+        segments.push(Segment {
+            kind: SegmentKind::Data,
+            address: text_end as u32,
+            data: flat[text_end..].to_vec(),
+        });
+    }
+
+    symbols.sort_by_key(|x| x.address);
+    lines.sort_by_key(|x| x.address);
+
+    Ok(Image {
+        entry,
+        memory: flat.len() as u32,
+        segments,
+        symbols,
+        lines,
+    })
+}
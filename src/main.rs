@@ -1,214 +1,406 @@
-use std::cmp::Ordering;
-use std::time::Instant;
-
-const ADD: u8 = 0x01;
-const SUB: u8 = 0x02;
-const JMP: u8 = 0x03;
-const JZ: u8 = 0x04;
-const JLZ: u8 = 0x05;
-const JMZ: u8 = 0x06;
-const MOV: u8 = 0x07;
-const LDP: u8 = 0x08;
-const STP: u8 = 0x09;
-const AND: u8 = 0x0A;
-const NOT: u8 = 0x0B;
-const OR: u8 = 0x0C;
-const NOR: u8 = 0x0D;
-const NAND: u8 = 0x0E;
-const XOR: u8 = 0x0F;
-const XNOR: u8 = 0x10;
-const HLT: u8 = 0x11;
-const NOP: u8 = 0x12;
-const INT: u8 = 0x13;
-const CMP: u8 = 0x14;
-
-const RR_MODE: u8 = 0x01;
-const RB_MODE: u8 = 0x02;
-const RW_MODE: u8 = 0x03;
-const RD_MODE: u8 = 0x04;
-
-struct Program {
-    regs: [u32; 4],
-    code: Vec<u8>,
-    counter: u32,
-    halted: bool,
-}
-
-impl Program {
-    fn new(code: Vec<u8>) -> Self {
-        Program {
-            regs: [0u32; 4],
-            code,
-            counter: 0,
-            halted: false,
-        }
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use cpu_tset::debugger::Debugger;
+use cpu_tset::image::Image;
+use cpu_tset::monitor::Monitor;
+use cpu_tset::vm::Program;
+use cpu_tset::{asm, asmtest, disasm, ihex, isa, link, opt, trace, verify};
+
+const USAGE: &str = "usage:
+    lim32 run [--trace] <image>
+    lim32 run [--trace-regs <r0,r1,...|all>] [--trace-mem] [--trace-range <from..to>] <image>
+    lim32 run --trace-json <trace> <image>
+    lim32 run --trace-chrome <trace> <image>...
+    lim32 asm [-g] [-O] [-I <dir>]... <source> [-o <image>] [-l <listing>]
+    lim32 disasm [--linear] <image>
+    lim32 diff <old image> <new image>
+    lim32 dump <image> [address] [length]
+    lim32 verify <image>
+    lim32 test <source>...
+    lim32 debug [--tui] <image>
+    lim32 monitor [image]
+
+images ending in `.hex` are read and written as Intel HEX, `-g` adds a line table to the image, `-O` runs
+the peephole optimizer over it and `-I` adds a directory to look for `%include`s in";
+
+fn read(path: &Path) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|err| format!("couldn't read `{}`: {}", path.display(), err))
+}
+
+fn write(path: &Path, contents: &[u8]) -> Result<(), String> {
+    fs::write(path, contents).map_err(|err| format!("couldn't write `{}`: {}", path.display(), err))
+}
+
+fn is_hex(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("hex"))
+}
+
+// images without a header are taken as raw code
+fn load(path: &Path) -> Result<Image, String> {
+    let bytes = read(path)?;
+
+    let image = if is_hex(path) {
+        let text = String::from_utf8(bytes)
+            .map_err(|_| format!("`{}` is not valid utf-8", path.display()))?;
+        ihex::from_ihex(&text).map_err(|err| err.to_string())
+    } else {
+        Image::load(&bytes).map_err(|err| err.to_string())
+    };
+
+    image.map_err(|err| format!("couldn't load `{}`: {}", path.display(), err))
+}
+
+fn number(text: &str) -> Result<u32, String> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse::<u32>(),
+    };
+
+    parsed.map_err(|_| format!("`{}` is not a valid number", text))
+}
+
+fn register(text: &str) -> Result<u8, String> {
+    text.trim()
+        .strip_prefix('r')
+        .and_then(|x| x.parse::<u8>().ok())
+        .filter(|x| *x < isa::REGISTERS)
+        .ok_or_else(|| format!("`{}` is not a valid register", text))
+}
+
+fn run(path: &Path, trace: bool) -> Result<(), String> {
+    let mut program = Program::from_image(&load(path)?);
+    program.set_trace(trace);
+
+    let start = Instant::now();
+    let result = program.execute();
+    let elapsed = start.elapsed();
+
+    report(&program, elapsed);
+    result.map_err(|err| err.to_string())
+}
+
+fn report(program: &Program, elapsed: Duration) {
+    for (idx, reg) in program.regs().iter().enumerate() {
+        println!("reg{}: {:#010x} ({})", idx, reg, reg);
     }
+    println!("elapsed time to run program: {:?}", elapsed);
+}
 
-    fn step(&mut self) {
-        println!("Advancing to next byte\n\tcounter: {}", self.counter);
+// like `run()` with `--trace`, but only logging the instructions the filters let through
+fn run_filtered(args: &[String], path: &Path) -> Result<(), String> {
+    let mut filter = trace::Filter::default();
 
-        for (idx, i) in self.regs.into_iter().enumerate() {
-            println!("\treg{idx}:    {:#010} ({:#034b}) ({:#010x})", i, i, i);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace-regs" => {
+                let regs = args.next().ok_or("`--trace-regs` needs registers")?;
+                filter.regs = Some(if regs == "all" {
+                    (0..isa::REGISTERS).collect()
+                } else {
+                    regs.split(',').map(register).collect::<Result<_, _>>()?
+                });
+            }
+            "--trace-mem" => filter.memory = true,
+            "--trace-range" => {
+                let range = args.next().ok_or("`--trace-range` needs a range")?;
+                let (start, end) = range
+                    .split_once("..")
+                    .ok_or_else(|| format!("`{}` is not a range like `0x100..0x200`", range))?;
+                filter.range = Some(number(start)?..number(end)?);
+            }
+            _ => return Err(USAGE.to_string()),
         }
+    }
+
+    let mut program = Program::from_image(&load(path)?);
+
+    let start = Instant::now();
+    let result = trace::trace_filtered(&mut program, &filter, io::stdout().lock());
+    let elapsed = start.elapsed();
+
+    report(&program, elapsed);
+    result.map_err(|err| err.to_string())
+}
+
+// `-` writes the trace to stdout
+fn run_json(path: &Path, out: &Path) -> Result<(), String> {
+    let mut program = Program::from_image(&load(path)?);
+
+    let result = if out == Path::new("-") {
+        trace::trace_json(&mut program, io::stdout().lock())
+    } else {
+        let file = fs::File::create(out)
+            .map_err(|err| format!("couldn't write `{}`: {}", out.display(), err))?;
+        trace::trace_json(&mut program, io::BufWriter::new(file))
+    };
 
-        self.counter += 1;
+    result.map_err(|err| err.to_string())
+}
+
+// every image runs as a process of its own, taking turns every thousand instructions
+fn run_chrome(out: &Path, images: &[String]) -> Result<(), String> {
+    let mut programs = vec![];
+    for image in images {
+        programs.push((image.clone(), Program::from_image(&load(Path::new(image))?)));
     }
 
-    fn next_byte(&mut self) -> u8 {
-        // dbg!(self.code[self.counter as usize]);
-        self.step();
+    let file = fs::File::create(out)
+        .map_err(|err| format!("couldn't write `{}`: {}", out.display(), err))?;
+    trace::trace_chrome(&mut programs, 1000, io::BufWriter::new(file))
+        .map_err(|err| err.to_string())
+}
+
+fn assemble(args: &[String]) -> Result<(), String> {
+    let mut source = None;
+    let mut output = None;
+    let mut listing = None;
+    let mut lines = false;
+    let mut optimize = false;
+    let mut include_paths = vec![];
 
-        if self.counter as usize >= self.code.len() {
-            std::process::exit(0);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(PathBuf::from(args.next().ok_or("`-o` needs a path")?)),
+            "-l" => listing = Some(PathBuf::from(args.next().ok_or("`-l` needs a path")?)),
+            "-g" => lines = true,
+            "-O" => optimize = true,
+            "-I" => include_paths.push(PathBuf::from(args.next().ok_or("`-I` needs a path")?)),
+            _ if source.is_none() => source = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument `{}`", arg)),
         }
-
-        self.code[self.counter as usize]
     }
 
-    fn next_word(&mut self) -> u16 {
-        let first = self.next_byte();
-        let second = self.next_byte();
+    let source = source.ok_or("missing the source file")?;
+    let output = output.unwrap_or_else(|| source.with_extension("bin"));
+
+    let text = String::from_utf8(read(&source)?)
+        .map_err(|_| format!("`{}` is not valid utf-8", source.display()))?;
 
-        let array: [u8; 2] = [second, first];
+    let report = |errors: Vec<asm::Diagnostic>| {
+        let diagnostics: Vec<String> = errors.iter().map(|x| x.to_string()).collect();
+        diagnostics.join("\n\n")
+    };
+    let preprocessed = asm::preprocess(&text, &source, &include_paths).map_err(report)?;
+    let mut object = asm::assemble_source(&preprocessed).map_err(report)?;
+    if !lines {
+        object.lines.clear();
+        object.files.clear();
+    }
+    let mut image = link::link_image(&[object]).map_err(|err| err.to_string())?;
+    if optimize {
+        image = opt::optimize_image(&image).map_err(|errors| {
+            let lines: Vec<String> = errors.iter().map(|x| x.to_string()).collect();
+            format!("couldn't optimize:\n{}", lines.join("\n"))
+        })?;
+    }
+    if is_hex(&output) {
+        write(&output, ihex::to_ihex(&image).as_bytes())?;
+    } else {
+        write(&output, &image.to_bytes())?;
+    }
 
-        ((array[0] as u16) << 8) | array[1] as u16
+    if let Some(listing) = listing {
+        // the line numbers are the ones of the text with all the includes pasted in
+        let (_, text_listing) =
+            asm::assemble_listing(&preprocessed.text).map_err(|err| err.to_string())?;
+        write(&listing, text_listing.as_bytes())?;
     }
 
-    fn next_dword(&mut self) -> u32 {
-        let first = self.next_word();
-        let second = self.next_word();
+    Ok(())
+}
+
+// read commands from stdin until `command` returns `None` or stdin ends
+fn repl(prompt: &str, mut command: impl FnMut(&str) -> Option<String>) -> Result<(), String> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("{}", prompt);
+        io::stdout().flush().map_err(|err| err.to_string())?;
 
-        let array: [u16; 2] = [second, first];
+        let line = match lines.next() {
+            Some(line) => line.map_err(|err| err.to_string())?,
+            None => return Ok(()),
+        };
 
-        ((array[0] as u32) << 16) | array[1] as u32
+        match command(&line) {
+            Some(out) if out.is_empty() => {}
+            Some(out) => println!("{}", out),
+            None => return Ok(()),
+        }
     }
+}
 
-    fn modded_instr(&mut self, which: u8, mode: u8) {
-        let target = self.next_byte();
+fn debug(path: &Path) -> Result<(), String> {
+    let mut debugger = Debugger::new(Program::from_image(&load(path)?));
+    println!("{}", debugger.current());
 
-        assert!(target < 4, "TARGET more than allowed");
+    repl("(lim32) ", |line| debugger.command(line))
+}
 
-        let source: u32 = match mode {
-            RR_MODE => {
-                let other_register = self.next_byte();
+fn monitor(path: Option<&Path>) -> Result<(), String> {
+    let mut monitor = match path {
+        Some(path) => Monitor::new(Program::from_image(&load(path)?)),
+        None => Monitor::default(),
+    };
 
-                assert!(other_register < 4, "REGISTER_ID more than allowed");
-                self.regs[other_register as usize]
-            }
-            RB_MODE => {
-                let byte = self.next_byte();
+    repl("-", |line| monitor.command(line))
+}
 
-                byte as u32
-            }
-            RW_MODE => {
-                let word = self.next_word();
+#[cfg(feature = "tui")]
+fn debug_tui(path: &Path) -> Result<(), String> {
+    let debugger = Debugger::new(Program::from_image(&load(path)?));
+    cpu_tset::tui::Tui::new(debugger)
+        .run()
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(not(feature = "tui"))]
+fn debug_tui(_path: &Path) -> Result<(), String> {
+    Err("lim32 was built without the `tui` feature".to_string())
+}
+
+// the control flow is followed unless asked for a linear sweep, which fails on any data
+fn disassemble(path: &Path, linear: bool) -> Result<(), String> {
+    let image = load(path)?;
+    let text = if linear {
+        disasm::disassemble_image(&image).map_err(|err| err.to_string())?
+    } else {
+        disasm::disassemble_flow(&image)
+    };
+    print!("{}", text);
+
+    Ok(())
+}
+
+fn diff(old: &Path, new: &Path) -> Result<(), String> {
+    let text = disasm::diff_images(&load(old)?, &load(new)?).map_err(|err| err.to_string())?;
+    print!("{}", text);
+
+    Ok(())
+}
+
+fn check(path: &Path) -> Result<(), String> {
+    let errors = match verify::verify_image(&load(path)?) {
+        Ok(()) => return Ok(()),
+        Err(errors) => errors,
+    };
+
+    let lines: Vec<String> = errors.iter().map(|x| x.to_string()).collect();
+    Err(lines.join("\n"))
+}
+
+// the memory of the image as it's laid out when the program starts
+fn dump(path: &Path, args: &[String]) -> Result<(), String> {
+    let program = Program::from_image(&load(path)?);
+
+    let (start, end) = match args {
+        [] => (0, u32::MAX),
+        [address] => (number(address)?, u32::MAX),
+        [address, len] => {
+            let address = number(address)?;
+            (address, address.saturating_add(number(len)?))
+        }
+        _ => return Err(USAGE.to_string()),
+    };
+
+    print!("{}", program.dump(start..end));
+    Ok(())
+}
+
+// how long a test program may run before it counts as stuck
+const TEST_BUDGET: u64 = 10_000_000;
+
+fn test(sources: &[String]) -> Result<(), String> {
+    let mut failed = 0;
+
+    for source in sources {
+        let path = Path::new(source);
+        let text = String::from_utf8(read(path)?)
+            .map_err(|_| format!("`{}` is not valid utf-8", path.display()))?;
 
-                word as u32
+        let report = match asmtest::run(&text, TEST_BUDGET) {
+            Ok(report) => report,
+            Err(err) => {
+                println!("{}: error: {}", source, err);
+                failed += 1;
+                continue;
             }
-            RD_MODE => self.next_dword(),
-            _ => todo!(),
         };
 
-        match which {
-            AND => {
-                self.regs[target as usize] &= source;
-            }
-            NAND => {
-                self.regs[target as usize] = !(self.regs[target as usize] & source);
-            }
-            OR => {
-                self.regs[target as usize] |= source;
-            }
-            NOR => {
-                self.regs[target as usize] = !(self.regs[target as usize] | source);
-            }
-            XOR => {
-                self.regs[target as usize] ^= source;
-            }
-            XNOR => {
-                self.regs[target as usize] = !(self.regs[target as usize] ^ source);
-            }
-            MOV => {
-                self.regs[target as usize] = source;
-            }
-            ADD => {
-                self.regs[target as usize] += source;
-            }
-            SUB => {
-                self.regs[target as usize] -= source;
-            }
-            CMP => {
-                // for jmz and jlz we will be using 2 and 1 respectively
-                self.regs[0] = match self.regs[target as usize].cmp(&source) {
-                    Ordering::Less => 1,
-                    Ordering::Equal => 0,
-                    Ordering::Greater => 2,
-                };
-            }
-            _ => todo!(),
+        for failure in report.failures.iter() {
+            let actual = match failure.actual {
+                Some(actual) => format!("it is {:#x} ({})", actual, actual),
+                None => "it is outside of memory".to_string(),
+            };
+            println!(
+                "{}:{}: `{}` failed, {}",
+                source, failure.assertion.line, failure.assertion.text, actual
+            );
+        }
+
+        if report.ok() {
+            println!("{}: ok ({} passed)", source, report.passed);
+        } else {
+            println!(
+                "{}: FAILED ({} passed, {} failed)",
+                source,
+                report.passed,
+                report.failures.len()
+            );
+            failed += 1;
         }
     }
 
-    fn execute(&mut self) {
-        while !self.halted {
-            if self.counter as usize >= self.code.len() {
-                return;
-            }
+    if failed > 0 {
+        return Err(format!("{} of {} test files failed", failed, sources.len()));
+    }
 
-            let byte = self.code[self.counter as usize];
-
-            // all the jumping code requires a subtraction of 1, because at the
-            // end of the loop there's a `self.step()`, so without it, it ends up
-            // going back to the given address + 1
-            match byte {
-                JMP => self.counter = self.next_dword() - 1,
-                JZ => {
-                    let address = self.next_dword() - 1;
-                    if self.regs[0] == 0 {
-                        self.counter = address;
-                    }
-                }
-                JLZ => {
-                    let address = self.next_dword() - 1;
-                    if self.regs[0] == 1 {
-                        self.counter = address;
-                    }
-                }
-                JMZ => {
-                    let address = self.next_dword() - 1;
-                    if self.regs[0] == 2 {
-                        self.counter = address;
-                    }
-                }
-                LDP => {}
-                STP => {}
-                AND | NAND | OR | NOR | XOR | XNOR | MOV | ADD | SUB | CMP => {
-                    let mode = self.next_byte();
-
-                    self.modded_instr(byte, mode);
-                }
-                NOT => {
-                    let reg = self.next_byte();
-                    assert!(reg < 4, "TARGET more than allowed");
-
-                    self.regs[reg as usize] = !self.regs[reg as usize];
-                }
-                HLT => {}
-                NOP => {}
-                INT => {}
-                _ => todo!(),
-            }
-            self.step();
+    Ok(())
+}
+
+fn cli(args: &[String]) -> Result<(), String> {
+    match args {
+        [cmd, image] if cmd == "run" => run(Path::new(image), false),
+        [cmd, flag, image] if cmd == "run" && flag == "--trace" => run(Path::new(image), true),
+        [cmd, flag, out, image] if cmd == "run" && flag == "--trace-json" => {
+            run_json(Path::new(image), Path::new(out))
+        }
+        [cmd, flag, out, images @ ..]
+            if cmd == "run" && flag == "--trace-chrome" && !images.is_empty() =>
+        {
+            run_chrome(Path::new(out), images)
+        }
+        [cmd, flags @ .., image] if cmd == "run" && !flags.is_empty() => {
+            run_filtered(flags, Path::new(image))
         }
+        [cmd, rest @ ..] if cmd == "asm" => assemble(rest),
+        [cmd, image] if cmd == "disasm" => disassemble(Path::new(image), false),
+        [cmd, flag, image] if cmd == "disasm" && flag == "--linear" => {
+            disassemble(Path::new(image), true)
+        }
+        [cmd, old, new] if cmd == "diff" => diff(Path::new(old), Path::new(new)),
+        [cmd] if cmd == "monitor" => monitor(None),
+        [cmd, image] if cmd == "monitor" => monitor(Some(Path::new(image))),
+        [cmd, image] if cmd == "verify" => check(Path::new(image)),
+        [cmd, image, rest @ ..] if cmd == "dump" => dump(Path::new(image), rest),
+        [cmd, sources @ ..] if cmd == "test" && !sources.is_empty() => test(sources),
+        [cmd, image] if cmd == "debug" => debug(Path::new(image)),
+        [cmd, flag, image] if cmd == "debug" && flag == "--tui" => debug_tui(Path::new(image)),
+        _ => Err(USAGE.to_string()),
     }
 }
 
 fn main() {
-    //let thing: Vec<u8> = vec![MOV, RB_MODE, 0, 12, MOV, RB_MODE, 1, 11, CMP, RR_MODE, 1, 0];
-    let thing: Vec<u8> = vec![ADD, RB_MODE, 1, 1, CMP, RW_MODE, 1, 16, 39, JLZ, 0, 0, 0, 0];
-    let start = Instant::now();
-    let mut p1 = Program::new(thing);
-    p1.execute();
-    let elapsed = start.elapsed();
-    println!("elapsed time to run program: {:?}", elapsed);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if let Err(err) = cli(&args) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
 }
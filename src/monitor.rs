@@ -0,0 +1,254 @@
+use std::fmt::Write;
+
+use crate::asm;
+use crate::isa;
+use crate::vm::Program;
+
+const HELP: &str = "commands:
+    a <address> <instruction>   assemble a single instruction (or directive) at the address
+    e <address> <byte>...       enter bytes at the address
+    d [address] [length]        dump memory, going on from the last dump without an address
+    u [address] [count]         unassemble instructions, going on from the last one
+    r [register] [value]        print the registers or set one (`pc` included)
+    g [address]                 run from the address (or the counter) until the program halts
+    t                           execute a single instruction
+    q                           leave the monitor
+    ?                           print this
+
+addresses, lengths and bytes are hex like in DOS DEBUG, but instructions go through the
+assembler so the numbers in them are decimal unless written with `0x`";
+
+// the memory a monitor starts with when it's not given a program
+const MEMORY: usize = 0x1000;
+// how many instructions `g` runs before giving up on the program halting
+const BUDGET: u64 = 10_000_000;
+
+/// A tiny machine monitor in the spirit of DOS DEBUG, for poking at memory and running bits of
+/// code by hand.
+///
+/// Unlike the `Debugger` it's meant to build programs in place, so it can write into (and grow,
+/// up to `Program::memory_limit()`) the memory and move the counter around.
+#[derive(Debug)]
+pub struct Monitor {
+    program: Program,
+    dump_at: u32,
+    unassemble_at: u32,
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self::new(Program::new(vec![0; MEMORY]))
+    }
+}
+
+impl Monitor {
+    pub fn new(program: Program) -> Self {
+        let counter = program.counter();
+
+        Self {
+            program,
+            dump_at: counter,
+            unassemble_at: counter,
+        }
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// Run a single command and return what it printed, or `None` if it asked to quit.
+    ///
+    /// Mistakes in the command and errors raised by the program are reported in the output.
+    pub fn command(&mut self, line: &str) -> Option<String> {
+        let line = line.trim();
+        let (cmd, rest) = match line.find(char::is_whitespace) {
+            Some(at) => (&line[..at], line[at..].trim()),
+            None => (line, ""),
+        };
+        let args: Vec<&str> = rest.split_whitespace().collect();
+
+        let mut out = String::new();
+        let result = match cmd.to_ascii_lowercase().as_str() {
+            "a" => self.assemble(rest, &mut out),
+            "e" => self.enter(&args),
+            "d" => self.dump(&args, &mut out),
+            "u" => self.unassemble(&args, &mut out),
+            "r" => self.regs(&args, &mut out),
+            "g" => self.go(&args, &mut out),
+            "t" => self.trace(&mut out),
+            "q" => return None,
+            "?" | "" => {
+                out.push_str(HELP);
+                Ok(())
+            }
+            _ => Err(format!("unknown command `{}`, try `?`", cmd)),
+        };
+
+        if let Err(err) = result {
+            out.push_str(&err);
+        }
+
+        Some(out.trim_end().to_string())
+    }
+
+    fn assemble(&mut self, rest: &str, out: &mut String) -> Result<(), String> {
+        let (address, instr) = match rest.find(char::is_whitespace) {
+            Some(at) => (parse_hex(&rest[..at])?, rest[at..].trim()),
+            None => return Err("usage: a <address> <instruction>".to_string()),
+        };
+
+        let bytes = asm::assemble(instr).map_err(|err| err.to_string())?;
+        self.program
+            .write(address, &bytes)
+            .map_err(|err| err.to_string())?;
+
+        // safe to unwrap because writing into a string can't fail
+        write!(out, "{:08x}:", address).unwrap();
+        for byte in bytes.iter() {
+            write!(out, " {:02x}", byte).unwrap();
+        }
+        self.unassemble_at = address;
+        Ok(())
+    }
+
+    fn enter(&mut self, args: &[&str]) -> Result<(), String> {
+        let (address, bytes) = match args {
+            [address, bytes @ ..] if !bytes.is_empty() => (parse_hex(address)?, bytes),
+            _ => return Err("usage: e <address> <byte>...".to_string()),
+        };
+
+        let mut parsed = vec![];
+        for byte in bytes {
+            parsed.push(
+                u8::from_str_radix(byte, 16)
+                    .map_err(|_| format!("`{}` is not a valid byte", byte))?,
+            );
+        }
+
+        self.program
+            .write(address, &parsed)
+            .map_err(|err| err.to_string())
+    }
+
+    fn dump(&mut self, args: &[&str], out: &mut String) -> Result<(), String> {
+        let (address, len) = match args {
+            [] => (self.dump_at, 0x80),
+            [address] => (parse_hex(address)?, 0x80),
+            [address, len] => (parse_hex(address)?, parse_hex(len)?),
+            _ => return Err("usage: d [address] [length]".to_string()),
+        };
+
+        let end = address.saturating_add(len);
+        out.push_str(&self.program.dump(address..end));
+        self.dump_at = end;
+        Ok(())
+    }
+
+    fn unassemble(&mut self, args: &[&str], out: &mut String) -> Result<(), String> {
+        let (mut address, count) = match args {
+            [] => (self.unassemble_at, 8),
+            [address] => (parse_hex(address)?, 8),
+            [address, count] => (parse_hex(address)?, parse_hex(count)?),
+            _ => return Err("usage: u [address] [count]".to_string()),
+        };
+
+        let memory = self.program.code();
+        for _ in 0..count {
+            if address as usize >= memory.len() {
+                break;
+            }
+
+            // bytes which aren't an instruction are shown one at a time, like DEBUG does
+            match isa::decode(memory, address) {
+                Ok(instr) => {
+                    writeln!(out, "{:08x}: {}", address, instr).unwrap();
+                    address += instr.size();
+                }
+                Err(_) => {
+                    writeln!(
+                        out,
+                        "{:08x}: .byte {:#04x}",
+                        address, memory[address as usize]
+                    )
+                    .unwrap();
+                    address += 1;
+                }
+            }
+        }
+
+        self.unassemble_at = address;
+        Ok(())
+    }
+
+    fn regs(&mut self, args: &[&str], out: &mut String) -> Result<(), String> {
+        match args {
+            [] => {}
+            [reg, value] if reg.eq_ignore_ascii_case("pc") => {
+                self.program.set_counter(parse_hex(value)?);
+            }
+            [reg, value] => {
+                let idx = reg
+                    .strip_prefix(['r', 'R'])
+                    .and_then(|x| x.parse::<u8>().ok())
+                    .filter(|&x| x < isa::REGISTERS)
+                    .ok_or_else(|| format!("`{}` is not a register", reg))?;
+
+                self.program.set_reg(idx, parse_hex(value)?);
+            }
+            _ => return Err("usage: r [register] [value]".to_string()),
+        }
+
+        self.status(out);
+        Ok(())
+    }
+
+    fn go(&mut self, args: &[&str], out: &mut String) -> Result<(), String> {
+        match args {
+            [] => {}
+            [address] => self.program.set_counter(parse_hex(address)?),
+            _ => return Err("usage: g [address]".to_string()),
+        }
+
+        for _ in 0..BUDGET {
+            if self.program.halted() {
+                break;
+            }
+
+            self.program.step().map_err(|err| err.to_string())?;
+        }
+
+        if !self.program.halted() {
+            writeln!(out, "still running after {} instructions", BUDGET).unwrap();
+        }
+
+        self.status(out);
+        Ok(())
+    }
+
+    fn trace(&mut self, out: &mut String) -> Result<(), String> {
+        self.program.step().map_err(|err| err.to_string())?;
+
+        self.status(out);
+        Ok(())
+    }
+
+    // the registers on one line and the next instruction below them
+    fn status(&self, out: &mut String) {
+        for (idx, reg) in self.program.regs().iter().enumerate() {
+            write!(out, "r{}={:08x}  ", idx, reg).unwrap();
+        }
+        writeln!(out, "pc={:08x}", self.program.counter()).unwrap();
+
+        let counter = self.program.counter();
+        if self.program.halted() {
+            out.push_str("halted\n");
+        } else if let Ok(instr) = isa::decode(self.program.code(), counter) {
+            writeln!(out, "{:08x}: {}", counter, instr).unwrap();
+        }
+    }
+}
+
+fn parse_hex(text: &str) -> Result<u32, String> {
+    let digits = text.strip_prefix("0x").unwrap_or(text);
+    u32::from_str_radix(digits, 16).map_err(|_| format!("`{}` is not a valid hex number", text))
+}
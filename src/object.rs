@@ -0,0 +1,316 @@
+use std::fmt;
+
+const MAGIC: [u8; 4] = *b"L32O";
+const VERSION: u8 = 3;
+
+// the section index undefined symbols have in the serialized form
+const UNDEFINED: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ObjectError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    BadName,
+    BadRelocKind(u8),
+}
+
+impl std::error::Error for ObjectError {}
+
+pub type Result<T> = std::result::Result<T, ObjectError>;
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjectError::BadMagic => write!(f, "this is not a lim32 object file"),
+            ObjectError::UnsupportedVersion(version) => {
+                write!(f, "object file version {} is not supported", version)
+            }
+            ObjectError::Truncated => write!(f, "the object file ends unexpectedly"),
+            ObjectError::BadName => write!(f, "a name in the object file is not valid utf-8"),
+            ObjectError::BadRelocKind(kind) => {
+                write!(f, "unknown relocation kind {:#04x}", kind)
+            }
+        }
+    }
+}
+
+/// A named chunk of bytes, the linker places all the sections with the same name next to each
+/// other (`.text` always goes first), starting each one at a multiple of its `align`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Section {
+    pub name: String,
+    pub align: u32,
+    pub data: Vec<u8>,
+}
+
+/// A name for an offset inside of a section, or a name the object needs from another object if
+/// `section` is `None`.
+///
+/// Only `global` symbols are visible to other objects.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub section: Option<usize>,
+    pub offset: u32,
+    pub global: bool,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RelocKind {
+    Abs8,
+    Abs16,
+    Abs32,
+}
+
+impl RelocKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            RelocKind::Abs8 => 1,
+            RelocKind::Abs16 => 2,
+            RelocKind::Abs32 => 4,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self> {
+        match byte {
+            1 => Ok(RelocKind::Abs8),
+            2 => Ok(RelocKind::Abs16),
+            4 => Ok(RelocKind::Abs32),
+            _ => Err(ObjectError::BadRelocKind(byte)),
+        }
+    }
+}
+
+/// A place in a section which has to be patched with the final address of a symbol (plus the
+/// addend) once the linker knows where everything goes.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Relocation {
+    pub section: usize,
+    pub offset: u32,
+    pub symbol: usize,
+    pub addend: u32,
+    pub kind: RelocKind,
+}
+
+/// Where the bytes assembled from a line of a source file start, one entry per statement.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Line {
+    pub section: usize,
+    pub offset: u32,
+    /// Index into the files of the object.
+    pub file: usize,
+    pub line: u32,
+}
+
+/// A relocatable object, as emitted by `asm::assemble_object` and consumed by `link::link`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Object {
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+    pub relocations: Vec<Relocation>,
+    /// The names of the source files the line table points into, the first one is the file
+    /// that was assembled and the rest are the ones it included.
+    pub files: Vec<String>,
+    /// The line table, empty unless the assembler was asked for debug info.
+    pub lines: Vec<Line>,
+}
+
+impl Object {
+    /// Create a new empty `Object`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serialize the object, all the integers are little endian.
+    ///
+    /// Version 1 objects, which end after the relocations and have no line table, and version 2
+    /// ones, which have a single source file, can still be read.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        out.extend_from_slice(&MAGIC);
+        out.push(VERSION);
+
+        out.extend_from_slice(&(self.sections.len() as u32).to_le_bytes());
+        for section in self.sections.iter() {
+            write_name(&mut out, &section.name);
+            out.extend_from_slice(&section.align.to_le_bytes());
+            out.extend_from_slice(&(section.data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&section.data);
+        }
+
+        out.extend_from_slice(&(self.symbols.len() as u32).to_le_bytes());
+        for symbol in self.symbols.iter() {
+            write_name(&mut out, &symbol.name);
+            let section = symbol.section.map_or(UNDEFINED, |x| x as u32);
+            out.extend_from_slice(&section.to_le_bytes());
+            out.extend_from_slice(&symbol.offset.to_le_bytes());
+            out.push(symbol.global as u8);
+        }
+
+        out.extend_from_slice(&(self.relocations.len() as u32).to_le_bytes());
+        for reloc in self.relocations.iter() {
+            out.extend_from_slice(&(reloc.section as u32).to_le_bytes());
+            out.extend_from_slice(&reloc.offset.to_le_bytes());
+            out.extend_from_slice(&(reloc.symbol as u32).to_le_bytes());
+            out.extend_from_slice(&reloc.addend.to_le_bytes());
+            out.push(reloc.kind.to_byte());
+        }
+
+        out.extend_from_slice(&(self.files.len() as u32).to_le_bytes());
+        for file in self.files.iter() {
+            write_name(&mut out, file);
+        }
+
+        out.extend_from_slice(&(self.lines.len() as u32).to_le_bytes());
+        for line in self.lines.iter() {
+            out.extend_from_slice(&(line.section as u32).to_le_bytes());
+            out.extend_from_slice(&line.offset.to_le_bytes());
+            out.extend_from_slice(&(line.file as u32).to_le_bytes());
+            out.extend_from_slice(&line.line.to_le_bytes());
+        }
+
+        out
+    }
+
+    /// Deserialize an object written by `to_bytes()`.
+    ///
+    /// It errors if the bytes aren't an object file of a supported version, or if they end
+    /// before the object does.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes, pos: 0 };
+
+        if reader.take(4)? != MAGIC {
+            return Err(ObjectError::BadMagic);
+        }
+
+        let version = reader.u8()?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(ObjectError::UnsupportedVersion(version));
+        }
+
+        let mut object = Object::new();
+
+        for _ in 0..reader.u32()? {
+            let name = reader.name()?;
+            let align = reader.u32()?;
+            let len = reader.u32()? as usize;
+            let data = reader.take(len)?.to_vec();
+
+            object.sections.push(Section { name, align, data });
+        }
+
+        for _ in 0..reader.u32()? {
+            let name = reader.name()?;
+            let section = match reader.u32()? {
+                UNDEFINED => None,
+                section => Some(section as usize),
+            };
+            let offset = reader.u32()?;
+            let global = reader.u8()? != 0;
+
+            object.symbols.push(Symbol {
+                name,
+                section,
+                offset,
+                global,
+            });
+        }
+
+        for _ in 0..reader.u32()? {
+            let section = reader.u32()? as usize;
+            let offset = reader.u32()?;
+            let symbol = reader.u32()? as usize;
+            let addend = reader.u32()?;
+            let kind = RelocKind::from_byte(reader.u8()?)?;
+
+            object.relocations.push(Relocation {
+                section,
+                offset,
+                symbol,
+                addend,
+                kind,
+            });
+        }
+
+        if version == 1 {
+            return Ok(object);
+        }
+
+        if version == 2 {
+            // a single source file, where an empty name means there's none
+            let source = reader.name()?;
+            if !source.is_empty() {
+                object.files.push(source);
+            }
+        } else {
+            for _ in 0..reader.u32()? {
+                object.files.push(reader.name()?);
+            }
+        }
+
+        for _ in 0..reader.u32()? {
+            let section = reader.u32()? as usize;
+            let offset = reader.u32()?;
+            let file = if version == 2 {
+                0
+            } else {
+                reader.u32()? as usize
+            };
+            let line = reader.u32()?;
+
+            object.lines.push(Line {
+                section,
+                offset,
+                file,
+                line,
+            });
+        }
+
+        Ok(object)
+    }
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    out.extend_from_slice(name.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).ok_or(ObjectError::Truncated)?;
+        let slice = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or(ObjectError::Truncated)?;
+        self.pos = end;
+
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn name(&mut self) -> Result<String> {
+        let len = self.u16()? as usize;
+        let bytes = self.take(len)?;
+
+        String::from_utf8(bytes.to_vec()).map_err(|_| ObjectError::BadName)
+    }
+}
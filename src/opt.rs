@@ -0,0 +1,215 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::image::{Image, SegmentKind};
+use crate::isa::{self, Instr, Operand};
+use crate::verify::{self, Result};
+
+// the start, end and instructions of a code segment
+type Code = (u32, u32, Vec<(u32, Instr)>);
+
+// the value of an immediate operand, `None` for registers
+fn immediate(operand: Operand) -> Option<u32> {
+    match operand {
+        Operand::Reg(_) => None,
+        Operand::Byte(byte) => Some(byte as u32),
+        Operand::Word(word) => Some(word as u32),
+        Operand::Dword(dword) => Some(dword),
+    }
+}
+
+// the instructions of a code segment which has already been verified
+fn decode_all(memory: &[u8], start: u32, end: u32) -> Vec<(u32, Instr)> {
+    let code = &memory[..end as usize];
+    let mut instrs = vec![];
+
+    let mut address = start;
+    while address < end {
+        // safe to unwrap because the verifier checked every instruction decodes
+        let instr = isa::decode(code, address).unwrap();
+        instrs.push((address, instr));
+        address += instr.size();
+    }
+
+    instrs
+}
+
+// rewrite the instructions of a single segment, leaving the jumps alone since their targets
+// can only be fixed up once every segment is done
+fn rewrite(instrs: Vec<(u32, Instr)>, targets: &BTreeSet<u32>) -> Vec<(u32, Instr)> {
+    let mut out: Vec<(u32, Instr)> = vec![];
+    // whether a jump went to the `NOP`s right before this instruction, which means it lands on
+    // this one now
+    let mut landed = false;
+
+    for (address, instr) in instrs {
+        let jumped_to = landed || targets.contains(&address);
+        landed = false;
+
+        let instr = match instr {
+            Instr::Bare(isa::NOP) => {
+                landed = jumped_to;
+                continue;
+            }
+            Instr::Modded(opcode, target, source) => match immediate(source) {
+                Some(value) => Instr::Modded(opcode, target, Operand::imm(value)),
+                None => instr,
+            },
+            _ => instr,
+        };
+
+        // `MOV r, a` followed by `ADD r, b` (or `SUB`) is the same as `MOV r, a + b`, unless
+        // something jumps right to the second one (or to the `NOP`s in front of it)
+        if let (
+            Some((_, Instr::Modded(isa::MOV, reg, first))),
+            Instr::Modded(opcode, target, second),
+        ) = (out.last_mut(), instr)
+        {
+            if let (Some(a), Some(b), true, false) = (
+                immediate(*first),
+                immediate(second),
+                *reg == target && (opcode == isa::ADD || opcode == isa::SUB),
+                jumped_to,
+            ) {
+                let value = if opcode == isa::ADD {
+                    a.wrapping_add(b)
+                } else {
+                    a.wrapping_sub(b)
+                };
+                *first = Operand::imm(value);
+
+                continue;
+            }
+        }
+
+        out.push((address, instr));
+    }
+
+    out
+}
+
+/// Run peephole optimizations over bytecode loaded at address zero, returning the new
+/// bytecode.
+///
+/// `NOP`s are removed, immediates are shrunk to the smallest mode they fit in and a `MOV` of an
+/// immediate followed by an `ADD` or `SUB` of another one into the same register is folded into
+/// a single `MOV`. Jumps are fixed up to point at the same instructions as before, a jump to a
+/// removed instruction goes to the one after it.
+///
+/// It errors if the code doesn't pass `verify::verify()`, as there'd be no telling what the
+/// rewritten code would do otherwise.
+pub fn optimize(code: &[u8]) -> Result<Vec<u8>> {
+    verify::verify(code)?;
+
+    let instrs = decode_all(code, 0, code.len() as u32);
+    let mut segments = optimize_segments(vec![(0, code.len() as u32, instrs)]).0;
+
+    Ok(segments.pop().unwrap_or_default())
+}
+
+/// Optimize all the code segments of an image like `optimize()`, every segment keeps its load
+/// address and the entry point, symbols and line table are moved along with the code.
+///
+/// It errors if the image doesn't pass `verify::verify_image()`.
+pub fn optimize_image(image: &Image) -> Result<Image> {
+    verify::verify_image(image)?;
+
+    let memory = image.flatten();
+    let code: Vec<Code> = image
+        .segments
+        .iter()
+        .filter(|x| x.kind == SegmentKind::Code)
+        .map(|x| {
+            let end = x.address + x.data.len() as u32;
+            (x.address, end, decode_all(&memory, x.address, end))
+        })
+        .collect();
+
+    // addresses outside of the code (data) map to themselves
+    let ranges: Vec<(u32, u32)> = code.iter().map(|x| (x.0, x.1)).collect();
+    let (mut optimized, moved) = optimize_segments(code);
+    let relocate = |address: u32| {
+        if ranges
+            .iter()
+            .any(|&(start, end)| start <= address && address < end)
+        {
+            moved(address)
+        } else {
+            address
+        }
+    };
+
+    let mut out = image.clone();
+    optimized.reverse();
+    for segment in out.segments.iter_mut() {
+        if segment.kind == SegmentKind::Code {
+            // safe to unwrap because there's one optimized segment for every code segment
+            segment.data = optimized.pop().unwrap();
+        }
+    }
+
+    out.entry = relocate(image.entry);
+    for symbol in out.symbols.iter_mut() {
+        symbol.address = relocate(symbol.address);
+    }
+    for line in out.lines.iter_mut() {
+        line.address = relocate(line.address);
+    }
+    // removed instructions can leave several lines on the same address, keep the last one
+    out.lines.reverse();
+    out.lines.dedup_by_key(|x| x.address);
+    out.lines.reverse();
+
+    Ok(out)
+}
+
+// optimize every code segment, returns the new bytes of each along with
+// a function which maps an old address inside of any of them to its new one
+fn optimize_segments(segments: Vec<Code>) -> (Vec<Vec<u8>>, impl Fn(u32) -> u32) {
+    let targets: BTreeSet<u32> = segments
+        .iter()
+        .flat_map(|x| x.2.iter())
+        .filter_map(|x| match x.1 {
+            Instr::Jump(_, target) => Some(target),
+            _ => None,
+        })
+        .collect();
+
+    // where every kept instruction (and the end of every segment) moved to, removed
+    // instructions are covered by the next kept one
+    let mut moved = BTreeMap::new();
+    let mut rewritten = vec![];
+    for (start, end, instrs) in segments {
+        let instrs = rewrite(instrs, &targets);
+
+        let mut address = start;
+        for (old, instr) in instrs.iter() {
+            moved.insert(*old, address);
+            address += instr.size();
+        }
+        moved.insert(end, address);
+
+        rewritten.push(instrs);
+    }
+
+    let map = move |address: u32| match moved.range(address..).next() {
+        Some((_, &new)) => new,
+        None => address,
+    };
+
+    let mut out = vec![];
+    for instrs in rewritten {
+        let mut bytes = vec![];
+        for (_, instr) in instrs {
+            let instr = match instr {
+                Instr::Jump(opcode, target) => Instr::Jump(opcode, map(target)),
+                _ => instr,
+            };
+
+            instr.encode(&mut bytes);
+        }
+
+        out.push(bytes);
+    }
+
+    (out, map)
+}
@@ -0,0 +1,324 @@
+use std::fmt::{self, Write as _};
+use std::io::{self, Write};
+use std::ops::Range;
+
+use crate::isa::{self, Instr, Operand};
+use crate::vm::{Program, VmError};
+
+#[derive(Debug)]
+pub enum TraceError {
+    Vm(VmError),
+    Io(io::Error),
+}
+
+impl std::error::Error for TraceError {}
+
+pub type Result<T> = std::result::Result<T, TraceError>;
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceError::Vm(err) => write!(f, "{}", err),
+            TraceError::Io(err) => write!(f, "couldn't write the trace: {}", err),
+        }
+    }
+}
+
+/// Everything about a single executed instruction.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Record {
+    pub counter: u32,
+    /// The counter named after the symbol it's in, see `Program::symbolize()`.
+    pub symbol: Option<String>,
+    pub instr: Instr,
+    /// The counter after the instruction, which is only interesting for jumps.
+    pub next: u32,
+    pub before: [u32; 4],
+    pub after: [u32; 4],
+}
+
+impl Record {
+    /// Execute the next instruction of `program` and describe what it did, `None` if it had
+    /// already halted or ran off the end of the code.
+    ///
+    /// It errors if the instruction is invalid, see `Program::step()`.
+    pub fn step(program: &mut Program) -> std::result::Result<Option<Self>, VmError> {
+        if program.halted() {
+            return Ok(None);
+        }
+
+        let counter = program.counter();
+        let before = *program.regs();
+        let instr = isa::decode(program.code(), counter);
+        program.step()?;
+
+        // the only decode error `step()` doesn't raise is running off the end of the code
+        let instr = match instr {
+            Ok(instr) => instr,
+            Err(_) => return Ok(None),
+        };
+
+        Ok(Some(Self {
+            counter,
+            symbol: program.symbolize(counter),
+            instr,
+            next: program.counter(),
+            before,
+            after: *program.regs(),
+        }))
+    }
+
+    /// The record as a single line JSON object, without the trailing newline.
+    pub fn to_json(&self) -> String {
+        let mut operands = vec![];
+        match self.instr {
+            Instr::Jump(_, address) => operands.push(("address", address)),
+            Instr::Modded(_, target, source) => {
+                operands.push(("reg", target as u32));
+                operands.push(match source {
+                    Operand::Reg(reg) => ("reg", reg as u32),
+                    Operand::Byte(byte) => ("byte", byte as u32),
+                    Operand::Word(word) => ("word", word as u32),
+                    Operand::Dword(dword) => ("dword", dword),
+                });
+            }
+            Instr::Not(reg) => operands.push(("reg", reg as u32)),
+            Instr::Bare(_) => {}
+        }
+
+        let operands: Vec<String> = operands
+            .iter()
+            .map(|(kind, value)| format!("{{\"kind\":\"{}\",\"value\":{}}}", kind, value))
+            .collect();
+
+        let regs: Vec<String> = (0..self.before.len())
+            .filter(|&idx| self.before[idx] != self.after[idx])
+            .map(|idx| {
+                format!(
+                    "{{\"reg\":{},\"old\":{},\"new\":{}}}",
+                    idx, self.before[idx], self.after[idx]
+                )
+            })
+            .collect();
+
+        let mut out = String::new();
+        // safe to unwrap because writing into a string can't fail, mnemonics never need
+        // escaping and memory is always empty since `LDP` and `STP` don't access any yet
+        write!(out, "{{\"pc\":{},", self.counter).unwrap();
+        if let Some(symbol) = &self.symbol {
+            write!(out, "\"symbol\":{},", json_string(symbol)).unwrap();
+        }
+        write!(
+            out,
+            "\"opcode\":\"{}\",\"operands\":[{}],\"next\":{},\"regs\":[{}],\"memory\":[]}}",
+            isa::mnemonic(self.instr.opcode()).unwrap_or("?"),
+            operands.join(","),
+            self.next,
+            regs.join(","),
+        )
+        .unwrap();
+
+        out
+    }
+}
+
+/// Run `program` until it halts, writing a newline delimited JSON `Record` for every executed
+/// instruction to `out`.
+///
+/// The records look like `{"pc":4,"opcode":"ADD","operands":[{"kind":"reg","value":1},
+/// {"kind":"byte","value":1}],"next":8,"regs":[{"reg":1,"old":0,"new":1}],"memory":[]}`, `regs`
+/// only lists the registers which changed and a `"symbol"` naming the counter follows `pc` if
+/// the program has a symbol table.
+///
+/// It errors on the first invalid instruction or if writing to `out` failed.
+pub fn trace_json<W: Write>(program: &mut Program, mut out: W) -> Result<()> {
+    while let Some(record) = Record::step(program).map_err(TraceError::Vm)? {
+        writeln!(out, "{}", record.to_json()).map_err(TraceError::Io)?;
+    }
+
+    out.flush().map_err(TraceError::Io)
+}
+
+/// Which instructions `trace_filtered()` logs, the default logs all of them.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Filter {
+    /// Only log instructions changing one of these registers, and only show those.
+    pub regs: Option<Vec<u8>>,
+    /// Only log instructions accessing memory, which are `LDP` and `STP`.
+    pub memory: bool,
+    /// Only log instructions at these addresses.
+    pub range: Option<Range<u32>>,
+}
+
+impl Filter {
+    /// Whether `record` should be logged, asking for registers and memory logs instructions
+    /// doing either.
+    pub fn matches(&self, record: &Record) -> bool {
+        if let Some(range) = &self.range {
+            if !range.contains(&record.counter) {
+                return false;
+            }
+        }
+
+        let memory = matches!(record.instr, Instr::Bare(isa::LDP | isa::STP));
+        match &self.regs {
+            Some(regs) => regs.iter().any(|x| self.changed(record, *x)) || (self.memory && memory),
+            None => !self.memory || memory,
+        }
+    }
+
+    fn changed(&self, record: &Record, reg: u8) -> bool {
+        let reg = reg as usize;
+        reg < record.before.len() && record.before[reg] != record.after[reg]
+    }
+
+    /// A line describing `record`, with the registers which changed (or the ones asked for
+    /// which did) as `r1: 0x0 -> 0x1`.
+    pub fn format(&self, record: &Record) -> String {
+        let mut out = format!("{:08x}", record.counter);
+        if let Some(symbol) = &record.symbol {
+            // safe to unwrap because writing into a string can't fail
+            write!(out, " <{}>", symbol).unwrap();
+        }
+        write!(out, " {}", record.instr).unwrap();
+
+        for reg in 0..record.before.len() as u8 {
+            let wanted = self.regs.as_ref().is_none_or(|x| x.contains(&reg));
+            if wanted && self.changed(record, reg) {
+                write!(
+                    out,
+                    "  r{}: {:#x} -> {:#x}",
+                    reg, record.before[reg as usize], record.after[reg as usize]
+                )
+                .unwrap();
+            }
+        }
+
+        out
+    }
+}
+
+/// Run `program` until it halts, writing a line for every executed instruction `filter` lets
+/// through to `out`, see `Filter::format()`.
+///
+/// It errors on the first invalid instruction or if writing to `out` failed.
+pub fn trace_filtered<W: Write>(program: &mut Program, filter: &Filter, mut out: W) -> Result<()> {
+    while let Some(record) = Record::step(program).map_err(TraceError::Vm)? {
+        if filter.matches(&record) {
+            writeln!(out, "{}", filter.format(&record)).map_err(TraceError::Io)?;
+        }
+    }
+
+    out.flush().map_err(TraceError::Io)
+}
+
+// a JSON string literal, quotes included
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            // safe to unwrap because writing into a string can't fail
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+
+    out
+}
+
+/// Run several programs until all of them halt, switching between them round robin every
+/// `quantum` instructions, and write what happened to `out` in the Chrome trace event format
+/// (the JSON object one), which `about://tracing` or Perfetto can show on a timeline.
+///
+/// Every program is a process of its own named after its `name`, with its time slices as
+/// `scheduler` events on one thread and its instructions as `instruction` events on another,
+/// `INT`s get an extra `interrupt` instant event. There's no clock in the vm so the timestamps
+/// count instructions, one microsecond each.
+///
+/// It errors as soon as any program reaches an invalid instruction, or if writing to `out`
+/// failed. The trace is still closed when a program fails so what ran before can be viewed.
+pub fn trace_chrome<W: Write>(
+    programs: &mut [(String, Program)],
+    quantum: u32,
+    mut out: W,
+) -> Result<()> {
+    let mut events = vec![];
+    for (idx, (name, _)) in programs.iter().enumerate() {
+        let pid = idx + 1;
+        events.push(format!(
+            "{{\"ph\":\"M\",\"name\":\"process_name\",\"pid\":{},\"args\":{{\"name\":{}}}}}",
+            pid,
+            json_string(name)
+        ));
+        for (tid, thread) in [(0, "scheduler"), (1, "instructions")] {
+            events.push(format!(
+                "{{\"ph\":\"M\",\"name\":\"thread_name\",\"pid\":{},\"tid\":{},\"args\":{{\"name\":\"{}\"}}}}",
+                pid, tid, thread
+            ));
+        }
+    }
+
+    let mut time = 0u64;
+    let mut error = None;
+    'run: while programs.iter().any(|x| !x.1.halted()) {
+        for (idx, (_, program)) in programs.iter_mut().enumerate() {
+            let pid = idx + 1;
+            let start = time;
+
+            for _ in 0..quantum.max(1) {
+                let record = match Record::step(program) {
+                    Ok(Some(record)) => record,
+                    Ok(None) => break,
+                    Err(err) => {
+                        error = Some(err);
+                        break;
+                    }
+                };
+
+                let name = isa::mnemonic(record.instr.opcode()).unwrap_or("?");
+                events.push(format!(
+                    "{{\"ph\":\"X\",\"cat\":\"instruction\",\"name\":\"{}\",\"pid\":{},\"tid\":1,\"ts\":{},\"dur\":1,\"args\":{{\"pc\":{},\"symbol\":{},\"instr\":{}}}}}",
+                    name,
+                    pid,
+                    time,
+                    record.counter,
+                    record.symbol.as_deref().map_or("null".to_string(), json_string),
+                    json_string(&record.instr.to_string())
+                ));
+
+                if let Instr::Bare(isa::INT) = record.instr {
+                    events.push(format!(
+                        "{{\"ph\":\"i\",\"cat\":\"interrupt\",\"name\":\"INT\",\"pid\":{},\"tid\":1,\"ts\":{},\"s\":\"p\"}}",
+                        pid, time
+                    ));
+                }
+
+                time += 1;
+            }
+
+            if time > start {
+                events.push(format!(
+                    "{{\"ph\":\"X\",\"cat\":\"scheduler\",\"name\":\"running\",\"pid\":{},\"tid\":0,\"ts\":{},\"dur\":{}}}",
+                    pid,
+                    start,
+                    time - start
+                ));
+            }
+
+            if error.is_some() {
+                break 'run;
+            }
+        }
+    }
+
+    writeln!(out, "{{\"traceEvents\":[\n{}\n]}}", events.join(",\n")).map_err(TraceError::Io)?;
+    out.flush().map_err(TraceError::Io)?;
+
+    match error {
+        Some(err) => Err(TraceError::Vm(err)),
+        None => Ok(()),
+    }
+}
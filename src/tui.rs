@@ -0,0 +1,186 @@
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Write};
+
+use crate::debugger::Debugger;
+use crate::isa::{self, Instr};
+
+// how many instructions are shown before and after the counter
+const CONTEXT: usize = 6;
+// rows of 16 bytes in the memory pane
+const MEMORY_ROWS: usize = 8;
+const LEFT_WIDTH: usize = 44;
+
+/// A full screen frontend for the `Debugger`, redrawn after every command using plain ANSI
+/// escapes so it doesn't need a terminal library.
+///
+/// It shows the disassembly around the counter, the registers along with the result of the last
+/// `CMP` (which the jumps read from `r0`, as there's no flags register) and a memory pane. There
+/// is no stack in the instruction set yet so there's no pane for it either.
+#[derive(Debug)]
+pub struct Tui {
+    debugger: Debugger,
+    memory: u32,
+    message: String,
+}
+
+impl Tui {
+    pub fn new(debugger: Debugger) -> Self {
+        Self {
+            debugger,
+            memory: 0,
+            message: String::from(
+                "type `help` for the commands, `m <address>` moves the memory pane",
+            ),
+        }
+    }
+
+    // (address, text) of every instruction from the start of memory up to a few past the
+    // counter, bytes which don't decode are shown one at a time
+    fn disassembly(&self) -> Vec<(u32, String)> {
+        let program = self.debugger.program();
+        let code = program.code();
+        let counter = program.counter();
+
+        let mut lines = vec![];
+        let mut after = 0;
+        let mut address = 0u32;
+        while (address as usize) < code.len() && after <= CONTEXT {
+            let (text, size) = match isa::decode(code, address) {
+                Ok(Instr::Jump(opcode, target)) => {
+                    let text = match program.symbolize(target) {
+                        Some(symbol) => format!("{} <{}>", Instr::Jump(opcode, target), symbol),
+                        None => Instr::Jump(opcode, target).to_string(),
+                    };
+
+                    (text, 5)
+                }
+                Ok(instr) => (instr.to_string(), instr.size()),
+                Err(_) => (format!(".byte {:#04x}", code[address as usize]), 1),
+            };
+
+            if address > counter {
+                after += 1;
+            }
+
+            lines.push((address, text));
+            address += size;
+        }
+
+        let at = lines
+            .iter()
+            .position(|x| x.0 >= counter)
+            .unwrap_or(lines.len());
+        lines.drain(..at.saturating_sub(CONTEXT));
+
+        lines
+    }
+
+    /// Draw the whole screen, including the message of the last command.
+    pub fn render(&self) -> String {
+        let program = self.debugger.program();
+
+        let mut left = vec![String::from("disassembly")];
+        for (address, text) in self.disassembly() {
+            for symbol in program.symbols().iter().filter(|x| x.address == address) {
+                left.push(format!("   {}:", symbol.name));
+            }
+
+            let marker = if address == program.counter() {
+                ">"
+            } else {
+                " "
+            };
+            let breakpoint = if program.breakpoints().any(|x| x == address) {
+                "*"
+            } else {
+                " "
+            };
+
+            left.push(format!(
+                "{}{} {:08x}: {}",
+                marker, breakpoint, address, text
+            ));
+        }
+
+        let mut right = vec![String::from("registers")];
+        right.push(format!("pc: {:08x}", program.counter()));
+        if let Some(line) = program.line(program.counter()) {
+            right.push(format!("at: {}:{}", line.file, line.line));
+        }
+        for (idx, reg) in program.regs().iter().enumerate() {
+            right.push(format!("r{}: {:#010x} ({})", idx, reg, reg));
+        }
+
+        let cmp = match program.regs()[0] {
+            0 => "equal",
+            1 => "less",
+            2 => "greater",
+            _ => "-",
+        };
+        right.push(String::new());
+        right.push(String::from("flags"));
+        right.push(format!("cmp: {}", cmp));
+        right.push(format!("halted: {}", program.halted()));
+
+        let mut screen = String::from("\x1b[2J\x1b[H");
+        for row in 0..left.len().max(right.len()) {
+            let l = left.get(row).map_or("", |x| x.as_str());
+            let r = right.get(row).map_or("", |x| x.as_str());
+
+            // safe to unwrap because writing into a string can't fail
+            writeln!(screen, "{:width$} {}", l, r, width = LEFT_WIDTH).unwrap();
+        }
+
+        writeln!(screen, "\nmemory").unwrap();
+        let end = self.memory.saturating_add(MEMORY_ROWS as u32 * 16);
+        screen.push_str(&program.dump(self.memory..end));
+
+        writeln!(screen, "\n{}", self.message).unwrap();
+        screen
+    }
+
+    /// Run a command, `None` if it asked to quit.
+    pub fn command(&mut self, line: &str) -> Option<()> {
+        let mut words = line.split_whitespace();
+
+        if let (Some("m"), Some(address)) = (words.next(), words.next()) {
+            let parsed = match address.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => address.parse::<u32>(),
+            };
+
+            self.message = match parsed {
+                Ok(address) => {
+                    self.memory = address;
+                    String::new()
+                }
+                Err(_) => format!("`{}` is not a valid number", address),
+            };
+
+            return Some(());
+        }
+
+        self.message = self.debugger.command(line)?;
+        Some(())
+    }
+
+    /// Keep drawing the screen and reading commands from stdin until the user quits.
+    pub fn run(&mut self) -> io::Result<()> {
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+
+        loop {
+            print!("{}(lim32) ", self.render());
+            io::stdout().flush()?;
+
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+
+            if self.command(&line).is_none() {
+                return Ok(());
+            }
+        }
+    }
+}
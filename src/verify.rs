@@ -0,0 +1,161 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::image::{Image, SegmentKind};
+use crate::isa::{self, DecodeError, Instr};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum VerifyError {
+    Decode(DecodeError),
+    /// The jump at the first address goes somewhere outside of the code.
+    JumpOutOfBounds(u32, u32),
+    /// The jump at the first address lands in the middle of an instruction.
+    JumpIntoInstruction(u32, u32),
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Every problem found, sorted by address.
+pub type Result<T> = std::result::Result<T, Vec<VerifyError>>;
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifyError::Decode(err) => write!(f, "{}", err),
+            VerifyError::JumpOutOfBounds(address, target) => write!(
+                f,
+                "{:#010x}: jump target {:#010x} is outside of the code",
+                address, target
+            ),
+            VerifyError::JumpIntoInstruction(address, target) => write!(
+                f,
+                "{:#010x}: jump target {:#010x} is in the middle of an instruction",
+                address, target
+            ),
+        }
+    }
+}
+
+// the instructions of the code between `start` and `end` in `memory`, a byte that doesn't
+// start a valid instruction is reported and skipped so the walk can carry on after it
+fn walk(
+    memory: &[u8],
+    start: u32,
+    end: u32,
+    starts: &mut BTreeSet<u32>,
+    jumps: &mut Vec<(u32, u32)>,
+    errors: &mut Vec<VerifyError>,
+) {
+    let code = &memory[..end as usize];
+    let mut address = start;
+
+    while address < end {
+        match isa::decode(code, address) {
+            Ok(instr) => {
+                if let Instr::Jump(_, target) = instr {
+                    jumps.push((address, target));
+                }
+
+                starts.insert(address);
+                address += instr.size();
+            }
+            Err(DecodeError::Truncated(at)) => {
+                // nothing after it could decode either
+                errors.push(VerifyError::Decode(DecodeError::Truncated(at)));
+                break;
+            }
+            Err(err) => {
+                errors.push(VerifyError::Decode(err));
+                address += 1;
+            }
+        }
+    }
+}
+
+fn check_jumps(
+    starts: &BTreeSet<u32>,
+    ranges: &[(u32, u32)],
+    jumps: Vec<(u32, u32)>,
+    errors: &mut Vec<VerifyError>,
+) {
+    for (address, target) in jumps {
+        if !ranges
+            .iter()
+            .any(|&(start, end)| start <= target && target < end)
+        {
+            errors.push(VerifyError::JumpOutOfBounds(address, target));
+        } else if !starts.contains(&target) {
+            errors.push(VerifyError::JumpIntoInstruction(address, target));
+        }
+    }
+}
+
+fn finish(mut errors: Vec<VerifyError>) -> Result<()> {
+    if errors.is_empty() {
+        return Ok(());
+    }
+
+    let address = |err: &VerifyError| match err {
+        VerifyError::Decode(DecodeError::UnknownOpcode(address, _))
+        | VerifyError::Decode(DecodeError::UnknownMode(address, _))
+        | VerifyError::Decode(DecodeError::BadRegister(address, _))
+        | VerifyError::Decode(DecodeError::Truncated(address))
+        | VerifyError::JumpOutOfBounds(address, _)
+        | VerifyError::JumpIntoInstruction(address, _) => *address,
+    };
+    errors.sort_by_key(address);
+
+    Err(errors)
+}
+
+/// Check bytecode loaded at address zero without running it, so a loader can reject an untrusted
+/// program up front instead of finding out halfway through executing it.
+///
+/// The code is walked from start to end, every instruction has to decode (known opcodes and
+/// modes, all of its operands present and register ids in range) and every jump has to land on
+/// the start of an instruction. Invalid bytes are skipped one at a time so everything after them
+/// still gets checked.
+///
+/// It errors with every problem it found, sorted by address.
+pub fn verify(code: &[u8]) -> Result<()> {
+    let mut starts = BTreeSet::new();
+    let mut jumps = vec![];
+    let mut errors = vec![];
+
+    walk(
+        code,
+        0,
+        code.len() as u32,
+        &mut starts,
+        &mut jumps,
+        &mut errors,
+    );
+    check_jumps(&starts, &[(0, code.len() as u32)], jumps, &mut errors);
+
+    finish(errors)
+}
+
+/// Check all the code segments of an image like `verify()`, jumps are allowed to go from one
+/// code segment into another but never into a data segment.
+///
+/// It errors with every problem it found, sorted by address.
+pub fn verify_image(image: &Image) -> Result<()> {
+    let memory = image.flatten();
+    let ranges: Vec<(u32, u32)> = image
+        .segments
+        .iter()
+        .filter(|x| x.kind == SegmentKind::Code)
+        .map(|x| (x.address, x.address + x.data.len() as u32))
+        .collect();
+
+    let mut starts = BTreeSet::new();
+    let mut jumps = vec![];
+    let mut errors = vec![];
+
+    for &(start, end) in ranges.iter() {
+        walk(&memory, start, end, &mut starts, &mut jumps, &mut errors);
+    }
+    check_jumps(&starts, &ranges, jumps, &mut errors);
+
+    finish(errors)
+}
@@ -0,0 +1,431 @@
+use alloc::collections::BTreeSet;
+use alloc::{format, string::String, vec, vec::Vec};
+use core::cmp::Ordering;
+use core::fmt;
+use core::ops::Range;
+
+use crate::hexdump;
+use crate::image::{self, Image, Line, Symbol};
+use crate::isa::{self, DecodeError, Instr, Operand};
+use crate::lilac::{AllocError, Allocator, Process};
+
+// how far `Program::write()` grows the memory unless it's told otherwise, see
+// `Program::set_memory_limit()`
+const MEMORY_LIMIT: u32 = 1 << 24;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum VmError {
+    Decode(DecodeError),
+    /// A write ending at `end` would have grown the memory past its limit of `limit` bytes, see
+    /// `Program::set_memory_limit()`.
+    OutOfMemory {
+        end: u64,
+        limit: u32,
+    },
+    /// The code at the counter couldn't be fetched from the heap, see `Program::step_heap()`.
+    Fetch(AllocError),
+}
+
+impl core::error::Error for VmError {}
+
+pub type Result<T> = core::result::Result<T, VmError>;
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmError::Decode(err) => write!(f, "{}", err),
+            VmError::OutOfMemory { end, limit } => write!(
+                f,
+                "writing up to {:#x} would grow the memory past its limit of {:#x} bytes",
+                end, limit
+            ),
+            VmError::Fetch(err) => write!(f, "couldn't fetch the next instruction: {}", err),
+        }
+    }
+}
+
+/// Why `resume()` gave control back.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Stop {
+    Halted,
+    Breakpoint(u32),
+}
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    regs: [u32; 4],
+    code: Vec<u8>,
+    memory_limit: u32,
+    counter: u32,
+    halted: bool,
+    trace: bool,
+    breakpoints: BTreeSet<u32>,
+    lines: Vec<Line>,
+    symbols: Vec<Symbol>,
+}
+
+impl Program {
+    /// Create a new `Program` which starts executing `code` from address zero.
+    pub fn new(code: Vec<u8>) -> Self {
+        Program {
+            regs: [0u32; 4],
+            code,
+            memory_limit: MEMORY_LIMIT,
+            counter: 0,
+            halted: false,
+            trace: false,
+            breakpoints: BTreeSet::new(),
+            lines: vec![],
+            symbols: vec![],
+        }
+    }
+
+    /// Create a new `Program` with all the segments of `image` loaded into its memory, which
+    /// starts executing from the entry point of the image, keeping its line and symbol tables
+    /// around.
+    pub fn from_image(image: &Image) -> Self {
+        let mut program = Self::new(image.flatten());
+        program.counter = image.entry;
+        program.lines = image.lines.clone();
+        program.symbols = image.symbols.clone();
+
+        program
+    }
+
+    pub fn regs(&self) -> &[u32; 4] {
+        &self.regs
+    }
+
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    /// Address of the next instruction to be executed.
+    pub fn counter(&self) -> u32 {
+        self.counter
+    }
+
+    /// The source line `address` was assembled from, if the image had a line table.
+    pub fn line(&self, address: u32) -> Option<&Line> {
+        image::line_at(&self.lines, address)
+    }
+
+    /// Move the counter, which also lets a halted program run again.
+    pub fn set_counter(&mut self, counter: u32) {
+        self.counter = counter;
+        self.halted = false;
+    }
+
+    /// Set the register `reg`, returns `false` if there's no such register.
+    pub fn set_reg(&mut self, reg: u8, value: u32) -> bool {
+        match self.regs.get_mut(reg as usize) {
+            Some(target) => {
+                *target = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// How far `write()` may grow the memory, 16 MiB unless it was changed.
+    pub fn memory_limit(&self) -> u32 {
+        self.memory_limit
+    }
+
+    /// Change how far `write()` may grow the memory, memory which is already there stays even
+    /// if it's past the new limit.
+    pub fn set_memory_limit(&mut self, memory_limit: u32) {
+        self.memory_limit = memory_limit;
+    }
+
+    /// Overwrite memory starting at `address`, growing it with zeros if the bytes don't fit.
+    ///
+    /// It errors if the memory would have to grow past its limit (`VmError::OutOfMemory`, see
+    /// `set_memory_limit()`), in which case nothing is written.
+    pub fn write(&mut self, address: u32, bytes: &[u8]) -> Result<()> {
+        // as a u64 since the bytes may run past the end of the address space
+        let end = address as u64 + bytes.len() as u64;
+        if end > self.code.len() as u64 {
+            if end > self.memory_limit as u64 {
+                return Err(VmError::OutOfMemory {
+                    end,
+                    limit: self.memory_limit,
+                });
+            }
+            self.code.resize(end as usize, 0);
+        }
+
+        self.code[address as usize..end as usize].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// The symbol table of the image, sorted by address.
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// Name `address` after the closest symbol at or before it, as `name` or `name+0x4`, if the
+    /// image had a symbol table.
+    pub fn symbolize(&self, address: u32) -> Option<String> {
+        match image::symbol_at(&self.symbols, address)? {
+            (symbol, 0) => Some(symbol.name.clone()),
+            (symbol, offset) => Some(format!("{}+{:#x}", symbol.name, offset)),
+        }
+    }
+
+    /// The address of a symbol, or of `name+offset` (a number like the ones the assembler
+    /// takes) past one.
+    pub fn lookup(&self, text: &str) -> Option<u32> {
+        let (name, offset) = match text.split_once('+') {
+            Some((name, offset)) => (name.trim(), parse_offset(offset.trim())?),
+            None => (text.trim(), 0),
+        };
+
+        self.symbols
+            .iter()
+            .find(|x| x.name == name)
+            .map(|x| x.address.wrapping_add(offset))
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Print the counter and all the registers after every executed instruction, without std
+    /// there is nowhere to print them to so it does nothing.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Stop `resume()` before the instruction at `address` gets executed, returns `false` if
+    /// there already was a breakpoint there.
+    pub fn add_breakpoint(&mut self, address: u32) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    /// Returns `false` if there was no breakpoint at `address`.
+    pub fn remove_breakpoint(&mut self, address: u32) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    /// All the breakpoints, sorted by address.
+    pub fn breakpoints(&self) -> impl Iterator<Item = u32> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Hex dump the memory in `range`, anything past the end of the memory is left out.
+    pub fn dump(&self, range: Range<u32>) -> String {
+        let end = (range.end as usize).min(self.code.len());
+        let start = (range.start as usize).min(end);
+
+        hexdump::hexdump(&self.code[start..end], start as u32)
+    }
+
+    #[cfg(feature = "std")]
+    fn dump_regs(&self) {
+        match self.symbolize(self.counter) {
+            Some(symbol) => println!(
+                "Advancing to next instruction\n\tcounter: {} ({})",
+                self.counter, symbol
+            ),
+            None => println!("Advancing to next instruction\n\tcounter: {}", self.counter),
+        }
+        if let Some(line) = self.line(self.counter) {
+            println!("\tsource:  {}:{}", line.file, line.line);
+        }
+
+        for (idx, i) in self.regs.into_iter().enumerate() {
+            println!("\treg{idx}:    {:#010} ({:#034b}) ({:#010x})", i, i, i);
+        }
+    }
+
+    fn source(&self, operand: Operand) -> u32 {
+        match operand {
+            Operand::Reg(reg) => self.regs[reg as usize],
+            Operand::Byte(byte) => byte as u32,
+            Operand::Word(word) => word as u32,
+            Operand::Dword(dword) => dword,
+        }
+    }
+
+    fn modded_instr(&mut self, which: u8, target: u8, source: u32) {
+        if which == isa::CMP {
+            // for jmz and jlz we will be using 2 and 1 respectively
+            self.regs[0] = match self.regs[target as usize].cmp(&source) {
+                Ordering::Less => 1,
+                Ordering::Equal => 0,
+                Ordering::Greater => 2,
+            };
+
+            return;
+        }
+
+        let target = &mut self.regs[target as usize];
+
+        match which {
+            isa::AND => *target &= source,
+            isa::NAND => *target = !(*target & source),
+            isa::OR => *target |= source,
+            isa::NOR => *target = !(*target | source),
+            isa::XOR => *target ^= source,
+            isa::XNOR => *target = !(*target ^ source),
+            isa::MOV => *target = source,
+            isa::ADD => *target = target.wrapping_add(source),
+            isa::SUB => *target = target.wrapping_sub(source),
+            _ => unreachable!("only modded opcodes are decoded as `Instr::Modded`"),
+        }
+    }
+
+    /// Execute the instruction at the counter, halting the program if it ran off the end of the
+    /// code (even in the middle of an instruction) or reached a `HLT`.
+    ///
+    /// It errors if the bytes at the counter aren't a valid instruction, the program is left as
+    /// it was before the call.
+    pub fn step(&mut self) -> Result<()> {
+        if self.halted {
+            return Ok(());
+        }
+
+        let decoded = isa::decode(&self.code, self.counter);
+        self.run(decoded)
+    }
+
+    /// Like `step()` but the instruction at the counter is fetched out of the block of
+    /// `process_id` on the heap of `allocator` the counter is in, instead of out of the memory of
+    /// the program. The block has to be executable, see `Allocator::protect()`, and running off
+    /// the end of it halts the program like running off the end of the code does.
+    ///
+    /// It errors like `step()` does, or if the process doesn't hold the block the counter is in
+    /// or may not execute it (`VmError::Fetch`, with the error of `Allocator::fetch()` inside).
+    pub fn step_heap(&mut self, allocator: &Allocator, process_id: Process) -> Result<()> {
+        if self.halted {
+            return Ok(());
+        }
+
+        let handle = allocator
+            .handle_at(process_id, self.counter)
+            .map_err(VmError::Fetch)?;
+        // safe to unwrap because `handle_at()` only returns handles of blocks which exist
+        let end = allocator.range(handle).unwrap().end;
+        let code = allocator
+            .fetch(process_id, self.counter..end)
+            .map_err(VmError::Fetch)?;
+
+        let decoded = isa::decode(code, 0).map_err(|x| rebase(x, self.counter));
+        self.run(decoded)
+    }
+
+    // execute the instruction `step()` or `step_heap()` decoded at the counter
+    fn run(&mut self, decoded: core::result::Result<Instr, DecodeError>) -> Result<()> {
+        let instr = match decoded {
+            Ok(instr) => instr,
+            Err(DecodeError::Truncated(_)) => {
+                self.halted = true;
+                return Ok(());
+            }
+            Err(err) => return Err(VmError::Decode(err)),
+        };
+
+        self.counter = self.counter.wrapping_add(instr.size());
+
+        match instr {
+            Instr::Jump(opcode, address) => {
+                let taken = match opcode {
+                    isa::JMP => true,
+                    isa::JZ => self.regs[0] == 0,
+                    isa::JLZ => self.regs[0] == 1,
+                    isa::JMZ => self.regs[0] == 2,
+                    _ => unreachable!("only jump opcodes are decoded as `Instr::Jump`"),
+                };
+
+                if taken {
+                    self.counter = address;
+                }
+            }
+            Instr::Modded(opcode, target, source) => {
+                let source = self.source(source);
+                self.modded_instr(opcode, target, source);
+            }
+            Instr::Not(reg) => self.regs[reg as usize] = !self.regs[reg as usize],
+            Instr::Bare(isa::HLT) => self.halted = true,
+            // LDP, STP, NOP and INT don't do anything yet
+            Instr::Bare(_) => {}
+        }
+
+        #[cfg(feature = "std")]
+        if self.trace {
+            self.dump_regs();
+        }
+
+        Ok(())
+    }
+
+    /// Execute instructions until the program halts or reaches a breakpoint, at least one
+    /// instruction is always executed so resuming from a breakpoint doesn't stop right away.
+    ///
+    /// It errors on the first invalid instruction, see `step()`.
+    pub fn resume(&mut self) -> Result<Stop> {
+        self.resume_with(Self::step)
+    }
+
+    /// Like `resume()` with every instruction fetched from the heap, see `step_heap()`.
+    ///
+    /// It errors on the first instruction that couldn't be fetched or is invalid.
+    pub fn resume_heap(&mut self, allocator: &Allocator, process_id: Process) -> Result<Stop> {
+        self.resume_with(|x| x.step_heap(allocator, process_id))
+    }
+
+    fn resume_with(&mut self, mut step: impl FnMut(&mut Self) -> Result<()>) -> Result<Stop> {
+        loop {
+            step(self)?;
+
+            if self.halted {
+                return Ok(Stop::Halted);
+            }
+
+            if self.breakpoints.contains(&self.counter) {
+                return Ok(Stop::Breakpoint(self.counter));
+            }
+        }
+    }
+
+    /// Execute instructions until the program halts, ignoring breakpoints.
+    ///
+    /// It errors on the first invalid instruction, see `step()`.
+    pub fn execute(&mut self) -> Result<()> {
+        while !self.halted {
+            self.step()?;
+        }
+
+        Ok(())
+    }
+
+    /// Like `execute()` with every instruction fetched from the heap, see `step_heap()`.
+    ///
+    /// It errors on the first instruction that couldn't be fetched or is invalid.
+    pub fn execute_heap(&mut self, allocator: &Allocator, process_id: Process) -> Result<()> {
+        while !self.halted {
+            self.step_heap(allocator, process_id)?;
+        }
+
+        Ok(())
+    }
+}
+
+// code fetched from the heap is decoded from the start of what was fetched, `start` is where
+// that is
+fn rebase(err: DecodeError, start: u32) -> DecodeError {
+    match err {
+        DecodeError::UnknownOpcode(at, byte) => DecodeError::UnknownOpcode(start + at, byte),
+        DecodeError::UnknownMode(at, byte) => DecodeError::UnknownMode(start + at, byte),
+        DecodeError::BadRegister(at, byte) => DecodeError::BadRegister(start + at, byte),
+        DecodeError::Truncated(at) => DecodeError::Truncated(start + at),
+    }
+}
+
+fn parse_offset(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse::<u32>().ok(),
+    }
+}
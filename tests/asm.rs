@@ -0,0 +1,346 @@
+// the assembler only exists with std
+#![cfg(feature = "std")]
+
+use cpu_tset::asm::{self, AsmError};
+use cpu_tset::disasm::disassemble;
+use cpu_tset::image::line_at;
+use cpu_tset::isa::{self, Instr, Operand};
+use cpu_tset::link;
+use cpu_tset::object::Object;
+use cpu_tset::vm::Program;
+
+// every instruction the encoder can produce, every modded one with every register and operand
+// mode, including immediates which would have fit in a smaller mode
+fn every_instr() -> Vec<Instr> {
+    let mut instrs = vec![];
+    let operands = [
+        Operand::Byte(0),
+        Operand::Byte(0xFF),
+        Operand::Word(1),
+        Operand::Word(0xFFFF),
+        Operand::Dword(2),
+        Operand::Dword(u32::MAX),
+    ];
+
+    for opcode in 0..=u8::MAX {
+        if isa::is_modded(opcode) {
+            for target in 0..isa::REGISTERS {
+                let regs = (0..isa::REGISTERS).map(Operand::Reg);
+                for source in regs.chain(operands) {
+                    instrs.push(Instr::Modded(opcode, target, source));
+                }
+            }
+        } else if isa::is_jump(opcode) {
+            for address in [0, 0x1234, u32::MAX] {
+                instrs.push(Instr::Jump(opcode, address));
+            }
+        }
+    }
+    for reg in 0..isa::REGISTERS {
+        instrs.push(Instr::Not(reg));
+    }
+    for opcode in [isa::LDP, isa::STP, isa::HLT, isa::NOP, isa::INT] {
+        instrs.push(Instr::Bare(opcode));
+    }
+
+    instrs
+}
+
+fn encode(instrs: &[Instr]) -> Vec<u8> {
+    let mut code = vec![];
+    for instr in instrs {
+        instr.encode(&mut code);
+    }
+    code
+}
+
+// immediates without a size keyword get the smallest mode they fit in
+#[test]
+fn mnemonics_assemble_into_their_bytes() {
+    let src = "MOV r0, 7
+    add r0, r1 ; the case doesn't matter
+    MOV r3, 0x1234
+    MOV r2, dword 1
+    NOT r2
+    JMP 0
+    HLT
+";
+    let expected = encode(&[
+        Instr::Modded(isa::MOV, 0, Operand::Byte(7)),
+        Instr::Modded(isa::ADD, 0, Operand::Reg(1)),
+        Instr::Modded(isa::MOV, 3, Operand::Word(0x1234)),
+        Instr::Modded(isa::MOV, 2, Operand::Dword(1)),
+        Instr::Not(2),
+        Instr::Jump(isa::JMP, 0),
+        Instr::Bare(isa::HLT),
+    ]);
+
+    assert_eq!(asm::assemble(src), Ok(expected));
+    assert!(asm::assemble("MOV r9, 1\n").is_err());
+    assert!(asm::assemble("FOO r0, 1\n").is_err());
+}
+
+#[test]
+fn every_instruction_assembles_back_into_its_bytes() {
+    let code = encode(&every_instr());
+
+    let text = disassemble(&code).unwrap();
+    assert_eq!(asm::assemble(&text).unwrap(), code, "{text}");
+}
+
+#[test]
+fn every_instruction_decodes_into_itself() {
+    let mut address = 0;
+    let instrs = every_instr();
+    let code = encode(&instrs);
+
+    for instr in instrs {
+        assert_eq!(isa::decode(&code, address), Ok(instr));
+        address += instr.size();
+    }
+    assert_eq!(address as usize, code.len());
+}
+
+// a jump is an opcode and a dword, so `end` is right after the `NOP`
+#[test]
+fn labels_can_be_used_before_and_after_they_are_defined() {
+    let src = "JMP end
+loop: NOP
+end: JMP loop
+";
+    let expected = encode(&[
+        Instr::Jump(isa::JMP, 6),
+        Instr::Bare(isa::NOP),
+        Instr::Jump(isa::JMP, 5),
+    ]);
+    assert_eq!(asm::assemble(src), Ok(expected));
+
+    assert_eq!(
+        asm::assemble("NOP\nJMP nowhere\n"),
+        Err(AsmError::UndefinedLabel(2, "nowhere".to_string()))
+    );
+    assert_eq!(
+        asm::assemble("here: NOP\nhere: HLT\n"),
+        Err(AsmError::DuplicateLabel(2, "here".to_string()))
+    );
+}
+
+// every expansion gets its own `%%skip`, so using the macro twice doesn't define it twice
+#[test]
+fn macros_expand_like_the_code_they_stand_for() {
+    let src = "%macro clamp 2
+    CMP %1, %2
+    JLZ %%skip
+    MOV %1, %2
+%%skip:
+%endmacro
+    clamp r0, 3
+    clamp r1, r0
+    HLT
+";
+    let expanded = "CMP r0, 3
+    JLZ a
+    MOV r0, 3
+a:  CMP r1, r0
+    JLZ b
+    MOV r1, r0
+b:  HLT
+";
+    assert_eq!(
+        asm::assemble(src).unwrap(),
+        asm::assemble(expanded).unwrap()
+    );
+
+    let err = asm::assemble("%macro forever 0\nNOP\n").unwrap_err();
+    assert!(matches!(err, AsmError::UnterminatedMacro(1, _)), "{err}");
+    let err = asm::assemble("%macro again 0\nagain\n%endmacro\nagain\n").unwrap_err();
+    assert!(matches!(err, AsmError::MacroRecursion(_, _)), "{err}");
+}
+
+#[test]
+fn objects_survive_a_round_trip() {
+    let src = ".global main
+main: MOV r0, value
+    HLT
+.data
+value: .dword 0x11223344
+";
+    let object = asm::assemble_object(src).unwrap();
+
+    assert_eq!(Object::from_bytes(&object.to_bytes()), Ok(object));
+}
+
+#[test]
+fn objects_link_against_each_other() {
+    let main = asm::assemble_object(".extern double\nMOV r0, 21\nJMP double\n").unwrap();
+    let lib = asm::assemble_object(".global double\ndouble: ADD r0, r0\nHLT\n").unwrap();
+
+    let code = link::link(&[main.clone(), lib]).unwrap();
+    let mut program = Program::new(code);
+    program.execute().unwrap();
+    assert_eq!(program.regs()[0], 42);
+
+    assert_eq!(
+        link::link(&[main]),
+        Err(link::LinkError::UndefinedSymbol("double".to_string()))
+    );
+}
+
+// the macro expands into two rows on the line it's used on, the second without the source
+#[test]
+fn the_listing_shows_where_every_line_went() {
+    let src = "start: MOV r0, 1
+%macro two 0
+NOP
+NOP
+%endmacro
+    two
+    HLT
+";
+    let (code, listing) = asm::assemble_listing(src).unwrap();
+    assert_eq!(code, asm::assemble(src).unwrap());
+
+    let rows: Vec<_> = listing.lines().collect();
+    assert_eq!(rows.len(), 8, "{listing}");
+    assert_eq!(
+        rows[0],
+        "    1 00000000 07 02 00 01          start: MOV r0, 1"
+    );
+    assert_eq!(rows[1], "    2                               %macro two 0");
+    assert_eq!(rows[5], "    6 00000004 12                       two");
+    assert_eq!(rows[6], "    6 00000005 12");
+    assert_eq!(rows[7], "    7 00000006 11                       HLT");
+}
+
+// padding in `.text` is `NOP`s so running into it doesn't do anything
+#[test]
+fn directives_emit_their_bytes() {
+    let src = ".byte 1, 2
+.word 0x0304
+.org 8
+.dword 0x05060708
+.ascii \"a\\n\"
+.align 4
+HLT
+";
+    let mut expected = vec![1, 2, 4, 3, isa::NOP, isa::NOP, isa::NOP, isa::NOP];
+    expected.extend_from_slice(&[8, 7, 6, 5, b'a', b'\n', isa::NOP, isa::NOP, isa::HLT]);
+    assert_eq!(asm::assemble(src), Ok(expected));
+
+    assert_eq!(
+        asm::assemble("NOP\n.align 3\n"),
+        Err(AsmError::BadAlign(2, 3))
+    );
+    assert_eq!(
+        asm::assemble(".dword 0\n.org 2\n"),
+        Err(AsmError::OrgBackwards(2, 2))
+    );
+}
+
+// the comment and the label on a line of its own don't emit anything, so they're not in the
+// line table
+#[test]
+fn the_line_table_points_back_at_the_source() {
+    let src = "; doubles r0
+    MOV r0, 21
+end:
+    ADD r0, r0
+    HLT
+";
+    let object = asm::assemble_object_with_lines(src, "double.s").unwrap();
+    assert_eq!(object.files, ["double.s"]);
+    assert!(asm::assemble_object(src).unwrap().lines.is_empty());
+
+    let image = link::link_image(&[object]).unwrap();
+    let lines: Vec<_> = image.lines.iter().map(|x| (x.address, x.line)).collect();
+    assert_eq!(lines, [(0, 2), (4, 4), (8, 5)]);
+    assert_eq!(line_at(&image.lines, 5).unwrap().file, "double.s");
+    assert_eq!(line_at(&image.lines, 5).unwrap().line, 4);
+}
+
+#[test]
+fn errors_point_at_their_line() {
+    let err = asm::assemble("NOP\nMOV r0, 1\nFOO r1\n").unwrap_err();
+    assert_eq!(err.line(), Some(3));
+}
+
+// both errors are reported, not just the first one
+#[test]
+fn diagnostics_underline_what_is_wrong() {
+    let errors = asm::assemble_diagnostics("NOP\nMOV r9, 1\nJMP nowhere\n", "main.s").unwrap_err();
+    assert_eq!(errors.len(), 2);
+
+    assert_eq!(
+        errors[0].to_string(),
+        "main.s:2:5: error: `r9` is not a valid register
+    2 | MOV r9, 1
+      |     ^^"
+    );
+    assert_eq!(
+        errors[1].error,
+        AsmError::UndefinedLabel(3, "nowhere".to_string())
+    );
+    assert_eq!(errors[1].text, "JMP nowhere");
+    let span = errors[1].span.unwrap();
+    assert_eq!((span.line, span.column, span.len), (3, 5, 7));
+}
+
+// `lib.s` isn't next to `main.s`, so it's found through the include path
+#[test]
+fn includes_paste_in_other_files() {
+    let dir = std::env::temp_dir().join(format!("lim32-include-{}", std::process::id()));
+    let lib = dir.join("lib");
+    std::fs::create_dir_all(&lib).unwrap();
+    std::fs::write(lib.join("lib.s"), "double: ADD r0, r0\n    FOO\n").unwrap();
+    std::fs::write(dir.join("loop.s"), "%include \"loop.s\"\n").unwrap();
+
+    let main = dir.join("main.s");
+    let src = "MOV r0, 21\n%include \"lib.s\"\nHLT\n";
+    let source = asm::preprocess(src, &main, std::slice::from_ref(&lib)).unwrap();
+    assert_eq!(
+        source.text,
+        "MOV r0, 21\ndouble: ADD r0, r0\n    FOO\nHLT\n"
+    );
+
+    // the error is in the included file, and on its own line numbers
+    let errors = asm::assemble_source(&source).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].file, lib.join("lib.s").display().to_string());
+    assert_eq!(errors[0].span.unwrap().line, 2);
+
+    let errors = asm::preprocess(src, &main, &[]).unwrap_err();
+    assert!(matches!(errors[0].error, AsmError::IncludeNotFound(2, _)));
+    let looping = dir.join("loop.s");
+    let errors = asm::preprocess("%include \"loop.s\"\n", &looping, &[]).unwrap_err();
+    assert!(matches!(errors[0].error, AsmError::IncludeCycle(1, _)));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// `*` binds tighter than `+`, and `SIZE` only uses numbers so it gets a byte while the difference
+// of the labels is a dword like any other immediate with a label in it
+#[test]
+fn expressions_are_evaluated_while_assembling() {
+    let src = ".equ SIZE, 2 + 3 * 4
+    MOV r0, SIZE
+    MOV r1, (~0 >> 28) | 1 << 4
+    MOV r2, end - start
+start: NOP
+end: HLT
+";
+    let expected = encode(&[
+        Instr::Modded(isa::MOV, 0, Operand::Byte(14)),
+        Instr::Modded(isa::MOV, 1, Operand::Byte(0x1F)),
+        Instr::Modded(isa::MOV, 2, Operand::Dword(1)),
+        Instr::Bare(isa::NOP),
+        Instr::Bare(isa::HLT),
+    ]);
+    assert_eq!(asm::assemble(src), Ok(expected));
+
+    let err = asm::assemble("MOV r0, 1 / (2 - 2)\n").unwrap_err();
+    assert!(matches!(err, AsmError::DivisionByZero(1, _)), "{err}");
+    let err = asm::assemble("MOV r0, SIZE\n.equ SIZE, 1\n").unwrap_err();
+    assert!(matches!(err, AsmError::ConstantBeforeEqu(1, _)), "{err}");
+    let err = asm::assemble("here: MOV r0, here * 2\n").unwrap_err();
+    assert!(matches!(err, AsmError::NotRelocatable(1, _)), "{err}");
+}
@@ -0,0 +1,37 @@
+// the test runner only exists with std
+#![cfg(feature = "std")]
+
+use cpu_tset::asmtest::{self, Comparison, Place, TestError};
+
+// the `.byte` comes right after the `HLT`, at 4 + 4 + 1
+#[test]
+fn assertions_are_checked_once_the_program_halts() {
+    let src = "MOV r0, 21
+    ADD r0, r0 ; assert r0 == 42
+    HLT ; assert r0 > 50
+.byte 7 ; assert mem[9] == 7
+";
+    let report = asmtest::run(src, 100).unwrap();
+
+    assert_eq!(report.passed, 2);
+    assert!(!report.ok());
+    assert_eq!(report.failures.len(), 1);
+    let failure = &report.failures[0];
+    assert_eq!(failure.assertion.line, 3);
+    assert_eq!(failure.assertion.place, Place::Reg(0));
+    assert_eq!(failure.assertion.comparison, Comparison::Gt);
+    assert_eq!(failure.actual, Some(42));
+}
+
+#[test]
+fn broken_tests_are_errors() {
+    assert_eq!(
+        asmtest::run("HLT ; assert r0 ~ 1\n", 100),
+        Err(TestError::BadAssertion(1, "r0 ~ 1".to_string()))
+    );
+    assert_eq!(
+        asmtest::run("loop: JMP loop\n", 100),
+        Err(TestError::Timeout(100))
+    );
+    assert!(matches!(asmtest::run("FOO\n", 100), Err(TestError::Asm(_))));
+}
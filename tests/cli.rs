@@ -0,0 +1,59 @@
+// the cli only exists with std
+#![cfg(feature = "std")]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn lim32(args: &[&Path]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_lim32"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+// a directory of its own for every test, so they can run at the same time
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lim32-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn assembled_programs_run_and_disassemble() {
+    let dir = scratch("asm");
+    let source = dir.join("double.s");
+    std::fs::write(&source, "MOV r0, 21\nADD r0, r0\nHLT\n").unwrap();
+
+    let out = lim32(&[Path::new("asm"), &source]);
+    assert!(out.status.success(), "{out:?}");
+    let image = dir.join("double.bin");
+    assert!(image.exists());
+
+    let out = lim32(&[Path::new("run"), &image]);
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(out.status.success(), "{stdout}");
+    assert!(stdout.contains("reg0: 0x0000002a (42)"), "{stdout}");
+
+    let out = lim32(&[Path::new("disasm"), &image]);
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains("ADD r0, r0"), "{stdout}");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn mistakes_fail_with_a_message() {
+    let dir = scratch("mistakes");
+    let source = dir.join("broken.s");
+    std::fs::write(&source, "NOP\nFOO r0\n").unwrap();
+
+    let out = lim32(&[Path::new("asm"), &source]);
+    assert!(!out.status.success());
+    assert!(!out.stderr.is_empty());
+
+    let out = lim32(&[Path::new("frobnicate")]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8(out.stderr).unwrap().starts_with("usage:"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,85 @@
+// the debugger only exists with std
+#![cfg(feature = "std")]
+
+use cpu_tset::asm;
+use cpu_tset::debugger::{Debugger, Watch};
+use cpu_tset::link;
+use cpu_tset::vm::Program;
+
+// counts r0 down from 3, with a symbol table
+fn countdown() -> Program {
+    let src = "start: MOV r0, 3
+loop: SUB r0, 1
+    JZ done
+    JMP loop
+done: HLT
+";
+    let image = link::link_image(&[asm::assemble_object(src).unwrap()]).unwrap();
+    Program::from_image(&image)
+}
+
+#[test]
+fn the_debugger_stops_at_breakpoints() {
+    let mut debugger = Debugger::new(countdown());
+
+    let out = debugger.command("b loop").unwrap();
+    assert!(!out.contains("unknown"), "{out}");
+    // the breakpoint is right after the `MOV`, and again after every round
+    for left in (1..=3).rev() {
+        let out = debugger.command("c").unwrap();
+        assert!(out.contains("breakpoint at"), "{out}");
+        assert!(out.contains("<loop>"), "{out}");
+        assert_eq!(debugger.program().regs()[0], left);
+    }
+
+    let out = debugger.command("p r0 + 1").unwrap();
+    assert_eq!(out, "0x00000002 (2)");
+    let out = debugger.command("c").unwrap();
+    assert!(out.contains("the program halted"), "{out}");
+    assert!(debugger.command("q").is_none());
+}
+
+#[test]
+fn the_debugger_reports_mistakes() {
+    let mut debugger = Debugger::new(countdown());
+
+    let out = debugger.command("frobnicate").unwrap();
+    assert!(out.contains("unknown command"), "{out}");
+    // an empty line repeats the last command
+    let out = debugger.command("").unwrap();
+    assert!(out.contains("unknown command"), "{out}");
+    let out = debugger.command("s 1 2").unwrap();
+    assert_eq!(out, "usage: step [count]");
+}
+
+// the code starts with `07 02 00 03`, the `MOV r0, 3`
+#[test]
+fn watch_expressions_read_the_program() {
+    let program = countdown();
+    let eval = |text| Watch::parse(text).unwrap().eval(&program);
+
+    assert_eq!(eval("1 + 2 * 3 == 7"), Ok(1));
+    assert_eq!(eval("mem16[1] | mem8[3] << 16"), Ok(0x03_0002));
+    assert_eq!(eval("loop + 1"), Ok(5));
+    assert_eq!(eval("r0 - 1"), Ok(u32::MAX));
+    assert!(eval("nothing").is_err());
+    assert!(eval("1 / r0").is_err());
+    assert!(eval("mem32[0xFFFF]").is_err());
+    assert!(Watch::parse("1 +").is_err());
+}
+
+#[test]
+fn watches_and_conditions_follow_the_program() {
+    let mut debugger = Debugger::new(countdown());
+
+    let out = debugger.command("w r0 * 2").unwrap();
+    assert!(out.contains("#1 r0 * 2 = 0x0 (0)"), "{out}");
+    let out = debugger.command("s").unwrap();
+    assert!(out.contains("#1 r0 * 2 = 0x6 (6)"), "{out}");
+
+    debugger.command("b if r0 == 1").unwrap();
+    debugger.command("c").unwrap();
+    assert_eq!(debugger.program().regs()[0], 1);
+    let out = debugger.command("unwatch 1").unwrap();
+    assert!(out.contains("removed"), "{out}");
+}
@@ -0,0 +1,125 @@
+// the disassembler only exists with std
+#![cfg(feature = "std")]
+
+use cpu_tset::asm;
+use cpu_tset::disasm::{
+    diff_images, disassemble, disassemble_at, disassemble_flow, disassemble_image,
+};
+use cpu_tset::image::{Image, Segment, SegmentKind};
+use cpu_tset::isa::{self, Instr, Operand};
+use cpu_tset::link;
+
+// two `NOP`s filling up the last two bytes of the address space
+fn nops() -> Vec<u8> {
+    let mut code = vec![];
+    Instr::Bare(isa::NOP).encode(&mut code);
+    Instr::Bare(isa::NOP).encode(&mut code);
+    code
+}
+
+#[test]
+fn the_sweep_stops_at_the_end_of_the_address_space() {
+    // along with a jump which would run past it
+    let mut code = nops();
+    Instr::Jump(isa::JMP, 0).encode(&mut code);
+
+    let text = disassemble_at(&code, u32::MAX - 1).unwrap();
+    assert_eq!(text.lines().count(), 2);
+    assert!(text.lines().all(|x| x.starts_with("NOP")));
+}
+
+#[test]
+fn the_flow_stops_at_the_end_of_the_address_space() {
+    let image = Image {
+        entry: u32::MAX - 1,
+        memory: 0,
+        segments: vec![Segment {
+            kind: SegmentKind::Code,
+            address: u32::MAX - 1,
+            data: nops(),
+        }],
+        symbols: vec![],
+        lines: vec![],
+    };
+
+    let text = disassemble_flow(&image);
+    assert_eq!(text.lines().count(), 2);
+    assert!(text.lines().all(|x| x.starts_with("NOP")));
+}
+
+#[test]
+fn symbols_survive_a_round_trip() {
+    let src = "start: MOV r0, 3
+loop: SUB r0, 1
+    JZ done
+    JMP loop
+done: HLT
+";
+    let image = link::link_image(&[asm::assemble_object(src).unwrap()]).unwrap();
+
+    let text = disassemble_image(&image).unwrap();
+    assert!(text.contains("loop:"), "{text}");
+    assert!(text.contains("JMP loop"), "{text}");
+    assert_eq!(asm::assemble(&text).unwrap(), image.flatten());
+}
+
+#[test]
+fn segments_land_at_their_addresses_again() {
+    let mut halt = vec![];
+    Instr::Bare(isa::HLT).encode(&mut halt);
+    let code = |address| Segment {
+        kind: SegmentKind::Code,
+        address,
+        data: halt.clone(),
+    };
+    // out of order, and neither of them starts at 0
+    let image = Image {
+        entry: 4,
+        memory: 0,
+        segments: vec![code(12), code(4)],
+        symbols: vec![],
+        lines: vec![],
+    };
+
+    let text = disassemble_image(&image).unwrap();
+    assert_eq!(text.matches(".org").count(), 2, "{text}");
+    // the gaps get padded with `NOP`s rather than the zeros of a flattened image
+    let mut expected = vec![isa::NOP; 13];
+    expected[4] = halt[0];
+    expected[12] = halt[0];
+    assert_eq!(asm::assemble(&text).unwrap(), expected, "{text}");
+}
+
+// the jump is named after its target, so the `NOP` moving it doesn't make it a change
+#[test]
+fn diffs_only_show_what_changed() {
+    let image = |src| link::link_image(&[asm::assemble_object(src).unwrap()]).unwrap();
+    let old = image("MOV r0, 1\nADD r0, 2\nJMP end\nend: HLT\n");
+    let new = image("MOV r0, 1\nADD r0, 3\nNOP\nJMP end\nend: HLT\n");
+
+    assert_eq!(diff_images(&old, &old), Ok(String::new()));
+    assert_eq!(
+        diff_images(&old, &new).unwrap(),
+        "@@ -00000004 +00000004 @@
+- 00000004: ADD r0, 2
++ 00000004: ADD r0, 3
++ 00000008: NOP
+"
+    );
+}
+
+#[test]
+fn data_between_the_code_survives_a_round_trip() {
+    // the bytes after the `JMP` are never executed and don't decode either
+    let mut code = vec![];
+    Instr::Modded(isa::MOV, 0, Operand::Byte(7)).encode(&mut code);
+    Instr::Jump(isa::JMP, 12).encode(&mut code);
+    code.extend_from_slice(&[0xFF, 0xFE, 0xFD]);
+    Instr::Bare(isa::HLT).encode(&mut code);
+    let image = Image::raw(code.clone());
+
+    assert!(disassemble(&code).is_err());
+    let text = disassemble_flow(&image);
+    assert!(text.contains(".byte"), "{text}");
+    assert_eq!(asm::assemble(&text).unwrap(), code, "{text}");
+}
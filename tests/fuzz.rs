@@ -0,0 +1,50 @@
+// the fuzzing entry points only exist with std
+#![cfg(feature = "std")]
+
+use cpu_tset::asm;
+use cpu_tset::fuzz::{fuzz_assemble, fuzz_decode, fuzz_execute, fuzz_load};
+use cpu_tset::image::Image;
+
+// a few thousand bytes of noise, from a xorshift so every run gets the same ones
+fn noise() -> Vec<u8> {
+    let mut state = 0x2545_f491_u32;
+    (0..4096)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+#[test]
+fn noise_does_not_panic() {
+    let noise = noise();
+
+    for chunk in noise.chunks(61) {
+        fuzz_decode(chunk);
+        fuzz_execute(chunk, 1000);
+        fuzz_load(chunk);
+        fuzz_assemble(chunk);
+    }
+}
+
+// cutting a valid input short anywhere makes for inputs that get a lot further than noise does
+#[test]
+fn truncated_inputs_do_not_panic() {
+    let src = "start: MOV r0, (end - start) * 2\nend: HLT\n.data\n.ascii \"hi\"\n";
+    let code = asm::assemble(src).unwrap();
+    let image = Image::raw(code.clone()).to_bytes();
+
+    for len in 0..src.len() {
+        fuzz_assemble(&src.as_bytes()[..len]);
+    }
+    for len in 0..code.len() {
+        fuzz_decode(&code[..len]);
+        fuzz_execute(&code[..len], 1000);
+    }
+    for len in 0..image.len() {
+        fuzz_load(&image[..len]);
+    }
+}
@@ -0,0 +1,36 @@
+use cpu_tset::hexdump::hexdump;
+use cpu_tset::vm::Program;
+
+#[test]
+fn rows_show_the_bytes_and_their_text() {
+    let dump = hexdump(b"Hello, world!\n\0\0", 0x10);
+    assert_eq!(
+        dump,
+        "00000010  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 00  |Hello, world!...|\n"
+    );
+    assert_eq!(hexdump(&[], 0), "");
+}
+
+// the row of the first byte starts at 0x10, so it has four blank columns before it
+#[test]
+fn dumps_away_from_a_row_start_leave_blanks() {
+    let dump = hexdump(&[0xAA; 14], 0x14);
+    let rows: Vec<_> = dump.lines().collect();
+
+    assert_eq!(rows.len(), 2);
+    assert_eq!(
+        rows[0],
+        "00000010              aa aa aa aa  aa aa aa aa aa aa aa aa  |    ............|"
+    );
+    assert_eq!(
+        rows[1],
+        "00000020  aa aa                                             |..|"
+    );
+}
+
+#[test]
+fn program_dumps_stop_at_the_end_of_memory() {
+    let program = Program::new(vec![1, 2, 3]);
+    assert_eq!(program.dump(1..100), hexdump(&[2, 3], 1));
+    assert_eq!(program.dump(50..100), "");
+}
@@ -0,0 +1,164 @@
+#[cfg(feature = "std")]
+use cpu_tset::ihex::{self, IhexError};
+use cpu_tset::image::{Image, ImageError, Line, Segment, SegmentKind, Symbol, MAGIC};
+#[cfg(feature = "sign")]
+use ed25519_dalek::SigningKey;
+
+fn image() -> Image {
+    Image {
+        entry: 4,
+        memory: 0x200,
+        segments: vec![
+            Segment {
+                kind: SegmentKind::Code,
+                address: 0,
+                data: vec![0x12, 0x12, 0x12, 0x12, 0x11],
+            },
+            Segment {
+                kind: SegmentKind::Data,
+                address: 0x100,
+                data: b"hello".to_vec(),
+            },
+        ],
+        symbols: vec![
+            Symbol {
+                name: "main".to_string(),
+                address: 4,
+            },
+            Symbol {
+                name: "greeting".to_string(),
+                address: 0x100,
+            },
+        ],
+        lines: vec![Line {
+            address: 4,
+            file: "main.s".to_string(),
+            line: 2,
+        }],
+    }
+}
+
+#[test]
+fn images_survive_a_round_trip() {
+    let image = image();
+    let bytes = image.to_bytes();
+
+    assert!(Image::is_image(&bytes));
+    assert_eq!(Image::from_bytes(&bytes), Ok(image.clone()));
+    assert_eq!(Image::load(&bytes), Ok(image));
+}
+
+#[test]
+fn raw_code_loads_as_a_single_segment() {
+    let code = vec![0x12, 0x11];
+    assert_eq!(Image::load(&code), Ok(Image::raw(code)));
+}
+
+#[test]
+fn corrupted_images_are_refused() {
+    let bytes = image().to_bytes();
+
+    // the last byte of "hello"
+    let mut corrupted = bytes.clone();
+    *corrupted.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+        Image::from_bytes(&corrupted),
+        Err(ImageError::BadChecksum(_, _))
+    ));
+
+    assert_eq!(
+        Image::from_bytes(&bytes[..MAGIC.len() + 4]),
+        Err(ImageError::Truncated)
+    );
+
+    let mut version = bytes.clone();
+    version[MAGIC.len()] = 0xFF;
+    assert_eq!(
+        Image::from_bytes(&version),
+        Err(ImageError::UnsupportedVersion(0xFF))
+    );
+
+    assert_eq!(Image::from_bytes(b"L32"), Err(ImageError::BadMagic));
+}
+
+#[cfg(feature = "sign")]
+#[test]
+fn signed_images_only_load_with_the_right_key() {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let other = SigningKey::from_bytes(&[8; 32]).verifying_key();
+    let bytes = image().to_signed_bytes(&key);
+
+    assert_eq!(
+        Image::from_signed_bytes(&bytes, &key.verifying_key()),
+        Ok(image())
+    );
+    // the signature is skipped when it's not checked
+    assert_eq!(Image::from_bytes(&bytes), Ok(image()));
+    assert_eq!(
+        Image::from_signed_bytes(&bytes, &other),
+        Err(ImageError::BadSignature)
+    );
+    assert_eq!(
+        Image::from_signed_bytes(&image().to_bytes(), &key.verifying_key()),
+        Err(ImageError::Unsigned)
+    );
+}
+
+#[test]
+fn symbols_can_be_looked_up() {
+    let image = image();
+
+    assert_eq!(image.lookup("greeting"), Some(0x100));
+    assert_eq!(image.lookup("nothing"), None);
+    let (symbol, offset) = image.symbolize(0x102).unwrap();
+    assert_eq!((&*symbol.name, offset), ("greeting", 2));
+    // nothing comes before `main`
+    assert!(image.symbolize(2).is_none());
+}
+
+// segments crossing a 64KiB boundary need an extended address record in the middle, the format
+// only keeps the bytes, their addresses and the entry point
+#[cfg(feature = "std")]
+#[test]
+fn intel_hex_survives_a_round_trip() {
+    let data: Vec<u8> = (0..100).collect();
+    let image = Image {
+        entry: 0xFFE0,
+        memory: 0xFFE0 + data.len() as u32,
+        segments: vec![Segment {
+            kind: SegmentKind::Code,
+            address: 0xFFE0,
+            data,
+        }],
+        symbols: vec![],
+        lines: vec![],
+    };
+
+    let text = ihex::to_ihex(&image);
+    assert!(text.contains(":020000040001F9"), "{text}");
+    assert!(text.ends_with(":00000001FF\n"), "{text}");
+    assert_eq!(ihex::from_ihex(&text), Ok(image));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn intel_hex_is_checked() {
+    let text = "\
+:0400000012121211B5
+:00000001FF
+";
+    let image = ihex::from_ihex(text).unwrap();
+    assert_eq!(image.segments[0].data, [0x12, 0x12, 0x12, 0x11]);
+
+    let corrupted = text.replace("B5", "B6");
+    assert_eq!(ihex::from_ihex(&corrupted), Err(IhexError::BadChecksum(1)));
+    assert_eq!(
+        ihex::from_ihex(text.lines().next().unwrap()),
+        Err(IhexError::MissingEndOfFile)
+    );
+    assert_eq!(
+        ihex::from_ihex(":0000000AF6\n"),
+        Err(IhexError::UnknownRecordType(1, 0x0A))
+    );
+    assert_eq!(ihex::from_ihex("0400\n"), Err(IhexError::BadRecord(1)));
+}
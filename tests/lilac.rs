@@ -0,0 +1,2588 @@
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+
+mod shadow;
+
+#[cfg(feature = "std")]
+use std::alloc::{GlobalAlloc, Layout};
+use std::cmp::Ordering;
+#[cfg(feature = "std")]
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use cpu_tset::image::crc32;
+use cpu_tset::isa::{self, Instr, Operand};
+use cpu_tset::lilac::guard::{FRESH_BYTE, GUARD_BYTE, POISON_BYTE};
+use cpu_tset::lilac::virt::PAGE_SIZE;
+use cpu_tset::vm::{Program, VmError};
+use cpu_tset::{
+    Access, AllocError, Allocator, BlockHandle, BlockInfo, FreeBlock, Inherit, ParseProcessError,
+    Pressure, PressureHandler, ProcBuilder, Process, ProcessStats, Protection, Snapshot, Strategy,
+    Violation,
+};
+#[cfg(feature = "std")]
+use cpu_tset::{Event, EventLog, GlobalLilac, HeapImageError, ParallelAlloc};
+use shadow::{Rng, Shadow};
+
+// an allocator with one process and `count` back to back blocks of `size` bytes
+fn blocks(count: u32, size: u32) -> (Allocator, Process, Vec<BlockHandle>) {
+    let mut allocator = Allocator::new();
+    let process = ProcBuilder::new().count();
+    allocator.register_process(process).unwrap();
+
+    let blocks = (0..count)
+        .map(|_| allocator.alloc(process, size).unwrap())
+        .collect();
+
+    (allocator, process, blocks)
+}
+
+fn cap(freed: FreeBlock) -> (bool, u32) {
+    match freed {
+        FreeBlock::Free(cap) => (false, cap),
+        FreeBlock::FreeMerge(cap) => (true, cap),
+        FreeBlock::RefcountDecreased => panic!("the block was still held"),
+    }
+}
+
+#[test]
+fn freeing_a_lone_block_does_not_merge() {
+    let (mut allocator, _, blocks) = blocks(3, 4);
+
+    let freed = allocator.free(blocks[1]).unwrap();
+    assert_eq!(cap(freed), (false, 4));
+}
+
+#[test]
+fn merges_with_the_block_before() {
+    let (mut allocator, process, blocks) = blocks(3, 4);
+
+    allocator.free(blocks[0]).unwrap();
+    let freed = allocator.free(blocks[1]).unwrap();
+    assert_eq!(cap(freed), (true, 8));
+
+    let block = allocator.alloc(process, 8).unwrap();
+    assert_eq!(allocator.range(block).unwrap(), 0..8);
+}
+
+#[test]
+fn merges_with_the_block_after() {
+    let (mut allocator, process, blocks) = blocks(3, 4);
+
+    allocator.free(blocks[2]).unwrap();
+    let freed = allocator.free(blocks[1]).unwrap();
+    assert_eq!(cap(freed), (true, 8));
+
+    let block = allocator.alloc(process, 8).unwrap();
+    assert_eq!(allocator.range(block).unwrap(), 4..12);
+}
+
+#[test]
+fn merges_on_both_sides() {
+    let (mut allocator, process, blocks) = blocks(3, 4);
+
+    allocator.free(blocks[0]).unwrap();
+    allocator.free(blocks[2]).unwrap();
+    let freed = allocator.free(blocks[1]).unwrap();
+    assert_eq!(cap(freed), (true, 12));
+
+    let block = allocator.alloc(process, 12).unwrap();
+    assert_eq!(allocator.range(block).unwrap(), 0..12);
+}
+
+// the old code worked out the size of a merged block as `end + 1`, which is only right for
+// blocks starting at zero
+#[test]
+fn merged_size_away_from_the_start_of_the_heap() {
+    let (mut allocator, process, blocks) = blocks(4, 4);
+
+    allocator.free(blocks[1]).unwrap();
+    let freed = allocator.free(blocks[2]).unwrap();
+    assert_eq!(cap(freed), (true, 8));
+
+    // the merged block is exactly 8 bytes, so 9 can't fit in it and go past the end of the heap
+    let big = allocator.alloc(process, 9).unwrap();
+    assert_eq!(allocator.range(big).unwrap(), 16..25);
+    let fits = allocator.alloc(process, 8).unwrap();
+    assert_eq!(allocator.range(fits).unwrap(), 4..12);
+}
+
+#[test]
+fn merges_a_whole_run_of_blocks() {
+    let (mut allocator, process, blocks) = blocks(16, 2);
+
+    // free every other block first so nothing merges, then fill in the gaps
+    for block in blocks.iter().step_by(2) {
+        let freed = allocator.free(*block).unwrap();
+        assert_eq!(cap(freed), (false, 2));
+    }
+    for block in blocks.iter().skip(1).step_by(2) {
+        allocator.free(*block).unwrap();
+    }
+
+    let block = allocator.alloc(process, 32).unwrap();
+    assert_eq!(allocator.range(block).unwrap(), 0..32);
+}
+
+#[test]
+fn leftovers_of_a_free_block_merge_again() {
+    let (mut allocator, process, blocks) = blocks(3, 8);
+
+    allocator.free(blocks[1]).unwrap();
+    let small = allocator.alloc(process, 3).unwrap();
+    assert_eq!(allocator.range(small).unwrap(), 8..11);
+
+    // the 5 bytes left over and the 3 bytes just given back become the original 8 again
+    let freed = allocator.free(small).unwrap();
+    assert_eq!(cap(freed), (true, 8));
+
+    let block = allocator.alloc(process, 8).unwrap();
+    assert_eq!(allocator.range(block).unwrap(), 8..16);
+}
+
+// two registered processes, the first one holding a block of 4 bytes full of 1s and the second
+// one holding a block of 4 bytes full of 2s which is shared with the first one
+fn shared() -> (Allocator, Process, Process, BlockHandle, BlockHandle) {
+    let mut allocator = Allocator::new();
+    let mut builder = ProcBuilder::new();
+    let (first, second) = (builder.count(), builder.count());
+    allocator.register_process(first).unwrap();
+    allocator.register_process(second).unwrap();
+
+    let own = allocator.alloc(first, 4).unwrap();
+    allocator.borrow_mut(own).unwrap().fill(1);
+    let held = allocator.alloc(second, 4).unwrap();
+    allocator.borrow_mut(held).unwrap().fill(2);
+    allocator.share(held, first).unwrap();
+
+    (allocator, first, second, own, held)
+}
+
+#[test]
+fn clean_process_reclaims_its_own_blocks() {
+    let (mut allocator, first, _, _, _) = shared();
+
+    allocator.clean_process(first).unwrap();
+    assert!(matches!(
+        allocator.alloc(first, 4),
+        Err(AllocError::NoSuchProcess(_))
+    ));
+
+    // the process is gone so its id can be registered again, and its block is free for the
+    // taking, but only by the new process
+    let again = allocator.register_process(first).unwrap();
+    assert!(matches!(
+        allocator.alloc(first, 4),
+        Err(AllocError::StaleProcess { current, .. }) if current == again
+    ));
+    let block = allocator.alloc(again, 4).unwrap();
+    assert_eq!(allocator.range(block).unwrap(), 0..4);
+}
+
+#[test]
+fn clean_process_leaves_shared_blocks_to_the_others() {
+    let (mut allocator, first, _, _, held) = shared();
+
+    allocator.clean_process(first).unwrap();
+    assert_eq!(allocator.borrow(held).unwrap(), &[2, 2, 2, 2]);
+
+    // nobody else holds it anymore, so freeing it now reclaims it
+    let freed = allocator.free(held).unwrap();
+    assert_eq!(cap(freed), (true, 8));
+}
+
+#[test]
+fn clean_process_of_the_last_holder_reclaims_shared_blocks() {
+    let (mut allocator, first, second, _, _) = shared();
+
+    allocator.clean_process(second).unwrap();
+    allocator.clean_process(first).unwrap();
+
+    let stats = allocator.stats();
+    assert_eq!(stats.free, stats.heap);
+    assert_eq!(stats.blocks, 0);
+}
+
+#[test]
+fn clean_process_clear_only_zeroes_reclaimed_blocks() {
+    let (mut allocator, first, second, _, held) = shared();
+
+    allocator.clean_process_clear(first).unwrap();
+    assert_eq!(allocator.borrow(held).unwrap(), &[2, 2, 2, 2]);
+
+    let block = allocator.alloc(second, 4).unwrap();
+    assert_eq!(allocator.range(block).unwrap(), 0..4);
+    assert_eq!(allocator.borrow(block).unwrap(), &[0, 0, 0, 0]);
+}
+
+#[test]
+fn share_gives_the_target_the_same_block() {
+    let (mut allocator, first, _, own, _) = shared();
+    let mut builder = ProcBuilder::new();
+    builder.count();
+    builder.count();
+    let third = builder.count();
+    allocator.register_process(third).unwrap();
+
+    // sharing goes by handle, so it's the exact block whatever else the process holds
+    let theirs = allocator.share(own, third).unwrap();
+    assert_eq!(
+        allocator.range(theirs).unwrap(),
+        allocator.range(own).unwrap()
+    );
+    assert_eq!(theirs.process_id(), third);
+    assert_eq!(allocator.refcount(own).unwrap(), 2);
+    assert!(allocator.owns(first, 0));
+}
+
+#[test]
+fn borrowing_through_a_cleaned_up_process_errors() {
+    let (mut allocator, _, second, _, held) = shared();
+    allocator.clean_process(second).unwrap();
+
+    assert_eq!(
+        allocator.borrow(held),
+        Err(AllocError::NoSuchProcess(second))
+    );
+}
+
+#[test]
+fn share_refuses_a_target_already_holding_the_block() {
+    let (mut allocator, first, second, _, held) = shared();
+
+    let err = allocator.share(held, first).unwrap_err();
+    assert!(matches!(err, AllocError::AlreadyShared(x) if x.process_id() == first));
+    assert!(matches!(
+        allocator.share(held, second),
+        Err(AllocError::ShareWithSelf(_))
+    ));
+    assert_eq!(allocator.refcount(held).unwrap(), 2);
+}
+
+// the range of the whole block is only kept around while there are windows into it, sharing
+// and freeing windows over and over doesn't leave anything behind
+#[test]
+fn freeing_the_last_window_forgets_the_block_behind_it() {
+    let (mut allocator, first, second, _, held) = shared();
+
+    for _ in 0..3 {
+        let windows = [
+            allocator.share_range(held, first, 4..6).unwrap(),
+            allocator.share_range(held, first, 6..8).unwrap(),
+        ];
+        assert_eq!(allocator.refcount(held).unwrap(), 4);
+
+        for window in windows {
+            allocator.free(window).unwrap();
+            assert!(allocator.validate().is_empty());
+        }
+        assert!(allocator.sweep().is_clean());
+    }
+
+    assert_eq!(allocator.refcount(held).unwrap(), 2);
+    allocator.clean_process(second).unwrap();
+    assert_eq!(
+        allocator.range(held),
+        Err(AllocError::NoSuchProcess(second))
+    );
+}
+
+// the middle of a block split twice has no guards of its own, moving it mustn't leave a guard
+// behind on the bytes it gave back
+#[test]
+fn realloc_moves_the_guards_along() {
+    let mut allocator = Allocator::with_guards(4);
+    let process = ProcBuilder::new().count();
+    allocator.register_process(process).unwrap();
+
+    let block = allocator.alloc(process, 24).unwrap();
+    let second = allocator.split(block, 8).unwrap();
+    let third = allocator.split(second, 8).unwrap();
+    assert_eq!(allocator.range(second).unwrap(), 12..20);
+
+    let (_, moved) = allocator.realloc(second, 9).unwrap();
+    assert!(moved);
+    assert_eq!(allocator.validate(), vec![]);
+    for handle in [block, second, third] {
+        allocator.check_guards(handle).unwrap();
+    }
+    Allocator::restore(allocator.snapshot()).unwrap();
+}
+
+#[test]
+fn aligned_blocks_stay_aligned_when_they_move() {
+    let (mut allocator, process, blocks) = blocks(1, 20);
+    let block = allocator.alloc_aligned(process, 8, 16).unwrap();
+    allocator.alloc(process, 16).unwrap();
+
+    // it can't grow in place with a block right after it
+    let (range, moved) = allocator.realloc(block, 64).unwrap();
+    assert!(moved);
+    assert_eq!(range.start % 16, 0);
+
+    allocator.free(blocks[0]).unwrap();
+    allocator.compact().unwrap();
+    assert_eq!(allocator.range(block).unwrap().start % 16, 0);
+    assert_eq!(allocator.validate(), vec![]);
+
+    // and it's still known to be aligned after a round trip through a heap image
+    let bytes = allocator.snapshot().to_bytes();
+    let mut restored = Allocator::restore(Snapshot::from_bytes(&bytes).unwrap()).unwrap();
+    let (range, _) = restored.realloc(block, 256).unwrap();
+    assert_eq!(range.start % 16, 0);
+}
+
+// code on the heap only runs once its block is made executable
+#[test]
+fn the_vm_only_runs_executable_blocks() {
+    let mut code = vec![];
+    Instr::Modded(isa::MOV, 1, Operand::Byte(7)).encode(&mut code);
+    Instr::Bare(isa::HLT).encode(&mut code);
+
+    let (mut allocator, process, blocks) = blocks(1, code.len() as u32);
+    allocator
+        .borrow_mut(blocks[0])
+        .unwrap()
+        .copy_from_slice(&code);
+
+    let mut program = Program::new(vec![]);
+    assert!(matches!(
+        program.execute_heap(&allocator, process),
+        Err(VmError::Fetch(AllocError::PermissionDenied { .. }))
+    ));
+    assert_eq!(program.regs()[1], 0);
+
+    allocator
+        .protect(blocks[0], Protection::READ_EXECUTE)
+        .unwrap();
+    program.execute_heap(&allocator, process).unwrap();
+    assert_eq!(program.regs()[1], 7);
+    assert!(program.halted());
+}
+
+#[test]
+fn protect_refuses_weak_shares() {
+    let (mut allocator, _, second, own, _) = shared();
+    let weak = allocator.share_weak(own, second).unwrap();
+
+    assert!(matches!(
+        allocator.protect(weak, Protection::READ_EXECUTE),
+        Err(AllocError::PermissionDenied { .. })
+    ));
+    assert_eq!(allocator.protection(own).unwrap(), Protection::READ_WRITE);
+}
+
+// nothing may move or zero the bytes a live token claims, it would be left looking at whatever
+// ends up there instead
+#[cfg(feature = "std")]
+#[test]
+fn compact_leaves_claimed_blocks_alone() {
+    let (mut allocator, process, blocks) = blocks(2, 8);
+    allocator.free(blocks[0]).unwrap();
+
+    let token = allocator.track_borrow(process, 8..16).unwrap();
+    assert!(matches!(
+        allocator.compact(),
+        Err(AllocError::BorrowConflict { .. })
+    ));
+    assert_eq!(allocator.range(blocks[1]).unwrap(), 8..16);
+    assert_eq!(allocator.validate(), vec![]);
+
+    drop(token);
+    allocator.compact().unwrap();
+    assert_eq!(allocator.range(blocks[1]).unwrap(), 0..8);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn realloc_does_not_move_claimed_blocks() {
+    let (mut allocator, process, blocks) = blocks(2, 8);
+
+    let token = allocator.track_borrow(process, 0..8).unwrap();
+    assert!(matches!(
+        allocator.realloc(blocks[0], 16),
+        Err(AllocError::BorrowConflict { .. })
+    ));
+    assert_eq!(allocator.range(blocks[0]).unwrap(), 0..8);
+
+    drop(token);
+    assert_eq!(allocator.realloc(blocks[0], 16).unwrap(), (16..32, true));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn free_clear_does_not_zero_claimed_blocks() {
+    let (mut allocator, process, blocks) = blocks(1, 8);
+    allocator.borrow_mut(blocks[0]).unwrap().fill(1);
+
+    let token = allocator.track_borrow(process, 0..8).unwrap();
+    assert!(matches!(
+        allocator.free_clear(blocks[0]),
+        Err(AllocError::BorrowConflict { .. })
+    ));
+    assert_eq!(allocator.borrow_token(&token).unwrap(), &[1; 8]);
+
+    drop(token);
+    allocator.free_clear(blocks[0]).unwrap();
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn free_secure_does_not_zero_claimed_blocks() {
+    let (mut allocator, process, blocks) = blocks(1, 8);
+    allocator.borrow_mut(blocks[0]).unwrap().fill(1);
+
+    let token = allocator.track_borrow(process, 2..4).unwrap();
+    assert!(matches!(
+        allocator.free_secure(blocks[0]),
+        Err(AllocError::BorrowConflict { .. })
+    ));
+    assert_eq!(allocator.borrow_token(&token).unwrap(), &[1; 2]);
+
+    drop(token);
+    allocator.free_secure(blocks[0]).unwrap();
+}
+
+// bytes another process gets next can't still be claimed by the token of the one before
+#[cfg(feature = "std")]
+#[test]
+fn freed_bytes_are_not_handed_out_while_claimed() {
+    let (mut allocator, first, blocks) = blocks(1, 16);
+    let second = ProcBuilder::from_seed(7).xorshift();
+    allocator.register_process(second).unwrap();
+
+    let token = allocator.track_borrow(first, 0..16).unwrap();
+    assert!(matches!(
+        allocator.free(blocks[0]),
+        Err(AllocError::BorrowConflict { .. })
+    ));
+    assert_eq!(allocator.range(blocks[0]).unwrap(), 0..16);
+
+    drop(token);
+    allocator.free(blocks[0]).unwrap();
+    let handle = allocator.alloc(second, 16).unwrap();
+    assert_eq!(allocator.range(handle).unwrap(), 0..16);
+    allocator.borrow_mut(handle).unwrap().fill(1);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn shrunk_off_bytes_are_not_handed_out_while_claimed() {
+    let (mut allocator, first, blocks) = blocks(1, 16);
+    let second = ProcBuilder::from_seed(7).xorshift();
+    allocator.register_process(second).unwrap();
+
+    let token = allocator.track_borrow(first, 8..12).unwrap();
+    assert!(matches!(
+        allocator.realloc(blocks[0], 4),
+        Err(AllocError::BorrowConflict { .. })
+    ));
+    // shrinking off bytes the token doesn't claim is fine
+    assert_eq!(allocator.realloc(blocks[0], 12).unwrap(), (0..12, false));
+
+    drop(token);
+    assert_eq!(allocator.realloc(blocks[0], 4).unwrap(), (0..4, false));
+    let handle = allocator.alloc(second, 12).unwrap();
+    assert_eq!(allocator.range(handle).unwrap(), 4..16);
+    allocator.borrow_mut(handle).unwrap().fill(1);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn letting_go_of_shared_or_transferred_blocks_drops_no_claims() {
+    let (mut allocator, first, blocks) = blocks(1, 8);
+    let mut builder = ProcBuilder::from_seed(7);
+    let (second, third) = (builder.xorshift(), builder.xorshift());
+    allocator.register_process(second).unwrap();
+    allocator.register_process(third).unwrap();
+    allocator.share(blocks[0], second).unwrap();
+
+    // the other holder keeps the block, but it still can't let go of it from under its token
+    let token = allocator.track_borrow(first, 0..4).unwrap();
+    let other = allocator.track_borrow(second, 4..8).unwrap();
+    assert!(matches!(
+        allocator.free(blocks[0]),
+        Err(AllocError::BorrowConflict { .. })
+    ));
+    // the token of the other holder is its own business
+    drop(token);
+    allocator.free(blocks[0]).unwrap();
+
+    assert!(matches!(
+        allocator.transfer(second, third, 0),
+        Err(AllocError::BorrowConflict { .. })
+    ));
+    drop(other);
+    let moved = allocator.transfer(second, third, 0).unwrap();
+    allocator.borrow_mut(moved).unwrap().fill(1);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn clean_process_gives_up_nothing_while_claimed() {
+    let (mut allocator, process, blocks) = blocks(2, 8);
+
+    let token = allocator.track_borrow(process, 8..16).unwrap();
+    assert!(matches!(
+        allocator.clean_process(process),
+        Err(AllocError::BorrowConflict { .. })
+    ));
+    // neither block was freed and the process is still there
+    assert_eq!(allocator.range(blocks[0]).unwrap(), 0..8);
+    assert_eq!(allocator.range(blocks[1]).unwrap(), 8..16);
+
+    drop(token);
+    allocator.clean_process(process).unwrap();
+    assert!(matches!(
+        allocator.range(blocks[0]),
+        Err(AllocError::NoSuchProcess(_))
+    ));
+}
+
+// half-open ranges can't end past `u32::MAX`, a mapping still can end right at the end of the
+// address space
+#[test]
+fn mappings_reach_the_end_of_the_address_space() {
+    let (mut allocator, process, blocks) = blocks(2, 8);
+
+    allocator.map_at(blocks[0], u32::MAX - 7).unwrap();
+    assert_eq!(allocator.translate(process, u32::MAX).unwrap(), 7);
+    assert!(matches!(
+        allocator.map_at(blocks[1], u32::MAX - 6),
+        Err(AllocError::BadMapping { .. })
+    ));
+    assert!(matches!(
+        allocator.map(blocks[1]),
+        Err(AllocError::BadMapping { .. })
+    ));
+}
+
+// images from before version 8 have ranges ending on their last byte, which have to come back
+// as the same blocks
+#[test]
+fn restore_moves_old_images_over_to_half_open_ranges() {
+    let (allocator, _, blocks) = blocks(1, 4);
+    let mut bytes = allocator.snapshot().to_bytes();
+
+    // the only block is its id, range, flags and parent with only the generation of its process
+    // after it, so the end of its range is 8 + 13 bytes before the end
+    let at = bytes.len() - 21;
+    assert_eq!(bytes[at..at + 4], 4u32.to_le_bytes());
+    bytes[at..at + 4].copy_from_slice(&3u32.to_le_bytes());
+    // and the header of version 7 ends before the alignment count
+    bytes.drain(83..87);
+    bytes[4] = 7;
+    bytes[7..11].fill(0);
+    let checksum = crc32(&bytes);
+    bytes[7..11].copy_from_slice(&checksum.to_le_bytes());
+
+    let restored = Allocator::restore(Snapshot::from_bytes(&bytes).unwrap()).unwrap();
+    assert_eq!(restored.range(blocks[0]).unwrap(), 0..4);
+    assert!(restored.validate().is_empty());
+}
+
+// random sequences of operations checked against the shadow model after every step, with a
+// fixed seed for each run so a failure comes up again the same way
+fn shadowed(new: fn() -> Allocator) {
+    for seed in 0..16 {
+        let mut shadow = Shadow::new(new());
+        shadow.run(&mut Rng::new(seed), 300);
+    }
+}
+
+#[test]
+fn first_fit_agrees_with_the_shadow_model() {
+    shadowed(Allocator::new);
+}
+
+#[test]
+fn best_fit_agrees_with_the_shadow_model() {
+    shadowed(|| Allocator::with_strategy(Strategy::BestFit));
+}
+
+#[test]
+fn worst_fit_agrees_with_the_shadow_model() {
+    shadowed(|| Allocator::with_strategy(Strategy::WorstFit));
+}
+
+#[test]
+fn buddy_mode_agrees_with_the_shadow_model() {
+    shadowed(Allocator::buddy);
+}
+
+#[test]
+fn guarded_blocks_agree_with_the_shadow_model() {
+    shadowed(|| Allocator::with_guards(4));
+}
+
+#[test]
+fn a_limited_heap_agrees_with_the_shadow_model() {
+    shadowed(|| Allocator::with_limit(1 << 20));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn threads_share_a_parallel_allocator() {
+    let (allocator, process, _) = blocks(0, 0);
+    let parallel = ParallelAlloc::from_allocator(allocator);
+
+    let threads: Vec<_> = (0..4u8)
+        .map(|idx| {
+            let parallel = parallel.clone();
+            std::thread::spawn(move || {
+                let handle = parallel.alloc(process, 16).unwrap();
+                parallel.borrow_mut(handle, |x| x.fill(idx)).unwrap();
+                handle
+            })
+        })
+        .collect();
+
+    for (idx, thread) in threads.into_iter().enumerate() {
+        let handle = thread.join().unwrap();
+        let bytes = parallel.borrow(handle, |x| x.to_vec()).unwrap();
+        assert_eq!(bytes, [idx as u8; 16]);
+    }
+    assert_eq!(parallel.read().unwrap().blocks(process).unwrap().count(), 4);
+    assert!(parallel.with(|x| x.validate()).unwrap().is_empty());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn a_panic_while_locked_poisons_the_parallel_allocator() {
+    let (allocator, process, _) = blocks(0, 0);
+    let parallel = ParallelAlloc::from_allocator(allocator);
+
+    let other = parallel.clone();
+    let panicked = std::thread::spawn(move || {
+        other
+            .with(|_| panic!("in the middle of something"))
+            .unwrap();
+    })
+    .join();
+    assert!(panicked.is_err());
+
+    assert!(matches!(parallel.lock(), Err(AllocError::Poisoned)));
+    assert!(matches!(parallel.read(), Err(AllocError::Poisoned)));
+    assert_eq!(parallel.alloc(process, 16), Err(AllocError::Poisoned));
+}
+
+#[cfg(all(feature = "std", unix))]
+#[test]
+fn mapped_heaps_live_in_their_file() {
+    let path = std::env::temp_dir().join(format!("lilac-mmap-{}", std::process::id()));
+    let mut allocator = Allocator::with_mmap(&path, 64).unwrap();
+    assert!(allocator.is_mapped());
+    let process = ProcBuilder::new().count();
+    allocator.register_process(process).unwrap();
+
+    let handle = allocator.alloc(process, 48).unwrap();
+    allocator.borrow_mut(handle).unwrap().fill(7);
+    allocator.sync().unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), [7; 48]);
+
+    // the mapping is as far as the heap can ever grow
+    assert!(matches!(
+        allocator.alloc(process, 32),
+        Err(AllocError::OutOfMemory { .. })
+    ));
+    assert!(matches!(
+        allocator.realloc(handle, 80),
+        Err(AllocError::OutOfMemory { .. })
+    ));
+    allocator.free(handle).unwrap();
+    assert_eq!(allocator.trim(), 48);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+
+    drop(allocator);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn seeded_builders_hand_out_the_same_ids_and_never_repeat_one() {
+    let ids = |seed| {
+        let mut builder = ProcBuilder::from_seed(seed);
+        (0..10_000).map(|_| builder.xorshift()).collect::<Vec<_>>()
+    };
+
+    let first = ids(7);
+    assert_eq!(first, ids(7));
+    assert_ne!(first, ids(8));
+
+    let unique: std::collections::HashSet<_> = first.iter().collect();
+    assert_eq!(unique.len(), first.len());
+}
+
+#[test]
+fn merged_blocks_keep_their_bytes() {
+    let (mut allocator, _, blocks) = blocks(3, 4);
+    for (idx, &block) in blocks.iter().enumerate() {
+        allocator.borrow_mut(block).unwrap().fill(idx as u8);
+    }
+
+    // in any order, the handle of the lower block is the one that's left
+    assert_eq!(allocator.merge(blocks[1], blocks[0]), Ok(0..8));
+    assert_eq!(
+        allocator.borrow(blocks[1]),
+        Err(AllocError::BlockNotFound(blocks[1]))
+    );
+    assert_eq!(
+        allocator.borrow(blocks[0]).unwrap(),
+        [0, 0, 0, 0, 1, 1, 1, 1]
+    );
+
+    assert_eq!(allocator.merge(blocks[0], blocks[2]), Ok(0..12));
+    assert_eq!(allocator.free(blocks[0]).map(cap), Ok((false, 12)));
+}
+
+#[test]
+fn merge_refuses_blocks_it_can_not_join() {
+    let (mut allocator, _, blocks) = blocks(3, 4);
+    assert_eq!(
+        allocator.merge(blocks[0], blocks[2]),
+        Err(AllocError::NotContiguous {
+            first: 0..4,
+            second: 8..12
+        })
+    );
+
+    let (mut allocator, first, _, own, held) = shared();
+    let start = allocator.range(held).unwrap().start;
+    let held = allocator.handle_at(first, start).unwrap();
+    assert_eq!(
+        allocator.merge(own, held),
+        Err(AllocError::BlockShared(held))
+    );
+
+    let mut allocator = Allocator::buddy();
+    let process = ProcBuilder::new().count();
+    allocator.register_process(process).unwrap();
+    let (a, b) = (
+        allocator.alloc(process, 4).unwrap(),
+        allocator.alloc(process, 4).unwrap(),
+    );
+    assert_eq!(allocator.merge(a, b), Err(AllocError::Unsupported));
+}
+
+#[test]
+fn realloc_grows_into_the_free_block_after_it() {
+    let (mut allocator, _, blocks) = blocks(3, 4);
+    allocator.borrow_mut(blocks[0]).unwrap().fill(9);
+    allocator.free(blocks[1]).unwrap();
+
+    assert_eq!(allocator.realloc(blocks[0], 6), Ok((0..6, false)));
+    assert_eq!(allocator.realloc(blocks[0], 8), Ok((0..8, false)));
+    assert_eq!(allocator.borrow(blocks[0]).unwrap()[..4], [9; 4]);
+
+    // the block after it is taken now, but the last block can always grow at the end of the heap
+    let (range, moved) = allocator.realloc(blocks[0], 12).unwrap();
+    assert!(moved);
+    assert_eq!(range.end - range.start, 12);
+    assert_eq!(allocator.borrow(blocks[0]).unwrap()[..4], [9; 4]);
+    assert_eq!(
+        allocator.realloc(blocks[0], 20),
+        Ok((range.start..range.start + 20, false))
+    );
+}
+
+#[test]
+fn split_blocks_are_freed_on_their_own() {
+    let (mut allocator, _, blocks) = blocks(1, 8);
+    allocator
+        .borrow_mut(blocks[0])
+        .unwrap()
+        .copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+
+    let second = allocator.split(blocks[0], 3).unwrap();
+    assert_eq!(allocator.range(blocks[0]), Ok(0..3));
+    assert_eq!(allocator.range(second), Ok(3..8));
+    assert_eq!(allocator.borrow(second).unwrap(), [4, 5, 6, 7, 8]);
+
+    assert_eq!(
+        allocator.split(second, 5),
+        Err(AllocError::BadSplit { size: 5, offset: 5 })
+    );
+    assert_eq!(
+        allocator.split(second, 0),
+        Err(AllocError::BadSplit { size: 5, offset: 0 })
+    );
+
+    assert_eq!(allocator.free(blocks[0]).map(cap), Ok((false, 3)));
+    assert_eq!(allocator.free(second).map(cap), Ok((true, 8)));
+}
+
+#[test]
+fn split_refuses_shared_blocks() {
+    let (mut allocator, _, _, _, held) = shared();
+    assert_eq!(allocator.split(held, 2), Err(AllocError::BlockShared(held)));
+}
+
+// free blocks of 8, 4 and 16 bytes at 0, 9 and 14, each kept apart by a byte that's still held
+fn holes(strategy: Strategy) -> (Allocator, Process) {
+    let mut allocator = Allocator::with_strategy(strategy);
+    let process = ProcBuilder::new().count();
+    allocator.register_process(process).unwrap();
+
+    let holes: Vec<_> = [8, 4, 16]
+        .into_iter()
+        .map(|size| {
+            let hole = allocator.alloc(process, size).unwrap();
+            allocator.alloc(process, 1).unwrap();
+            hole
+        })
+        .collect();
+    for hole in holes {
+        allocator.free(hole).unwrap();
+    }
+
+    (allocator, process)
+}
+
+#[test]
+fn the_strategy_picks_the_free_block() {
+    for (strategy, start) in [
+        (Strategy::FirstFit, 0),
+        (Strategy::BestFit, 9),
+        (Strategy::WorstFit, 14),
+    ] {
+        let (mut allocator, process) = holes(strategy);
+        assert_eq!(allocator.strategy(), strategy);
+
+        let handle = allocator.alloc(process, 4).unwrap();
+        assert_eq!(
+            allocator.range(handle),
+            Ok(start..start + 4),
+            "{strategy:?}"
+        );
+    }
+
+    // it can be changed along the way
+    let (mut allocator, process) = holes(Strategy::FirstFit);
+    allocator.set_strategy(Strategy::WorstFit);
+    let handle = allocator.alloc(process, 4).unwrap();
+    assert_eq!(allocator.range(handle), Ok(14..18));
+}
+
+// freeing the byte at 8 merges the first two holes into one of 13 bytes, which the size index
+// has to know about for best fit to pick it over the one of 16
+#[test]
+fn the_size_index_follows_merges() {
+    let (mut allocator, process) = holes(Strategy::BestFit);
+    let separator = allocator.handle_at(process, 8).unwrap();
+    allocator.free(separator).unwrap();
+
+    let handle = allocator.alloc(process, 12).unwrap();
+    assert_eq!(allocator.range(handle), Ok(0..12));
+    // and the leftover byte of it
+    let handle = allocator.alloc(process, 1).unwrap();
+    assert_eq!(allocator.range(handle), Ok(12..13));
+    assert_eq!(allocator.validate(), vec![]);
+}
+
+// blocks are rounded up to a power of two and start at a multiple of it, so the 12 bytes skip
+// over the 8 after the first block, which the 3 bytes get later
+#[test]
+fn buddy_blocks_split_and_merge_in_halves() {
+    let mut allocator = Allocator::buddy();
+    assert!(allocator.is_buddy());
+    let process = ProcBuilder::new().count();
+    allocator.register_process(process).unwrap();
+
+    let first = allocator.alloc(process, 5).unwrap();
+    let big = allocator.alloc(process, 12).unwrap();
+    let small = allocator.alloc(process, 3).unwrap();
+    assert_eq!(allocator.range(first), Ok(0..5));
+    assert_eq!(allocator.range(big), Ok(16..28));
+    assert_eq!(allocator.range(small), Ok(8..11));
+    assert_eq!(allocator.heap_len(), 32);
+
+    assert_eq!(allocator.free(first).map(cap), Ok((false, 8)));
+    assert_eq!(allocator.free(small).map(cap), Ok((true, 16)));
+    assert_eq!(allocator.free(big).map(cap), Ok((true, 32)));
+    assert_eq!(allocator.validate(), vec![]);
+}
+
+#[test]
+fn arenas_bump_through_their_block() {
+    let (mut allocator, process, blocks) = blocks(1, 4);
+    let mut arena = allocator.arena(process, 16).unwrap();
+    assert_eq!(arena.block(), 4..20);
+
+    assert_eq!(arena.alloc(4), Ok(4..8));
+    assert_eq!(arena.alloc(10), Ok(8..18));
+    assert_eq!(
+        arena.alloc(3),
+        Err(AllocError::ArenaFull {
+            size: 3,
+            remaining: 2
+        })
+    );
+    assert_eq!(arena.alloc(0), Err(AllocError::ZeroSize));
+    allocator.range_borrow_mut(process, 8..18).unwrap().fill(5);
+    assert_eq!((arena.used(), arena.remaining()), (14, 2));
+
+    arena.reset();
+    assert_eq!(arena.alloc(4), Ok(4..8));
+
+    // the arena's block moves to the start of the heap once the block before it is gone
+    allocator.free(blocks[0]).unwrap();
+    allocator.compact().unwrap();
+    arena.relocate(&allocator).unwrap();
+    assert_eq!(arena.block(), 0..16);
+    assert_eq!(allocator.range_borrow(process, 4..14).unwrap(), [5; 10]);
+    // and merges with the free bytes compacting left behind it
+    assert_eq!(arena.release(&mut allocator).map(cap), Ok((true, 20)));
+}
+
+#[test]
+fn compaction_closes_the_holes_between_blocks() {
+    let (mut allocator, _, blocks) = blocks(4, 4);
+    allocator.borrow_mut(blocks[1]).unwrap().fill(1);
+    allocator.borrow_mut(blocks[3]).unwrap().fill(3);
+    allocator.free(blocks[0]).unwrap();
+    allocator.free(blocks[2]).unwrap();
+
+    let compaction = allocator.compact().unwrap();
+    assert_eq!(compaction.reclaimed, 8);
+    assert_eq!(compaction.moved, [(4, 0), (12, 4)]);
+    assert_eq!(compaction.new_start(12), 4);
+
+    assert_eq!(allocator.range(blocks[1]), Ok(0..4));
+    assert_eq!(allocator.range(blocks[3]), Ok(4..8));
+    assert_eq!(allocator.borrow(blocks[1]).unwrap(), [1; 4]);
+    assert_eq!(allocator.borrow(blocks[3]).unwrap(), [3; 4]);
+    // everything that was free is one block at the end now, and a second run has nothing to do
+    assert_eq!(allocator.trim(), 8);
+    assert_eq!(allocator.compact(), Ok(Default::default()));
+}
+
+// the bytes skipped to align the block are free, so a small block fits in there afterwards
+#[test]
+fn aligned_blocks_start_at_a_multiple_of_their_alignment() {
+    let (mut allocator, process, _) = blocks(1, 3);
+
+    let aligned = allocator.alloc_aligned(process, 4, 32).unwrap();
+    assert_eq!(allocator.range(aligned), Ok(32..36));
+    let small = allocator.alloc(process, 8).unwrap();
+    assert_eq!(allocator.range(small), Ok(3..11));
+
+    assert_eq!(
+        allocator.alloc_aligned(process, 4, 24),
+        Err(AllocError::BadAlignment(24))
+    );
+    assert_eq!(allocator.validate(), vec![]);
+}
+
+#[test]
+fn a_limited_heap_runs_out_of_memory() {
+    let mut allocator = Allocator::with_limit(16);
+    assert_eq!(allocator.limit(), Some(16));
+    let process = ProcBuilder::new().count();
+    allocator.register_process(process).unwrap();
+
+    let block = allocator.alloc(process, 12).unwrap();
+    assert_eq!(
+        allocator.alloc(process, 8),
+        Err(AllocError::OutOfMemory {
+            size: 8,
+            heap: 12,
+            limit: Some(16)
+        })
+    );
+    // nothing was given out for the failed allocation, the last 4 bytes still fit
+    let last = allocator.alloc(process, 4).unwrap();
+    assert_eq!(allocator.range(last), Ok(12..16));
+    assert!(matches!(
+        allocator.realloc(block, 13),
+        Err(AllocError::OutOfMemory { .. })
+    ));
+
+    allocator.set_limit(None);
+    assert_eq!(allocator.realloc(block, 13).map(|x| x.1), Ok(true));
+}
+
+#[test]
+fn trim_cuts_off_the_free_end_of_the_heap() {
+    let (mut allocator, process, blocks) = blocks(3, 4);
+    assert_eq!(allocator.trim(), 0);
+
+    allocator.free(blocks[1]).unwrap();
+    allocator.free(blocks[2]).unwrap();
+    assert_eq!(allocator.trim(), 8);
+    assert_eq!(allocator.heap_len(), 4);
+    assert_eq!(allocator.validate(), vec![]);
+
+    // the heap grows again from where it was cut off
+    let block = allocator.alloc(process, 4).unwrap();
+    assert_eq!(allocator.range(block), Ok(4..8));
+
+    // a buddy heap is only ever halved
+    let mut allocator = Allocator::buddy();
+    allocator.register_process(process).unwrap();
+    let blocks: Vec<_> = (0..4)
+        .map(|_| allocator.alloc(process, 8).unwrap())
+        .collect();
+    allocator.free(blocks[3]).unwrap();
+    assert_eq!(allocator.trim(), 0);
+    allocator.free(blocks[2]).unwrap();
+    assert_eq!(allocator.trim(), 16);
+    assert_eq!(allocator.heap_len(), 16);
+}
+
+#[test]
+fn reserve_makes_room_without_growing_the_heap() {
+    let (mut allocator, process, _) = blocks(1, 4);
+    allocator.reserve(100);
+    assert!(allocator.heap_capacity() >= 104);
+    assert_eq!(allocator.heap_len(), 4);
+
+    // the room is used without reallocating, and trimming gives it back
+    let block = allocator.alloc(process, 100).unwrap();
+    assert!(allocator.heap_capacity() >= 104);
+    allocator.free(block).unwrap();
+    allocator.trim();
+    assert!(allocator.heap_capacity() < 104);
+
+    // it never reserves past the limit
+    let mut allocator = Allocator::with_limit(16);
+    allocator.reserve(1000);
+    assert!(allocator.heap_capacity() < 1000);
+    assert!(Allocator::with_capacity(64).heap_capacity() >= 64);
+}
+
+// the shared block counts once for the heap but for both of the processes holding it
+#[test]
+fn stats_count_shared_blocks_once() {
+    let (mut allocator, first, second, own, _) = shared();
+    let extra = allocator.alloc(second, 8).unwrap();
+    allocator.alloc(second, 1).unwrap();
+    allocator.free(extra).unwrap();
+
+    let stats = allocator.stats();
+    assert_eq!((stats.heap, stats.allocated, stats.blocks), (17, 9, 3));
+    assert_eq!(
+        (stats.free, stats.largest_free, stats.free_blocks),
+        (8, 8, 1)
+    );
+    assert_eq!(
+        stats.processes[&first],
+        ProcessStats {
+            allocated: 8,
+            blocks: 2,
+            shared: 1
+        }
+    );
+    assert_eq!(
+        stats.processes[&second],
+        ProcessStats {
+            allocated: 5,
+            blocks: 2,
+            shared: 1
+        }
+    );
+
+    allocator.free(own).unwrap();
+    assert_eq!(allocator.stats().free_blocks, 2);
+}
+
+#[test]
+fn fragmentation_grows_with_the_holes() {
+    let (mut allocator, _, blocks) = blocks(4, 4);
+    assert_eq!(allocator.fragmentation(), 0.0);
+
+    allocator.free(blocks[0]).unwrap();
+    allocator.free(blocks[2]).unwrap();
+    assert_eq!(allocator.fragmentation(), 0.5);
+
+    allocator.free(blocks[3]).unwrap();
+    assert_eq!(
+        allocator.free_report(),
+        "12 free bytes in 2 holes of a 16 byte heap, 33.3% fragmented
+  00000000..00000004 (4 bytes)
+  00000008..00000010 (8 bytes, at the end of the heap)
+"
+    );
+
+    allocator.compact().unwrap();
+    assert_eq!(allocator.fragmentation(), 0.0);
+}
+
+#[test]
+fn leaks_list_what_nobody_freed() {
+    let (mut allocator, first, second, own, held) = shared();
+
+    let leaks = allocator.leaks();
+    assert_eq!(leaks.len(), 2);
+    assert_eq!((leaks[0].range.clone(), leaks[0].refcount), (0..4, 1));
+    assert_eq!(leaks[0].processes, [first]);
+    assert_eq!((leaks[1].range.clone(), leaks[1].refcount), (4..8, 2));
+    let mut holders = leaks[1].processes.clone();
+    holders.sort();
+    assert_eq!(holders, [first, second]);
+
+    let report = allocator.leak_report();
+    assert!(
+        report.starts_with("2 blocks (8 bytes) are still allocated\n"),
+        "{report}"
+    );
+    assert!(
+        report.contains("  00000000..00000004 (4 bytes) refcount 1, held by 0\n"),
+        "{report}"
+    );
+
+    allocator.free(own).unwrap();
+    allocator.clean_process(first).unwrap();
+    allocator.free(held).unwrap();
+    assert_eq!(allocator.leaks(), vec![]);
+    assert_eq!(
+        allocator.leak_report(),
+        "0 blocks (0 bytes) are still allocated\n"
+    );
+}
+
+#[test]
+fn tags_label_blocks_for_every_holder() {
+    let (mut allocator, first, _, own, held) = shared();
+    allocator.set_tag(held, "framebuffer").unwrap();
+    allocator.set_tag(own, "stack").unwrap();
+    allocator.set_tag(own, "heap").unwrap();
+
+    let start = allocator.range(held).unwrap().start;
+    let shared = allocator.handle_at(first, start).unwrap();
+    assert_eq!(allocator.tag(shared), Ok(Some("framebuffer")));
+    assert_eq!(allocator.tag(own), Ok(Some("heap")));
+
+    let stats = allocator.stats();
+    assert_eq!(stats.tags["framebuffer"], 4);
+    assert_eq!(stats.tags["heap"], 4);
+    assert!(allocator.leak_report().contains("(4 bytes, framebuffer)"));
+
+    assert_eq!(allocator.remove_tag(own), Ok(Some("heap".to_string())));
+    assert_eq!(allocator.tag(own), Ok(None));
+    allocator.free(own).unwrap();
+    assert!(matches!(
+        allocator.set_tag(own, "gone"),
+        Err(AllocError::BlockNotFound(_))
+    ));
+}
+
+// only the last three events fit, and the failed free isn't one
+#[cfg(feature = "std")]
+#[test]
+fn the_event_log_keeps_the_last_events() {
+    let mut allocator = Allocator::new();
+    let process = ProcBuilder::new().count();
+    allocator.register_process(process).unwrap();
+    let log = EventLog::new(3);
+    allocator.observe(log.clone());
+
+    let first = allocator.alloc(process, 4).unwrap();
+    let second = allocator.alloc(process, 4).unwrap();
+    allocator.free(first).unwrap();
+    assert!(allocator.free(first).is_err());
+    allocator.free(second).unwrap();
+
+    assert_eq!(
+        log.events(),
+        [
+            Event::Alloc {
+                handle: second,
+                range: 4..8
+            },
+            Event::Free {
+                handle: first,
+                freed: FreeBlock::Free(4)
+            },
+            Event::Free {
+                handle: second,
+                freed: FreeBlock::FreeMerge(8)
+            },
+        ]
+    );
+    assert_eq!(log.drain().len(), 3);
+    assert_eq!(log.events(), []);
+    allocator.trim();
+    assert_eq!(log.events(), [Event::Trim(8)]);
+}
+
+// a heap image can come from anywhere, one with a block reaching past the end of its heap is
+// caught before it's used
+#[test]
+fn the_validator_catches_broken_bookkeeping() {
+    let (allocator, _, _) = blocks(1, 4);
+    assert_eq!(allocator.validate(), vec![]);
+
+    let mut bytes = allocator.snapshot().to_bytes();
+    // see `restore_moves_old_images_over_to_half_open_ranges()` for where the end of the block is
+    let at = bytes.len() - 21;
+    bytes[at..at + 4].copy_from_slice(&6u32.to_le_bytes());
+    bytes[7..11].fill(0);
+    let checksum = crc32(&bytes);
+    bytes[7..11].copy_from_slice(&checksum.to_le_bytes());
+
+    let violation = Violation::OutOfBounds(0..6);
+    assert_eq!(
+        violation.to_string(),
+        "the block at 0x0..0x6 isn't inside the heap"
+    );
+    assert_eq!(
+        Allocator::restore(Snapshot::from_bytes(&bytes).unwrap()).unwrap_err(),
+        AllocError::BadSnapshot(violation)
+    );
+}
+
+#[test]
+fn the_sharing_graph_shows_who_holds_what() {
+    let (mut allocator, _, _, own, _) = shared();
+    allocator.set_tag(own, "the \"stack\"").unwrap();
+
+    assert_eq!(
+        allocator.sharing_graph(),
+        r#"digraph sharing {
+    "p0" [shape=box, label="process 0"];
+    "p1" [shape=box, label="process 1"];
+    "b0" [label="00000000..00000004\n4 bytes\nthe \"stack\"\nrefcount 1"];
+    "p0" -> "b0";
+    "b4" [label="00000004..00000008\n4 bytes\nrefcount 2", style=bold];
+    "p0" -> "b4" [style=bold];
+    "p1" -> "b4" [style=bold];
+}
+"#
+    );
+}
+
+// the checksum of a heap image covers all of it, one that was tampered with has to be resealed
+// with it zeroed out to load again
+fn reseal(bytes: &mut [u8]) {
+    bytes[7..11].fill(0);
+    let checksum = crc32(bytes);
+    bytes[7..11].copy_from_slice(&checksum.to_le_bytes());
+}
+
+#[test]
+fn overwritten_guards_are_caught() {
+    let mut allocator = Allocator::with_guards(4);
+    let process = ProcBuilder::new().count();
+    allocator.register_process(process).unwrap();
+    assert_eq!(allocator.guards(), 4);
+
+    let handle = allocator.alloc(process, 4).unwrap();
+    assert_eq!(allocator.range(handle).unwrap(), 4..8);
+    allocator.borrow_mut(handle).unwrap().fill(0x11);
+    assert_eq!(allocator.check_guards(handle), Ok(()));
+
+    // the guards are only outside the allocator's reach in a heap image, so write past the end
+    // of the block in one
+    let mut bytes = allocator.snapshot().to_bytes();
+    let mut heap = [GUARD_BYTE; 12];
+    heap[4..8].fill(0x11);
+    // safe to unwrap because the heap is in the image as it is
+    let at = bytes.windows(12).position(|x| x == heap).unwrap();
+    bytes[at + 8] = 0x22;
+    reseal(&mut bytes);
+
+    let mut allocator = Allocator::restore(Snapshot::from_bytes(&bytes).unwrap()).unwrap();
+    assert_eq!(
+        allocator.check_guards(handle),
+        Err(AllocError::Corruption(handle))
+    );
+    assert_eq!(
+        allocator.borrow(handle).unwrap_err(),
+        AllocError::Corruption(handle)
+    );
+    assert_eq!(
+        allocator.free(handle).unwrap_err(),
+        AllocError::Corruption(handle)
+    );
+    // the block is still freed, it's only its bytes that can't be trusted
+    assert!(allocator.range(handle).is_err());
+
+    allocator.set_guards(0);
+    let handle = allocator.alloc(process, 4).unwrap();
+    assert_eq!(allocator.check_guards(handle), Ok(()));
+}
+
+#[test]
+fn poisoned_memory_is_caught_when_used_after_free() {
+    let (mut allocator, process, blocks) = blocks(2, 4);
+    allocator.borrow_mut(blocks[0]).unwrap().fill(1);
+    allocator.free(blocks[0]).unwrap();
+    // without poison there's no telling freed memory apart from anything else
+    assert_eq!(
+        allocator.range_borrow(process, 0..4).unwrap_err(),
+        AllocError::NotOwned {
+            process_id: process,
+            range: 0..4
+        }
+    );
+
+    allocator.set_poison(true);
+    assert!(allocator.poison());
+    allocator.free(blocks[1]).unwrap();
+    let err = allocator.range_borrow(process, 6..8).unwrap_err();
+    assert_eq!(
+        err,
+        AllocError::UseAfterFree {
+            process_id: process,
+            range: 6..8
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "process 0 used the memory range 0x6..0x8 after it was freed"
+    );
+
+    // the bytes stay poisoned until the memory is handed out again
+    let handle = allocator.alloc(process, 8).unwrap();
+    assert_eq!(allocator.range(handle).unwrap(), 0..8);
+    assert_eq!(allocator.borrow(handle).unwrap()[4..], [POISON_BYTE; 4]);
+    assert!(allocator.range_borrow(process, 6..8).is_ok());
+}
+
+#[test]
+fn freeing_a_block_twice_is_a_double_free() {
+    let (mut allocator, process, blocks) = blocks(2, 4);
+    allocator.free(blocks[1]).unwrap();
+
+    let err = allocator.free(blocks[1]).unwrap_err();
+    assert_eq!(
+        err,
+        AllocError::DoubleFree {
+            process_id: process,
+            start: 4
+        }
+    );
+    assert_eq!(err.to_string(), "process 0 already freed the block at 0x4");
+
+    // only so many frees are remembered, after that it's just a block that isn't there
+    allocator.free(blocks[0]).unwrap();
+    for _ in 0..64 {
+        let handle = allocator.alloc(process, 4).unwrap();
+        allocator.free(handle).unwrap();
+    }
+    assert_eq!(
+        allocator.free(blocks[1]),
+        Err(AllocError::BlockNotFound(blocks[1]))
+    );
+}
+
+#[test]
+fn errors_say_what_was_involved() {
+    let (mut allocator, process, blocks) = blocks(1, 4);
+    allocator.set_limit(Some(8));
+
+    let stranger = ProcBuilder::new().count();
+    let mut cases = vec![
+        allocator.register_process(process).unwrap_err(),
+        allocator.range_borrow(process, 2..6).unwrap_err(),
+        allocator.split(blocks[0], 4).unwrap_err(),
+        allocator.alloc(process, 8).unwrap_err(),
+        allocator.alloc_aligned(process, 4, 3).unwrap_err(),
+    ];
+    let mut other = Allocator::new();
+    cases.push(other.alloc(stranger, 4).unwrap_err());
+
+    assert_eq!(
+        cases,
+        [
+            AllocError::AlreadyRegistered(process),
+            AllocError::NotOwned {
+                process_id: process,
+                range: 2..6
+            },
+            AllocError::BadSplit { size: 4, offset: 4 },
+            AllocError::OutOfMemory {
+                size: 8,
+                heap: 4,
+                limit: Some(8)
+            },
+            AllocError::BadAlignment(3),
+            AllocError::NoSuchProcess(stranger),
+        ]
+    );
+    assert_eq!(
+        cases.iter().map(|x| x.to_string()).collect::<Vec<_>>(),
+        [
+            "process 0 is already registered",
+            "the memory range 0x2..0x6 is not owned by process 0",
+            "splitting a 4 byte block at 4 doesn't leave a byte on both sides",
+            "there's no room for 8 bytes in the 4 byte heap and it can't grow past 8",
+            "the alignment 3 isn't a power of two",
+            "process 0 does not exist",
+        ]
+    );
+}
+
+#[test]
+fn reused_memory_can_be_handed_out_zeroed() {
+    let (mut allocator, process, blocks) = blocks(2, 4);
+    allocator.borrow_mut(blocks[0]).unwrap().fill(1);
+    allocator.free(blocks[0]).unwrap();
+    // stale bytes are what's handed out by default
+    let stale = allocator.alloc(process, 4).unwrap();
+    assert_eq!(allocator.borrow(stale).unwrap(), [1; 4]);
+    allocator.free(stale).unwrap();
+
+    let zeroed = allocator.alloc_zeroed(process, 4).unwrap();
+    assert_eq!(allocator.borrow(zeroed).unwrap(), [0; 4]);
+    allocator.borrow_mut(zeroed).unwrap().fill(1);
+    allocator.free(zeroed).unwrap();
+
+    assert!(!allocator.zero_on_alloc());
+    allocator.set_zero_on_alloc(true);
+    assert!(allocator.zero_on_alloc());
+    let handle = allocator.alloc(process, 4).unwrap();
+    assert_eq!(allocator.borrow(handle).unwrap(), [0; 4]);
+}
+
+#[test]
+fn secrets_are_scrubbed_out_of_free_memory() {
+    let (mut allocator, process, blocks) = blocks(3, 4);
+    for handle in &blocks {
+        allocator.borrow_mut(*handle).unwrap().fill(7);
+    }
+
+    assert_eq!(cap(allocator.free_secure(blocks[0]).unwrap()), (false, 4));
+    let handle = allocator.alloc(process, 4).unwrap();
+    assert_eq!(allocator.borrow(handle).unwrap(), [0; 4]);
+
+    // a plain free leaves the secret behind until the free memory is scrubbed
+    allocator.free(blocks[1]).unwrap();
+    assert_eq!(allocator.scrub_free(), 4);
+    let handle = allocator.alloc(process, 4).unwrap();
+    assert_eq!(allocator.borrow(handle).unwrap(), [0; 4]);
+    assert_eq!(allocator.borrow(blocks[2]).unwrap(), [7; 4]);
+    assert_eq!(allocator.scrub_free(), 0);
+}
+
+#[test]
+fn blocks_can_be_freed_from_inside() {
+    let (mut allocator, process, blocks) = blocks(3, 4);
+
+    assert_eq!(allocator.handle_at(process, 6), Ok(blocks[1]));
+    assert_eq!(
+        cap(allocator.free_containing(process, 6).unwrap()),
+        (false, 4)
+    );
+    assert!(allocator.range(blocks[1]).is_err());
+    // the end of a block is the start of the next one
+    assert_eq!(
+        cap(allocator.free_containing(process, 8).unwrap()),
+        (true, 8)
+    );
+    assert_eq!(
+        allocator.free_containing(process, 4),
+        Err(AllocError::NotOwned {
+            process_id: process,
+            range: 4..5
+        })
+    );
+    assert_eq!(allocator.range(blocks[0]), Ok(0..4));
+}
+
+#[test]
+fn block_info_describes_the_block() {
+    let (mut allocator, first, second, own, held) = shared();
+    allocator.set_tag(held, "pipe").unwrap();
+
+    assert_eq!(
+        allocator.block_info(own),
+        Ok(BlockInfo {
+            handle: own,
+            range: 0..4,
+            size: 4,
+            refcount: 1,
+            shared: false,
+            read_only: false,
+            protection: Protection::default(),
+            tag: None,
+        })
+    );
+    let held = allocator.handle_at(first, 4).unwrap();
+    let info = allocator.block_info(held).unwrap();
+    assert_eq!((info.range, info.refcount), (4..8, 2));
+    assert!(info.shared);
+    assert_eq!(info.tag.as_deref(), Some("pipe"));
+
+    allocator.clean_process(second).unwrap();
+    assert_eq!(allocator.block_info(held).unwrap().refcount, 1);
+    allocator.free(own).unwrap();
+    assert_eq!(
+        allocator.block_info(own),
+        Err(AllocError::BlockNotFound(own))
+    );
+}
+
+#[test]
+fn every_block_of_a_process_can_be_walked() {
+    let (mut allocator, first, second, own, _) = shared();
+    let mut ranges: Vec<_> = allocator.blocks(first).unwrap().map(|x| x.range).collect();
+    ranges.sort_by_key(|x| x.start);
+    assert_eq!(ranges, [0..4, 4..8]);
+    assert_eq!(
+        allocator
+            .blocks(second)
+            .unwrap()
+            .map(|x| (x.range, x.shared))
+            .collect::<Vec<_>>(),
+        [(4..8, true)]
+    );
+
+    allocator.free(own).unwrap();
+    assert_eq!(allocator.blocks(first).unwrap().count(), 1);
+    let stranger = ProcBuilder::new().count();
+    assert!(matches!(
+        Allocator::new().blocks(stranger),
+        Err(AllocError::NoSuchProcess(_))
+    ));
+}
+
+#[test]
+fn owns_covers_shared_blocks_too() {
+    let (mut allocator, first, second, own, _) = shared();
+
+    assert!((0..8).all(|x| allocator.owns(first, x)));
+    assert!(!allocator.owns(first, 8));
+    assert!(!allocator.owns(second, 3));
+    assert!(allocator.owns(second, 4));
+
+    allocator.free(own).unwrap();
+    assert!(!allocator.owns(first, 0));
+    allocator.clean_process(second).unwrap();
+    assert!(!allocator.owns(second, 4));
+}
+
+#[test]
+fn shared_blocks_count_who_holds_them() {
+    let (mut allocator, first, _, own, held) = shared();
+
+    assert_eq!(allocator.refcount(own), Ok(1));
+    assert_eq!(allocator.is_shared(own), Ok(false));
+    assert_eq!(allocator.refcount(held), Ok(2));
+    assert_eq!(allocator.is_shared(held), Ok(true));
+    // which is what keeps a shared block from being changed under the other process
+    assert_eq!(
+        allocator.realloc(held, 8),
+        Err(AllocError::BlockShared(held))
+    );
+
+    let copy = allocator.handle_at(first, 4).unwrap();
+    allocator.free(copy).unwrap();
+    assert_eq!(allocator.is_shared(held), Ok(false));
+    assert!(allocator.realloc(held, 8).is_ok());
+    assert_eq!(
+        allocator.refcount(copy),
+        Err(AllocError::BlockNotFound(copy))
+    );
+}
+
+#[test]
+fn windows_only_show_their_part_of_the_block() {
+    let (mut allocator, first, second, _, held) = shared();
+    let copy = allocator.handle_at(first, 4).unwrap();
+    allocator
+        .borrow_mut(held)
+        .unwrap()
+        .copy_from_slice(&[1, 2, 3, 4]);
+
+    let window = allocator.share_range(held, first, 5..7).unwrap();
+    assert_eq!(allocator.range(window), Ok(5..7));
+    assert_eq!(allocator.borrow(window).unwrap(), [2, 3]);
+    // the bytes are the block's, not a copy of them
+    allocator.borrow_mut(window).unwrap().fill(9);
+    assert_eq!(allocator.borrow(held).unwrap(), [1, 9, 9, 4]);
+    assert!(allocator.range_borrow(first, 5..7).is_ok());
+
+    assert_eq!(
+        allocator.share_range(held, first, 6..10),
+        Err(AllocError::NotOwned {
+            process_id: second,
+            range: 6..10
+        })
+    );
+    // the window keeps the block it's part of alive
+    allocator.free(copy).unwrap();
+    allocator.clean_process(second).unwrap();
+    assert_eq!(allocator.borrow(window).unwrap(), [9, 9]);
+}
+
+#[test]
+fn read_only_shares_can_not_be_written() {
+    let mut allocator = Allocator::new();
+    let mut builder = ProcBuilder::new();
+    let (first, second) = (builder.count(), builder.count());
+    allocator.register_process(first).unwrap();
+    allocator.register_process(second).unwrap();
+    let own = allocator.alloc(first, 4).unwrap();
+    allocator.borrow_mut(own).unwrap().fill(1);
+
+    let theirs = allocator.share_read_only(own, second).unwrap();
+    assert_eq!(allocator.borrow(theirs).unwrap(), [1; 4]);
+    assert!(allocator.block_info(theirs).unwrap().read_only);
+    let denied = AllocError::PermissionDenied {
+        process_id: second,
+        range: 0..4,
+        access: Access::Write,
+    };
+    assert_eq!(allocator.borrow_mut(theirs).unwrap_err(), denied);
+    assert_eq!(
+        allocator.range_borrow_mut(second, 0..4).unwrap_err(),
+        denied
+    );
+    assert_eq!(
+        denied.to_string(),
+        "process 1 may not write to the memory range 0x0..0x4"
+    );
+
+    // the process it was shared from can still write it
+    allocator.borrow_mut(own).unwrap().fill(2);
+    assert_eq!(allocator.range_borrow(second, 0..4).unwrap(), [2; 4]);
+}
+
+#[test]
+fn weak_shares_dangle_once_the_block_is_freed() {
+    let (mut allocator, _, second, own, _) = shared();
+    let weak = allocator.share_weak(own, second).unwrap();
+    assert!(allocator.is_weak(weak));
+    assert_eq!(allocator.refcount(own), Ok(1));
+    assert_eq!(allocator.borrow(weak).unwrap(), [1; 4]);
+
+    // nothing of the block is left to see through it once it's gone, even when something else
+    // is allocated in its place
+    allocator.free(own).unwrap();
+    allocator.alloc(second, 4).unwrap();
+    assert_eq!(allocator.borrow(weak), Err(AllocError::Dangling(weak)));
+    assert_eq!(allocator.upgrade(weak), Err(AllocError::Dangling(weak)));
+    allocator.drop_weak(weak).unwrap();
+    assert!(!allocator.is_weak(weak));
+    assert_eq!(
+        allocator.drop_weak(weak),
+        Err(AllocError::BlockNotFound(weak))
+    );
+}
+
+#[test]
+fn upgraded_weak_shares_keep_the_block_alive() {
+    let (mut allocator, _, second, own, _) = shared();
+    let weak = allocator.share_weak(own, second).unwrap();
+
+    assert_eq!(allocator.upgrade(weak), Ok(weak));
+    assert!(!allocator.is_weak(weak));
+    assert_eq!(allocator.refcount(own), Ok(2));
+    allocator.free(own).unwrap();
+    assert_eq!(allocator.borrow(weak).unwrap(), [1; 4]);
+}
+
+#[test]
+fn transferred_blocks_change_hands() {
+    let (mut allocator, first, second, own, _) = shared();
+
+    let theirs = allocator.transfer(first, second, 0).unwrap();
+    assert_eq!(theirs.process_id(), second);
+    assert_eq!(allocator.borrow(theirs).unwrap(), [1; 4]);
+    assert_eq!(allocator.refcount(theirs), Ok(1));
+    assert!(!allocator.owns(first, 0));
+    assert_eq!(allocator.borrow(own), Err(AllocError::BlockNotFound(own)));
+
+    assert_eq!(
+        allocator.transfer(first, second, 0),
+        Err(AllocError::NotOwned {
+            process_id: first,
+            range: 0..1
+        })
+    );
+    // the target can't end up holding the same block twice
+    assert!(matches!(
+        allocator.transfer(first, second, 4),
+        Err(AllocError::AlreadyShared(x)) if x.process_id() == second
+    ));
+}
+
+#[test]
+fn copies_go_between_processes_and_may_overlap() {
+    let (mut allocator, first, second, _, held) = shared();
+    allocator
+        .borrow_mut(held)
+        .unwrap()
+        .copy_from_slice(&[1, 2, 3, 4]);
+
+    allocator.copy(second, 4..6, first, 0..2).unwrap();
+    assert_eq!(allocator.range_borrow(first, 0..4).unwrap(), [1, 2, 1, 1]);
+    allocator.copy(second, 4..7, second, 5..8).unwrap();
+    assert_eq!(allocator.borrow(held).unwrap(), [1, 1, 2, 3]);
+
+    assert_eq!(
+        allocator.copy(second, 4..6, first, 0..4),
+        Err(AllocError::LengthMismatch { src: 2, dst: 4 })
+    );
+    // and nothing gets copied unless both sides are the processes' own
+    assert_eq!(
+        allocator.copy(first, 0..2, second, 2..4),
+        Err(AllocError::NotOwned {
+            process_id: second,
+            range: 2..4
+        })
+    );
+    assert_eq!(allocator.borrow(held).unwrap(), [1, 1, 2, 3]);
+}
+
+#[test]
+fn fill_and_compare_work_on_ranges() {
+    let (mut allocator, first, second, _, _) = shared();
+
+    allocator.fill(first, 1..3, 7).unwrap();
+    assert_eq!(allocator.range_borrow(first, 0..4).unwrap(), [1, 7, 7, 1]);
+    assert_eq!(
+        allocator.fill(second, 0..2, 7),
+        Err(AllocError::NotOwned {
+            process_id: second,
+            range: 0..2
+        })
+    );
+
+    assert_eq!(
+        allocator.compare(first, 0..1, second, 4..5),
+        Ok(Ordering::Less)
+    );
+    assert_eq!(
+        allocator.compare(first, 1..3, first, 1..3),
+        Ok(Ordering::Equal)
+    );
+    // running out first makes it the smaller one
+    assert_eq!(
+        allocator.compare(second, 4..8, first, 4..6),
+        Ok(Ordering::Greater)
+    );
+    assert!(allocator.compare(first, 0..1, second, 0..1).is_err());
+}
+
+#[test]
+fn integers_are_read_and_written_little_endian() {
+    let (mut allocator, process, blocks) = blocks(2, 4);
+
+    allocator.write_u32(process, 0, 0x04030201).unwrap();
+    assert_eq!(allocator.borrow(blocks[0]).unwrap(), [1, 2, 3, 4]);
+    assert_eq!(allocator.read_u16(process, 2), Ok(0x0403));
+    allocator.write_u16(process, 4, 0xbeef).unwrap();
+    allocator.write_u8(process, 6, 0x7f).unwrap();
+    assert_eq!(allocator.read_u8(process, 5), Ok(0xbe));
+    assert_eq!(allocator.read_u32(process, 4).unwrap() & 0xffffff, 0x7fbeef);
+
+    // back to back blocks are still two blocks
+    assert_eq!(
+        allocator.read_u32(process, 2),
+        Err(AllocError::NotOwned {
+            process_id: process,
+            range: 2..6
+        })
+    );
+    assert_eq!(
+        allocator.write_u16(process, u32::MAX, 0),
+        Err(AllocError::NotOwned {
+            process_id: process,
+            range: u32::MAX..u32::MAX
+        })
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn cursors_stream_into_a_block() {
+    let (mut allocator, _, blocks) = blocks(2, 4);
+    let mut cursor = allocator.cursor(blocks[0]).unwrap();
+
+    // writes only go as far as the block does
+    assert_eq!(cursor.write(&[1, 2, 3, 4, 5]).unwrap(), 4);
+    assert_eq!(cursor.position(), 4);
+    assert_eq!(cursor.write(&[5]).unwrap(), 0);
+    assert_eq!(cursor.seek(SeekFrom::End(-3)).unwrap(), 1);
+    let mut buf = vec![];
+    cursor.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, [2, 3, 4]);
+    assert!(cursor.seek(SeekFrom::Current(-5)).is_err());
+
+    cursor.set_position(2);
+    cursor.write_all(&[9, 9]).unwrap();
+    assert!(cursor.write_all(&[9]).is_err());
+    assert_eq!(allocator.borrow(blocks[0]).unwrap(), [1, 2, 9, 9]);
+    assert_eq!(allocator.borrow(blocks[1]).unwrap(), [0; 4]);
+}
+
+#[test]
+fn borrows_can_span_back_to_back_blocks() {
+    let (mut allocator, process, blocks) = blocks(3, 4);
+    allocator.borrow_mut(blocks[0]).unwrap().fill(1);
+    allocator.borrow_mut(blocks[1]).unwrap().fill(2);
+
+    assert!(allocator.range_borrow(process, 2..6).is_err());
+    assert_eq!(
+        allocator.range_borrow_spanning(process, 2..6).unwrap(),
+        [1, 1, 2, 2]
+    );
+    allocator
+        .range_borrow_spanning_mut(process, 3..9)
+        .unwrap()
+        .fill(7);
+    assert_eq!(allocator.borrow(blocks[2]).unwrap(), [7, 0, 0, 0]);
+
+    // a hole in the middle breaks the run up
+    allocator.free(blocks[1]).unwrap();
+    assert_eq!(
+        allocator.range_borrow_spanning(process, 2..10).unwrap_err(),
+        AllocError::NotOwned {
+            process_id: process,
+            range: 2..10
+        }
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn tokens_keep_borrows_from_aliasing() {
+    let (mut allocator, process, _) = blocks(1, 8);
+
+    let reading = allocator.track_borrow(process, 0..4).unwrap();
+    assert!(!reading.is_mut());
+    assert_eq!(reading.range(), 0..4);
+    // readers don't get in each other's way
+    let other = allocator.track_borrow(process, 2..6).unwrap();
+    assert!(allocator.range_borrow(process, 0..8).is_ok());
+    assert_eq!(
+        allocator.range_borrow_mut(process, 3..8).unwrap_err(),
+        AllocError::BorrowConflict {
+            range: 3..8,
+            with: 0..4
+        }
+    );
+    assert!(matches!(
+        allocator.borrow_token_mut(&reading),
+        Err(AllocError::PermissionDenied { .. })
+    ));
+    drop(reading);
+    drop(other);
+
+    let writing = allocator.track_borrow_mut(process, 4..8).unwrap();
+    assert!(allocator.range_borrow(process, 6..7).is_err());
+    assert!(allocator.track_borrow(process, 4..5).is_err());
+    allocator.borrow_token_mut(&writing).unwrap().fill(3);
+    assert_eq!(allocator.borrow_token(&writing).unwrap(), [3; 4]);
+    allocator.range_borrow_mut(process, 0..4).unwrap().fill(1);
+
+    drop(writing);
+    assert_eq!(
+        allocator.range_borrow(process, 0..8).unwrap(),
+        [1, 1, 1, 1, 3, 3, 3, 3]
+    );
+}
+
+#[test]
+fn scoped_allocations_free_themselves() {
+    let (mut allocator, process, _) = blocks(0, 4);
+
+    let handle = {
+        let mut scoped = allocator.alloc_scoped(process, 4).unwrap();
+        scoped.borrow_mut().unwrap().fill(1);
+        assert_eq!(scoped.range(), Ok(0..4));
+        scoped.handle()
+    };
+    assert_eq!(
+        allocator.free(handle),
+        Err(AllocError::DoubleFree {
+            process_id: process,
+            start: 0
+        })
+    );
+
+    let scoped = allocator.alloc_scoped(process, 4).unwrap();
+    assert_eq!(cap(scoped.free().unwrap()), (false, 4));
+    // one that's kept is up to whoever kept it
+    let kept = allocator.alloc_scoped(process, 4).unwrap().into_handle();
+    assert_eq!(allocator.range(kept), Ok(0..4));
+}
+
+#[test]
+fn typed_blocks_are_slices_of_their_type() {
+    let (mut allocator, process, _) = blocks(1, 1);
+
+    let values = allocator.alloc_typed::<u32>(process, 3).unwrap();
+    assert_eq!(allocator.range(values.handle()), Ok(4..16));
+    values
+        .as_mut_slice(&mut allocator)
+        .unwrap()
+        .copy_from_slice(&[1, 2, 0x01020304]);
+    assert_eq!(values.as_slice(&allocator).unwrap(), [1, 2, 0x01020304]);
+    assert_eq!(
+        allocator.range_borrow(process, 12..16).unwrap(),
+        0x01020304u32.to_ne_bytes()
+    );
+
+    assert_eq!(
+        allocator.alloc_typed::<u16>(process, 0),
+        Err(AllocError::ZeroSize)
+    );
+    let pairs = allocator.alloc_typed::<[u8; 2]>(process, 2).unwrap();
+    assert_eq!(pairs.as_slice(&allocator).unwrap().len(), 2);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn the_global_alloc_adapter_hands_out_heap_memory() {
+    let global = GlobalLilac::new(64);
+    let process = global.process_id();
+    let layout = Layout::from_size_align(16, 8).unwrap();
+
+    // safe because the layout isn't zero sized, and the pointer is only used while it's alive
+    unsafe {
+        let ptr = global.alloc(layout);
+        assert!(!ptr.is_null());
+        assert_eq!(ptr.align_offset(8), 0);
+        ptr.write_bytes(7, 16);
+        assert_eq!(
+            global.with(|x| x.range_borrow(process, 0..16).unwrap().to_vec()),
+            [7; 16]
+        );
+
+        // the heap never grows past its capacity
+        let big = Layout::from_size_align(64, 1).unwrap();
+        assert!(global.alloc(big).is_null());
+
+        global.dealloc(ptr, layout);
+    }
+    assert_eq!(global.with(|x| x.blocks(process).unwrap().count()), 0);
+}
+
+#[cfg(feature = "allocator_api")]
+#[test]
+fn collections_can_live_in_the_lilac_heap() {
+    let global = GlobalLilac::new(64);
+    let mut builder = ProcBuilder::new();
+    // the first id is the one `GlobalLilac` allocates under itself
+    builder.count();
+    let (first, second) = (builder.count(), builder.count());
+    let first = global.process(first).unwrap();
+    let second = global.process(second).unwrap();
+
+    let mut values = Vec::with_capacity_in(4, first);
+    values.extend_from_slice(&[1u32, 2, 3, 4]);
+    let boxed = Box::new_in(5u64, second);
+    assert_eq!(values.iter().sum::<u32>() as u64 + *boxed, 15);
+    global.with(|x| {
+        assert_eq!(x.blocks(first.process_id()).unwrap().count(), 1);
+        assert_eq!(x.blocks(second.process_id()).unwrap().count(), 1);
+    });
+
+    // the memory goes back to lilac along with the collection
+    drop(values);
+    drop(boxed);
+    global.with(|x| assert_eq!(x.blocks(first.process_id()).unwrap().count(), 0));
+    // a handle for a process that's there already is just another handle for it
+    assert!(global.process(first.process_id()).is_ok());
+}
+
+#[test]
+fn snapshots_restore_the_whole_allocator() {
+    let (mut allocator, first, second, own, held) = shared();
+    allocator.set_tag(own, "stack").unwrap();
+    let snapshot = allocator.snapshot();
+    assert_eq!(snapshot.heap_len(), 8);
+    let mut processes: Vec<_> = snapshot.processes().collect();
+    processes.sort_by_key(|x| x.id());
+    assert_eq!(processes, [first, second]);
+
+    // the restored allocator goes on from where the snapshot was taken, the original doesn't
+    // affect it anymore
+    allocator.free(own).unwrap();
+    let mut restored = Allocator::restore(snapshot).unwrap();
+    assert_eq!(restored.borrow(own).unwrap(), [1; 4]);
+    assert_eq!(
+        restored.block_info(own).unwrap().tag.as_deref(),
+        Some("stack")
+    );
+    assert_eq!(restored.refcount(held), Ok(2));
+    let next = restored.alloc(first, 4).unwrap();
+    assert_ne!(next, own);
+    assert_eq!(restored.range(next), Ok(8..12));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn allocators_survive_a_serde_round_trip() {
+    let (allocator, first, _, own, held) = shared();
+
+    let json = serde_json::to_string(&allocator).unwrap();
+    let mut restored: Allocator = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.borrow(own).unwrap(), [1; 4]);
+    assert_eq!(restored.borrow(held).unwrap(), [2; 4]);
+    restored.free(own).unwrap();
+    let handle = restored.alloc(first, 4).unwrap();
+    assert_eq!(restored.range(handle), Ok(0..4));
+
+    let snapshot: Snapshot = serde_json::from_str(&json).unwrap();
+    assert_eq!(snapshot.to_bytes(), allocator.snapshot().to_bytes());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn heap_images_carry_over_to_the_next_run() {
+    let path = std::env::temp_dir().join(format!("lilac-image-{}", std::process::id()));
+    let (allocator, _, second, own, held) = shared();
+    allocator.save_image(&path).unwrap();
+    drop(allocator);
+
+    let mut allocator = Allocator::load_image(&path).unwrap();
+    assert_eq!(allocator.borrow(own).unwrap(), [1; 4]);
+    assert_eq!(allocator.borrow(held).unwrap(), [2; 4]);
+    allocator.clean_process(second).unwrap();
+    assert_eq!(allocator.refcount(own), Ok(1));
+
+    let mut bytes = std::fs::read(&path).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    std::fs::write(&path, &bytes).unwrap();
+    let err = Allocator::load_image(&path).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(matches!(
+        err.get_ref().and_then(|x| x.downcast_ref()),
+        Some(HeapImageError::BadChecksum(..))
+    ));
+
+    std::fs::write(&path, b"not an image").unwrap();
+    assert_eq!(
+        Allocator::load_image(&path).unwrap_err().to_string(),
+        "this is not a lilac heap image"
+    );
+    std::fs::remove_file(&path).unwrap();
+    assert!(Allocator::load_image(&path).is_err());
+}
+
+#[cfg(all(feature = "std", unix))]
+#[test]
+fn only_mapped_heaps_touch_the_disk() {
+    let (allocator, _, _) = blocks(1, 4);
+    assert!(!allocator.is_mapped());
+    allocator.sync().unwrap();
+
+    // whatever was in the file before is gone, the bookkeeping for it isn't in there
+    let path = std::env::temp_dir().join(format!("lilac-mmap-empty-{}", std::process::id()));
+    std::fs::write(&path, [9; 16]).unwrap();
+    let mut allocator = Allocator::with_mmap(&path, 32).unwrap();
+    let process = ProcBuilder::new().count();
+    allocator.register_process(process).unwrap();
+    let handle = allocator.alloc(process, 8).unwrap();
+    assert_eq!(allocator.borrow(handle).unwrap(), [0; 8]);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 8);
+
+    drop(allocator);
+    std::fs::remove_file(&path).unwrap();
+    assert!(Allocator::with_mmap(std::env::temp_dir(), 32).is_err());
+}
+
+#[test]
+fn segments_are_attached_to_by_name() {
+    let mut allocator = Allocator::new();
+    let mut builder = ProcBuilder::new();
+    let (first, second, third) = (builder.count(), builder.count(), builder.count());
+    for process in [first, second, third] {
+        allocator.register_process(process).unwrap();
+    }
+
+    let vram = allocator.create_segment(first, "vram", 8).unwrap();
+    allocator.borrow_mut(vram).unwrap().fill(3);
+    assert_eq!(
+        allocator.create_segment(second, "vram", 4),
+        Err(AllocError::SegmentExists("vram".to_string()))
+    );
+    assert_eq!(
+        allocator.block_info(vram).unwrap().tag.as_deref(),
+        Some("vram")
+    );
+
+    let theirs = allocator.attach_segment(second, "vram").unwrap();
+    assert_eq!(allocator.borrow(theirs).unwrap(), [3; 8]);
+    assert_eq!(allocator.segment_name(theirs), Ok(Some("vram")));
+    let looking = allocator.attach_segment_read_only(third, "vram").unwrap();
+    assert!(allocator.borrow_mut(looking).is_err());
+    assert_eq!(allocator.refcount(vram), Ok(3));
+
+    allocator.create_segment(first, "audio", 4).unwrap();
+    assert_eq!(allocator.segments().collect::<Vec<_>>(), ["audio", "vram"]);
+    // unlinking only takes the name away, the holders keep the block
+    allocator.unlink_segment("vram").unwrap();
+    assert_eq!(
+        allocator.attach_segment(third, "vram"),
+        Err(AllocError::NoSuchSegment("vram".to_string()))
+    );
+    assert_eq!(allocator.segment_name(theirs), Ok(None));
+    assert_eq!(allocator.borrow(theirs).unwrap(), [3; 8]);
+}
+
+#[test]
+fn futexes_park_processes_until_they_are_woken() {
+    let mut allocator = Allocator::new();
+    let mut builder = ProcBuilder::new();
+    let (first, second, third) = (builder.count(), builder.count(), builder.count());
+    for process in [first, second, third] {
+        allocator.register_process(process).unwrap();
+    }
+    let lock = allocator.alloc(first, 8).unwrap();
+    allocator.share(lock, second).unwrap();
+    allocator.share(lock, third).unwrap();
+    allocator.write_u32(first, 4, 1).unwrap();
+
+    // a value that moved on means looking again instead of sleeping
+    assert_eq!(allocator.futex_wait(second, 4, 0), Ok(false));
+    assert!(!allocator.is_waiting(second));
+    assert_eq!(allocator.futex_wait(second, 4, 1), Ok(true));
+    assert_eq!(allocator.futex_wait(third, 4, 1), Ok(true));
+    assert!(allocator.is_waiting(second));
+
+    // the one waiting the longest goes first
+    assert_eq!(allocator.futex_wake(first, 0, 1), Ok(vec![]));
+    assert_eq!(allocator.futex_wake(first, 4, 1), Ok(vec![second]));
+    assert!(!allocator.is_waiting(second));
+    assert_eq!(allocator.futex_wake(first, 4, 8), Ok(vec![third]));
+    assert!(!allocator.is_waiting(third));
+    assert!(allocator.futex_wait(second, 6, 1).is_err());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn futexes_block_host_threads_until_they_are_woken() {
+    let (allocator, process, _) = blocks(1, 4);
+    let parallel = ParallelAlloc::from_allocator(allocator);
+
+    assert_eq!(
+        parallel.futex_wait_timeout(process, 0, 0, std::time::Duration::from_millis(1)),
+        Ok(false)
+    );
+    let waiter = {
+        let parallel = parallel.clone();
+        std::thread::spawn(move || parallel.futex_wait(process, 0, 0))
+    };
+    // the waiter might not be asleep yet, then it sees the new value instead
+    parallel.write_u32(process, 0, 1).unwrap();
+    while parallel.futex_wake(process, 0, 1).unwrap() == 0 && !waiter.is_finished() {
+        std::thread::yield_now();
+    }
+    assert!(waiter.join().unwrap().is_ok());
+    assert_eq!(parallel.futex_wait(process, 0, 0), Ok(false));
+}
+
+#[test]
+fn groups_are_limited_frozen_and_cleaned_together() {
+    let (mut allocator, first, second, own, held) = shared();
+    let group = allocator.create_group();
+    allocator.add_to_group(group, second).unwrap();
+    allocator.add_to_group(group, first).unwrap();
+    assert_eq!(
+        allocator.add_to_group(group, first),
+        Err(AllocError::AlreadyInGroup {
+            process_id: first,
+            group
+        })
+    );
+    assert_eq!(allocator.group_of(first), Some(group));
+    assert_eq!(
+        allocator.members(group).unwrap().collect::<Vec<_>>(),
+        [first, second]
+    );
+
+    // the block they share only counts once
+    assert_eq!(allocator.group_usage(group), Ok(8));
+    allocator.set_group_quota(group, Some(12)).unwrap();
+    assert_eq!(allocator.group_quota(group), Ok(Some(12)));
+    assert_eq!(
+        allocator.alloc(second, 8),
+        Err(AllocError::QuotaExceeded {
+            group,
+            size: 8,
+            quota: 12
+        })
+    );
+    allocator.alloc(second, 4).unwrap();
+
+    allocator.freeze_group(group).unwrap();
+    assert!(allocator.is_frozen(first));
+    assert_eq!(allocator.alloc(first, 1), Err(AllocError::Frozen(first)));
+    assert_eq!(
+        allocator.borrow_mut(own).unwrap_err(),
+        AllocError::Frozen(first)
+    );
+    assert_eq!(allocator.borrow(held).unwrap(), [2; 4]);
+    allocator.thaw_group(group).unwrap();
+    allocator.borrow_mut(own).unwrap().fill(3);
+
+    allocator.remove_from_group(group, first).unwrap();
+    assert_eq!(allocator.group_of(first), None);
+    allocator.clean_group(group).unwrap();
+    assert!(matches!(
+        allocator.alloc(second, 4),
+        Err(AllocError::NoSuchProcess(_))
+    ));
+    assert!(matches!(
+        allocator.members(group),
+        Err(AllocError::NoSuchGroup(_))
+    ));
+    assert_eq!(allocator.borrow(own).unwrap(), [3; 4]);
+}
+
+#[test]
+fn children_inherit_the_blocks_of_their_parent() {
+    let (mut allocator, first, second, own, _) = shared();
+    let mut builder = ProcBuilder::new();
+    builder.count();
+    builder.count();
+    let (sharing, copying, grandchild) = (builder.count(), builder.count(), builder.count());
+
+    let (sharing, inherited) = allocator
+        .register_child(first, sharing, Inherit::Share)
+        .unwrap();
+    assert_eq!(inherited.len(), 2);
+    assert_eq!(inherited[0].0, own);
+    let (_, theirs) = inherited[0];
+    assert_eq!(allocator.refcount(own), Ok(2));
+    allocator.borrow_mut(theirs).unwrap().fill(5);
+    assert_eq!(allocator.borrow(own).unwrap(), [5; 4]);
+
+    // copies are the child's alone and live somewhere else
+    let (copying, copies) = allocator
+        .register_child(first, copying, Inherit::Copy)
+        .unwrap();
+    let (_, copy) = copies[0];
+    assert_eq!(allocator.range(copy), Ok(8..12));
+    assert_eq!(allocator.borrow(copy).unwrap(), [5; 4]);
+    allocator.borrow_mut(copy).unwrap().fill(6);
+    assert_eq!(allocator.borrow(own).unwrap(), [5; 4]);
+
+    allocator
+        .register_child(sharing, grandchild, Inherit::Share)
+        .unwrap();
+    assert_eq!(allocator.parent_of(grandchild), Some(sharing));
+    assert_eq!(
+        allocator.children(first).collect::<Vec<_>>(),
+        [sharing, copying]
+    );
+    assert_eq!(
+        allocator.register_child(first, second, Inherit::Share),
+        Err(AllocError::AlreadyRegistered(second))
+    );
+
+    // the whole family goes with the parent
+    allocator.clean_process(first).unwrap();
+    for process in [sharing, copying, grandchild] {
+        assert!(matches!(
+            allocator.alloc(process, 4),
+            Err(AllocError::NoSuchProcess(_))
+        ));
+    }
+    assert!(allocator.alloc(second, 4).is_ok());
+}
+
+#[test]
+fn reused_ids_get_a_new_generation() {
+    let (mut allocator, old, blocks) = blocks(1, 4);
+    assert_eq!(old.generation(), 0);
+    allocator.clean_process(old).unwrap();
+
+    let new = allocator.register_process(old).unwrap();
+    assert_eq!((new.id(), new.generation()), (old.id(), 1));
+    assert_ne!(new, old);
+    let handle = allocator.alloc(new, 4).unwrap();
+    allocator.borrow_mut(handle).unwrap().fill(8);
+
+    // the old process can't get at anything of the new one, whatever it held
+    let stale = AllocError::StaleProcess {
+        process_id: old,
+        current: new,
+    };
+    assert_eq!(allocator.range_borrow(old, 0..4).unwrap_err(), stale);
+    assert_eq!(allocator.alloc(old, 4).unwrap_err(), stale);
+    assert_eq!(allocator.free(blocks[0]).unwrap_err(), stale);
+    assert!(!allocator.owns(old, 0));
+    assert_eq!(
+        stale.to_string(),
+        "process 0 is gone, its id belongs to process 0 (generation 1) now"
+    );
+    assert_eq!(
+        allocator.register_process(old),
+        Err(AllocError::AlreadyRegistered(new))
+    );
+}
+
+#[test]
+fn processes_convert_to_and_from_what_they_print_as() {
+    let (mut allocator, old, _) = blocks(0, 0);
+    allocator.clean_process(old).unwrap();
+    let new = allocator.register_process(old).unwrap();
+
+    assert_eq!(Process::from(0), old);
+    assert_eq!(old.id(), 0);
+    assert_eq!(new.to_string(), "0 (generation 1)");
+    for process in [old, new, Process::from(u32::MAX)] {
+        assert_eq!(process.to_string().parse(), Ok(process));
+        assert_eq!(Process::from_bits(process.to_bits()), process);
+    }
+    assert_eq!(Process::try_from("7"), Ok(Process::from(7)));
+    assert_eq!(new.to_bits(), 1 << 32);
+
+    let err = "0 (generation".parse::<Process>().unwrap_err();
+    assert_eq!(err, ParseProcessError("0 (generation".to_string()));
+    assert_eq!(err.to_string(), "\"0 (generation\" is not a process");
+    assert!(Process::try_from("-1").is_err());
+
+    // the newest generation sorts last
+    let mut processes = vec![Process::from(1), new, old];
+    processes.sort();
+    assert_eq!(processes, [old, new, Process::from(1)]);
+}
+
+#[test]
+fn protection_applies_to_every_holder() {
+    let (mut allocator, first, second, _, held) = shared();
+    assert_eq!(allocator.protection(held), Ok(Protection::READ_WRITE));
+    assert!(allocator.fetch(second, 4..8).is_err());
+
+    allocator.protect(held, Protection::READ_EXECUTE).unwrap();
+    assert_eq!(allocator.fetch(first, 4..8).unwrap(), [2; 4]);
+    assert_eq!(allocator.range_borrow(first, 4..8).unwrap(), [2; 4]);
+    assert_eq!(
+        allocator.range_borrow_mut(first, 4..6).unwrap_err(),
+        AllocError::PermissionDenied {
+            process_id: first,
+            range: 4..6,
+            access: Access::Write
+        }
+    );
+
+    // code doesn't have to be readable to run
+    allocator
+        .protect(
+            held,
+            Protection {
+                execute: true,
+                ..Protection::NONE
+            },
+        )
+        .unwrap();
+    assert!(matches!(
+        allocator.range_borrow(second, 4..8),
+        Err(AllocError::PermissionDenied {
+            access: Access::Read,
+            ..
+        })
+    ));
+    assert!(allocator.fetch(second, 4..8).is_ok());
+    allocator.protect(held, Protection::default()).unwrap();
+    allocator.fill(second, 4..8, 3).unwrap();
+}
+
+#[test]
+fn processes_see_their_own_address_space() {
+    let (mut allocator, first, second, own, held) = shared();
+    let theirs = allocator.handle_at(first, 4).unwrap();
+
+    assert_eq!(allocator.map(held), Ok(0));
+    assert_eq!(allocator.map(own), Ok(0));
+    assert_eq!(allocator.map(theirs), Ok(PAGE_SIZE));
+    assert_eq!(
+        allocator.map(own),
+        Err(AllocError::AlreadyMapped {
+            handle: own,
+            addr: 0
+        })
+    );
+    assert_eq!(allocator.translate(first, PAGE_SIZE + 1), Ok(5));
+    assert_eq!(
+        allocator.range_borrow_virtual(second, 0..4).unwrap(),
+        [2; 4]
+    );
+    allocator
+        .range_borrow_virtual_mut(first, 1..3)
+        .unwrap()
+        .fill(7);
+    assert_eq!(allocator.borrow(own).unwrap(), [1, 7, 7, 1]);
+
+    // running off the end of a mapping falls into the gap before the next one
+    assert_eq!(
+        allocator.range_borrow_virtual(first, 2..6).unwrap_err(),
+        AllocError::Unmapped {
+            process_id: first,
+            addr: 4
+        }
+    );
+    assert_eq!(
+        allocator.mappings(first).collect::<Vec<_>>(),
+        [(0, own), (PAGE_SIZE, theirs)]
+    );
+    assert_eq!(allocator.unmap(first, 0), Ok(own));
+    assert!(allocator.translate(first, 0).is_err());
+    assert!(allocator.unmap(first, 0).is_err());
+
+    // the mapping follows the block around
+    allocator.free(own).unwrap();
+    allocator.compact().unwrap();
+    assert_eq!(allocator.translate(second, 0), Ok(0));
+    assert_eq!(
+        allocator.range_borrow_virtual(second, 0..4).unwrap(),
+        [2; 4]
+    );
+}
+
+#[test]
+fn named_heaps_keep_their_blocks_apart() {
+    let (mut allocator, process, blocks) = blocks(1, 4);
+
+    assert_eq!(allocator.create_heap("code", 8), Ok(4..12));
+    assert_eq!(
+        allocator.create_heap("code", 8),
+        Err(AllocError::HeapExists("code".to_string()))
+    );
+    let code = allocator.alloc_in(process, "code", 6).unwrap();
+    assert_eq!(allocator.range(code), Ok(4..10));
+    assert_eq!(allocator.heap_of(code), Ok(Some("code")));
+    assert_eq!(allocator.heap_of(blocks[0]), Ok(None));
+    assert_eq!(allocator.heap_free("code"), Ok(2));
+    assert_eq!(
+        allocator.alloc_in(process, "code", 4),
+        Err(AllocError::HeapFull {
+            heap: "code".to_string(),
+            size: 4
+        })
+    );
+    assert_eq!(
+        allocator.alloc_in(process, "data", 4),
+        Err(AllocError::NoSuchHeap("data".to_string()))
+    );
+
+    // the rest of the heap grows past it instead of taking any of its room
+    let data = allocator.alloc(process, 2).unwrap();
+    assert_eq!(allocator.range(data), Ok(12..14));
+    assert_eq!(allocator.heaps().collect::<Vec<_>>(), [("code", 4..12)]);
+    assert_eq!(
+        allocator.remove_heap("code"),
+        Err(AllocError::HeapInUse("code".to_string()))
+    );
+    allocator.free(code).unwrap();
+    assert_eq!(allocator.heap_free("code"), Ok(8));
+    allocator.remove_heap("code").unwrap();
+    assert_eq!(allocator.heaps().count(), 0);
+    let handle = allocator.alloc(process, 8).unwrap();
+    assert_eq!(allocator.range(handle), Ok(4..12));
+}
+
+// frees the block it's given the first time an allocation finds no room, and remembers what it
+// was told
+#[derive(Debug)]
+struct Evict {
+    victim: Option<BlockHandle>,
+    seen: std::sync::Arc<std::sync::Mutex<Vec<Pressure>>>,
+}
+
+impl PressureHandler for Evict {
+    fn on_pressure(&mut self, allocator: &mut Allocator, pressure: Pressure) -> bool {
+        let low = matches!(pressure, Pressure::Low { .. });
+        self.seen.lock().unwrap().push(pressure);
+        match self.victim.take_if(|_| !low) {
+            Some(victim) => allocator.free(victim).is_ok(),
+            None => false,
+        }
+    }
+}
+
+#[test]
+fn the_pressure_handler_gets_to_make_room() {
+    let (mut allocator, process, blocks) = blocks(2, 4);
+    allocator.set_limit(Some(12));
+    let seen = std::sync::Arc::default();
+    allocator.set_pressure_handler(Evict {
+        victim: Some(blocks[0]),
+        seen: std::sync::Arc::clone(&seen),
+    });
+    allocator.set_low_water(Some(4));
+    assert_eq!(allocator.low_water(), Some(4));
+    assert_eq!(allocator.headroom(), Some(4));
+
+    // the low water mark is only passed once on the way down
+    allocator.alloc(process, 2).unwrap();
+    allocator.alloc(process, 1).unwrap();
+    assert_eq!(*seen.lock().unwrap(), [Pressure::Low { headroom: 2 }]);
+
+    let handle = allocator.alloc(process, 4).unwrap();
+    assert_eq!(allocator.range(handle), Ok(0..4));
+    assert_eq!(
+        allocator.alloc(process, 4),
+        Err(AllocError::OutOfMemory {
+            size: 4,
+            heap: 11,
+            limit: Some(12)
+        })
+    );
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [
+            Pressure::Low { headroom: 2 },
+            Pressure::OutOfMemory { size: 4 },
+            Pressure::OutOfMemory { size: 4 },
+        ]
+    );
+    assert!(allocator.remove_pressure_handler().is_some());
+    assert!(allocator.alloc(process, 4).is_err());
+    assert_eq!(seen.lock().unwrap().len(), 3);
+}
+
+// the only way to end up with orphans is bookkeeping gone wrong, like a heap image that lost
+// part of a free block
+#[test]
+fn the_sweep_reclaims_bytes_nobody_holds() {
+    let (mut allocator, _, blocks) = blocks(2, 4);
+    allocator.borrow_mut(blocks[0]).unwrap().fill(0xff);
+    allocator.free(blocks[0]).unwrap();
+    assert!(allocator.sweep().is_clean());
+
+    let mut bytes = allocator.snapshot().to_bytes();
+    // the free blocks come after the blocks, whose id and range end up looking like it too
+    let free = [0, 0, 0, 0, 4, 0, 0, 0];
+    // safe to unwrap because the free block is in the image as it is
+    let at = bytes.windows(8).rposition(|x| x == free).unwrap();
+    bytes[at + 4] = 2;
+    reseal(&mut bytes);
+
+    let mut allocator = Allocator::restore(Snapshot::from_bytes(&bytes).unwrap()).unwrap();
+    assert_eq!(allocator.stats().free, 2);
+    let sweep = allocator.sweep();
+    assert_eq!(sweep.reclaimed.first(), Some(&(2..4)));
+    assert_eq!(sweep.reclaimed_bytes(), 2);
+    assert!(sweep.refcounts.is_empty());
+    assert!(!sweep.is_clean());
+    assert!(allocator.sweep().is_clean());
+    assert_eq!(allocator.stats().free, 4);
+    assert_eq!(allocator.range(blocks[1]), Ok(4..8));
+}
+
+#[test]
+fn debug_fill_makes_fresh_and_freed_memory_stand_out() {
+    let (mut allocator, process, _) = blocks(0, 0);
+    allocator.set_debug_fill(true);
+    assert!(allocator.debug_fill() && allocator.poison());
+
+    let handle = allocator.alloc(process, 4).unwrap();
+    assert_eq!(allocator.borrow(handle).unwrap(), [FRESH_BYTE; 4]);
+    allocator.borrow_mut(handle).unwrap().fill(1);
+    allocator.realloc(handle, 6).unwrap();
+    assert_eq!(
+        allocator.borrow(handle).unwrap(),
+        [1, 1, 1, 1, FRESH_BYTE, FRESH_BYTE]
+    );
+
+    // turning it off turns poison mode off with it, so the freed bytes are handed out as they
+    // were left
+    allocator.free(handle).unwrap();
+    allocator.set_debug_fill(false);
+    assert!(!allocator.poison());
+    let handle = allocator.alloc(process, 6).unwrap();
+    assert_eq!(allocator.borrow(handle).unwrap(), [POISON_BYTE; 6]);
+
+    // and zeroing wins over it
+    allocator.set_debug_fill(true);
+    allocator.set_zero_on_alloc(true);
+    let handle = allocator.alloc(process, 2).unwrap();
+    assert_eq!(allocator.borrow(handle).unwrap(), [0; 2]);
+}
@@ -0,0 +1,35 @@
+// lilac and the VM only need `core` and `alloc`, so this crate doesn't get std either
+#![no_std]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::vec;
+
+use cpu_tset::isa::{self, Instr, Operand};
+use cpu_tset::vm::Program;
+use cpu_tset::{Allocator, ProcBuilder, Protection};
+
+#[test]
+fn a_program_runs_out_of_the_heap_with_just_alloc() {
+    let mut allocator = Allocator::new();
+    let process = ProcBuilder::new().count();
+    allocator.register_process(process).unwrap();
+    assert_eq!(format!("{}", process), "0");
+
+    let mut code = vec![];
+    Instr::Modded(isa::MOV, 1, Operand::Byte(7)).encode(&mut code);
+    Instr::Modded(isa::ADD, 1, Operand::Byte(2)).encode(&mut code);
+    Instr::Bare(isa::HLT).encode(&mut code);
+    let handle = allocator.alloc(process, code.len() as u32).unwrap();
+    allocator.borrow_mut(handle).unwrap().copy_from_slice(&code);
+    allocator.protect(handle, Protection::READ_EXECUTE).unwrap();
+
+    let mut program = Program::new(vec![]);
+    program.execute_heap(&allocator, process).unwrap();
+    assert_eq!(program.regs()[1], 9);
+    assert!(program.halted());
+
+    allocator.free(handle).unwrap();
+    assert_eq!(allocator.validate(), vec![]);
+}
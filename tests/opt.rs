@@ -0,0 +1,55 @@
+// the assembler and the optimizer only exist with std
+#![cfg(feature = "std")]
+
+use cpu_tset::image::{Image, Segment, SegmentKind, Symbol};
+use cpu_tset::vm::Program;
+use cpu_tset::{asm, isa, opt};
+
+// the registers after running `code` until it halts
+fn run(code: Vec<u8>) -> [u32; 4] {
+    let mut program = Program::new(code);
+    program.execute().unwrap();
+    *program.regs()
+}
+
+// the `SUB` can't be folded into the `MOV` before it, the jump to the `NOP` lands on it once the
+// `NOP` is gone
+#[test]
+fn optimizing_keeps_what_the_code_does() {
+    let src = "MOV r0, dword 5
+    NOP
+    ADD r0, 3
+    NOP
+    JMP skip
+    MOV r0, 0
+skip: NOP
+    SUB r0, word 1
+    HLT
+";
+    let code = asm::assemble(src).unwrap();
+    let optimized = opt::optimize(&code).unwrap();
+    assert!(optimized.len() < code.len());
+    assert!(!optimized.contains(&isa::NOP));
+    assert_eq!(run(optimized), run(code));
+}
+
+#[test]
+fn optimizing_keeps_jump_targets_apart_from_symbols() {
+    let image = Image {
+        entry: 0,
+        memory: 16,
+        segments: vec![Segment {
+            kind: SegmentKind::Code,
+            address: 0,
+            data: asm::assemble("NOP\nend: HLT\n").unwrap(),
+        }],
+        symbols: vec![Symbol {
+            name: "end".to_string(),
+            address: 1,
+        }],
+        lines: vec![],
+    };
+
+    let image = opt::optimize_image(&image).unwrap();
+    assert_eq!(image.lookup("end"), Some(0));
+}
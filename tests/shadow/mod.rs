@@ -0,0 +1,415 @@
+// a shadow model of an `Allocator`: every operation runs on the allocator and on a plain map of
+// which process holds which range, and `check()` makes sure the two still agree. the model
+// doesn't know where the allocator puts blocks, it takes the range it was given and checks
+// everything that has to hold for it (inside the heap, not overlapping anything else that's
+// held, the contents staying what was written), so it works for every strategy
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use cpu_tset::{AllocError, Allocator, BlockHandle, FreeBlock, ProcBuilder, Process};
+
+// a block as the model sees it, which every process in `holders` has a handle to
+#[derive(Debug, Clone)]
+struct Block {
+    range: Range<u32>,
+    // what was written to every byte of it, `None` for bytes nobody wrote which can be anything
+    bytes: Vec<Option<u8>>,
+    holders: Vec<BlockHandle>,
+}
+
+impl Block {
+    fn len(&self) -> u32 {
+        self.range.end - self.range.start
+    }
+}
+
+// one thing to do to both the allocator and the model, picked by `Shadow::random_op()`
+#[derive(Debug, Clone)]
+pub enum Op {
+    Register,
+    Alloc {
+        process: usize,
+        size: u32,
+    },
+    Free {
+        handle: usize,
+    },
+    Realloc {
+        handle: usize,
+        size: u32,
+    },
+    Share {
+        handle: usize,
+        process: usize,
+    },
+    Write {
+        handle: usize,
+        offset: u32,
+        byte: u8,
+    },
+    Clean {
+        process: usize,
+    },
+    Compact,
+}
+
+// xorshift64, so a failing sequence comes up again with the same seed
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck on zero
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // a number in `0..n`, `n` can't be zero
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+pub struct Shadow {
+    pub allocator: Allocator,
+    builder: ProcBuilder,
+    processes: Vec<Process>,
+    // the model itself, by an id of its own since shared blocks have a handle for every holder
+    blocks: BTreeMap<u64, Block>,
+    handles: BTreeMap<BlockHandle, u64>,
+    // every handle which was freed or whose process was cleaned up, none of them work anymore
+    dead: Vec<BlockHandle>,
+    next_id: u64,
+    // the operations so far, for telling what led up to a mismatch
+    pub log: Vec<Op>,
+}
+
+impl Shadow {
+    pub fn new(allocator: Allocator) -> Self {
+        Self {
+            allocator,
+            builder: ProcBuilder::new(),
+            processes: vec![],
+            blocks: BTreeMap::new(),
+            handles: BTreeMap::new(),
+            dead: vec![],
+            next_id: 0,
+            log: vec![],
+        }
+    }
+
+    // run `count` random operations, checking the allocator against the model after each one
+    pub fn run(&mut self, rng: &mut Rng, count: usize) {
+        for _ in 0..count {
+            let op = self.random_op(rng);
+            self.apply(op);
+            self.check();
+        }
+    }
+
+    // an operation that makes sense for the state the model is in, mostly allocating and
+    // freeing, every so often something that fails on purpose
+    pub fn random_op(&mut self, rng: &mut Rng) -> Op {
+        if self.processes.is_empty() {
+            return Op::Register;
+        }
+
+        let process = rng.below(self.processes.len() as u64) as usize;
+        let size = match rng.below(8) {
+            0 => 1 + rng.below(1024) as u32,
+            _ => 1 + rng.below(64) as u32,
+        };
+
+        // a handle to a freed block now and then, which has to fail without touching anything
+        let handles = self.handles.len() + self.dead.len().min(1);
+        if handles == 0 {
+            return Op::Alloc { process, size };
+        }
+        let handle = rng.below(handles as u64) as usize;
+
+        match rng.below(100) {
+            0..=2 => Op::Register,
+            3..=39 => Op::Alloc { process, size },
+            40..=64 => Op::Free { handle },
+            65..=74 => Op::Realloc { handle, size },
+            75..=82 => Op::Share { handle, process },
+            83..=95 => Op::Write {
+                handle,
+                offset: rng.next() as u32,
+                byte: rng.next() as u8,
+            },
+            96..=97 => Op::Clean { process },
+            _ => Op::Compact,
+        }
+    }
+
+    // the `n`th live handle, or the last dead one for `n` past all of them
+    fn handle(&self, n: usize) -> BlockHandle {
+        match self.handles.keys().nth(n) {
+            Some(handle) => *handle,
+            None => *self.dead.last().unwrap(),
+        }
+    }
+
+    fn is_dead(&self, handle: BlockHandle) -> bool {
+        !self.handles.contains_key(&handle)
+    }
+
+    pub fn apply(&mut self, op: Op) {
+        self.log.push(op.clone());
+
+        match op {
+            Op::Register => {
+                let process = self.builder.count();
+                let process = self.allocator.register_process(process).unwrap();
+                self.processes.push(process);
+            }
+            Op::Alloc { process, size } => {
+                let process = self.processes[process];
+                let handle = self.allocator.alloc(process, size).unwrap();
+                let range = self.allocator.range(handle).unwrap();
+                assert_eq!(range.end - range.start, size, "{:?}", self.log);
+
+                self.insert(handle, range);
+            }
+            Op::Free { handle } => {
+                let handle = self.handle(handle);
+                if self.is_dead(handle) {
+                    assert!(self.allocator.free(handle).is_err(), "{:?}", self.log);
+                    return;
+                }
+
+                let freed = self.allocator.free(handle).unwrap();
+                let id = self.handles.remove(&handle).unwrap();
+                self.dead.push(handle);
+
+                let block = self.blocks.get_mut(&id).unwrap();
+                block.holders.retain(|x| *x != handle);
+                if block.holders.is_empty() {
+                    // the guards around it are freed along with it
+                    let len = block.len() + 2 * self.allocator.guards();
+                    self.blocks.remove(&id);
+                    // whatever it merged with it's at least as big as the block, in buddy mode
+                    // even a lone block is rounded up to a power of two
+                    let buddy = self.allocator.is_buddy();
+                    match freed {
+                        FreeBlock::Free(cap) if buddy => assert!(cap >= len, "{:?}", self.log),
+                        FreeBlock::Free(cap) => assert_eq!(cap, len, "{:?}", self.log),
+                        FreeBlock::FreeMerge(cap) => assert!(cap > len, "{:?}", self.log),
+                        FreeBlock::RefcountDecreased => panic!("nobody holds it: {:?}", self.log),
+                    }
+                } else {
+                    assert_eq!(freed, FreeBlock::RefcountDecreased, "{:?}", self.log);
+                }
+            }
+            Op::Realloc { handle, size } => {
+                let handle = self.handle(handle);
+                let result = self.allocator.realloc(handle, size);
+                if self.is_dead(handle) {
+                    assert!(result.is_err(), "{:?}", self.log);
+                    return;
+                }
+
+                let id = self.handles[&handle];
+                let block = self.blocks.get_mut(&id).unwrap();
+                if block.holders.len() > 1 {
+                    assert_eq!(
+                        result,
+                        Err(AllocError::BlockShared(handle)),
+                        "{:?}",
+                        self.log
+                    );
+                    return;
+                }
+
+                let (range, moved) = result.unwrap();
+                assert_eq!(range.end - range.start, size, "{:?}", self.log);
+                assert_eq!(moved, range.start != block.range.start, "{:?}", self.log);
+
+                // the bytes it kept stay what they were, the ones it gained can be anything
+                block.bytes.resize(size as usize, None);
+                let bytes = block.bytes.clone();
+                self.blocks.remove(&id);
+                self.handles.remove(&handle);
+                self.insert(handle, range);
+                self.blocks.get_mut(&self.handles[&handle]).unwrap().bytes = bytes;
+            }
+            Op::Share { handle, process } => {
+                let handle = self.handle(handle);
+                let target = self.processes[process];
+                let result = self.allocator.share(handle, target);
+                if self.is_dead(handle) {
+                    assert!(result.is_err(), "{:?}", self.log);
+                    return;
+                }
+
+                let id = self.handles[&handle];
+                let block = self.blocks.get_mut(&id).unwrap();
+                if target == handle.process_id() {
+                    assert!(
+                        matches!(result, Err(AllocError::ShareWithSelf(_))),
+                        "{:?}",
+                        self.log
+                    );
+                } else if let Some(held) = block.holders.iter().find(|x| x.process_id() == target) {
+                    assert_eq!(
+                        result,
+                        Err(AllocError::AlreadyShared(*held)),
+                        "{:?}",
+                        self.log
+                    );
+                } else {
+                    // the handle of a process to a block is the same every time, so one it freed
+                    // works again once the block is shared with it again
+                    let shared = result.unwrap();
+                    self.dead.retain(|x| *x != shared);
+                    block.holders.push(shared);
+                    self.handles.insert(shared, id);
+                }
+            }
+            Op::Write {
+                handle,
+                offset,
+                byte,
+            } => {
+                let handle = self.handle(handle);
+                if self.is_dead(handle) {
+                    assert!(self.allocator.borrow_mut(handle).is_err(), "{:?}", self.log);
+                    return;
+                }
+
+                let block = self.blocks.get_mut(&self.handles[&handle]).unwrap();
+                let offset = offset % block.len();
+                self.allocator.borrow_mut(handle).unwrap()[offset as usize] = byte;
+                block.bytes[offset as usize] = Some(byte);
+            }
+            Op::Clean { process } => {
+                let process = self.processes.remove(process);
+                self.allocator.clean_process(process).unwrap();
+
+                let handles: Vec<BlockHandle> = self
+                    .handles
+                    .keys()
+                    .filter(|x| x.process_id() == process)
+                    .copied()
+                    .collect();
+                for handle in handles {
+                    let id = self.handles.remove(&handle).unwrap();
+                    self.dead.push(handle);
+
+                    let block = self.blocks.get_mut(&id).unwrap();
+                    block.holders.retain(|x| *x != handle);
+                    if block.holders.is_empty() {
+                        self.blocks.remove(&id);
+                    }
+                }
+            }
+            Op::Compact => match self.allocator.compact() {
+                // the blocks move but stay in the same order, with the same contents
+                Ok(compaction) => {
+                    for block in self.blocks.values_mut() {
+                        let start = compaction.new_start(block.range.start);
+                        block.range = start..start + (block.range.end - block.range.start);
+                    }
+                }
+                Err(err) => {
+                    assert!(self.allocator.is_buddy(), "{err}");
+                    assert_eq!(err, AllocError::Unsupported, "{:?}", self.log);
+                }
+            },
+        }
+    }
+
+    // put a block into the model which the allocator just handed out at `range`
+    fn insert(&mut self, handle: BlockHandle, range: Range<u32>) {
+        for block in self.blocks.values() {
+            assert!(
+                range.end <= block.range.start || range.start >= block.range.end,
+                "{range:?} overlaps {:?}: {:?}",
+                block.range,
+                self.log
+            );
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.blocks.insert(
+            id,
+            Block {
+                bytes: vec![None; (range.end - range.start) as usize],
+                range,
+                holders: vec![handle],
+            },
+        );
+        self.handles.insert(handle, id);
+    }
+
+    // make sure the allocator and the model agree on everything the model knows
+    pub fn check(&self) {
+        let log = &self.log;
+
+        for (handle, id) in &self.handles {
+            let block = &self.blocks[id];
+            assert_eq!(
+                self.allocator.range(*handle),
+                Ok(block.range.clone()),
+                "{log:?}"
+            );
+            assert_eq!(
+                self.allocator.refcount(*handle),
+                Ok(block.holders.len() as u32),
+                "{log:?}"
+            );
+
+            let bytes = self.allocator.borrow(*handle).unwrap();
+            for (offset, (byte, expected)) in bytes.iter().zip(&block.bytes).enumerate() {
+                if let Some(expected) = expected {
+                    assert_eq!(byte, expected, "byte {offset} of {handle:?}: {log:?}");
+                }
+            }
+        }
+
+        for handle in &self.dead {
+            assert!(
+                self.allocator.range(*handle).is_err(),
+                "{handle:?}: {log:?}"
+            );
+        }
+
+        let mut ranges: Vec<Range<u32>> = self.blocks.values().map(|x| x.range.clone()).collect();
+        ranges.sort_unstable_by_key(|x| x.start);
+        for pair in ranges.windows(2) {
+            assert!(pair[0].end <= pair[1].start, "{pair:?}: {log:?}");
+        }
+        if let Some(last) = ranges.last() {
+            assert!(last.end <= self.allocator.heap_len(), "{last:?}: {log:?}");
+        }
+
+        let stats = self.allocator.stats();
+        let allocated: u32 = self.blocks.values().map(|x| x.len()).sum();
+        assert_eq!(stats.allocated, allocated, "{log:?}");
+        assert_eq!(stats.blocks, self.blocks.len(), "{log:?}");
+        // outside of buddy mode every byte is either held, a guard or free
+        if !self.allocator.is_buddy() {
+            let guards = 2 * self.allocator.guards() * self.blocks.len() as u32;
+            assert_eq!(stats.allocated + guards + stats.free, stats.heap, "{log:?}");
+        }
+        for process in &self.processes {
+            let held = self
+                .handles
+                .keys()
+                .filter(|x| x.process_id() == *process)
+                .count();
+            let blocks = stats.processes.get(process).map_or(0, |x| x.blocks);
+            assert_eq!(blocks, held, "{process:?}: {log:?}");
+        }
+
+        assert_eq!(self.allocator.validate(), vec![], "{log:?}");
+    }
+}
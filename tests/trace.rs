@@ -0,0 +1,132 @@
+// tracing only exists with std
+#![cfg(feature = "std")]
+
+use cpu_tset::asm;
+use cpu_tset::link;
+use cpu_tset::trace::{trace_chrome, trace_filtered, trace_json, Filter};
+use cpu_tset::vm::Program;
+
+// counts r0 down from 3, with a symbol table
+fn countdown() -> Program {
+    let src = "start: MOV r0, 3
+loop: SUB r0, 1
+    JZ done
+    JMP loop
+done: HLT
+";
+    let image = link::link_image(&[asm::assemble_object(src).unwrap()]).unwrap();
+    Program::from_image(&image)
+}
+
+#[test]
+fn the_trace_has_a_record_for_every_instruction() {
+    let mut out = vec![];
+    trace_json(&mut countdown(), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    // `MOV`, three rounds of `SUB` and `JZ`, two `JMP`s back and the `HLT`
+    assert_eq!(out.lines().count(), 1 + 3 * 2 + 2 + 1, "{out}");
+    assert!(out.lines().all(|x| x.starts_with("{\"pc\":")), "{out}");
+    assert!(
+        out.lines()
+            .next()
+            .unwrap()
+            .contains("\"regs\":[{\"reg\":0,\"old\":0,\"new\":3}]"),
+        "{out}"
+    );
+    assert!(
+        out.lines().last().unwrap().contains("\"opcode\":\"HLT\""),
+        "{out}"
+    );
+}
+
+// with a quantum of 4 the countdown runs in three slices and the other program in one, which
+// comes right after the first slice of the countdown
+#[test]
+fn the_chrome_trace_takes_turns() {
+    let other = Program::new(asm::assemble("NOP\nHLT\n").unwrap());
+    let mut programs = vec![
+        ("countdown".to_string(), countdown()),
+        ("other".to_string(), other),
+    ];
+    let mut out = vec![];
+    trace_chrome(&mut programs, 4, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    assert!(out.starts_with("{\"traceEvents\":["), "{out}");
+    assert!(out.ends_with("]}\n"), "{out}");
+    assert_eq!(out.matches("\"ph\":\"M\"").count(), 6, "{out}");
+    assert_eq!(
+        out.matches("\"cat\":\"instruction\"").count(),
+        10 + 2,
+        "{out}"
+    );
+    assert_eq!(out.matches("\"cat\":\"scheduler\"").count(), 3 + 1, "{out}");
+    assert!(out.contains("\"name\":\"other\""), "{out}");
+    assert!(
+        out.contains("\"name\":\"running\",\"pid\":2,\"tid\":0,\"ts\":4,\"dur\":2"),
+        "{out}"
+    );
+    assert!(programs.iter().all(|x| x.1.halted()));
+}
+
+// `JZ done` is 4 bytes past `loop`, and the `SUB` is 4 bytes long
+#[test]
+fn traces_name_addresses_after_symbols() {
+    let program = countdown();
+    assert_eq!(program.symbolize(0), Some("start".to_string()));
+    assert_eq!(program.symbolize(8), Some("loop+0x4".to_string()));
+    assert_eq!(program.lookup("loop+4"), Some(8));
+    assert_eq!(program.lookup("nothing"), None);
+
+    let mut out = vec![];
+    trace_filtered(&mut countdown(), &Filter::default(), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let first: Vec<_> = out.lines().take(3).collect();
+    assert_eq!(
+        first,
+        [
+            "00000000 <start> MOV r0, 3  r0: 0x0 -> 0x3",
+            "00000004 <loop> SUB r0, 1  r0: 0x3 -> 0x2",
+            "00000008 <loop+0x4> JZ 0x00000012",
+        ],
+        "{out}"
+    );
+}
+
+// the countdown only ever changes r0 and never touches memory
+#[test]
+fn filters_pick_what_gets_traced() {
+    let trace = |filter: Filter| {
+        let mut out = vec![];
+        trace_filtered(&mut countdown(), &filter, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    let subs = trace(Filter {
+        range: Some(4..8),
+        ..Filter::default()
+    });
+    assert_eq!(subs.lines().count(), 3, "{subs}");
+    assert!(subs.lines().all(|x| x.contains("SUB r0, 1")), "{subs}");
+
+    let changes = trace(Filter {
+        regs: Some(vec![0]),
+        ..Filter::default()
+    });
+    assert_eq!(changes.lines().count(), 1 + 3, "{changes}");
+    assert_eq!(
+        trace(Filter {
+            regs: Some(vec![1]),
+            ..Filter::default()
+        }),
+        ""
+    );
+    assert_eq!(
+        trace(Filter {
+            memory: true,
+            ..Filter::default()
+        }),
+        ""
+    );
+}
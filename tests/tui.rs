@@ -0,0 +1,49 @@
+// the frontend only exists with the `tui` feature
+#![cfg(feature = "tui")]
+
+use cpu_tset::asm;
+use cpu_tset::debugger::Debugger;
+use cpu_tset::link;
+use cpu_tset::tui::Tui;
+use cpu_tset::vm::Program;
+
+fn tui() -> Tui {
+    let src = "start: MOV r0, 3
+loop: SUB r0, 1
+    JZ done
+    JMP loop
+done: HLT
+";
+    let image = link::link_image(&[asm::assemble_object(src).unwrap()]).unwrap();
+    Tui::new(Debugger::new(Program::from_image(&image)))
+}
+
+#[test]
+fn the_screen_follows_the_counter() {
+    let mut tui = tui();
+
+    let screen = tui.render();
+    assert!(screen.contains(">  00000000: MOV r0, 3"), "{screen}");
+    assert!(screen.contains("   loop:"), "{screen}");
+    assert!(screen.contains("r0: 0x00000000 (0)"), "{screen}");
+
+    tui.command("s").unwrap();
+    let screen = tui.render();
+    assert!(screen.contains(">  00000004: SUB r0, 1"), "{screen}");
+    assert!(screen.contains("r0: 0x00000003 (3)"), "{screen}");
+}
+
+#[test]
+fn the_memory_pane_moves() {
+    let mut tui = tui();
+
+    tui.command("m 0x10").unwrap();
+    assert!(
+        tui.render().contains("\nmemory\n00000010"),
+        "{}",
+        tui.render()
+    );
+    tui.command("m nowhere").unwrap();
+    assert!(tui.render().ends_with("`nowhere` is not a valid number\n"));
+    assert!(tui.command("q").is_none());
+}
@@ -0,0 +1,63 @@
+// the verifier only exists with std
+#![cfg(feature = "std")]
+
+use cpu_tset::image::{Image, Segment, SegmentKind};
+use cpu_tset::isa::{self, DecodeError, Instr};
+use cpu_tset::verify::{verify, verify_image, VerifyError};
+
+#[test]
+fn sound_code_verifies() {
+    let mut code = vec![];
+    Instr::Bare(isa::NOP).encode(&mut code);
+    Instr::Jump(isa::JMP, 0).encode(&mut code);
+    Instr::Bare(isa::HLT).encode(&mut code);
+
+    assert_eq!(verify(&code), Ok(()));
+}
+
+// the bad byte is skipped, so the jump after it still gets checked
+#[test]
+fn every_problem_is_reported_in_order() {
+    let mut code = vec![];
+    Instr::Jump(isa::JMP, 0x100).encode(&mut code);
+    code.push(0xFF);
+    Instr::Jump(isa::JMP, 1).encode(&mut code);
+
+    assert_eq!(
+        verify(&code),
+        Err(vec![
+            VerifyError::JumpOutOfBounds(0, 0x100),
+            VerifyError::Decode(DecodeError::UnknownOpcode(5, 0xFF)),
+            VerifyError::JumpIntoInstruction(6, 1),
+        ])
+    );
+}
+
+#[test]
+fn jumps_into_data_segments_are_refused() {
+    let mut code = vec![];
+    Instr::Jump(isa::JMP, 0x10).encode(&mut code);
+    let image = Image {
+        entry: 0,
+        memory: 0,
+        segments: vec![
+            Segment {
+                kind: SegmentKind::Code,
+                address: 0,
+                data: code,
+            },
+            Segment {
+                kind: SegmentKind::Data,
+                address: 0x10,
+                data: vec![isa::HLT],
+            },
+        ],
+        symbols: vec![],
+        lines: vec![],
+    };
+
+    assert_eq!(
+        verify_image(&image),
+        Err(vec![VerifyError::JumpOutOfBounds(0, 0x10)])
+    );
+}
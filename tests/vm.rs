@@ -0,0 +1,32 @@
+#[cfg(feature = "std")]
+use cpu_tset::monitor::Monitor;
+use cpu_tset::vm::{Program, VmError};
+
+#[test]
+fn write_grows_the_memory_up_to_its_limit() {
+    let mut program = Program::new(vec![]);
+    program.set_memory_limit(16);
+
+    program.write(12, &[1, 2, 3, 4]).unwrap();
+    assert_eq!(
+        program.code(),
+        &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4]
+    );
+    assert_eq!(
+        program.write(13, &[5; 4]),
+        Err(VmError::OutOfMemory { end: 17, limit: 16 })
+    );
+    assert_eq!(program.code().len(), 16);
+}
+
+// a poke right at the end of the address space would have needed 4 GiB of memory, and more than
+// a u32 can count
+#[cfg(feature = "std")]
+#[test]
+fn the_monitor_refuses_to_poke_past_the_memory_limit() {
+    let mut monitor = Monitor::default();
+
+    let out = monitor.command("e ffffffff 01 02").unwrap();
+    assert!(out.contains("limit"), "{out}");
+    assert_eq!(monitor.program().code().len(), 0x1000);
+}